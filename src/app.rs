@@ -1,43 +1,254 @@
-use std::{fs, path::Path, process::Command};
+use std::{
+    collections::{HashMap, VecDeque},
+    env, io,
+    path::Path,
+    process::{Command, Output},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::audit;
+use crate::binary::{self, FileContent};
+use crate::cargo_tools;
+use crate::commands::{self, CommandOptions};
+use crate::crates;
+use crate::diff;
+use crate::dry_run;
+use crate::encoding::{self, TextFormat};
+use crate::error::ToolError;
+use crate::fetch;
+use crate::file_cache;
+use crate::file_tree::{self, FileMark, TreeEntry};
+use crate::git;
+use crate::hooks::Hooks;
+use crate::ignore_rules::IgnoreRules;
+use crate::image;
+use crate::input_history::{HistorySearch, InputHistory};
+use crate::interrupt;
+use crate::llm::ChatMessage;
+use crate::outline;
+use crate::overlay;
+use crate::plan::Plan;
+use crate::timeline::{IterationRecord, Timeline};
+use crate::plugins;
+use crate::project::ProjectKind;
+use crate::quality;
+use crate::rename;
+use crate::replay::Replay;
+use crate::sanitize;
+use crate::semantic;
+use crate::sandbox::SandboxConfig;
+use crate::test_runner;
+use crate::theme::Theme;
+use crate::vfs::{self, OverlayFs};
+use crate::workspace;
+use crate::write_scope::{self, WriteScope};
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum RunMode {
+    #[default]
+    Running,
+    Paused,
+    // Execute a single pending tool, then pause again
+    Step,
+}
+
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum Focus {
+    #[default]
+    Thoughts,
+    Tools,
+    Files,
+}
+
+// Full-screen, scrollable view of a complete text such as a tool result
+pub struct DetailView {
+    pub title: String,
+    pub content: String,
+    // Syntax used to highlight the whole content, if known
+    pub lang: Option<String>,
+    pub scroll: u16,
+}
+
+// /timeline: the session's iterations, read from its log when opened
+pub struct TimelineView {
+    pub records: Vec<IterationRecord>,
+    pub selected: usize,
+}
+
+// A tool running on a worker thread while the UI stays live, with the
+// latest lines its command printed
+pub struct RunningTool {
+    pub tool: String,
+    pub param: String,
+    pub started: Instant,
+    pub output: VecDeque<String>,
+}
+
+impl RunningTool {
+    pub fn new(tool: &str, param: &str) -> Self {
+        Self {
+            tool: tool.to_string(),
+            param: param.to_string(),
+            started: Instant::now(),
+            output: VecDeque::new(),
+        }
+    }
+
+    pub fn push_line(&mut self, line: String) {
+        if self.output.len() == LIVE_LINES {
+            self.output.pop_front();
+        }
+        self.output.push_back(line);
+    }
+}
+
+pub const MIN_PANEL_WIDTH: u16 = 10;
+// Output lines of a running tool shown in the Tool Execution panel
+const LIVE_LINES: usize = 6;
+
+const DEFAULT_READ_LINES: usize = 400;
 
 pub struct AppState {
     pub iteration: u32,
-    pub conversation_history: Vec<String>,
+    // The message box, edited by the TUI's key handler rather than a line
+    // editor like rustyline, which would need the terminal to itself. Text
+    // is typed and deleted at the end only.
     pub chat_input: String,
+    // Sent messages for ↑/↓, and the Ctrl+R search while one is typed
+    pub input_history: InputHistory,
+    pub history_search: Option<HistorySearch>,
     pub current_thoughts: String,
-    pub current_tools: Vec<(String, String, String)>,
+    // Reasoning split off the latest reply of a reasoning model
+    pub reasoning: String,
+    // Tool, parameter, result and, for calls that ran, their status
+    pub current_tools: Vec<(String, String, String, Option<ToolStatus>)>,
+    pub run_mode: RunMode,
+    pub focus: Focus,
+    pub selected_tool: usize,
+    pub detail: Option<DetailView>,
+    // The session's timeline log and, while it is shown, its iterations
+    pub timeline: Option<Timeline>,
+    pub timeline_view: Option<TimelineView>,
+    // The name the conversation was last saved, loaded or forked as
+    pub session_name: Option<String>,
+    // Commit message of a queued git_commit, or why a queued edit is over
+    // the write limits, waiting for the user's approval
+    pub approval: Option<String>,
+    // The model's ask_user question, answered from the message box
+    pub question: Option<String>,
+    // --plan: the first message is turned into a plan before anything runs
+    pub plan_mode: bool,
+    // The drafted plan while it is reviewed, then the engine's copy with
+    // each step's status
+    pub plan: Option<Plan>,
+    pub plan_review: bool,
+    pub selected_step: usize,
+    // Step whose text is being edited in the message box
+    pub editing_step: Option<usize>,
+    pub file_tree: Vec<TreeEntry>,
+    pub file_marks: HashMap<String, FileMark>,
+    pub selected_file: usize,
+    pub theme: Theme,
+    // Router role and model used for the latest request
+    pub active_model: String,
+    // Column widths in percent: files, thoughts, tools, stats
+    pub panel_widths: [u16; 4],
+    // Splitter being dragged with the mouse, by index of its left panel
+    pub dragging: Option<usize>,
     pub stats: Stats,
     pub should_quit: bool,
     pub success_achieved: bool,
     pub thoughts_scroll: u32,
     pub tools_scroll: u32,
     pub processing: bool,
+    // A request held back by the rate limit: when it can go and which limit
+    // it waits for
+    pub rate_limited: Option<(Instant, &'static str)>,
+    // botcoder replay: the recorded session being played back
+    pub replay: Option<Replay>,
+    pub running_tool: Option<RunningTool>,
 }
 
 impl Default for AppState {
     fn default() -> Self {
         Self {
             iteration: 0,
-            conversation_history: Vec::new(),
+            chat_input: String::new(),
+            input_history: InputHistory::default(),
+            history_search: None,
             current_thoughts: String::new(),
+            reasoning: String::new(),
             current_tools: Vec::new(),
+            run_mode: RunMode::default(),
+            focus: Focus::default(),
+            selected_tool: 0,
+            detail: None,
+            timeline: None,
+            timeline_view: None,
+            session_name: None,
+            approval: None,
+            question: None,
+            plan_mode: false,
+            plan: None,
+            plan_review: false,
+            selected_step: 0,
+            editing_step: None,
+            file_tree: Vec::new(),
+            file_marks: HashMap::new(),
+            selected_file: 0,
+            theme: Theme::default(),
+            active_model: String::new(),
+            panel_widths: [20, 30, 30, 20],
+            dragging: None,
             stats: Stats::default(),
             should_quit: false,
             success_achieved: false,
             thoughts_scroll: 0,
             tools_scroll: 0,
-            chat_input: String::new(),
             processing: false,
+            rate_limited: None,
+            replay: None,
+            running_tool: None,
+        }
+    }
+}
+
+impl AppState {
+    // Moves the splitter right of panel `index` to column `x` within the
+    // panels area, keeping both neighbours at least MIN_PANEL_WIDTH wide
+    pub fn resize_panels(&mut self, index: usize, x: u16, area_x: u16, area_width: u16) {
+        if index + 1 >= self.panel_widths.len() || area_width == 0 {
+            return;
         }
+
+        let before: u16 = self.panel_widths[..index].iter().sum();
+        let pair = self.panel_widths[index] + self.panel_widths[index + 1];
+        let position = (x.saturating_sub(area_x) as u32 * 100 / area_width as u32) as u16;
+
+        let left = position
+            .saturating_sub(before)
+            .clamp(MIN_PANEL_WIDTH, pair - MIN_PANEL_WIDTH);
+        self.panel_widths[index] = left;
+        self.panel_widths[index + 1] = pair - left;
     }
 }
 
+// Minutes of token usage kept for the statistics panel's chart
+pub const HISTORY_MINUTES: usize = 30;
+
 pub struct Stats {
     pub total_tokens: u32,
     pub current_tpm: u32,
     pub max_tpm: u32,
+    pub current_rpm: u32,
+    pub max_rpm: u32,
     pub input_tokens: u32,
     pub output_tokens: u32,
+    // Tokens used per minute, by minute since the epoch, oldest first
+    pub history: VecDeque<(u64, u32)>,
 }
 
 impl Default for Stats {
@@ -46,12 +257,83 @@ impl Default for Stats {
             total_tokens: 0,
             current_tpm: 0,
             max_tpm: 20000,
+            current_rpm: 0,
+            max_rpm: 120,
             input_tokens: 0,
             output_tokens: 0,
+            history: VecDeque::new(),
         }
     }
 }
 
+impl Stats {
+    // Sets the session total; the tokens used since the last update count
+    // towards the current minute
+    pub fn set_total_tokens(&mut self, total: u32) {
+        let used = total.saturating_sub(self.total_tokens);
+        self.total_tokens = total;
+        self.record(current_minute(), used);
+    }
+
+    pub fn record(&mut self, minute: u64, tokens: u32) {
+        match self.history.back_mut() {
+            Some((last, used)) if *last == minute => *used += tokens,
+            _ => self.history.push_back((minute, tokens)),
+        }
+        let oldest = minute.saturating_sub(HISTORY_MINUTES as u64 - 1);
+        while self.history.front().is_some_and(|(m, _)| *m < oldest) {
+            self.history.pop_front();
+        }
+    }
+
+    // Tokens of each of the last HISTORY_MINUTES minutes up to `minute`,
+    // oldest first, with idle minutes as 0
+    pub fn per_minute(&self, minute: u64) -> Vec<u64> {
+        let oldest = minute.saturating_sub(HISTORY_MINUTES as u64 - 1);
+        (oldest..=minute)
+            .map(|m| {
+                self.history
+                    .iter()
+                    .find(|(recorded, _)| *recorded == m)
+                    .map_or(0, |(_, tokens)| u64::from(*tokens))
+            })
+            .collect()
+    }
+}
+
+pub fn current_minute() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 60)
+}
+
+// System prompt with the project path, followed by the conversation so far
+pub fn build_messages(
+    prompt: &str,
+    project_root: &str,
+    history: &[ChatMessage],
+) -> Vec<ChatMessage> {
+    let mut messages = vec![ChatMessage::system(format!(
+        "{}\n\nProject: {}",
+        prompt, project_root
+    ))];
+
+    if history.is_empty() {
+        messages.push(ChatMessage::user("Start working on the project."));
+    } else {
+        messages.extend(history.iter().cloned());
+    }
+
+    messages
+}
+
+pub fn count_message_tokens(messages: &[ChatMessage]) -> u32 {
+    messages
+        .iter()
+        .map(|m| count_tokens(&m.content) + m.images.len() as u32 * image::IMAGE_TOKENS)
+        .sum()
+}
+
 pub fn count_tokens(text: &str) -> u32 {
     // Rough approximation: ~4 chars per token
     (text.len() / 4).max(text.split_whitespace().count()) as u32
@@ -65,112 +347,181 @@ pub fn filter_thinking_tokens(text: &str) -> String {
         .to_string()
 }
 
+// Splits a reply into its reasoning and the answer. Reasoning comes as
+// <think> or <thinking> blocks, from models that reason inline or from the
+// client passing on a separate reasoning field; an unclosed block runs to
+// the end of the reply.
+pub fn split_reasoning(text: &str) -> (Option<String>, String) {
+    let mut reasoning = Vec::new();
+    let mut answer = String::new();
+    let mut rest = text;
+    loop {
+        let open = ["<think>", "<thinking>"]
+            .iter()
+            .filter_map(|tag| rest.find(tag).map(|at| (at, *tag)))
+            .min();
+        let Some((at, tag)) = open else {
+            answer.push_str(rest);
+            break;
+        };
+        answer.push_str(&rest[..at]);
+        let inner = &rest[at + tag.len()..];
+        let close = tag.replace('<', "</");
+        let (block, after) = match inner.find(&close) {
+            Some(end) => (&inner[..end], &inner[end + close.len()..]),
+            None => (inner, ""),
+        };
+        if !block.trim().is_empty() {
+            reasoning.push(block.trim().to_string());
+        }
+        rest = after;
+    }
+    let reasoning = (!reasoning.is_empty()).then(|| reasoning.join("\n\n"));
+    (reasoning, answer.trim().to_string())
+}
+
 pub fn extract_tools(text: &str) -> Vec<(String, String)> {
+    extract_tools_checked(text).0
+}
+
+// The tool calls in a reply, and a note for each call that was malformed
+// and left out rather than run with half its parameter
+pub fn extract_tools_checked(text: &str) -> (Vec<(String, String)>, Vec<String>) {
     let mut tools = Vec::new();
+    let mut diagnostics = Vec::new();
 
     let cleaned_text = text
+        .replace("\r\n", "\n")
         .replace("```rust", "")
         .replace("```sh", "")
         .replace("```bash", "")
         .replace("```", "");
     let text = cleaned_text.as_str();
 
-    // Extract read_file calls
-    if text.contains("read_file") {
-        for line in text.lines() {
-            if line.contains("read_file(") {
-                if let Some(start) = line.find("read_file(") {
-                    let after_open = &line[start + 10..];
-                    if let Some(end) = after_open.find(')') {
-                        let param = after_open[..end]
-                            .trim()
-                            .trim_matches('"')
-                            .trim_matches('\'')
-                            .to_string();
-                        if !param.is_empty() {
-                            tools.push(("read_file".to_string(), param));
-                        }
-                    }
-                }
-            }
+    // Extract switch_project calls first, so the other calls in the reply
+    // run against the project it switches to
+    for args in calls(text, "switch_project", &mut diagnostics) {
+        if let Some(name) = call_args(args).first().filter(|n| !n.is_empty()) {
+            tools.push(("switch_project".to_string(), name.clone()));
         }
     }
 
-    // Extract execute_command calls
-    if text.contains("execute_command") {
-        for line in text.lines() {
-            if line.contains("execute_command(") {
-                if let Some(start) = line.find("execute_command(") {
-                    let after_open = &line[start + 16..];
-                    if let Some(end) = after_open.find(')') {
-                        let content = &after_open[..end];
-                        if let Some(quote_start) = content.find('"') {
-                            if let Some(quote_end) = content[quote_start + 1..].find('"') {
-                                let param = &content[quote_start + 1..quote_start + 1 + quote_end];
-                                if !param.is_empty() {
-                                    tools.push(("execute_command".to_string(), param.to_string()));
-                                }
-                            }
-                        }
-                    }
-                }
+    // Extract read_file calls, optionally read_file("path", 200-400, numbered)
+    for args in calls(text, "read_file", &mut diagnostics) {
+        let args = call_args(args);
+        let Some(path) = args.first().filter(|p| !p.is_empty()) else {
+            continue;
+        };
+        let options: Vec<&str> = args[1..]
+            .iter()
+            .map(|a| a.trim_start_matches("lines").trim())
+            .filter(|a| !a.is_empty())
+            .collect();
+        let param = if options.is_empty() {
+            path.clone()
+        } else {
+            format!("{}:::{}", path, options.join(","))
+        };
+        tools.push(("read_file".to_string(), param));
+    }
+
+    // Extract outline calls
+    for args in calls(text, "outline", &mut diagnostics) {
+        if let Some(path) = call_args(args).first().filter(|p| !p.is_empty()) {
+            tools.push(("outline".to_string(), path.clone()));
+        }
+    }
+
+    // Extract execute_command calls; the command must be one quoted string,
+    // optionally followed by cwd="dir" and env="NAME=value ..."
+    for args in calls(text, "execute_command", &mut diagnostics) {
+        match command_call(args) {
+            Ok(param) => tools.push(("execute_command".to_string(), param)),
+            Err(problem) => diagnostics.push(format!(
+                "execute_command({}) {}, so it was not run",
+                args, problem
+            )),
+        }
+    }
+
+    // Extract run_tests calls, with an optional test name filter
+    for args in calls(text, "run_tests", &mut diagnostics) {
+        tools.push(("run_tests".to_string(), unquote(args)));
+    }
+
+    // Extract the git read tools and the cargo tools, each with an optional
+    // argument: a path for git_diff, a commit count for git_log, cargo
+    // arguments for cargo_add/cargo_remove/cargo_tree/cargo_clippy, a crate
+    // name for cargo_deps and lookup_crate, "check" for cargo_fmt, the URL
+    // for fetch_url, the query for semantic_search, a summary for step_done,
+    // the question for ask_user
+    for name in [
+        "git_status",
+        "git_diff",
+        "git_log",
+        "cargo_add",
+        "cargo_remove",
+        "cargo_tree",
+        "cargo_deps",
+        "cargo_clippy",
+        "cargo_fmt",
+        "lookup_crate",
+        "fetch_url",
+        "semantic_search",
+        "step_done",
+        "ask_user",
+    ] {
+        for args in calls(text, name, &mut diagnostics) {
+            tools.push((name.to_string(), unquote(args)));
+        }
+    }
+
+    // Extract rename_symbol calls: the old and the new name
+    for args in calls(text, "rename_symbol", &mut diagnostics) {
+        match call_args(args).as_slice() {
+            [old, new] if !old.is_empty() && !new.is_empty() => {
+                tools.push(("rename_symbol".to_string(), format!("{}:::{}", old, new)))
             }
+            _ => diagnostics.push(format!(
+                "rename_symbol({}) needs the old and the new name, so it was not run",
+                args
+            )),
+        }
+    }
+
+    // Extract git_commit calls with the commit message
+    for args in calls(text, "git_commit", &mut diagnostics) {
+        let message = unquote(args);
+        if !message.is_empty() {
+            tools.push(("git_commit".to_string(), message));
         }
     }
 
     // Extract file changes
     if text.contains("CHANGE:") {
-        let lines: Vec<&str> = text.lines().collect();
-        let mut i = 0;
-
-        while i < lines.len() {
-            let line = lines[i].trim();
-
-            if line.starts_with("CHANGE:") {
-                let file_path = line.replace("CHANGE:", "").trim().to_string();
-                let mut current_content = String::new();
-                let mut new_content = String::new();
-                let mut in_current = false;
-                let mut in_new = false;
-
-                i += 1;
-                while i < lines.len() {
-                    let current_line = lines[i];
-
-                    if current_line.contains("<<<<<<< CURRENT") {
-                        in_current = true;
-                        in_new = false;
-                    } else if current_line.contains("=======") {
-                        in_current = false;
-                        in_new = true;
-                    } else if current_line.contains(">>>>>>> NEW") {
-                        break;
-                    } else if in_current {
-                        current_content.push_str(current_line);
-                        current_content.push('\n');
-                    } else if in_new {
-                        new_content.push_str(current_line);
-                        new_content.push('\n');
-                    }
-
-                    i += 1;
-                }
+        tools.extend(extract_delta_format(text, &mut diagnostics));
+    }
 
-                if !file_path.is_empty() {
-                    tools.push((
-                        "write_file_delta".to_string(),
-                        format!(
-                            "{}:::{}\n{}",
-                            file_path,
-                            current_content.trim(),
-                            new_content.trim()
-                        ),
-                    ));
-                }
-            }
+    // Extract line-range edits
+    if text.contains("EDIT_LINES:") {
+        tools.extend(extract_line_edits(text, &mut diagnostics));
+    }
 
-            i += 1;
-        }
+    // Extract appends and anchored inserts
+    if text.contains("APPEND_TO_FILE:") {
+        tools.extend(extract_appends(text, &mut diagnostics));
+    }
+    if text.contains("INSERT_AFTER:") {
+        tools.extend(extract_inserts(text, &mut diagnostics));
+    }
+
+    // Extract project tool calls
+    if text.contains("TOOL:") {
+        tools.extend(
+            extract_blocks(text, "TOOL:", "END_TOOL", &mut diagnostics)
+                .into_iter()
+                .filter(|(name, _)| !name.is_empty()),
+        );
     }
 
     // Remove duplicates
@@ -181,100 +532,1182 @@ pub fn extract_tools(text: &str) -> Vec<(String, String)> {
         }
     }
 
-    unique_tools
+    (unique_tools, diagnostics)
+}
+
+// The argument text of each name(...) call, one call per line. The call
+// ends at the parenthesis matching its opening one, outside quoted
+// arguments; a call left open is noted instead.
+fn calls<'a>(text: &'a str, name: &str, diagnostics: &mut Vec<String>) -> Vec<&'a str> {
+    let call = format!("{}(", name);
+    let mut found = Vec::new();
+    for line in text.lines() {
+        let Some(start) = line.find(&call) else {
+            continue;
+        };
+        // Part of a longer name, e.g. my_read_file(
+        let before = line[..start].chars().next_back();
+        if before.is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            continue;
+        }
+        match call_body(&line[start + call.len()..]) {
+            Some(args) => found.push(args),
+            None => diagnostics.push(format!(
+                "{} has no closing parenthesis or an unterminated quote, so it was not run",
+                line[start..].trim()
+            )),
+        }
+    }
+    found
+}
+
+// The text before the ')' closing a call. Quotes open only at the start of
+// an argument, so an apostrophe inside an unquoted one doesn't count, and a
+// backslash escapes the next character inside quotes.
+fn call_body(after_open: &str) -> Option<&str> {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut arg_start = true;
+
+    for (i, c) in after_open.char_indices() {
+        if let Some(q) = quote {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                c if c == q => quote = None,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' if arg_start => quote = Some(c),
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(&after_open[..i]),
+            ')' => depth -= 1,
+            _ => {}
+        }
+        arg_start = c == ',' || (arg_start && c.is_whitespace());
+    }
+    None
+}
+
+// A single quoted argument with its escaped quotes restored, or None when
+// the argument is anything else
+fn quoted_arg(args: &str) -> Option<String> {
+    quoted_prefix(args).and_then(|(value, rest)| rest.trim().is_empty().then_some(value))
+}
+
+// A leading quoted string, unescaped, and what follows it
+fn quoted_prefix(args: &str) -> Option<(String, &str)> {
+    let args = args.trim();
+    let quote = args.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+    let mut value = String::new();
+    let mut escaped = false;
+    for (i, c) in args.char_indices().skip(1) {
+        match c {
+            c if escaped => {
+                if c != quote {
+                    value.push('\\');
+                }
+                value.push(c);
+                escaped = false;
+            }
+            '\\' => escaped = true,
+            c if c == quote => return Some((value, &args[i + 1..])),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+// The execute_command parameter for `"command", cwd="dir", env="A=1 B=2"`,
+// or what is wrong with the call
+fn command_call(args: &str) -> Result<String, String> {
+    let not_quoted = || "is not a single quoted command".to_string();
+    let (command, rest) = quoted_prefix(args).filter(|(c, _)| !c.is_empty()).ok_or_else(not_quoted)?;
+    let rest = rest.trim();
+    if rest.is_empty() {
+        return Ok(command);
+    }
+    let rest = rest.strip_prefix(',').ok_or_else(not_quoted)?;
+    let (mut cwd, mut env) = (String::new(), String::new());
+    for arg in call_args(rest) {
+        match arg.split_once('=').map(|(name, value)| (name.trim(), unquote(value))) {
+            Some(("cwd", value)) => cwd = value,
+            Some(("env", value)) => env = value,
+            _ => return Err(format!("has an unknown argument {:?} (it takes cwd= and env=)", arg)),
+        }
+    }
+    let options = CommandOptions::parse(&cwd, &env).map_err(|e| format!("has a bad env: {}", e))?;
+    Ok(commands::encode(&command, &options))
+}
+
+// An optional argument, quoted or not
+fn unquote(args: &str) -> String {
+    quoted_arg(args).unwrap_or_else(|| args.trim().trim_matches('"').trim_matches('\'').to_string())
+}
+
+// Comma-separated call arguments with surrounding quotes removed
+fn call_args(inner: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    let mut escaped = false;
+
+    for c in inner.chars() {
+        match (c, quote) {
+            (c, Some(_)) if escaped => {
+                current.push(c);
+                escaped = false;
+            }
+            ('\\', Some(_)) => escaped = true,
+            ('"' | '\'', None) if current.trim().is_empty() => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            (',', None) => args.push(std::mem::take(&mut current).trim().to_string()),
+            (c, _) => current.push(c),
+        }
+    }
+    args.push(current.trim().to_string());
+
+    args
+}
+
+// Blocks that start with a "HEADER: args" line and run to an END line,
+// as (args, body) pairs. A block without its END line is noted and left
+// out, since where it was meant to stop is unknown.
+fn extract_blocks(
+    text: &str,
+    header: &str,
+    end: &str,
+    diagnostics: &mut Vec<String>,
+) -> Vec<(String, String)> {
+    let mut blocks = Vec::new();
+    let mut lines = text.lines();
+
+    while let Some(line) = lines.next() {
+        let Some(args) = line.trim().strip_prefix(header) else {
+            continue;
+        };
+
+        let mut body = Vec::new();
+        let mut ended = false;
+        for line in lines.by_ref() {
+            if line.trim() == end {
+                ended = true;
+                break;
+            }
+            body.push(line);
+        }
+
+        if ended {
+            blocks.push((args.trim().to_string(), body.join("\n")));
+        } else {
+            diagnostics.push(format!(
+                "{} {} has no {} line, so it was not applied",
+                header,
+                args.trim(),
+                end
+            ));
+        }
+    }
+
+    blocks
+}
+
+// EDIT_LINES: path START-END, then the replacement lines up to END_EDIT
+fn extract_line_edits(text: &str, diagnostics: &mut Vec<String>) -> Vec<(String, String)> {
+    extract_blocks(text, "EDIT_LINES:", "END_EDIT", diagnostics)
+        .into_iter()
+        .filter_map(|(args, body)| {
+            let (path, range) = args.rsplit_once(' ')?;
+            Some((
+                "edit_lines".to_string(),
+                format!("{}:::{}\n{}", path.trim(), range.trim(), body),
+            ))
+        })
+        .collect()
+}
+
+// APPEND_TO_FILE: path, then the content up to END_APPEND
+fn extract_appends(text: &str, diagnostics: &mut Vec<String>) -> Vec<(String, String)> {
+    extract_blocks(text, "APPEND_TO_FILE:", "END_APPEND", diagnostics)
+        .into_iter()
+        .filter(|(path, _)| !path.is_empty())
+        .map(|(path, body)| ("append_to_file".to_string(), format!("{}:::{}", path, body)))
+        .collect()
+}
+
+// INSERT_AFTER: path "anchor", then the content up to END_INSERT
+fn extract_inserts(text: &str, diagnostics: &mut Vec<String>) -> Vec<(String, String)> {
+    extract_blocks(text, "INSERT_AFTER:", "END_INSERT", diagnostics)
+        .into_iter()
+        .filter_map(|(args, body)| {
+            let (path, anchor) = args.split_once(' ')?;
+            let anchor = anchor.trim().trim_matches('"');
+            if anchor.is_empty() {
+                return None;
+            }
+            Some((
+                "insert_after".to_string(),
+                format!("{}:::{}\n{}", path.trim(), anchor, body),
+            ))
+        })
+        .collect()
+}
+
+// Path argument of the file tools, whose parameters start with the path
+// followed by ":::" and any options
+pub fn tool_path<'a>(tool: &str, param: &'a str) -> Option<&'a str> {
+    match tool {
+        "read_file" | "outline" | "write_file_delta" | "edit_lines" | "append_to_file"
+        | "insert_after" => {
+            param.split(":::").next().map(|p| p.trim())
+        }
+        _ => None,
+    }
+}
+
+// param with its path argument's "." and ".." resolved
+fn clean_path(tool: &str, param: &str) -> String {
+    match tool_path(tool, param) {
+        Some(path) => {
+            let rest = param.split_once(":::").map(|(_, rest)| rest);
+            let path = file_tree::clean(path);
+            rest.map_or(path.clone(), |rest| format!("{}:::{}", path, rest))
+        }
+        None => param.to_string(),
+    }
+}
+
+// The size an edit would leave its file at, at most: a whole-file CHANGE
+// sets it, other hunks add their net growth, and the line tools add
+// everything they insert
+pub fn projected_size(tool: &str, param: &str, root: &str) -> Option<u64> {
+    let path = tool_path(tool, param).filter(|_| is_edit_tool(tool))?;
+    let current = vfs::current().len(&Path::new(root).join(path)).unwrap_or(0);
+    let (_, body) = param.split_once(":::")?;
+    if tool != "write_file_delta" {
+        return Some(current + body.len() as u64);
+    }
+    let hunks = parse_hunks(body).unwrap_or_default();
+    if let [(old, new)] = hunks.as_slice() {
+        if old.is_empty() {
+            return Some(new.len() as u64);
+        }
+    }
+    let added: usize = hunks.iter().map(|(_, new)| new.len()).sum();
+    let removed: usize = hunks.iter().map(|(old, _)| old.len()).sum();
+    Some((current + added as u64).saturating_sub(removed as u64))
+}
+
+// Files a successful call changed: the edited file for the edit tools,
+// the manifest for cargo_add and cargo_remove, the reformatted files for
+// cargo_fmt
+pub fn modified_paths(tool: &str, param: &str, output: &ToolOutput) -> Vec<String> {
+    match tool {
+        "cargo_add" | "cargo_remove" if output.exit_code == Some(0) => {
+            vec!["Cargo.toml".to_string()]
+        }
+        "cargo_fmt" if output.exit_code == Some(0) => quality::formatted_files(&output.text),
+        "rename_symbol" => rename::renamed_files(&output.text),
+        _ if is_edit_tool(tool) => tool_path(tool, param).map(str::to_string).into_iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Tools that change file contents
+pub fn is_edit_tool(tool: &str) -> bool {
+    matches!(
+        tool,
+        "write_file_delta" | "edit_lines" | "append_to_file" | "insert_after"
+    )
+}
+
+// CHANGE blocks, each with one or more CURRENT/NEW hunks that run until the
+// next CHANGE header. A header always starts a new block, so one hunk
+// missing its >>>>>>> NEW line can't swallow the next file's change.
+fn extract_delta_format(text: &str, diagnostics: &mut Vec<String>) -> Vec<(String, String)> {
+    let mut tools = Vec::new();
+    let mut file_path: Option<String> = None;
+    let mut body = String::new();
+    let mut in_hunk = false;
+
+    for line in text.lines() {
+        if line.trim().starts_with("CHANGE:") {
+            if let Some(path) = file_path.take() {
+                tools.extend(delta_tool(&path, &body, diagnostics));
+            }
+            file_path = Some(line.trim().replace("CHANGE:", "").trim().to_string());
+            body.clear();
+            in_hunk = false;
+            continue;
+        }
+        if file_path.is_none() {
+            continue;
+        }
+
+        if line.contains("<<<<<<< CURRENT") {
+            in_hunk = true;
+        } else if line.contains(">>>>>>> NEW") {
+            in_hunk = false;
+        } else if !in_hunk {
+            // Prose between hunks
+            continue;
+        }
+        body.push_str(line);
+        body.push('\n');
+    }
+
+    if let Some(path) = file_path {
+        tools.extend(delta_tool(&path, &body, diagnostics));
+    }
+
+    tools
+}
+
+fn delta_tool(path: &str, body: &str, diagnostics: &mut Vec<String>) -> Option<(String, String)> {
+    if path.is_empty() {
+        diagnostics.push("a CHANGE block has no file path, so it was not applied".to_string());
+        return None;
+    }
+    match parse_hunks(body) {
+        Ok(hunks) if !hunks.is_empty() => Some(("write_file_delta".to_string(), encode_delta(path, &hunks))),
+        Ok(_) => None,
+        Err(problem) => {
+            diagnostics.push(format!("the CHANGE block for {} {}, so none of it was applied", path, problem));
+            None
+        }
+    }
+}
+
+// write_file_delta parameter: "path:::" followed by the hunks in CHANGE
+// block syntax
+pub fn encode_delta(path: &str, hunks: &[(String, String)]) -> String {
+    let mut param = format!("{}:::", path);
+    for (old, new) in hunks {
+        param.push_str(&format!(
+            "<<<<<<< CURRENT\n{}\n=======\n{}\n>>>>>>> NEW\n",
+            old.trim(),
+            new.trim()
+        ));
+    }
+    param
+}
+
+// The (old, new) pairs of a CHANGE block, or what is wrong with it when a
+// hunk is cut short
+fn parse_hunks(body: &str) -> Result<Vec<(String, String)>, &'static str> {
+    let mut hunks = Vec::new();
+    let mut old = String::new();
+    let mut new = String::new();
+    let mut in_current = false;
+    let mut in_new = false;
+
+    for line in body.lines() {
+        if line.contains("<<<<<<< CURRENT") {
+            if in_current || in_new {
+                return Err("has a hunk without its >>>>>>> NEW line");
+            }
+            in_current = true;
+            old.clear();
+            new.clear();
+        } else if line.contains("=======") && in_current {
+            in_current = false;
+            in_new = true;
+        } else if line.contains(">>>>>>> NEW") {
+            if in_current {
+                return Err("has a hunk without its ======= separator");
+            }
+            if in_new {
+                hunks.push((old.trim().to_string(), new.trim().to_string()));
+            }
+            in_new = false;
+        } else if in_current {
+            old.push_str(line);
+            old.push('\n');
+        } else if in_new {
+            new.push_str(line);
+            new.push('\n');
+        }
+    }
+
+    if in_current || in_new {
+        return Err("has a hunk without its >>>>>>> NEW line");
+    }
+    Ok(hunks)
+}
+
+// Each output stream of a command is cut to this much, head and tail, in
+// the text the model is sent
+const STREAM_LIMIT: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputKind {
+    #[default]
+    Text,
+    // A process ran, or a report on one such as test counts
+    Command,
+}
+
+// What a tool produced. Commands also report their exit status, which
+// doesn't make the tool call itself a failure.
+#[derive(Clone, Debug, Default)]
+pub struct ToolOutput {
+    pub kind: OutputKind,
+    // What the model is sent
+    pub text: String,
+    pub exit_code: Option<i32>,
+    // Set by execute_tool, hooks included
+    pub duration: Duration,
+    // A process's output in full
+    pub stdout: String,
+    pub stderr: String,
+    // The text leaves part of stdout or stderr out
+    pub truncated: bool,
+}
+
+impl ToolOutput {
+    pub fn text(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn command(text: impl Into<String>, exit_code: i32) -> Self {
+        Self {
+            kind: OutputKind::Command,
+            text: text.into(),
+            exit_code: Some(exit_code),
+            ..Self::default()
+        }
+    }
+
+    // A process's output, without terminal escapes; the text has only the
+    // streams that aren't empty:
+    //
+    //   stdout:
+    //   ...
+    //   stderr:
+    //   ...
+    //   exit_code: 1
+    pub fn process(stdout: String, stderr: String, exit_code: i32) -> Self {
+        let (stdout, stderr) = (sanitize::clean(&stdout), sanitize::clean(&stderr));
+        let mut text = String::new();
+        let mut truncated = false;
+        for (name, stream) in [("stdout", &stdout), ("stderr", &stderr)] {
+            let stream = stream.trim_end();
+            if stream.is_empty() {
+                continue;
+            }
+            let (cut, was_cut) = cut_middle(stream, STREAM_LIMIT);
+            text.push_str(&format!("{}:\n{}\n", name, cut));
+            truncated |= was_cut;
+        }
+        text.push_str(&format!("exit_code: {}", exit_code));
+        Self {
+            kind: OutputKind::Command,
+            text,
+            exit_code: Some(exit_code),
+            stdout,
+            stderr,
+            truncated,
+            ..Self::default()
+        }
+    }
+
+    // The same output with other text for the model, e.g. a summary of it
+    pub fn with_text(self, text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            ..self
+        }
+    }
+
+    // stdout then stderr
+    pub fn streams(&self) -> String {
+        match (self.stdout.is_empty(), self.stderr.is_empty()) {
+            (_, true) => self.stdout.clone(),
+            (true, false) => self.stderr.clone(),
+            (false, false) => format!("{}\n{}", self.stdout.trim_end(), self.stderr),
+        }
+    }
+
+    // The streams, or the text of a tool that reports without them, like
+    // run_tests
+    pub fn raw(&self) -> String {
+        if self.stdout.is_empty() && self.stderr.is_empty() {
+            self.text.clone()
+        } else {
+            self.streams()
+        }
+    }
+
+    pub fn status(&self) -> ToolStatus {
+        ToolStatus {
+            kind: self.kind,
+            exit_code: self.exit_code,
+            duration_ms: self.duration.as_millis() as u64,
+            stdout_bytes: self.stdout.len() as u64,
+            stderr_bytes: self.stderr.len() as u64,
+            truncated: self.truncated,
+        }
+    }
+}
+
+// The shape of a tool's output without its text, for observers
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ToolStatus {
+    pub kind: OutputKind,
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    pub stdout_bytes: u64,
+    pub stderr_bytes: u64,
+    pub truncated: bool,
+}
+
+impl ToolStatus {
+    // e.g. "exit 1 · 2.3s · 4.1 KB out · 96 bytes err · cut"
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(code) = self.exit_code {
+            parts.push(format!("exit {}", code));
+        }
+        parts.push(format!("{:.1}s", self.duration_ms as f64 / 1000.0));
+        if self.stdout_bytes > 0 {
+            parts.push(format!("{} out", write_scope::human_size(self.stdout_bytes)));
+        }
+        if self.stderr_bytes > 0 {
+            parts.push(format!("{} err", write_scope::human_size(self.stderr_bytes)));
+        }
+        if self.truncated {
+            parts.push("cut".to_string());
+        }
+        parts.join(" · ")
+    }
+}
+
+// The first and last limit / 2 bytes of text, and whether any were left out
+fn cut_middle(text: &str, limit: usize) -> (String, bool) {
+    if text.len() <= limit {
+        return (text.to_string(), false);
+    }
+    let mut head = limit / 2;
+    while !text.is_char_boundary(head) {
+        head -= 1;
+    }
+    let mut tail = text.len() - limit / 2;
+    while !text.is_char_boundary(tail) {
+        tail += 1;
+    }
+    let cut = format!(
+        "{}\n[... {} cut ...]\n{}",
+        &text[..head],
+        write_scope::human_size((tail - head) as u64),
+        &text[tail..]
+    );
+    (cut, true)
+}
+
+// The text sent back to the model for a tool call
+pub fn result_text(result: &Result<ToolOutput, ToolError>) -> String {
+    match result {
+        Ok(output) => output.text.clone(),
+        Err(e) => format!("✗ {}", e),
+    }
+}
+
+// Runs a tool, timing it, and records it in the audit log
+pub fn execute_tool(tool: &str, param: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    let started = Instant::now();
+    let mut result = audited(tool, param, root);
+    if let Ok(output) = &mut result {
+        output.duration = started.elapsed();
+    }
+    result
+}
+
+fn audited(tool: &str, param: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    if !audit::enabled() || dry_run::enabled() {
+        return run_hooked(tool, param, root);
+    }
+
+    let edited = if is_edit_tool(tool) {
+        tool_path(tool, param).map(|path| Path::new(root).join(path))
+    } else {
+        None
+    };
+    let before = edited
+        .as_ref()
+        .map(|path| String::from_utf8_lossy(&vfs::current().read(path).unwrap_or_default()).into_owned());
+
+    let result = run_hooked(tool, param, root);
+
+    let diff_hash = edited.zip(before).map(|(path, before)| {
+        audit::diff_hash(&before, &String::from_utf8_lossy(&vfs::current().read(&path).unwrap_or_default()))
+    });
+    audit::record(root, tool, param, diff_hash, &result_text(&result));
+
+    result
+}
+
+// The tool wrapped in the project's before and after hooks. They're
+// skipped in dry-run mode, and under --patch-output, where they would run
+// on the project the edits leave alone. A call the project's rules refuse
+// never reaches them.
+fn run_hooked(tool: &str, param: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    let param = checked(tool, param, root)?;
+    let param = param.as_str();
+    if dry_run::enabled() || overlay::enabled() {
+        return run_checked(tool, param, root);
+    }
+    let hooks = Hooks::load(root);
+    hooks.run_before(tool, param, root)?;
+    let output = run_checked(tool, param, root)?;
+    hooks.run_after(tool, param, root)?;
+    Ok(output)
+}
+
+fn run_tool(tool: &str, param: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    run_checked(tool, &checked(tool, param, root)?, root)
+}
+
+// The call's parameter once the project's rules allow it. The checks and
+// the tool itself all see "src/../secrets/key" as "secrets/key"
+fn checked(tool: &str, param: &str, root: &str) -> Result<String, ToolError> {
+    let cleaned = clean_path(tool, param);
+    let param = cleaned.as_str();
+    if let Some(path) = tool_path(tool, param) {
+        workspace::refuse_outside(root, path)?;
+    }
+    if matches!(tool, "read_file" | "outline") {
+        IgnoreRules::load(root).refuse(tool_path(tool, param).unwrap_or(""))?;
+    }
+    WriteScope::load(root).refuse(tool, param)?;
+    Ok(cleaned)
 }
 
-pub fn execute_tool(tool: &str, param: &str, root: &str) -> String {
+// Runs a call `checked` has allowed
+fn run_checked(tool: &str, param: &str, root: &str) -> Result<ToolOutput, ToolError> {
     match tool {
         "read_file" => {
-            let path = Path::new(root).join(param);
-            fs::read_to_string(&path).unwrap_or_else(|e| format!("Error reading file: {}", e))
+            let (path, options) = param.split_once(":::").unwrap_or((param, ""));
+            match read_text(root, path.trim())? {
+                FileContent::Text(content) => read_file(path.trim(), &content, options),
+                FileContent::Binary(description) => Ok(ToolOutput::text(description)),
+            }
         }
-        "write_file_delta" => {
-            let parts: Vec<&str> = param.splitn(2, ":::").collect();
-            if parts.len() == 2 {
-                let path = Path::new(root).join(parts[0].trim());
-                let content_parts: Vec<&str> = parts[1].splitn(2, '\n').collect();
-
-                if content_parts.len() == 2 {
-                    let old_content = content_parts[0].trim();
-                    let new_content = content_parts[1].trim();
-                    apply_delta(&path, old_content, new_content)
+        "outline" => match read_text(root, param.trim())? {
+            FileContent::Binary(description) => Ok(ToolOutput::text(description)),
+            FileContent::Text(content) => {
+                let items = outline::outline(param.trim(), &content);
+                if items.is_empty() {
+                    Ok(ToolOutput::text(format!("No items found in {}", param.trim())))
                 } else {
-                    "Error: Invalid delta format".to_string()
+                    Ok(ToolOutput::text(outline::format_outline(&items)))
                 }
-            } else {
-                "Error: Invalid write_file_delta format".to_string()
             }
+        },
+        "edit_lines" => match param.split_once(":::") {
+            Some((path, rest)) => {
+                let (range, replacement) = rest.split_once('\n').unwrap_or((rest, ""));
+                edit_lines(&Path::new(root).join(path.trim()), range, replacement)
+            }
+            None => Err(ToolError::InvalidFormat("edit_lines")),
+        },
+        "append_to_file" => match param.split_once(":::") {
+            Some((path, content)) => append_to_file(&Path::new(root).join(path.trim()), content),
+            None => Err(ToolError::InvalidFormat("append_to_file")),
+        },
+        "insert_after" => match param.split_once(":::") {
+            Some((path, rest)) => {
+                let (anchor, content) = rest.split_once('\n').unwrap_or((rest, ""));
+                insert_after(&Path::new(root).join(path.trim()), anchor, content)
+            }
+            None => Err(ToolError::InvalidFormat("insert_after")),
+        },
+        "write_file_delta" => {
+            let (path, body) = param
+                .split_once(":::")
+                .ok_or(ToolError::InvalidFormat("write_file_delta"))?;
+            let hunks = parse_hunks(body).unwrap_or_default();
+            if hunks.is_empty() {
+                return Err(ToolError::InvalidFormat("write_file_delta"));
+            }
+            apply_delta(&Path::new(root).join(path.trim()), &hunks)
+        }
+        "execute_command" if dry_run::enabled() => {
+            let (command, options) = commands::decode(param);
+            let dir = options.over(&CommandOptions::load(root)).dir(root)?;
+            Ok(ToolOutput::text(match dir {
+                Some(dir) => format!("Would run in {}: {}", dir, command),
+                None => format!("Would run: {}", command),
+            }))
         }
         "execute_command" => {
-            let output = if cfg!(target_os = "windows") {
-                Command::new("cmd")
-                    .args(["/C", param])
-                    .current_dir(root)
-                    .output()
-            } else {
-                Command::new("sh")
-                    .arg("-c")
-                    .arg(param)
-                    .current_dir(root)
-                    .output()
-            };
-
-            match output {
-                Ok(output) => {
-                    let stdout = String::from_utf8_lossy(&output.stdout);
-                    let stderr = String::from_utf8_lossy(&output.stderr);
-                    let exit_code = output.status.code().unwrap_or(-1);
-
-                    format!(
-                        "stdout:\n{}\nstderr:\n{}\nexit_code: {}",
-                        stdout, stderr, exit_code
-                    )
-                }
-                Err(e) => format!("Error executing command: {}", e),
-            }
+            let (command, options) = commands::decode(param);
+            run_command_with(command, root, &options.over(&CommandOptions::load(root)))
         }
-        _ => format!("Unknown tool: {}", tool),
+        "run_tests" if dry_run::enabled() => Ok(ToolOutput::text(
+            format!("Would run: {} {}", test_runner::test_command(root), param)
+                .trim_end()
+                .to_string(),
+        )),
+        "run_tests" => Ok(test_runner::run_tests(param, root)?.to_tool_output()),
+        "git_commit" => git::commit(param, root),
+        "git_status" => git::status(root),
+        "git_diff" => git::diff_tool(param, root),
+        "git_log" => git::log(param, root),
+        "cargo_add" => cargo_tools::cargo_edit("add", param, root),
+        "cargo_remove" => cargo_tools::cargo_edit("remove", param, root),
+        "cargo_tree" => cargo_tools::cargo_tree(param, root),
+        "cargo_deps" => cargo_tools::cargo_deps(param, root),
+        "cargo_clippy" => quality::clippy(param, root),
+        "cargo_fmt" => quality::fmt(param, root),
+        "lookup_crate" => crates::lookup(param, root),
+        "fetch_url" => fetch::fetch_url(param),
+        "semantic_search" => semantic::search_tool(param, root),
+        "rename_symbol" => rename::rename_symbol(param, root),
+        // Answered by the engine, or by the frontend driving it
+        "ask_user" => Err(ToolError::NoOneToAsk),
+        _ => match plugins::find(tool) {
+            Some(plugin) => plugin.run(param, root),
+            None => Err(ToolError::UnknownTool(tool.to_string())),
+        },
     }
 }
 
-fn apply_delta(path: &Path, old_content: &str, new_content: &str) -> String {
-    let existing_content = match fs::read_to_string(path) {
-        Ok(content) => content,
-        Err(_) => {
-            if let Some(parent) = path.parent() {
-                let _ = fs::create_dir_all(parent);
-            }
-            return match fs::write(path, new_content) {
-                Ok(_) => format!("✓ Created new file: {}", path.display()),
-                Err(e) => format!("✗ Error creating file: {}", e),
-            };
+// The file an edit tool would leave behind, worked out in memory so the
+// user can tweak it in $EDITOR before it is applied
+pub fn proposed_content(tool: &str, param: &str, root: &str) -> Result<String, ToolError> {
+    let path = tool_path(tool, param)
+        .filter(|_| is_edit_tool(tool) && !dry_run::enabled())
+        .ok_or_else(|| ToolError::InvalidArgs(format!("{} can't be edited before it runs", tool)))?;
+    workspace::refuse_outside(root, path)?;
+    // The edit lands in a layer over the session's files that is then dropped
+    let _fs = vfs::install(Arc::new(OverlayFs::over(vfs::current())));
+    run_tool(tool, param, root)
+        .and_then(|_| read_existing(&Path::new(root).join(file_tree::clean(path))))
+        .map(|(content, _)| content)
+}
+
+// Runs a shell command in the project, inside the sandbox if configured
+pub fn run_command(command: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    run_command_with(command, root, &CommandOptions::default())
+}
+
+// run_command in a subdirectory and with extra environment variables
+pub fn run_command_with(command: &str, root: &str, options: &CommandOptions) -> Result<ToolOutput, ToolError> {
+    let cmd = match SandboxConfig::from_env() {
+        Some(sandbox) => {
+            let mut cmd = sandbox.command(command, root, options.dir(root)?.as_deref(), &options.env);
+            cmd.current_dir(root);
+            cmd
+        }
+        None => {
+            let mut cmd = shell_command(command);
+            options.apply(&mut cmd, root)?;
+            cmd
         }
     };
+    let output = interrupt::output(cmd).map_err(ToolError::Spawn)?;
+    Ok(command_output(&output))
+}
 
-    if old_content.is_empty() {
-        return match fs::write(path, new_content) {
-            Ok(_) => format!("✓ Replaced entire file: {}", path.display()),
-            Err(e) => format!("✗ Error replacing file: {}", e),
-        };
+pub fn command_output(output: &Output) -> ToolOutput {
+    ToolOutput::process(
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+        output.status.code().unwrap_or(-1),
+    )
+}
+
+fn read_text(root: &str, path: &str) -> Result<FileContent, ToolError> {
+    file_cache::read(&Path::new(root).join(path)).map_err(|source| ToolError::Read {
+        path: path.to_string(),
+        source,
+    })
+}
+
+// BOTCODER_SHELL overrides the platform default (cmd on Windows, sh elsewhere);
+// accepted values are cmd, powershell, pwsh or any POSIX-style shell binary
+pub fn shell_command(command: &str) -> Command {
+    let default_shell = if cfg!(target_os = "windows") {
+        "cmd"
+    } else {
+        "sh"
+    };
+    let shell = env::var("BOTCODER_SHELL").unwrap_or_else(|_| default_shell.to_string());
+
+    let mut cmd = Command::new(&shell);
+    match shell.to_lowercase().trim_end_matches(".exe") {
+        "cmd" => {
+            cmd.args(["/C", command]);
+        }
+        "powershell" | "pwsh" => {
+            cmd.args(["-NoProfile", "-NonInteractive", "-Command", command]);
+        }
+        _ => {
+            cmd.args(["-c", command]);
+        }
     }
+    cmd
+}
 
-    if let Some(pos) = existing_content.find(old_content) {
-        let mut updated_content = String::new();
-        updated_content.push_str(&existing_content[..pos]);
-        updated_content.push_str(new_content);
-        updated_content.push_str(&existing_content[pos + old_content.len()..]);
+// BOTCODER_PACE_MS: how long the TUI leaves each reply and tool result on
+// screen before running the next tool (default 0). Headless runs are never
+// paced.
+pub fn tool_pace() -> Duration {
+    env::var("BOTCODER_PACE_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or_default()
+}
 
-        match fs::write(path, updated_content) {
-            Ok(_) => format!("✓ Successfully applied delta to: {}", path.display()),
-            Err(e) => format!("✗ Error applying delta: {}", e),
+// BOTCODER_SUCCESS selects what counts as task completion: "cargo_run"
+// (default; running the program, e.g. with python or npm start outside Rust
+// projects) or "tests_pass"
+pub fn success_criteria() -> String {
+    env::var("BOTCODER_SUCCESS").unwrap_or_else(|_| "cargo_run".to_string())
+}
+
+pub fn is_success(tool: &str, param: &str, output: &ToolOutput, root: &str) -> bool {
+    match success_criteria().as_str() {
+        "tests_pass" => tool == "run_tests" && output.exit_code == Some(0),
+        _ => {
+            tool == "execute_command"
+                && ProjectKind::detect(root)
+                    .run_commands()
+                    .iter()
+                    .any(|run| param.contains(run))
+                && output.exit_code == Some(0)
+                && !output.streams().to_lowercase().contains("error")
         }
-    } else {
+    }
+}
+
+// Options are comma-separated: "numbered" and/or a "START-END" line range.
+// Without a range, long files are cut to the first read_limit() lines
+// followed by an outline of the whole file.
+fn read_file(path: &str, content: &str, options: &str) -> Result<ToolOutput, ToolError> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut numbered = false;
+    let mut range = None;
+
+    for option in options.split(',').map(|o| o.trim()) {
+        if option == "numbered" {
+            numbered = true;
+        } else if let Some((start, end)) = option.split_once('-') {
+            match (start.trim().parse::<usize>(), end.trim().parse::<usize>()) {
+                (Ok(start), Ok(end)) if start >= 1 && start <= end => range = Some((start, end)),
+                _ => return Err(ToolError::InvalidRange(option.to_string())),
+            }
+        }
+    }
+
+    let format = |start: usize, end: usize| {
+        if numbered {
+            number_lines(&lines[start - 1..end], start, lines.len())
+        } else {
+            let mut out = lines[start - 1..end].join("\n");
+            out.push('\n');
+            out
+        }
+    };
+
+    let text = match range {
+        Some((start, end)) if start > lines.len() => {
+            return Err(ToolError::OutOfRange {
+                path: path.to_string(),
+                start,
+                end,
+                lines: lines.len(),
+            })
+        }
+        Some((start, end)) => {
+            let end = end.min(lines.len());
+            format!(
+                "[{}: lines {}-{} of {}]\n{}",
+                path,
+                start,
+                end,
+                lines.len(),
+                format(start, end)
+            )
+        }
+        None if lines.len() > read_limit() => {
+            let limit = read_limit();
+            format!(
+                "[{}: lines 1-{} of {}; read more with read_file(\"{}\", {}-{})]\n{}\n[outline]\n{}",
+                path,
+                limit,
+                lines.len(),
+                path,
+                limit + 1,
+                (limit * 2).min(lines.len()),
+                format(1, limit),
+                outline::format_outline(&outline::outline(path, content))
+            )
+        }
+        None if numbered => number_lines(&lines, 1, lines.len()),
+        None => content.to_string(),
+    };
+    Ok(ToolOutput::text(text))
+}
+
+// Lines returned by a read_file without a range, from BOTCODER_READ_LINES
+fn read_limit() -> usize {
+    env::var("BOTCODER_READ_LINES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|&n| n > 0)
+        .unwrap_or(DEFAULT_READ_LINES)
+}
+
+pub fn number_lines(lines: &[&str], first: usize, total: usize) -> String {
+    let width = total.to_string().len();
+    lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| format!("{:>width$} | {}\n", first + i, line, width = width))
+        .collect()
+}
+
+// Replaces lines START-END (1-based, inclusive); an empty replacement
+// deletes them
+fn edit_lines(path: &Path, range: &str, replacement: &str) -> Result<ToolOutput, ToolError> {
+    binary::refuse_edit(path)?;
+
+    let (start, end) = range
+        .trim()
+        .split_once('-')
+        .and_then(|(s, e)| Some((s.trim().parse::<usize>().ok()?, e.trim().parse::<usize>().ok()?)))
+        .ok_or_else(|| ToolError::InvalidRange(range.trim().to_string()))?;
+
+    let (content, format) = read_existing(path)?;
+    let lines: Vec<&str> = content.lines().collect();
+    if start == 0 || start > end || end > lines.len() {
+        return Err(ToolError::OutOfRange {
+            path: path.display().to_string(),
+            start,
+            end,
+            lines: lines.len(),
+        });
+    }
+
+    let mut updated: Vec<&str> = lines[..start - 1].to_vec();
+    if !replacement.is_empty() {
+        updated.extend(replacement.lines());
+    }
+    updated.extend(&lines[end..]);
+
+    let mut updated = updated.join("\n");
+    if content.ends_with('\n') {
+        updated.push('\n');
+    }
+
+    save(
+        path,
+        &content,
+        updated,
+        format,
         format!(
-            "✗ Could not find content in {}\nSearching for:\n{}",
+            "✓ Replaced lines {}-{} of {} with {} line(s)",
+            start,
+            end,
             path.display(),
-            old_content
+            replacement.lines().count()
+        ),
+    )
+}
+
+// Creates the file if needed, starting the content on a new line
+fn append_to_file(path: &Path, content: &str) -> Result<ToolOutput, ToolError> {
+    binary::refuse_edit(path)?;
+
+    let (existing, format) = read_if_exists(path)?.unwrap_or_default();
+    let mut updated = existing.clone();
+    if !updated.is_empty() && !updated.ends_with('\n') {
+        updated.push('\n');
+    }
+    updated.push_str(content);
+    if !updated.ends_with('\n') {
+        updated.push('\n');
+    }
+
+    save(
+        path,
+        &existing,
+        updated,
+        format,
+        format!(
+            "✓ Appended {} line(s) to {}",
+            content.lines().count(),
+            path.display()
+        ),
+    )
+}
+
+// Inserts after the single line containing the anchor text
+fn insert_after(path: &Path, anchor: &str, content: &str) -> Result<ToolOutput, ToolError> {
+    binary::refuse_edit(path)?;
+
+    let (existing, format) = read_existing(path)?;
+    let lines: Vec<&str> = existing.lines().collect();
+    let matches: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| line.contains(anchor))
+        .map(|(i, _)| i)
+        .collect();
+
+    let index = match matches.as_slice() {
+        [index] => *index,
+        [] => {
+            return Err(ToolError::AnchorNotFound {
+                path: path.display().to_string(),
+                anchor: anchor.to_string(),
+            })
+        }
+        _ => {
+            return Err(ToolError::AmbiguousAnchor {
+                path: path.display().to_string(),
+                anchor: anchor.to_string(),
+                matches: matches.len(),
+            })
+        }
+    };
+
+    let mut updated: Vec<&str> = lines[..=index].to_vec();
+    updated.extend(content.lines());
+    updated.extend(&lines[index + 1..]);
+
+    let mut updated = updated.join("\n");
+    if existing.ends_with('\n') {
+        updated.push('\n');
+    }
+
+    save(
+        path,
+        &existing,
+        updated,
+        format,
+        format!(
+            "✓ Inserted {} line(s) after line {} of {}",
+            content.lines().count(),
+            index + 1,
+            path.display()
+        ),
+    )
+}
+
+// Hunks are applied in order to the file in memory, so a later hunk can
+// match text produced by an earlier one; nothing is written unless all match
+fn apply_delta(path: &Path, hunks: &[(String, String)]) -> Result<ToolOutput, ToolError> {
+    binary::refuse_edit(path)?;
+
+    // Matched and written with \n line breaks, whatever the file uses
+    let hunks: Vec<(String, String)> = hunks
+        .iter()
+        .map(|(old, new)| (old.replace("\r\n", "\n"), new.replace("\r\n", "\n")))
+        .collect();
+
+    let Some((existing_content, format)) = read_if_exists(path)? else {
+        let content: Vec<&str> = hunks.iter().map(|(_, new)| new.as_str()).collect();
+        return save(
+            path,
+            "",
+            content.join("\n"),
+            TextFormat::default(),
+            format!("✓ Created new file: {}", path.display()),
+        );
+    };
+
+    if let [(old_content, new_content)] = hunks.as_slice() {
+        if old_content.is_empty() {
+            return save(
+                path,
+                &existing_content,
+                new_content.clone(),
+                format,
+                format!("✓ Replaced entire file: {}", path.display()),
+            );
+        }
+    }
+
+    let mut updated_content = existing_content.clone();
+    for (i, (old_content, new_content)) in hunks.iter().enumerate() {
+        let pos = updated_content
+            .find(old_content.as_str())
+            .ok_or_else(|| ToolError::NoMatch {
+                path: path.display().to_string(),
+                hunk: i + 1,
+                hunks: hunks.len(),
+                search: old_content.clone(),
+            })?;
+        updated_content.replace_range(pos..pos + old_content.len(), new_content);
+    }
+
+    let summary = if hunks.len() > 1 {
+        format!(
+            "✓ Successfully applied {} hunks to: {}",
+            hunks.len(),
+            path.display()
         )
+    } else {
+        format!("✓ Successfully applied delta to: {}", path.display())
+    };
+    save(path, &existing_content, updated_content, format, summary)
+}
+
+fn read_existing(path: &Path) -> Result<(String, TextFormat), ToolError> {
+    encoding::read(path).map_err(|source| ToolError::Read {
+        path: path.display().to_string(),
+        source,
+    })
+}
+
+// None for a file that isn't there yet; any other failure is an error, not
+// an empty file to write over
+fn read_if_exists(path: &Path) -> Result<Option<(String, TextFormat)>, ToolError> {
+    match encoding::read(path) {
+        Ok(read) => Ok(Some(read)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(source) => Err(ToolError::Read {
+            path: path.display().to_string(),
+            source,
+        }),
+    }
+}
+
+// Writes an edited file in its original format through the session's file
+// system. Where that takes no writes (dry run) the diff is returned instead.
+pub fn save(
+    path: &Path,
+    before: &str,
+    after: String,
+    format: TextFormat,
+    summary: String,
+) -> Result<ToolOutput, ToolError> {
+    match vfs::current().write(path, &encoding::encode(&after, format)) {
+        Ok(()) => {
+            file_cache::invalidate(path);
+            Ok(ToolOutput::text(summary))
+        }
+        Err(e) if e.kind() == io::ErrorKind::ReadOnlyFilesystem => {
+            let diff = diff::unified(&path.display().to_string(), before, &after);
+            Ok(ToolOutput::text(format!(
+                "Would write {} (dry run)\n{}",
+                path.display(),
+                diff
+            )))
+        }
+        Err(source) => Err(ToolError::Write {
+            path: path.display().to_string(),
+            source,
+        }),
     }
 }
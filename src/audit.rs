@@ -0,0 +1,153 @@
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::overlay;
+
+// Append-only record of every executed tool, one JSON object per line.
+// Each entry stores the hash of the previous one and its own hash over its
+// contents, so editing or removing a line breaks the chain from there on.
+const AUDIT_FILE: &str = ".botcoder/audit.log";
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+const SUMMARY_LEN: usize = 200;
+
+// The hash of each log's last entry and the log's length after it, so an
+// entry is chained without reading the log again. Held while appending, so
+// reads run side by side still append one entry at a time; other processes
+// are kept out by a lock on the file.
+static LAST: Mutex<Option<HashMap<PathBuf, (u64, String)>>> = Mutex::new(None);
+
+// BOTCODER_AUDIT=off disables the log
+pub fn enabled() -> bool {
+    !matches!(
+        env::var("BOTCODER_AUDIT").as_deref(),
+        Ok("off" | "0" | "false")
+    )
+}
+
+pub fn path(root: &str) -> PathBuf {
+    overlay::state_path(root, AUDIT_FILE)
+}
+
+// diff_hash identifies the change a tool made to a file: the hash of its
+// contents before and after, or None for tools that don't edit files
+pub fn record(root: &str, tool: &str, args: &str, diff_hash: Option<String>, result: &str) {
+    let path = path(root);
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    // Commands are summarized by their exit code, everything else by its
+    // first line
+    let summary: String = result
+        .lines()
+        .rev()
+        .find(|line| line.starts_with("exit_code:"))
+        .or_else(|| result.lines().next())
+        .unwrap_or("")
+        .chars()
+        .take(SUMMARY_LEN)
+        .collect();
+
+    let entry = json!({
+        "timestamp": timestamp,
+        "tool": tool,
+        "args": args,
+        "diff_hash": diff_hash,
+        "result": summary,
+    });
+    if let Err(e) = append(&path, entry) {
+        log::error!("Failed to write audit log {}: {}", path.display(), e);
+    }
+}
+
+// Chains the entry to the log's last one and appends it, with the file
+// locked. The log is only read again when its length isn't the one this
+// process left it at: on the first entry, or after another process wrote.
+fn append(path: &Path, mut entry: Value) -> std::io::Result<()> {
+    let mut last = LAST.lock().unwrap_or_else(|e| e.into_inner());
+    let last = last.get_or_insert_with(HashMap::new);
+    let mut file = OpenOptions::new().create(true).read(true).append(true).open(path)?;
+    file.lock()?;
+
+    let len = file.metadata()?.len();
+    let prev = match last.get(path).filter(|(known, _)| *known == len) {
+        Some((_, hash)) => hash.clone(),
+        None => {
+            let mut log = String::new();
+            file.seek(SeekFrom::Start(0))?;
+            file.read_to_string(&mut log)?;
+            last_hash(&log).unwrap_or_else(|| GENESIS.to_string())
+        }
+    };
+    entry["prev"] = Value::String(prev);
+    let hash = entry_hash(&entry);
+    entry["hash"] = Value::String(hash.clone());
+
+    writeln!(file, "{}", entry)?;
+    last.insert(path.to_path_buf(), (file.metadata()?.len(), hash));
+    Ok(())
+}
+
+pub fn diff_hash(before: &str, after: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(before.as_bytes());
+    hasher.update([0]);
+    hasher.update(after.as_bytes());
+    hex(&hasher.finalize())
+}
+
+// Checks the whole chain; Ok with the number of entries, or the first
+// broken line
+pub fn verify(root: &str) -> Result<usize, String> {
+    let log =
+        fs::read_to_string(path(root)).map_err(|e| format!("Cannot read audit log: {}", e))?;
+    let mut prev = GENESIS.to_string();
+
+    for (i, line) in log.lines().enumerate() {
+        let line_no = i + 1;
+        let mut entry: Map<String, Value> = serde_json::from_str(line)
+            .map_err(|e| format!("line {}: not a valid entry ({})", line_no, e))?;
+        let hash = entry
+            .remove("hash")
+            .and_then(|h| h.as_str().map(String::from))
+            .ok_or_else(|| format!("line {}: missing hash", line_no))?;
+
+        if entry.get("prev").and_then(|p| p.as_str()) != Some(prev.as_str()) {
+            return Err(format!("line {}: previous hash does not match", line_no));
+        }
+        if entry_hash(&Value::Object(entry)) != hash {
+            return Err(format!("line {}: contents do not match hash", line_no));
+        }
+        prev = hash;
+    }
+
+    Ok(log.lines().count())
+}
+
+fn last_hash(log: &str) -> Option<String> {
+    let line = log.lines().last()?;
+    let entry: Value = serde_json::from_str(line).ok()?;
+    entry.get("hash")?.as_str().map(String::from)
+}
+
+// serde_json objects serialize with sorted keys, so this is stable
+fn entry_hash(entry: &Value) -> String {
+    hex(&Sha256::digest(entry.to_string().as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,409 @@
+use async_trait::async_trait;
+use dotenvy::dotenv;
+use log::{error, info};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::deterministic;
+use crate::error::LlmError;
+use crate::http;
+use crate::limits;
+use crate::llm::{self, ChatMessage, LLMProvider, ModelOptions, RateLimiter, Usage, Vars, MAX_REPLY_TOKENS};
+
+const SERVICE: &str = "bedrock";
+
+// Static credentials from AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY and, for
+// temporary ones, AWS_SESSION_TOKEN
+#[derive(Clone, Debug)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    pub fn from_env() -> Result<Self, LlmError> {
+        Self::from_vars(&llm::env_var)
+    }
+
+    pub fn from_vars(vars: Vars) -> Result<Self, LlmError> {
+        let var = |name: &str| vars(name).filter(|v| !v.is_empty());
+        Ok(Self {
+            access_key_id: var("AWS_ACCESS_KEY_ID")
+                .ok_or_else(|| LlmError::MissingConfig("AWS_ACCESS_KEY_ID".to_string()))?,
+            secret_access_key: var("AWS_SECRET_ACCESS_KEY")
+                .ok_or_else(|| LlmError::MissingConfig("AWS_SECRET_ACCESS_KEY".to_string()))?,
+            session_token: var("AWS_SESSION_TOKEN"),
+        })
+    }
+}
+
+// Models hosted on Amazon Bedrock in the user's own account, called
+// through the Converse API
+pub struct BedrockClient {
+    region: String,
+    // Bedrock-runtime endpoint, e.g. for a VPC endpoint
+    endpoint: String,
+    // Model or inference profile ID, e.g. anthropic.claude-3-5-sonnet-20240620-v1:0
+    model: String,
+    options: ModelOptions,
+    credentials: AwsCredentials,
+    client: Client,
+    rate_limiter: Arc<RateLimiter>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConverseResponse {
+    output: ConverseOutput,
+    #[serde(default)]
+    usage: Option<ConverseUsage>,
+}
+
+#[derive(Deserialize)]
+struct ConverseOutput {
+    message: ConverseMessage,
+}
+
+#[derive(Deserialize)]
+struct ConverseMessage {
+    content: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ConverseUsage {
+    input_tokens: u32,
+    output_tokens: u32,
+    total_tokens: u32,
+}
+
+impl BedrockClient {
+    // Reads <PREFIX>_MODEL and <PREFIX>_REGION (or AWS_REGION), falling back
+    // to the LLM_* variables; <PREFIX>_URL overrides the regional endpoint
+    pub fn from_env_prefix(prefix: &str) -> Result<Self, LlmError> {
+        dotenv().ok();
+        Self::from_vars(prefix, &llm::env_var)
+    }
+
+    pub fn from_vars(prefix: &str, vars: Vars) -> Result<Self, LlmError> {
+        let var = llm::prefixed(vars, prefix);
+
+        let model = var("MODEL").map_err(|_| LlmError::MissingConfig(format!("{}_MODEL", prefix)))?;
+        let region = var("REGION")
+            .ok()
+            .or_else(|| vars("AWS_REGION"))
+            .or_else(|| vars("AWS_DEFAULT_REGION"))
+            .ok_or_else(|| LlmError::MissingConfig(format!("{}_REGION or AWS_REGION", prefix)))?;
+        let endpoint = var("URL")
+            .unwrap_or_else(|_| format!("https://bedrock-runtime.{}.amazonaws.com", region));
+        let options = ModelOptions::from_env(&var, &model);
+        let rate_limiter = RateLimiter::from_profile(&limits::for_client("bedrock", &model));
+
+        Ok(Self {
+            region,
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            model,
+            options,
+            credentials: AwsCredentials::from_vars(vars)?,
+            client: http::client()?,
+            rate_limiter: Arc::new(rate_limiter),
+        })
+    }
+
+    pub async fn converse(&self, messages: &[ChatMessage]) -> Result<String, LlmError> {
+        let path = format!("/model/{}/converse", uri_encode(&self.model, false));
+        let url = format!("{}{}", self.endpoint, path);
+        let body = serde_json::to_vec(&converse_request(messages, &self.options))
+            .map_err(|e| LlmError::InvalidConfig(format!("Could not encode the request: {}", e)))?;
+        let estimated = messages.iter().map(|m| m.content.len() / 4).sum::<usize>() as u32 + 100;
+        let _permit = self.rate_limiter.wait_if_needed(estimated).await;
+
+        let host = self
+            .endpoint
+            .split("://")
+            .nth(1)
+            .unwrap_or(&self.endpoint)
+            .to_string();
+        let amz_date = amz_date(SystemTime::now());
+        let mut headers = vec![
+            ("content-type", "application/json".to_string()),
+            ("host", host),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let authorization = sign(
+            &self.credentials,
+            &self.region,
+            SERVICE,
+            "POST",
+            &path,
+            &headers,
+            &body,
+            &amz_date,
+        );
+
+        info!("Sending request to Bedrock ({})", self.model);
+
+        let mut request = self.client.post(&url).header("authorization", authorization);
+        for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+            request = request.header(*name, value);
+        }
+        let response = request
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| http::connection_error(&url, e))?;
+
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let body = response.text().await?;
+            error!("Bedrock API error ({}): {}", status, body);
+            return Err(LlmError::Api { status, body });
+        }
+
+        let response: ConverseResponse = response.json().await?;
+        if let Some(usage) = &response.usage {
+            self.rate_limiter
+                .record_usage(Usage {
+                    prompt_tokens: usage.input_tokens,
+                    completion_tokens: usage.output_tokens,
+                    total_tokens: usage.total_tokens,
+                })
+                .await;
+        }
+        converse_text(&response.output.message.content)
+    }
+}
+
+#[async_trait]
+impl LLMProvider for BedrockClient {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn provider(&self) -> &str {
+        "bedrock"
+    }
+
+    fn supports_images(&self) -> bool {
+        self.options.vision
+    }
+
+    fn last_usage(&self) -> Option<Usage> {
+        self.rate_limiter.last_usage()
+    }
+
+    async fn generate(&self, prompt: &str, config: &Value) -> Result<String, LlmError> {
+        let messages = [
+            ChatMessage::system("You are a helpful AI coding assistant."),
+            ChatMessage::user(prompt),
+        ];
+        self.generate_chat(&messages, config).await
+    }
+
+    async fn generate_chat(
+        &self,
+        messages: &[ChatMessage],
+        _config: &Value,
+    ) -> Result<String, LlmError> {
+        self.converse(messages).await
+    }
+}
+
+// System messages go in "system"; the conversation has to start with a user
+// turn and alternate, so consecutive turns of one role are merged
+pub fn converse_request(messages: &[ChatMessage], options: &ModelOptions) -> Value {
+    let system: Vec<Value> = messages
+        .iter()
+        .filter(|m| m.role == "system")
+        .map(|m| json!({"text": m.content}))
+        .collect();
+
+    let mut turns: Vec<(String, Vec<Value>)> = Vec::new();
+    for message in messages.iter().filter(|m| m.role != "system") {
+        let role = if message.role == "assistant" { "assistant" } else { "user" };
+        let mut content = vec![json!({"text": message.content})];
+        content.extend(message.images.iter().filter_map(|url| image_block(url)));
+        match turns.last_mut() {
+            Some((last, blocks)) if last == role => blocks.extend(content),
+            _ => turns.push((role.to_string(), content)),
+        }
+    }
+    if turns.first().is_some_and(|(role, _)| role == "assistant") {
+        turns.insert(0, ("user".to_string(), vec![json!({"text": "Continue."})]));
+    }
+
+    let mut inference = json!({"maxTokens": MAX_REPLY_TOKENS});
+    if !options.reasoning {
+        inference["temperature"] = json!(deterministic::temperature(0.7));
+    }
+    json!({
+        "system": system,
+        "messages": turns
+            .into_iter()
+            .map(|(role, content)| json!({"role": role, "content": content}))
+            .collect::<Vec<_>>(),
+        "inferenceConfig": inference,
+    })
+}
+
+// A data URL as a Converse image block
+fn image_block(url: &str) -> Option<Value> {
+    let (media_type, data) = url.strip_prefix("data:")?.split_once(";base64,")?;
+    let format = media_type.strip_prefix("image/")?;
+    Some(json!({"image": {"format": format, "source": {"bytes": data}}}))
+}
+
+// The reply's text blocks; reasoning blocks are passed on as <think> blocks
+// like the other providers'
+fn converse_text(content: &[Value]) -> Result<String, LlmError> {
+    let mut reasoning = Vec::new();
+    let mut text = Vec::new();
+    for block in content {
+        if let Some(t) = block["text"].as_str() {
+            text.push(t);
+        } else if let Some(r) = block["reasoningContent"]["reasoningText"]["text"].as_str() {
+            reasoning.push(r);
+        }
+    }
+    if text.is_empty() && reasoning.is_empty() {
+        return Err(LlmError::EmptyResponse);
+    }
+    let text = text.join("\n");
+    Ok(if reasoning.is_empty() {
+        text
+    } else {
+        format!("<think>{}</think>\n{}", reasoning.join("\n"), text)
+    })
+}
+
+// AWS Signature Version 4: the Authorization header for a request with
+// these headers (lowercase names, all of them signed) and body. `path` is
+// the request path as sent, already URI-encoded once.
+#[allow(clippy::too_many_arguments)]
+pub fn sign(
+    credentials: &AwsCredentials,
+    region: &str,
+    service: &str,
+    method: &str,
+    path: &str,
+    headers: &[(&str, String)],
+    payload: &[u8],
+    amz_date: &str,
+) -> String {
+    let mut headers: Vec<(String, String)> = headers
+        .iter()
+        .map(|(name, value)| (name.to_lowercase(), value.trim().to_string()))
+        .collect();
+    headers.sort();
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+
+    // Every service but S3 encodes the already encoded path again
+    let canonical_request = format!(
+        "{}\n{}\n\n{}\n{}\n{}",
+        method,
+        uri_encode(path, true),
+        canonical_headers,
+        signed_headers,
+        hex(&Sha256::digest(payload))
+    );
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let key = format!("AWS4{}", credentials.secret_access_key);
+    let key = hmac(key.as_bytes(), date.as_bytes());
+    let key = hmac(&key, region.as_bytes());
+    let key = hmac(&key, service.as_bytes());
+    let key = hmac(&key, b"aws4_request");
+    let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key_id, scope, signed_headers, signature
+    )
+}
+
+// YYYYMMDD'T'HHMMSS'Z' in UTC
+pub fn amz_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3_600,
+        rest % 3_600 / 60,
+        rest % 60
+    )
+}
+
+// Percent-encodes all but the unreserved characters, and '/' when asked
+fn uri_encode(text: &str, keep_slash: bool) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b'/' if keep_slash => "/".to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn hmac(key: &[u8], message: &[u8]) -> Vec<u8> {
+    const BLOCK: usize = 64;
+    let mut block = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let pad = |byte: u8| block.iter().map(|b| b ^ byte).collect::<Vec<u8>>();
+    let inner = Sha256::new()
+        .chain_update(pad(0x36))
+        .chain_update(message)
+        .finalize();
+    Sha256::new()
+        .chain_update(pad(0x5c))
+        .chain_update(inner)
+        .finalize()
+        .to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
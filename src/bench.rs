@@ -0,0 +1,316 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::app;
+use crate::engine::AgentEngine;
+use crate::error::{AgentError, ToolError};
+use crate::glyphs;
+use crate::interrupt;
+use crate::oneshot;
+
+const TASK_FILE: &str = "task.toml";
+const SNAPSHOT_DIR: &str = "repo";
+const REPORT_DIR: &str = ".botcoder/bench";
+
+// `botcoder bench <dir>` runs every task fixture in the directory, one
+// subdirectory each:
+//
+//   off-by-one/
+//     task.toml   instruction = "Fix the failing test"
+//                 check = "cargo test"       (optional)
+//                 max_iterations = 8         (optional)
+//     repo/       the project snapshot the agent works on
+//
+// Each task runs on a scratch copy of its snapshot. It succeeds when the
+// check command exits 0 afterwards or, without one, when the run meets the
+// BOTCODER_SUCCESS condition.
+#[derive(Deserialize)]
+struct TaskFile {
+    instruction: String,
+    check: Option<String>,
+    max_iterations: Option<u32>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TaskResult {
+    pub name: String,
+    pub success: bool,
+    pub iterations: u32,
+    pub tokens: u32,
+    pub wall_ms: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BenchReport {
+    pub model: String,
+    // Seconds since the epoch, also the report's file name
+    pub started: u64,
+    pub tasks: Vec<TaskResult>,
+}
+
+impl BenchReport {
+    pub fn success_rate(&self) -> f64 {
+        if self.tasks.is_empty() {
+            return 0.0;
+        }
+        self.tasks.iter().filter(|t| t.success).count() as f64 / self.tasks.len() as f64
+    }
+
+    fn mean(&self, value: impl Fn(&TaskResult) -> f64) -> f64 {
+        if self.tasks.is_empty() {
+            return 0.0;
+        }
+        self.tasks.iter().map(value).sum::<f64>() / self.tasks.len() as f64
+    }
+
+    pub fn mean_iterations(&self) -> f64 {
+        self.mean(|t| t.iterations as f64)
+    }
+
+    pub fn mean_tokens(&self) -> f64 {
+        self.mean(|t| t.tokens as f64)
+    }
+
+    pub fn mean_seconds(&self) -> f64 {
+        self.mean(|t| t.wall_ms as f64 / 1000.0)
+    }
+
+    // One line per task and the totals
+    pub fn render(&self) -> String {
+        let mut text = format!("Benchmark of {} ({} task(s))\n", self.model, self.tasks.len());
+        for task in &self.tasks {
+            text.push_str(&format!(
+                "{} {:<24} {:>3} iteration(s) {:>8} tokens {:>7.1}s{}\n",
+                if task.success { "✓" } else { "✗" },
+                task.name,
+                task.iterations,
+                task.tokens,
+                task.wall_ms as f64 / 1000.0,
+                task.error.as_ref().map(|e| format!("  {}", e)).unwrap_or_default()
+            ));
+        }
+        text.push_str(&format!(
+            "Success {:.0}% | {:.1} iteration(s), {:.0} tokens, {:.1}s per task\n",
+            self.success_rate() * 100.0,
+            self.mean_iterations(),
+            self.mean_tokens(),
+            self.mean_seconds()
+        ));
+        text
+    }
+
+    // How this run differs from an earlier one: the averages side by side,
+    // then the tasks that flipped
+    pub fn compare(&self, baseline: &BenchReport) -> String {
+        let mut text = format!("Compared with {} ({}):\n", baseline.started, baseline.model);
+        let rows = [
+            ("success", baseline.success_rate() * 100.0, self.success_rate() * 100.0, "%"),
+            ("iterations", baseline.mean_iterations(), self.mean_iterations(), ""),
+            ("tokens", baseline.mean_tokens(), self.mean_tokens(), ""),
+            ("seconds", baseline.mean_seconds(), self.mean_seconds(), "s"),
+        ];
+        for (label, before, after, unit) in rows {
+            text.push_str(&format!(
+                "  {:<10} {:>9.1}{} → {:>9.1}{} ({:+.1})\n",
+                label,
+                before,
+                unit,
+                after,
+                unit,
+                after - before
+            ));
+        }
+        for task in &self.tasks {
+            let Some(before) = baseline.tasks.iter().find(|t| t.name == task.name) else {
+                continue;
+            };
+            match (before.success, task.success) {
+                (false, true) => text.push_str(&format!("  ✓ {} now succeeds\n", task.name)),
+                (true, false) => text.push_str(&format!("  ✗ {} now fails\n", task.name)),
+                _ => {}
+            }
+        }
+        text
+    }
+
+    // Saves to .botcoder/bench/<started>.json
+    pub fn save(&self, project_root: &str) -> io::Result<PathBuf> {
+        let dir = Path::new(project_root).join(REPORT_DIR);
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.json", self.started));
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(&path, json)?;
+        Ok(path)
+    }
+}
+
+// The newest report saved before `started`, to compare against
+pub fn previous_report(project_root: &str, started: u64) -> Option<BenchReport> {
+    let mut reports: Vec<(u64, PathBuf)> = fs::read_dir(Path::new(project_root).join(REPORT_DIR))
+        .ok()?
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let stamp = path.file_stem()?.to_str()?.parse().ok()?;
+            Some((stamp, path))
+        })
+        .filter(|(stamp, _)| *stamp < started)
+        .collect();
+    reports.sort();
+    let (_, path) = reports.pop()?;
+    load_report(&path).ok()
+}
+
+pub fn load_report(path: &Path) -> Result<BenchReport, ToolError> {
+    let text = fs::read_to_string(path).map_err(|e| ToolError::Read {
+        path: path.display().to_string(),
+        source: e,
+    })?;
+    serde_json::from_str(&text)
+        .map_err(|e| ToolError::InvalidArgs(format!("{} is not a bench report: {}", path.display(), e)))
+}
+
+// Runs the tasks in name order, each with an engine from `new_engine` rooted
+// at its scratch copy. Failed tasks are recorded; only a model client that
+// can't be set up stops the run.
+pub async fn run(
+    dir: &Path,
+    max_iterations: u32,
+    new_engine: impl Fn(&str) -> Result<AgentEngine, AgentError>,
+) -> Result<BenchReport, AgentError> {
+    let mut tasks: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|e| ToolError::Read {
+            path: dir.display().to_string(),
+            source: e,
+        })?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.join(TASK_FILE).is_file())
+        .collect();
+    tasks.sort();
+    if tasks.is_empty() {
+        return Err(ToolError::InvalidArgs(format!(
+            "no task fixtures in {} (each needs a {} and a {}/ snapshot)",
+            dir.display(),
+            TASK_FILE,
+            SNAPSHOT_DIR
+        ))
+        .into());
+    }
+
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let mut report = BenchReport {
+        model: String::new(),
+        started,
+        tasks: Vec::new(),
+    };
+    for task in tasks {
+        if interrupt::is_interrupted() {
+            break;
+        }
+        let name = task
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let result = run_task(&task, &name, max_iterations, &new_engine, &mut report.model).await?;
+        // Progress goes to stderr, leaving stdout to the report
+        eprintln!(
+            "{}",
+            glyphs::plain(&format!(
+                "{} {}: {} iteration(s), {} tokens, {:.1}s",
+                if result.success { "✓" } else { "✗" },
+                name,
+                result.iterations,
+                result.tokens,
+                result.wall_ms as f64 / 1000.0
+            ))
+        );
+        report.tasks.push(result);
+    }
+    Ok(report)
+}
+
+async fn run_task(
+    task: &Path,
+    name: &str,
+    max_iterations: u32,
+    new_engine: &impl Fn(&str) -> Result<AgentEngine, AgentError>,
+    model: &mut String,
+) -> Result<TaskResult, AgentError> {
+    let mut result = TaskResult {
+        name: name.to_string(),
+        success: false,
+        iterations: 0,
+        tokens: 0,
+        wall_ms: 0,
+        error: None,
+    };
+    let fixture = match fs::read_to_string(task.join(TASK_FILE))
+        .map_err(|e| e.to_string())
+        .and_then(|text| toml::from_str::<TaskFile>(&text).map_err(|e| e.to_string()))
+    {
+        Ok(fixture) => fixture,
+        Err(e) => {
+            result.error = Some(format!("bad {}: {}", TASK_FILE, e));
+            return Ok(result);
+        }
+    };
+    let scratch = env::temp_dir().join(format!("botcoder-bench-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&scratch);
+    if let Err(e) = copy_dir(&task.join(SNAPSHOT_DIR), &scratch) {
+        result.error = Some(format!("could not copy the {}/ snapshot: {}", SNAPSHOT_DIR, e));
+        return Ok(result);
+    }
+
+    let root = scratch.to_string_lossy().to_string();
+    let mut engine = new_engine(&root)?;
+    if model.is_empty() {
+        *model = engine.next_model();
+    }
+    let start = Instant::now();
+    let end = oneshot::run(
+        &mut engine,
+        &fixture.instruction,
+        fixture.max_iterations.unwrap_or(max_iterations),
+    )
+    .await;
+    result.wall_ms = start.elapsed().as_millis() as u64;
+    result.success = match (&end, &fixture.check) {
+        (Err(e), _) => {
+            result.error = Some(e.to_string());
+            false
+        }
+        (Ok(_), Some(check)) => {
+            let mut command = app::shell_command(check);
+            command.current_dir(&scratch);
+            interrupt::output(command).is_ok_and(|output| output.status.success())
+        }
+        (Ok(_), None) => engine.succeeded(),
+    };
+    result.iterations = engine.iteration();
+    result.tokens = engine.tpm_limiter().get_total_tokens();
+    let _ = fs::remove_dir_all(&scratch);
+    Ok(result)
+}
+
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use std::{io, path::Path};
+
+use crate::encoding;
+use crate::error::ToolError;
+use crate::vfs;
+
+// Bytes sniffed for NULs, like git's own binary check
+const SNIFF_LEN: usize = 8000;
+
+pub enum FileContent {
+    Text(String),
+    // Human-readable description: size and a type guess
+    Binary(String),
+}
+
+// Reads a file as text, or describes it when it isn't UTF-8 text
+pub fn read(path: &Path) -> io::Result<FileContent> {
+    Ok(decode(path, vfs::current().read(path)?))
+}
+
+// read() for bytes already read from path
+pub fn decode(path: &Path, bytes: Vec<u8>) -> FileContent {
+    // UTF-16 text is full of NULs, but starts with a BOM
+    if encoding::is_utf16(&bytes) {
+        if let Some((text, _)) = encoding::decode(&bytes) {
+            return FileContent::Text(text);
+        }
+    }
+    if is_binary(&bytes) {
+        return FileContent::Binary(describe(path, &bytes));
+    }
+    match String::from_utf8(bytes) {
+        Ok(text) => FileContent::Text(text),
+        Err(e) => FileContent::Binary(describe(path, e.as_bytes())),
+    }
+}
+
+// Fails edit tools when the target exists and isn't text
+pub fn refuse_edit(path: &Path) -> Result<(), ToolError> {
+    match read(path) {
+        Ok(FileContent::Binary(description)) => Err(ToolError::Binary(description)),
+        _ => Ok(()),
+    }
+}
+
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(SNIFF_LEN)].contains(&0)
+}
+
+fn describe(path: &Path, bytes: &[u8]) -> String {
+    let kind = if is_binary(bytes) {
+        type_guess(path, bytes)
+    } else {
+        "text that is not valid UTF-8"
+    };
+    format!(
+        "Binary file: {}\nsize: {} bytes\ntype: {}",
+        path.display(),
+        bytes.len(),
+        kind
+    )
+}
+
+fn type_guess(path: &Path, bytes: &[u8]) -> &'static str {
+    const MAGIC: [(&[u8], &str); 10] = [
+        (b"\x89PNG", "PNG image"),
+        (b"\xFF\xD8\xFF", "JPEG image"),
+        (b"GIF8", "GIF image"),
+        (b"%PDF", "PDF document"),
+        (b"PK\x03\x04", "ZIP archive"),
+        (b"\x1F\x8B", "gzip archive"),
+        (b"\x7FELF", "ELF executable"),
+        (b"MZ", "Windows executable"),
+        (b"\0asm", "WebAssembly module"),
+        (b"SQLite format 3", "SQLite database"),
+    ];
+
+    if let Some((_, kind)) = MAGIC.iter().find(|(magic, _)| bytes.starts_with(magic)) {
+        return kind;
+    }
+
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("rlib" | "a" | "o" | "so" | "dylib" | "dll") => "compiled library",
+        Some("ico" | "bmp" | "webp") => "image",
+        Some("ttf" | "otf" | "woff" | "woff2") => "font",
+        _ => "unknown binary data",
+    }
+}
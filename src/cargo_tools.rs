@@ -0,0 +1,177 @@
+use std::{fs, path::Path};
+
+use crate::app::{self, ToolOutput};
+use crate::dry_run;
+use crate::error::ToolError;
+
+// Lines of cargo_tree output before it is cut off
+const TREE_LINES: usize = 200;
+
+const DEPENDENCY_TABLES: [&str; 3] = ["dependencies", "dev-dependencies", "build-dependencies"];
+
+// Progress lines cargo prints that say nothing about the result
+const NOISE: [&str; 6] = [
+    "Updating ",
+    "Locking ",
+    "Blocking waiting",
+    "Downloading ",
+    "Downloaded ",
+    "Adding ",
+];
+
+// cargo_add: "serde --features derive", cargo_remove: "serde"
+pub fn cargo_edit(subcommand: &str, args: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    let args = args.trim();
+    if args.is_empty() {
+        return Err(ToolError::InvalidArgs(format!(
+            "cargo_{} needs a crate name",
+            subcommand
+        )));
+    }
+
+    let command = format!("cargo {} {}", subcommand, args);
+    if dry_run::enabled() {
+        return Ok(ToolOutput::text(format!("Would run: {}", command)));
+    }
+    Ok(clean(app::run_command(&command, root)?, subcommand == "add"))
+}
+
+// cargo_tree: direct dependencies by default, or the given cargo tree
+// arguments, e.g. "-i serde" to see what pulls serde in
+pub fn cargo_tree(args: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    let args = match args.trim() {
+        "" => "--depth 1",
+        args => args,
+    };
+    let output = clean(app::run_command(&format!("cargo tree {}", args), root)?, false);
+
+    let lines: Vec<&str> = output.text.lines().collect();
+    if lines.len() <= TREE_LINES {
+        return Ok(output);
+    }
+    let text = format!(
+        "{}\n[{} more lines; narrow it with --depth or -i <crate>]",
+        lines[..TREE_LINES].join("\n"),
+        lines.len() - TREE_LINES
+    );
+    Ok(output.with_text(text))
+}
+
+// cargo_deps: the dependencies declared in Cargo.toml, read from the
+// manifest instead of the whole file, or just the entries for one crate
+pub fn cargo_deps(name: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    let path = Path::new(root).join("Cargo.toml");
+    let content = fs::read_to_string(&path).map_err(|source| ToolError::Read {
+        path: "Cargo.toml".to_string(),
+        source,
+    })?;
+    let manifest: toml::Table = content
+        .parse()
+        .map_err(|e| ToolError::InvalidArgs(format!("Cargo.toml does not parse: {}", e)))?;
+
+    // (section, name, spec)
+    let mut entries = Vec::new();
+    let mut collect = |section: String, table: Option<&toml::Value>| {
+        let Some(table) = table.and_then(|t| t.as_table()) else {
+            return;
+        };
+        for (crate_name, spec) in table {
+            entries.push((section.clone(), crate_name.clone(), describe(spec)));
+        }
+    };
+    for key in DEPENDENCY_TABLES {
+        collect(key.to_string(), manifest.get(key));
+    }
+    if let Some(targets) = manifest.get("target").and_then(|t| t.as_table()) {
+        for (target, tables) in targets {
+            for key in DEPENDENCY_TABLES {
+                collect(format!("target.'{}'.{}", target, key), tables.get(key));
+            }
+        }
+    }
+    collect(
+        "workspace.dependencies".to_string(),
+        manifest.get("workspace").and_then(|w| w.get("dependencies")),
+    );
+
+    let name = name.trim();
+    if !name.is_empty() {
+        let found: Vec<String> = entries
+            .iter()
+            .filter(|(_, crate_name, _)| crate_name == name)
+            .map(|(section, crate_name, spec)| format!("[{}] {} = {}", section, crate_name, spec))
+            .collect();
+        return Ok(ToolOutput::text(if found.is_empty() {
+            format!("{} is not a dependency in Cargo.toml", name)
+        } else {
+            found.join("\n")
+        }));
+    }
+
+    if entries.is_empty() {
+        return Ok(ToolOutput::text("No dependencies in Cargo.toml"));
+    }
+    let mut out = String::new();
+    let mut current = "";
+    for (section, crate_name, spec) in &entries {
+        if section != current {
+            out.push_str(&format!("[{}]\n", section));
+            current = section;
+        }
+        out.push_str(&format!("{} = {}\n", crate_name, spec));
+    }
+    Ok(ToolOutput::text(out))
+}
+
+// "1.0", or "1.0 features=[derive] optional" for a detailed entry
+fn describe(spec: &toml::Value) -> String {
+    let Some(table) = spec.as_table() else {
+        return spec.as_str().unwrap_or("*").to_string();
+    };
+
+    let mut parts = Vec::new();
+    if let Some(version) = table.get("version").and_then(|v| v.as_str()) {
+        parts.push(version.to_string());
+    }
+    for source in ["path", "git", "branch", "tag", "rev", "package"] {
+        if let Some(value) = table.get(source).and_then(|v| v.as_str()) {
+            parts.push(format!("{}={}", source, value));
+        }
+    }
+    if table.get("workspace").and_then(|v| v.as_bool()) == Some(true) {
+        parts.push("workspace".to_string());
+    }
+    if let Some(features) = table.get("features").and_then(|v| v.as_array()) {
+        let features: Vec<&str> = features.iter().filter_map(|f| f.as_str()).collect();
+        parts.push(format!("features=[{}]", features.join(", ")));
+    }
+    if table.get("default-features").and_then(|v| v.as_bool()) == Some(false) {
+        parts.push("no-default-features".to_string());
+    }
+    if table.get("optional").and_then(|v| v.as_bool()) == Some(true) {
+        parts.push("optional".to_string());
+    }
+    if parts.is_empty() {
+        "*".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+// stdout and stderr without cargo's progress lines. cargo add
+// reports what it added on "Adding" lines, so those are kept for it.
+fn clean(output: ToolOutput, keep_adding: bool) -> ToolOutput {
+    let streams = output.streams();
+    let text: Vec<&str> = streams
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter(|line| {
+            let trimmed = line.trim_start();
+            !NOISE
+                .iter()
+                .any(|noise| trimmed.starts_with(noise) && !(keep_adding && *noise == "Adding "))
+        })
+        .collect();
+    let text = text.join("\n");
+    output.with_text(text)
+}
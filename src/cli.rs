@@ -0,0 +1,206 @@
+use std::env;
+
+use crate::isolation::Isolation;
+use crate::orchestrator;
+
+pub enum Mode {
+    Chat,
+    Fix { max_iterations: u32 },
+    // botcoder run <task> | ask <question>: one task, result on stdout; None
+    // (or "-") reads the task from stdin
+    Run { task: Option<String>, max_iterations: u32 },
+    // REST API over HTTP; max_iterations applies to each posted message
+    Serve { port: u16, max_iterations: u32 },
+    // botcoder bench <dir>: runs the task fixtures in dir and compares the
+    // results with the previous report, or the --baseline one
+    Bench { dir: String, max_iterations: u32, baseline: Option<String> },
+    // botcoder queue <file>: runs the tasks listed in the TOML or JSON file
+    // one after another on the project, committing between them
+    Queue { file: String, max_iterations: u32 },
+    // botcoder parallel <file>: runs the same tasks at once, each in its own
+    // git worktree, at most `sessions` at a time
+    Parallel { file: String, sessions: usize, max_iterations: u32 },
+    // botcoder replay <session>: plays a recorded session back in the TUI;
+    // --step starts it paused
+    Replay { session: String, speed: f32, step: bool },
+    // Checks the hash chain of .botcoder/audit.log
+    VerifyAudit,
+    // botcoder sessions list|show <name>|delete <name>
+    Sessions(SessionsCommand),
+}
+
+pub enum SessionsCommand {
+    List,
+    Show(String),
+    Delete(String),
+}
+
+pub struct Args {
+    pub mode: Mode,
+    // Plain ASCII output for screen readers and dumb terminals
+    pub ascii: bool,
+    pub no_color: bool,
+    // Fix mode prints agent events as JSON lines
+    pub json: bool,
+    // Fixture file to save model responses to, or to replay them from
+    // instead of calling the model
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    // Preview edits and commands without applying them
+    pub dry_run: bool,
+    // Take over .botcoder/lock even if another instance holds it
+    pub force_lock: bool,
+    // Let the agent work across the members of the project's Cargo
+    // workspace
+    pub workspace: bool,
+    // Draft a plan from the first message and have the user approve it
+    // before any tool runs
+    pub plan: bool,
+    // Run each plan step as a nested agent run with its own history and
+    // budget; implies --plan
+    pub subtasks: bool,
+    // Images sent with the first message, one --image flag each
+    pub images: Vec<String>,
+    // Temperature 0 and a fixed seed, for comparable benchmark runs
+    pub deterministic: bool,
+    // Where fix, run and ask write their result.json
+    pub result: Option<String>,
+    // Turn off every tool that writes to the project
+    pub read_only: bool,
+    // fix and run work in a temporary git worktree: --isolate merges a
+    // successful run back, --isolate-patch saves it as a patch
+    pub isolate: Option<Isolation>,
+    // Edits go to an in-memory overlay, written to this file as a patch at
+    // the end; the project is never modified. Commands, builds and tests are
+    // turned off (so is fix) and session state goes to the temp directory.
+    pub patch_output: Option<String>,
+}
+
+pub fn parse_args() -> Args {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    Args {
+        mode: parse_mode(&args),
+        ascii: args.iter().any(|a| a == "--ascii"),
+        no_color: args.iter().any(|a| a == "--no-color"),
+        json: args.iter().any(|a| a == "--json"),
+        record: flag_value(&args, "--record"),
+        replay: flag_value(&args, "--replay"),
+        dry_run: args.iter().any(|a| a == "--dry-run"),
+        force_lock: args.iter().any(|a| a == "--force-lock"),
+        workspace: args.iter().any(|a| a == "--workspace"),
+        plan: args.iter().any(|a| a == "--plan"),
+        subtasks: args.iter().any(|a| a == "--subtasks"),
+        images: flag_values(&args, "--image"),
+        deterministic: args.iter().any(|a| a == "--deterministic"),
+        result: flag_value(&args, "--result"),
+        read_only: args.iter().any(|a| a == "--read-only"),
+        isolate: if args.iter().any(|a| a == "--isolate-patch") {
+            Some(Isolation::Patch)
+        } else if args.iter().any(|a| a == "--isolate") {
+            Some(Isolation::Merge)
+        } else {
+            None
+        },
+        patch_output: flag_value(&args, "--patch-output"),
+    }
+}
+
+fn parse_mode(args: &[String]) -> Mode {
+    match args.first().map(|s| s.as_str()) {
+        Some("fix") => Mode::Fix {
+            max_iterations: max_iterations(args),
+        },
+        Some("run" | "ask") => {
+            let words = positional(&args[1..]);
+            Mode::Run {
+                task: Some(words.join(" ")).filter(|t| !t.is_empty() && t != "-"),
+                max_iterations: max_iterations(args),
+            }
+        }
+        Some("serve") => Mode::Serve {
+            port: flag_value(args, "--port")
+                .or_else(|| env::var("BOTCODER_PORT").ok())
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(8080),
+            max_iterations: max_iterations(args),
+        },
+        Some("bench") => Mode::Bench {
+            dir: positional(&args[1..]).first().cloned().unwrap_or_else(|| "bench".to_string()),
+            max_iterations: max_iterations(args),
+            baseline: flag_value(args, "--baseline"),
+        },
+        Some("queue") => Mode::Queue {
+            file: positional(&args[1..]).first().cloned().unwrap_or_else(|| "tasks.toml".to_string()),
+            max_iterations: max_iterations(args),
+        },
+        Some("parallel") => Mode::Parallel {
+            file: positional(&args[1..]).first().cloned().unwrap_or_else(|| "tasks.toml".to_string()),
+            sessions: flag_value(args, "--sessions")
+                .and_then(|v| v.parse().ok())
+                .filter(|n| *n > 0)
+                .unwrap_or_else(orchestrator::sessions),
+            max_iterations: max_iterations(args),
+        },
+        Some("replay") => Mode::Replay {
+            session: positional(&args[1..]).join(" "),
+            speed: flag_value(args, "--speed")
+                .and_then(|v| v.parse().ok())
+                .filter(|speed: &f32| *speed > 0.0)
+                .unwrap_or(1.0),
+            step: args.iter().any(|a| a == "--step"),
+        },
+        Some("audit") if args.get(1).map(|s| s.as_str()) == Some("verify") => Mode::VerifyAudit,
+        Some("sessions") => {
+            let name = args.get(2).cloned().unwrap_or_default();
+            Mode::Sessions(match args.get(1).map(|s| s.as_str()) {
+                Some("show") => SessionsCommand::Show(name),
+                Some("delete") => SessionsCommand::Delete(name),
+                _ => SessionsCommand::List,
+            })
+        }
+        _ => Mode::Chat,
+    }
+}
+
+fn max_iterations(args: &[String]) -> u32 {
+    flag_value(args, "--max-iterations")
+        .or_else(|| env::var("BOTCODER_MAX_ITERATIONS").ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(10)
+}
+
+// Flags followed by a value
+const VALUE_FLAGS: &[&str] = &[
+    "--record", "--replay", "--image", "--max-iterations", "--port", "--result", "--speed",
+    "--baseline", "--sessions", "--patch-output",
+];
+
+// The arguments that aren't flags or flag values
+fn positional(args: &[String]) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if VALUE_FLAGS.contains(&arg.as_str()) {
+            args.next();
+        } else if arg == "-" || !arg.starts_with("--") {
+            words.push(arg.clone());
+        }
+    }
+    words
+}
+
+fn flag_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
+
+// Every value of a flag that may be given more than once
+fn flag_values(args: &[String], flag: &str) -> Vec<String> {
+    args.windows(2)
+        .filter(|pair| pair[0] == flag)
+        .map(|pair| pair[1].clone())
+        .collect()
+}
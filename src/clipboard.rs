@@ -0,0 +1,111 @@
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use std::{
+    env,
+    io::{self, Write},
+    process::{Command, Stdio},
+};
+
+use crate::error::ToolError;
+
+// The system clipboard through the platform's own tools: pbcopy/pbpaste on
+// macOS, clip and PowerShell on Windows, and wl-copy, xclip or xsel,
+// whichever is installed, elsewhere. Copying falls back to the OSC 52
+// escape, which most terminals turn into a clipboard write, even over SSH.
+// Used in place of the arboard crate: text only, pasting needs one of the
+// tools, and a terminal ignoring OSC 52 goes unnoticed.
+fn copy_commands() -> Vec<Vec<&'static str>> {
+    if cfg!(target_os = "macos") {
+        vec![vec!["pbcopy"]]
+    } else if cfg!(target_os = "windows") {
+        vec![vec!["clip"]]
+    } else {
+        let mut commands = vec![
+            vec!["xclip", "-selection", "clipboard"],
+            vec!["xsel", "--clipboard", "--input"],
+        ];
+        if env::var_os("WAYLAND_DISPLAY").is_some() {
+            commands.insert(0, vec!["wl-copy"]);
+        }
+        commands
+    }
+}
+
+fn paste_commands() -> Vec<Vec<&'static str>> {
+    if cfg!(target_os = "macos") {
+        vec![vec!["pbpaste"]]
+    } else if cfg!(target_os = "windows") {
+        vec![vec!["powershell", "-NoProfile", "-Command", "Get-Clipboard -Raw"]]
+    } else {
+        let mut commands = vec![
+            vec!["xclip", "-selection", "clipboard", "-o"],
+            vec!["xsel", "--clipboard", "--output"],
+        ];
+        if env::var_os("WAYLAND_DISPLAY").is_some() {
+            commands.insert(0, vec!["wl-paste", "--no-newline"]);
+        }
+        commands
+    }
+}
+
+// Copies the text, returning the tool that took it
+pub fn copy(text: &str) -> Result<&'static str, ToolError> {
+    copy_with(&copy_commands(), text)
+}
+
+pub fn paste() -> Result<String, ToolError> {
+    paste_with(&paste_commands())
+}
+
+// As copy, trying the given commands (program and arguments) in order
+pub fn copy_with<'a>(commands: &[Vec<&'a str>], text: &str) -> Result<&'a str, ToolError> {
+    for command in commands {
+        if run_copy(command, text).is_ok() {
+            return Ok(command[0]);
+        }
+    }
+    osc52(text).map_err(ToolError::Spawn)?;
+    Ok("the terminal (OSC 52)")
+}
+
+pub fn paste_with(commands: &[Vec<&str>]) -> Result<String, ToolError> {
+    for command in commands {
+        let Ok(output) = Command::new(command[0]).args(&command[1..]).stdin(Stdio::null()).output() else {
+            continue;
+        };
+        if output.status.success() {
+            return Ok(String::from_utf8_lossy(&output.stdout).replace("\r\n", "\n"));
+        }
+    }
+    let tried: Vec<&str> = commands.iter().map(|c| c[0]).collect();
+    Err(ToolError::InvalidArgs(format!(
+        "could not read the clipboard; install one of: {}",
+        tried.join(", ")
+    )))
+}
+
+// xclip stays behind to serve the selection, holding on to any pipe it was
+// given, so only its stdin is piped and just its exit status awaited
+fn run_copy(command: &[&str], text: &str) -> io::Result<()> {
+    let mut child = Command::new(command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    let status = child.wait()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("{} exited with {}", command[0], status)))
+    }
+}
+
+fn osc52(text: &str) -> io::Result<()> {
+    let encoded = STANDARD.encode(text);
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", encoded)?;
+    stdout.flush()
+}
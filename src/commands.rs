@@ -0,0 +1,119 @@
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::Path, process::Command};
+
+use crate::config;
+use crate::error::ToolError;
+use crate::workspace;
+
+// Where an execute_command runs, relative to the project root, and the
+// environment variables it gets on top of botcoder's own. The model passes
+// them with the command:
+//
+//   execute_command("cargo test", cwd="crates/api", env="RUST_LOG=debug")
+//
+// and .botcoder.toml can set defaults, which the model's override:
+//
+//   [commands]
+//   cwd = "crates/api"
+//   env = { RUST_LOG = "debug" }
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct CommandOptions {
+    #[serde(default)]
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+impl CommandOptions {
+    // No defaults without a .botcoder.toml; an invalid one is skipped with a
+    // warning
+    pub fn load(root: &str) -> Self {
+        config::section(root, "commands")
+    }
+
+    // From the model's arguments: a directory and space-separated
+    // NAME=value pairs, either possibly empty
+    pub fn parse(cwd: &str, env: &str) -> Result<Self, String> {
+        let mut options = Self {
+            cwd: Some(cwd.trim().to_string()).filter(|cwd| !cwd.is_empty()),
+            env: BTreeMap::new(),
+        };
+        for pair in env.split_whitespace() {
+            let (name, value) = pair
+                .split_once('=')
+                .filter(|(name, _)| is_env_name(name))
+                .ok_or_else(|| format!("env entries are NAME=value, not {:?}", pair))?;
+            options.env.insert(name.to_string(), value.to_string());
+        }
+        Ok(options)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cwd.is_none() && self.env.is_empty()
+    }
+
+    // These options over `defaults`
+    pub fn over(self, defaults: &CommandOptions) -> Self {
+        let mut env = defaults.env.clone();
+        env.extend(self.env);
+        Self {
+            cwd: self.cwd.or_else(|| defaults.cwd.clone()),
+            env,
+        }
+    }
+
+    // The directory to run in, checked to be inside the project
+    pub fn dir(&self, root: &str) -> Result<Option<String>, ToolError> {
+        let Some(cwd) = &self.cwd else {
+            return Ok(None);
+        };
+        workspace::refuse_outside(root, cwd)?;
+        if !Path::new(root).join(cwd).is_dir() {
+            return Err(ToolError::InvalidArgs(format!("cwd {} is not a directory in the project", cwd)));
+        }
+        Ok(Some(cwd.trim_end_matches('/').to_string()))
+    }
+
+    pub fn apply(&self, cmd: &mut Command, root: &str) -> Result<(), ToolError> {
+        let dir = self.dir(root)?;
+        cmd.current_dir(Path::new(root).join(dir.unwrap_or_default()));
+        cmd.envs(&self.env);
+        Ok(())
+    }
+}
+
+// The execute_command parameter: the command, then the options after a
+// ":::" when there are any, e.g. "cargo test:::cwd=crates/api RUST_LOG=debug"
+pub fn encode(command: &str, options: &CommandOptions) -> String {
+    if options.is_empty() {
+        return command.to_string();
+    }
+    let mut fields: Vec<String> = options.cwd.iter().map(|cwd| format!("cwd={}", cwd)).collect();
+    fields.extend(options.env.iter().map(|(name, value)| format!("{}={}", name, value)));
+    format!("{}:::{}", command, fields.join(" "))
+}
+
+// The command and its options. A parameter whose tail isn't all
+// NAME=value fields is a plain command that happens to contain ":::".
+pub fn decode(param: &str) -> (&str, CommandOptions) {
+    let Some((command, fields)) = param.rsplit_once(":::") else {
+        return (param, CommandOptions::default());
+    };
+    let mut options = CommandOptions::default();
+    for field in fields.split_whitespace() {
+        match field.split_once('=') {
+            Some(("cwd", cwd)) => options.cwd = Some(cwd.to_string()),
+            Some((name, value)) if is_env_name(name) => {
+                options.env.insert(name.to_string(), value.to_string());
+            }
+            _ => return (param, CommandOptions::default()),
+        }
+    }
+    (command, options)
+}
+
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
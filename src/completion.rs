@@ -0,0 +1,106 @@
+use crate::file_tree::TreeEntry;
+use crate::tool_registry;
+
+// The chat commands, completed at the start of the message box
+pub const COMMANDS: &[&str] = &[
+    "/commit", "/copy", "/edit", "/fork", "/image", "/load", "/paste", "/pin", "/save",
+    "/search", "/theme", "/timeline", "/unpin",
+];
+
+// Commands whose argument is a project path
+const PATH_COMMANDS: &[&str] = &["/image", "/pin", "/unpin"];
+
+// Characters ending the word before the one being completed
+const WORD_BREAKS: &[char] = &[' ', '\n', '(', '"', '\'', ',', '`'];
+
+pub struct Completion {
+    // The input with its last word completed as far as the candidates agree
+    pub input: String,
+    // Every match when there are several
+    pub candidates: Vec<String>,
+}
+
+// Tab in the message box completes the last word: a command at the start,
+// a path after a path command or inside a tool call such as read_file(...),
+// and otherwise a tool name or a path. Paths come from the file tree, so
+// ignored files aren't offered, and are completed a directory at a time.
+pub fn complete(input: &str, files: &[TreeEntry]) -> Completion {
+    let start = input.rfind(WORD_BREAKS).map(|i| i + 1).unwrap_or(0);
+    let (before, word) = input.split_at(start);
+
+    let first_word = input.split_whitespace().next().unwrap_or_default();
+    let in_tool_call = before
+        .trim_end_matches(['"', '\''])
+        .strip_suffix('(')
+        .and_then(|call| call.rsplit(WORD_BREAKS).next())
+        .is_some_and(|tool| tool_registry::find(tool).is_some());
+
+    let mut matches: Vec<String> = Vec::new();
+    if start == 0 && word.starts_with('/') {
+        matches.extend(
+            COMMANDS
+                .iter()
+                .filter(|c| c.starts_with(word))
+                .map(|c| format!("{} ", c)),
+        );
+    } else if PATH_COMMANDS.contains(&first_word) || in_tool_call {
+        matches.extend(paths(word, files));
+    } else if !word.is_empty() {
+        if !word.contains('/') {
+            matches.extend(
+                tool_registry::names()
+                    .into_iter()
+                    .filter(|name| name.starts_with(word)),
+            );
+        }
+        matches.extend(paths(word, files));
+    }
+
+    let completed = match matches.as_slice() {
+        [only] => only.clone(),
+        _ => common_prefix(matches.iter().map(String::as_str)).unwrap_or(word.to_string()),
+    };
+    Completion {
+        input: format!("{}{}", before, completed),
+        candidates: if matches.len() > 1 {
+            matches.into_iter().map(|m| m.trim_end().to_string()).collect()
+        } else {
+            Vec::new()
+        },
+    }
+}
+
+// Entries in the directory the word points into whose names start with
+// its last part; directories end with '/'
+fn paths(word: &str, files: &[TreeEntry]) -> Vec<String> {
+    let word = word.trim_start_matches("./");
+    let dir = word.rfind('/').map(|i| &word[..i]).unwrap_or("");
+    files
+        .iter()
+        .filter(|entry| entry.path.starts_with(word))
+        .filter(|entry| entry.path.rfind('/').map(|i| &entry.path[..i]).unwrap_or("") == dir)
+        .map(|entry| {
+            if entry.is_dir {
+                format!("{}/", entry.path)
+            } else {
+                entry.path.clone()
+            }
+        })
+        .collect()
+}
+
+fn common_prefix<'a>(mut words: impl Iterator<Item = &'a str>) -> Option<String> {
+    let first = words.next()?;
+    let mut prefix = first.len();
+    for word in words {
+        prefix = first
+            .char_indices()
+            .zip(word.chars())
+            .take_while(|((_, a), b)| a == b)
+            .last()
+            .map(|((i, c), _)| i + c.len_utf8())
+            .unwrap_or(0)
+            .min(prefix);
+    }
+    Some(first[..prefix].to_string())
+}
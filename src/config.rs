@@ -0,0 +1,65 @@
+use serde::de::DeserializeOwned;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+// Project settings: [commands], [hooks.*], [[filters.*]], [limits.*] and
+// [write], each read by its own module
+pub const CONFIG_FILE: &str = ".botcoder.toml";
+
+// Each project's file, parsed once and parsed again only when its size or
+// modification time changes
+static PARSED: Mutex<Option<HashMap<PathBuf, Parsed>>> = Mutex::new(None);
+
+struct Parsed {
+    len: u64,
+    modified: Option<SystemTime>,
+    table: Arc<toml::Table>,
+}
+
+// One section of root's .botcoder.toml, e.g. "hooks". The default without
+// the file or the section; an invalid one is skipped with a warning.
+pub fn section<T: DeserializeOwned + Default>(root: &str, name: &str) -> T {
+    let Some(value) = load(root).and_then(|table| table.get(name).cloned()) else {
+        return T::default();
+    };
+    value.try_into().unwrap_or_else(|e| {
+        log::warn!("Ignoring [{}] in {}: {}", name, CONFIG_FILE, e);
+        T::default()
+    })
+}
+
+fn load(root: &str) -> Option<Arc<toml::Table>> {
+    let path = Path::new(root).join(CONFIG_FILE);
+    let metadata = fs::metadata(&path).ok()?;
+    let (len, modified) = (metadata.len(), metadata.modified().ok());
+    let key = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+
+    let mut parsed = PARSED.lock().unwrap_or_else(|e| e.into_inner());
+    let parsed = parsed.get_or_insert_with(HashMap::new);
+    if let Some(entry) = parsed.get(&key).filter(|e| e.len == len && e.modified == modified) {
+        return Some(entry.table.clone());
+    }
+    let table = match fs::read_to_string(&path).map(|text| text.parse::<toml::Table>()) {
+        Ok(Ok(table)) => table,
+        Ok(Err(e)) => {
+            log::warn!("Ignoring {}: {}", CONFIG_FILE, e);
+            toml::Table::new()
+        }
+        Err(_) => return None,
+    };
+    let table = Arc::new(table);
+    parsed.insert(
+        key,
+        Parsed {
+            len,
+            modified,
+            table: table.clone(),
+        },
+    );
+    Some(table)
+}
@@ -0,0 +1,196 @@
+use std::env;
+
+use crate::app;
+use crate::llm::{self, ChatMessage, MAX_REASONING_REPLY_TOKENS, MAX_REPLY_TOKENS};
+
+const DEFAULT_CONTEXT: u32 = 128_000;
+// Room kept for the summary of turns that no longer fit
+const SUMMARY_TOKENS: u32 = 1_000;
+const SUMMARY_LINE: usize = 120;
+
+// Context windows by model name prefix, checked in order so "gpt-4o" wins
+// over "gpt-4". Azure deployments named otherwise get the default, or
+// BOTCODER_CONTEXT_TOKENS.
+const CONTEXT_WINDOWS: [(&str, u32); 17] = [
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4", 8_192),
+    ("gpt-5", 400_000),
+    ("gpt-35-turbo", 16_385),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4", 200_000),
+    ("claude", 200_000),
+    ("gemini", 1_048_576),
+    ("llama", 128_000),
+    ("mistral", 32_000),
+    ("qwen", 32_768),
+    ("deepseek", 64_000),
+];
+
+// How much of a model's context window a request may use: everything but
+// the room left for the reply
+#[derive(Clone, Copy, Debug)]
+pub struct ContextBudget {
+    pub max_tokens: u32,
+    pub reply_tokens: u32,
+}
+
+impl ContextBudget {
+    pub fn for_model(model: &str) -> Self {
+        let max_tokens = env::var("BOTCODER_CONTEXT_TOKENS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or_else(|| context_window(model));
+        let reply = if llm::is_reasoning_model(model) {
+            MAX_REASONING_REPLY_TOKENS
+        } else {
+            MAX_REPLY_TOKENS
+        };
+        Self {
+            max_tokens,
+            reply_tokens: reply.min(max_tokens / 4),
+        }
+    }
+
+    pub fn available(&self) -> u32 {
+        self.max_tokens.saturating_sub(self.reply_tokens)
+    }
+
+    // Fits the system message and conversation from build_messages, with
+    // the pinned files (path, content) added to the system message. Filled
+    // greedily: the system prompt, the pinned files from the most recently
+    // pinned, then the turns from the newest. Files that don't fit are named
+    // without their content; older turns are summarized a line each.
+    pub fn fit(
+        &self,
+        mut messages: Vec<ChatMessage>,
+        pinned: &[(String, String)],
+    ) -> Vec<ChatMessage> {
+        let history = messages.split_off(1.min(messages.len()));
+        let Some(mut system) = messages.pop() else {
+            return history;
+        };
+        let mut left = self.available().saturating_sub(app::count_tokens(&system.content));
+
+        if !pinned.is_empty() {
+            let header = "PINNED FILES\nThe user pinned these files; their current content:";
+            left = left.saturating_sub(app::count_tokens(header));
+            let mut sections = Vec::new();
+            for (path, content) in pinned.iter().rev() {
+                let section = format!("== {} ==\n{}", path, content);
+                let tokens = app::count_tokens(&section);
+                if tokens <= left {
+                    left -= tokens;
+                    sections.push(section);
+                } else {
+                    sections.push(format!(
+                        "== {} == (about {} tokens, left out to fit the context window)",
+                        path, tokens
+                    ));
+                }
+            }
+            sections.reverse();
+            system.content = format!(
+                "{}\n\n{}\n\n{}",
+                system.content,
+                header,
+                sections.join("\n\n")
+            );
+        }
+
+        let total: u32 = history.iter().map(|m| app::count_tokens(&m.content)).sum();
+        let mut out = vec![system];
+        if total <= left {
+            out.extend(history);
+            return out;
+        }
+
+        // Newest turns first, then whatever summary of the rest still fits
+        let mut turns_left = left.saturating_sub(SUMMARY_TOKENS);
+        let mut kept = Vec::new();
+        let mut older = history.len();
+        for (i, message) in history.iter().enumerate().rev() {
+            let tokens = app::count_tokens(&message.content);
+            if tokens > turns_left {
+                // The newest turn is cut rather than dropped
+                if kept.is_empty() {
+                    kept.push(cut(message, turns_left));
+                    older = i;
+                }
+                break;
+            }
+            turns_left -= tokens;
+            kept.push(message.clone());
+            older = i;
+        }
+        kept.reverse();
+
+        if older > 0 {
+            out.push(summary(&history[..older], SUMMARY_TOKENS + turns_left));
+        }
+        out.extend(kept);
+        out
+    }
+}
+
+fn context_window(model: &str) -> u32 {
+    let name = model.rsplit('/').next().unwrap_or(model).to_lowercase();
+    CONTEXT_WINDOWS
+        .iter()
+        .find(|(prefix, _)| name.starts_with(prefix))
+        .map(|(_, tokens)| *tokens)
+        .unwrap_or(DEFAULT_CONTEXT)
+}
+
+// One line per dropped turn, keeping the most recent lines that fit
+fn summary(dropped: &[ChatMessage], budget: u32) -> ChatMessage {
+    let mut lines = Vec::new();
+    let mut used = 0;
+    for message in dropped.iter().rev() {
+        let line = summary_line(message);
+        let tokens = app::count_tokens(&line);
+        if used + tokens > budget {
+            lines.push(format!("- ({} earlier turns left out)", dropped.len() - lines.len()));
+            break;
+        }
+        used += tokens;
+        lines.push(line);
+    }
+    lines.reverse();
+    ChatMessage::user(format!(
+        "Earlier in this session, summarized to fit the context window:\n{}",
+        lines.join("\n")
+    ))
+}
+
+fn summary_line(message: &ChatMessage) -> String {
+    let (who, text) = if message.is_tool_results() {
+        let mut tools: Vec<&str> = message
+            .content
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_once(": ").map(|(tool, _)| tool))
+            .filter(|tool| tool.chars().all(|c| c.is_ascii_lowercase() || c == '_'))
+            .collect();
+        tools.dedup();
+        ("tool results", tools.join(", "))
+    } else {
+        let first = message.content.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+        (message.role.as_str(), first.trim().to_string())
+    };
+    let text: String = text.chars().take(SUMMARY_LINE).collect();
+    format!("- {}: {}", who, text)
+}
+
+fn cut(message: &ChatMessage, budget: u32) -> ChatMessage {
+    let text: String = message.content.chars().take(budget as usize * 4).collect();
+    ChatMessage {
+        role: message.role.clone(),
+        content: format!("{}\n[cut to fit the context window]", text),
+        images: message.images.clone(),
+    }
+}
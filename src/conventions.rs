@@ -0,0 +1,38 @@
+use std::{env, fs, path::Path};
+
+use crate::context::ContextBudget;
+use crate::fetch;
+
+// Team rules kept with the project, e.g. "use anyhow, not Box<dyn Error>"
+// or "never touch generated/", added to every system prompt
+const FILES: [&str; 2] = ["CONVENTIONS.md", ".botcoder/rules.md"];
+const DEFAULT_MAX_TOKENS: usize = 4_000;
+// Never more than this share of the model's context window
+const WINDOW_SHARE: usize = 8;
+
+// The conventions files found at the project root, cut to fit
+// BOTCODER_CONVENTIONS_TOKENS (default 4000) and an eighth of the context
+// window; empty without any
+pub fn prompt_section(root: &str, budget: &ContextBudget) -> String {
+    let sections: Vec<String> = FILES
+        .iter()
+        .filter_map(|file| {
+            let content = fs::read_to_string(Path::new(root).join(file)).ok()?;
+            let content = content.trim();
+            (!content.is_empty()).then(|| format!("== {} ==\n{}", file, content))
+        })
+        .collect();
+    if sections.is_empty() {
+        return String::new();
+    }
+
+    let max_tokens = env::var("BOTCODER_CONVENTIONS_TOKENS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_TOKENS)
+        .min(budget.available() as usize / WINDOW_SHARE);
+    format!(
+        "PROJECT CONVENTIONS\nThe team's rules for this project; they take precedence over your own habits.\n\n{}",
+        fetch::truncate_tokens(&sections.join("\n\n"), max_tokens)
+    )
+}
@@ -0,0 +1,273 @@
+use reqwest::blocking::Client;
+use serde_json::Value;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::app::ToolOutput;
+use crate::error::ToolError;
+use crate::overlay;
+
+const CRATES_API: &str = "https://crates.io/api/v1/crates";
+const DOCS_RS: &str = "https://docs.rs";
+
+// Looked-up crates, one file per crate and version
+const CACHE_DIR: &str = ".botcoder/cache/crates";
+// Entries older than this are fetched again
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+// Oldest entries are removed once the cache grows past this
+const CACHE_BYTES: u64 = 2 * 1024 * 1024;
+
+// Cap on one lookup's result, and on the items listed per kind
+const MAX_RESULT: usize = 6_000;
+const ITEMS_PER_KIND: usize = 40;
+
+// lookup_crate: "serde" for the latest stable release, "serde@1.0.200"
+// for a specific one. Shows the version, description, features and the
+// public items docs.rs lists, so the model works from the real API.
+pub fn lookup(param: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    let (name, version) = match param.trim().split_once('@') {
+        Some((name, version)) => (name.trim(), Some(version.trim())),
+        None => (param.trim(), None),
+    };
+    if name.is_empty() {
+        return Err(ToolError::InvalidArgs("lookup_crate needs a crate name".to_string()));
+    }
+    // Both end up in the cache path and the URLs
+    if !is_crate_name(name) {
+        return Err(ToolError::InvalidArgs(format!("{:?} is not a crate name", name)));
+    }
+    if let Some(version) = version.filter(|v| !is_version(v)) {
+        return Err(ToolError::InvalidArgs(format!(
+            "{:?} is not a version; use MAJOR.MINOR.PATCH, e.g. serde@1.0.200",
+            version
+        )));
+    }
+
+    let key = format!("{}@{}", name.to_lowercase(), version.unwrap_or("latest"));
+    let cache = overlay::state_path(root, CACHE_DIR);
+    let entry = cache.join(format!("{}.md", key));
+    if let Some(cached) = read_fresh(&entry) {
+        return Ok(ToolOutput::text(cached));
+    }
+
+    // The blocking client runs its own runtime, which can't live on one of
+    // tokio's threads
+    let (name, version) = (name.to_string(), version.map(str::to_string));
+    let text = thread::spawn(move || fetch(&name, version.as_deref()))
+        .join()
+        .map_err(|_| ToolError::InvalidArgs("crate lookup panicked".to_string()))??;
+    let text = truncate(&text, MAX_RESULT);
+
+    if fs::create_dir_all(&cache).is_ok() && fs::write(&entry, &text).is_ok() {
+        evict(&cache, CACHE_BYTES);
+    }
+    Ok(ToolOutput::text(text))
+}
+
+// crates.io's rules: up to 64 ASCII letters, digits, - and _, starting with
+// a letter
+fn is_crate_name(name: &str) -> bool {
+    name.len() <= 64
+        && name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// A semver version: 1.0.200, 1.0.0-beta.2, 0.3.1+build.5
+fn is_version(version: &str) -> bool {
+    let (version, build) = version.split_once('+').map_or((version, None), |(v, b)| (v, Some(b)));
+    let (core, pre) = version.split_once('-').map_or((version, None), |(v, p)| (v, Some(p)));
+    let identifiers = |text: &str| {
+        text.split('.')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+    };
+    let numbers: Vec<&str> = core.split('.').collect();
+    numbers.len() == 3
+        && numbers.iter().all(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+        && pre.is_none_or(identifiers)
+        && build.is_none_or(identifiers)
+}
+
+fn fetch(name: &str, version: Option<&str>) -> Result<String, ToolError> {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(15))
+        // crates.io refuses requests without a user agent
+        .user_agent(concat!("botcoder/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+
+    let response = client.get(format!("{}/{}", CRATES_API, name)).send()?;
+    if response.status().as_u16() == 404 {
+        return Err(ToolError::UnknownCrate(name.to_string()));
+    }
+    let info: Value = response.error_for_status()?.json()?;
+    let krate = &info["crate"];
+    let version = match version {
+        Some(version) => version.to_string(),
+        None => krate["max_stable_version"]
+            .as_str()
+            .or_else(|| krate["newest_version"].as_str())
+            .unwrap_or_default()
+            .to_string(),
+    };
+    let release = info["versions"]
+        .as_array()
+        .and_then(|versions| versions.iter().find(|v| v["num"] == version.as_str()))
+        .ok_or_else(|| {
+            ToolError::InvalidArgs(format!("{} has no version {} on crates.io", name, version))
+        })?;
+
+    let mut out = format!("{} {}", name, version);
+    if release["yanked"] == true {
+        out.push_str(" (yanked)");
+    }
+    if let Some(newest) = krate["max_stable_version"].as_str() {
+        if newest != version {
+            out.push_str(&format!(", latest stable is {}", newest));
+        }
+    }
+    out.push('\n');
+    if let Some(description) = krate["description"].as_str() {
+        out.push_str(&format!("{}\n", description.trim()));
+    }
+    if let Some(msrv) = release["rust_version"].as_str() {
+        out.push_str(&format!("rust-version: {}\n", msrv));
+    }
+    if let Some(repository) = krate["repository"].as_str() {
+        out.push_str(&format!("repository: {}\n", repository));
+    }
+    out.push_str(&format!("docs: {}/{}/{}\n", DOCS_RS, name, version));
+
+    out.push_str("\nFeatures:\n");
+    match release["features"].as_object().filter(|f| !f.is_empty()) {
+        Some(features) => {
+            for (feature, enables) in features {
+                let enables: Vec<&str> = enables
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|e| e.as_str())
+                    .collect();
+                out.push_str(&format!("  {} = [{}]\n", feature, enables.join(", ")));
+            }
+        }
+        None => out.push_str("  (none)\n"),
+    }
+
+    // The crate's library is usually its name with dashes as underscores
+    let url = format!(
+        "{}/{}/{}/{}/all.html",
+        DOCS_RS,
+        name,
+        version,
+        name.replace('-', "_")
+    );
+    out.push_str("\nItems (docs.rs):\n");
+    let page = client
+        .get(&url)
+        .send()
+        .and_then(|r| r.error_for_status())
+        .and_then(|r| r.text());
+    match page.map(|html| all_items(&html)) {
+        Ok(items) if !items.is_empty() => {
+            for (kind, names) in items {
+                let shown: Vec<&str> = names.iter().take(ITEMS_PER_KIND).map(String::as_str).collect();
+                let more = match names.len().saturating_sub(ITEMS_PER_KIND) {
+                    0 => String::new(),
+                    more => format!(" (+{} more)", more),
+                };
+                out.push_str(&format!("  {}: {}{}\n", kind, shown.join(", "), more));
+            }
+        }
+        _ => out.push_str("  (not available; docs.rs may not have built this version)\n"),
+    }
+    Ok(out)
+}
+
+// The sections of rustdoc's "all items" page: each kind ("Structs",
+// "Traits", ...) with its item paths
+fn all_items(html: &str) -> Vec<(String, Vec<String>)> {
+    let mut sections = Vec::new();
+    for section in html.split("<h3 id=\"").skip(1) {
+        let Some(kind) = section
+            .split_once('>')
+            .and_then(|(_, rest)| rest.split_once("</h3>"))
+            .map(|(kind, _)| strip_tags(kind))
+        else {
+            continue;
+        };
+        let list = section
+            .split_once("<ul")
+            .map(|(_, rest)| rest.split("</ul>").next().unwrap_or(rest))
+            .unwrap_or("");
+        let names: Vec<String> = list
+            .split("<a ")
+            .skip(1)
+            .filter_map(|link| link.split_once('>'))
+            .filter_map(|(_, rest)| rest.split_once("</a>"))
+            .map(|(name, _)| strip_tags(name))
+            .filter(|name| !name.is_empty())
+            .collect();
+        if !names.is_empty() {
+            sections.push((kind, names));
+        }
+    }
+    sections
+}
+
+fn strip_tags(html: &str) -> String {
+    let mut out = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+    out.replace("&amp;", "&")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .trim()
+        .to_string()
+}
+
+fn read_fresh(path: &Path) -> Option<String> {
+    let age = fs::metadata(path).ok()?.modified().ok()?.elapsed().ok()?;
+    (age < CACHE_TTL).then(|| fs::read_to_string(path).ok()).flatten()
+}
+
+// Removes the least recently written entries until the cache fits
+fn evict(dir: &Path, limit: u64) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let meta = entry.metadata().ok()?;
+            Some((meta.modified().ok()?, meta.len(), entry.path()))
+        })
+        .collect();
+    files.sort();
+
+    let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+    for (_, len, path) in files {
+        if total <= limit {
+            break;
+        }
+        if fs::remove_file(&path).is_ok() {
+            total -= len;
+        }
+    }
+}
+
+fn truncate(text: &str, limit: usize) -> String {
+    match text.char_indices().nth(limit) {
+        Some((end, _)) => format!("{}\n[truncated]", &text[..end]),
+        None => text.to_string(),
+    }
+}
@@ -0,0 +1,39 @@
+use std::{
+    env,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+const DEFAULT_SEED: u64 = 42;
+
+// --deterministic: temperature 0 and a fixed seed on every request, so
+// benchmark runs can be compared. Providers that take no seed (Bedrock)
+// only get the temperature; the model's fingerprint goes in the session
+// transcript to tell apart runs against different backends.
+static DETERMINISTIC: AtomicBool = AtomicBool::new(false);
+
+pub fn set_enabled(enabled: bool) {
+    DETERMINISTIC.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    DETERMINISTIC.load(Ordering::Relaxed)
+}
+
+// The sampling temperature, 0 in deterministic runs
+pub fn temperature(default: f32) -> f32 {
+    if enabled() {
+        0.0
+    } else {
+        default
+    }
+}
+
+// BOTCODER_SEED, 42 by default; None outside deterministic runs
+pub fn seed() -> Option<u64> {
+    enabled().then(|| {
+        env::var("BOTCODER_SEED")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_SEED)
+    })
+}
@@ -0,0 +1,132 @@
+// Line-based unified diff, for previewing edits without writing them
+
+const CONTEXT: usize = 3;
+// Beyond this many cells the changed region is shown as one replacement
+// instead of running the quadratic LCS
+const MAX_TABLE: usize = 4_000_000;
+
+#[derive(Clone, Copy)]
+enum Op {
+    Same,
+    Removed,
+    Added,
+}
+
+// Empty when the two versions are equal
+pub fn unified(path: &str, before: &str, after: &str) -> String {
+    let old: Vec<&str> = before.lines().collect();
+    let new: Vec<&str> = after.lines().collect();
+
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    // (op, old line, new line), 0-based
+    let mut ops: Vec<(Op, usize, usize)> = (0..prefix).map(|i| (Op::Same, i, i)).collect();
+    for op in changed(&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]) {
+        let (o, n) = ops.last().map_or((0, 0), |&(op, o, n)| match op {
+            Op::Same => (o + 1, n + 1),
+            Op::Removed => (o + 1, n),
+            Op::Added => (o, n + 1),
+        });
+        ops.push((op, o, n));
+    }
+    for i in 0..suffix {
+        ops.push((Op::Same, old.len() - suffix + i, new.len() - suffix + i));
+    }
+
+    let changes: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, (op, _, _))| !matches!(op, Op::Same))
+        .map(|(i, _)| i)
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    let path = path.trim_start_matches("./").trim_start_matches('/');
+    let mut out = format!("--- a/{}\n+++ b/{}\n", path, path);
+    let mut i = 0;
+    while i < changes.len() {
+        let first = changes[i];
+        let mut last = first;
+        i += 1;
+        while i < changes.len() && changes[i] - last <= 2 * CONTEXT {
+            last = changes[i];
+            i += 1;
+        }
+
+        let hunk = &ops[first.saturating_sub(CONTEXT)..(last + CONTEXT + 1).min(ops.len())];
+        let (old_start, new_start) = (hunk[0].1, hunk[0].2);
+        let old_len = hunk.iter().filter(|(op, _, _)| !matches!(op, Op::Added)).count();
+        let new_len = hunk.iter().filter(|(op, _, _)| !matches!(op, Op::Removed)).count();
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(old_start, old_len),
+            range(new_start, new_len)
+        ));
+        for &(op, o, n) in hunk {
+            match op {
+                Op::Same => out.push_str(&format!(" {}\n", old[o])),
+                Op::Removed => out.push_str(&format!("-{}\n", old[o])),
+                Op::Added => out.push_str(&format!("+{}\n", new[n])),
+            }
+        }
+    }
+    out
+}
+
+// Edit script for the region between the common prefix and suffix, from a
+// longest common subsequence table
+fn changed(old: &[&str], new: &[&str]) -> Vec<Op> {
+    if old.len() * new.len() > MAX_TABLE {
+        return old
+            .iter()
+            .map(|_| Op::Removed)
+            .chain(new.iter().map(|_| Op::Added))
+            .collect();
+    }
+
+    let width = new.len() + 1;
+    let mut lcs = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i * width + j] = if old[i] == new[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut ops = Vec::new();
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            ops.push(Op::Same);
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+            ops.push(Op::Removed);
+            i += 1;
+        } else {
+            ops.push(Op::Added);
+            j += 1;
+        }
+    }
+    ops
+}
+
+// "start,len" with 1-based start; an empty range names the line before it
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
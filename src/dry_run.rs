@@ -0,0 +1,13 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// --dry-run: edit tools report the diff they would apply and commands are
+// echoed instead of run. Reads work as usual.
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_enabled(enabled: bool) {
+    DRY_RUN.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
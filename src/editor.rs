@@ -0,0 +1,48 @@
+use std::{env, fs, process::Stdio};
+
+use crate::app;
+use crate::error::ToolError;
+
+// $VISUAL or $EDITOR, which may carry arguments ("code --wait"); vi, or
+// notepad on Windows, without either
+pub fn command() -> String {
+    env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| if cfg!(target_os = "windows") { "notepad" } else { "vi" }.to_string())
+}
+
+// Opens the text in the editor and returns it as saved. The editor takes
+// over the terminal, so the TUI has to leave it first. `name` ends the
+// temporary file's name, giving the editor the extension to highlight.
+pub fn edit(text: &str, name: &str) -> Result<String, ToolError> {
+    edit_with(&command(), text, name)
+}
+
+// As edit, with the given editor command instead of command()
+pub fn edit_with(editor: &str, text: &str, name: &str) -> Result<String, ToolError> {
+    let path = env::temp_dir().join(format!("botcoder-{}-{}", std::process::id(), name));
+    let path_text = path.display().to_string();
+    fs::write(&path, text).map_err(|source| ToolError::Write {
+        path: path_text.clone(),
+        source,
+    })?;
+
+    let status = app::shell_command(&format!("{} \"{}\"", editor, path_text))
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status();
+    let edited = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+
+    let status = status.map_err(ToolError::Spawn)?;
+    if !status.success() {
+        return Err(ToolError::InvalidArgs(format!("{} exited with {}", editor, status)));
+    }
+    edited.map_err(|source| ToolError::Read {
+        path: path_text,
+        source,
+    })
+}
@@ -0,0 +1,109 @@
+use std::{io, path::Path};
+
+use crate::vfs;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const UTF16LE_BOM: &[u8] = b"\xFF\xFE";
+const UTF16BE_BOM: &[u8] = b"\xFE\xFF";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+}
+
+// How a text file is stored. The edit tools work on its content as UTF-8
+// with \n line breaks and write it back in the same form: encoding and BOM,
+// line endings, and whether it ends with a newline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextFormat {
+    pub encoding: Encoding,
+    // Most line breaks are \r\n; a file mixing both is written back with
+    // the more common one
+    pub crlf: bool,
+    pub final_newline: bool,
+}
+
+// New files: UTF-8, \n, ending with a newline
+impl Default for TextFormat {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::Utf8,
+            crlf: false,
+            final_newline: true,
+        }
+    }
+}
+
+// The content with \n line breaks and how it was stored, or None for
+// anything but UTF-8 or UTF-16 with a BOM
+pub fn decode(bytes: &[u8]) -> Option<(String, TextFormat)> {
+    let (encoding, text) = if let Some(rest) = bytes.strip_prefix(UTF8_BOM) {
+        (Encoding::Utf8Bom, String::from_utf8(rest.to_vec()).ok()?)
+    } else if let Some(rest) = bytes.strip_prefix(UTF16LE_BOM) {
+        (Encoding::Utf16Le, utf16(rest, u16::from_le_bytes)?)
+    } else if let Some(rest) = bytes.strip_prefix(UTF16BE_BOM) {
+        (Encoding::Utf16Be, utf16(rest, u16::from_be_bytes)?)
+    } else {
+        (Encoding::Utf8, String::from_utf8(bytes.to_vec()).ok()?)
+    };
+
+    let crlf = text.matches("\r\n").count();
+    let lf = text.matches('\n').count() - crlf;
+    let format = TextFormat {
+        encoding,
+        crlf: crlf > lf,
+        final_newline: text.is_empty() || text.ends_with('\n'),
+    };
+    Some((text.replace("\r\n", "\n"), format))
+}
+
+fn utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> Option<String> {
+    if !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| unit([pair[0], pair[1]])).collect();
+    String::from_utf16(&units).ok()
+}
+
+pub fn encode(text: &str, format: TextFormat) -> Vec<u8> {
+    let mut text = text.replace("\r\n", "\n");
+    match (format.final_newline, text.ends_with('\n')) {
+        (true, false) if !text.is_empty() => text.push('\n'),
+        (false, true) => {
+            text.pop();
+        }
+        _ => {}
+    }
+    if format.crlf {
+        text = text.replace('\n', "\r\n");
+    }
+
+    match format.encoding {
+        Encoding::Utf8 => text.into_bytes(),
+        Encoding::Utf8Bom => [UTF8_BOM, text.as_bytes()].concat(),
+        Encoding::Utf16Le => UTF16LE_BOM
+            .iter()
+            .copied()
+            .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect(),
+        Encoding::Utf16Be => UTF16BE_BOM
+            .iter()
+            .copied()
+            .chain(text.encode_utf16().flat_map(u16::to_be_bytes))
+            .collect(),
+    }
+}
+
+pub fn is_utf16(bytes: &[u8]) -> bool {
+    bytes.starts_with(UTF16LE_BOM) || bytes.starts_with(UTF16BE_BOM)
+}
+
+// A text file to edit, as decode() gives it
+pub fn read(path: &Path) -> io::Result<(String, TextFormat)> {
+    let bytes = vfs::current().read(path)?;
+    decode(&bytes).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not UTF-8 or UTF-16 text"))
+}
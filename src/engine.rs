@@ -0,0 +1,1252 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::Path,
+};
+
+use crate::app::{self, ToolOutput};
+use crate::binary::FileContent;
+use crate::context::ContextBudget;
+use crate::conventions;
+use crate::dry_run;
+use crate::error::{AgentError, ToolError};
+use crate::error_context;
+use crate::events::{AgentEvent, AgentObserver, EventBus};
+use crate::failures::FailureTracker;
+use crate::file_cache;
+use crate::filters::Filters;
+use crate::file_tree;
+use crate::git;
+use crate::ignore_rules::IgnoreRules;
+use crate::image::Image;
+use crate::interrupt;
+use crate::limits::LimitProfiles;
+use crate::llm::ChatMessage;
+use crate::vfs;
+use crate::parser::ResponseParser;
+use crate::plan::{self, Plan, SubtaskBudget};
+use crate::plugins;
+use crate::project::ProjectKind;
+use crate::quality;
+use crate::router::{self, ModelRouter, Role};
+use crate::semantic;
+use crate::tpm_limiter::TPMLimiter;
+use crate::workspace::{self, Workspace};
+use crate::write_scope::WriteLimits;
+
+// Conversation turns kept between iterations
+const HISTORY_LEN: usize = 10;
+// Reads of one reply run at most this many at a time
+const PARALLEL_READS: usize = 4;
+// Takes an ask_user question, returning the answer if there is one
+type Asker = dyn FnMut(&str) -> Option<String> + Send;
+// The result of an answered ask_user; the answer itself follows it
+const ANSWERED: &str = "✓ Asked the user; their answer is the next message";
+
+// The model's reply to one request
+pub struct Response {
+    pub role: Role,
+    pub model: String,
+    pub text: String,
+    // One note per fallback model taken
+    pub fallbacks: Vec<String>,
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    // Set when the reply was rejected by the parser
+    pub parse_error: Option<String>,
+}
+
+// The agent loop without any frontend: builds the context, calls the model,
+// parses tool calls, executes them and feeds the results back. Frontends
+// either drive it one tool at a time with request()/execute_next(), or use
+// step()/run_to_completion().
+pub struct AgentEngine {
+    router: ModelRouter,
+    prompt: String,
+    project_root: String,
+    tpm_limiter: TPMLimiter,
+    parser: ResponseParser,
+    // Tool results sent back to the model are cut to this many characters
+    result_limit: Option<usize>,
+    history: Vec<ChatMessage>,
+    pending: VecDeque<(String, String)>,
+    executed: Vec<(String, String, String)>,
+    // Files edited since the last git_commit, relative to the project root
+    modified: Vec<String>,
+    // Hash of each file's content as the model last read or wrote it, to
+    // catch edits made outside the agent in between
+    seen: HashMap<String, u64>,
+    // Files whose whole content is in a tool result still in the history:
+    // the content's hash and the absolute index of that message
+    in_context: HashMap<String, (u64, usize)>,
+    // Messages trimmed from the front of the history so far
+    trimmed: usize,
+    // Absolute index of the oldest message the last request sent whole;
+    // older ones were summarized or cut to fit the context window
+    sent_from: usize,
+    iteration: u32,
+    success: bool,
+    parse_retries: u32,
+    retry_due: bool,
+    // A tool of the last batch failed, so the planner takes the next turn
+    tools_failed: bool,
+    // The next edit was rewritten by the user, which its result mentions
+    edited_by_user: bool,
+    // Other project roots switch_project can move to
+    workspace: Option<Workspace>,
+    // The approved plan in plan mode; every request is anchored to its
+    // current step
+    plan: Option<Plan>,
+    // Files whose current content goes with every request, relative to the
+    // project root
+    pinned: Vec<String>,
+    // Images waiting to go with the next user message
+    images: Vec<Image>,
+    // --subtasks: the budget of each step's nested run, and the run in
+    // progress
+    subtasks: Option<SubtaskBudget>,
+    subtask: Option<Subtask>,
+    // Bytes the edit tools wrote this session, checked against the limits
+    // before each edit
+    write_limits: WriteLimits,
+    written: u64,
+    // The user confirmed the next edit despite the limits
+    write_confirmed: bool,
+    // Chunks of related code added to the system prompt for each new task,
+    // and those found for the latest one
+    retrieval: usize,
+    retrieved: String,
+    // Lines shown around each error of the latest failed build, and where
+    // those errors are
+    error_radius: usize,
+    error_locations: Vec<(String, usize)>,
+    // Failures in a row by kind, and the advice for the latest batch's
+    failures: FailureTracker,
+    advice: String,
+    // Answers ask_user where no frontend takes the question itself, like a
+    // console run; without one the model is told to assume
+    asker: Option<Box<Asker>>,
+    // The user's answer, sent as the next user message once the batch ends
+    answer: Option<String>,
+    events: EventBus,
+}
+
+// A nested run for one plan step; the main session's history waits here
+// until the step is rolled up into it
+struct Subtask {
+    step: usize,
+    started_iteration: u32,
+    started_tokens: u32,
+    parent_history: Vec<ChatMessage>,
+}
+
+impl AgentEngine {
+    pub fn new(
+        router: ModelRouter,
+        prompt: impl Into<String>,
+        project_root: impl Into<String>,
+        tpm_limiter: TPMLimiter,
+        parser: ResponseParser,
+    ) -> Self {
+        let project_root = project_root.into();
+        plugins::register(&project_root);
+        Self {
+            router,
+            prompt: parser.system_prompt(&prompt.into()),
+            project_root,
+            tpm_limiter,
+            parser,
+            result_limit: None,
+            history: Vec::new(),
+            pending: VecDeque::new(),
+            executed: Vec::new(),
+            modified: Vec::new(),
+            seen: HashMap::new(),
+            in_context: HashMap::new(),
+            trimmed: 0,
+            sent_from: 0,
+            iteration: 0,
+            success: false,
+            parse_retries: 0,
+            retry_due: false,
+            tools_failed: false,
+            edited_by_user: false,
+            workspace: None,
+            plan: None,
+            pinned: Vec::new(),
+            images: Vec::new(),
+            subtasks: None,
+            subtask: None,
+            write_limits: WriteLimits::from_env(),
+            written: 0,
+            write_confirmed: false,
+            retrieval: semantic::retrieval_chunks(),
+            retrieved: String::new(),
+            error_radius: error_context::radius(),
+            error_locations: Vec::new(),
+            failures: FailureTracker::default(),
+            advice: String::new(),
+            asker: None,
+            answer: None,
+            events: EventBus::default(),
+        }
+    }
+
+    pub fn with_result_limit(mut self, limit: usize) -> Self {
+        self.result_limit = Some(limit);
+        self
+    }
+
+    pub fn with_workspace(mut self, workspace: Workspace) -> Self {
+        self.workspace = Some(workspace);
+        self
+    }
+
+    pub fn with_subtasks(mut self, budget: SubtaskBudget) -> Self {
+        self.subtasks = Some(budget);
+        self
+    }
+
+    pub fn with_write_limits(mut self, limits: WriteLimits) -> Self {
+        self.write_limits = limits;
+        self
+    }
+
+    pub fn with_retrieval(mut self, chunks: usize) -> Self {
+        self.retrieval = chunks;
+        self
+    }
+
+    pub fn with_error_context(mut self, radius: usize) -> Self {
+        self.error_radius = radius;
+        self
+    }
+
+    pub fn with_asker(mut self, asker: impl FnMut(&str) -> Option<String> + Send + 'static) -> Self {
+        self.asker = Some(Box::new(asker));
+        self
+    }
+
+    // Observers see every event of the run; closures taking &AgentEvent
+    // work too
+    pub fn subscribe(&mut self, observer: impl AgentObserver + 'static) {
+        self.events.subscribe(observer);
+    }
+
+    pub fn project_root(&self) -> &str {
+        &self.project_root
+    }
+
+    pub fn history(&self) -> &[ChatMessage] {
+        &self.history
+    }
+
+    pub fn pending_tools(&self) -> &VecDeque<(String, String)> {
+        &self.pending
+    }
+
+    // The question of a queued ask_user that is next to run
+    pub fn pending_question(&self) -> Option<&str> {
+        self.pending
+            .front()
+            .filter(|(tool, _)| tool == "ask_user")
+            .map(|(_, question)| question.as_str())
+    }
+
+    // Finishes the queued ask_user with the user's reply, for frontends
+    // that ask it themselves
+    pub fn answer_question(&mut self, reply: &str) -> Option<(String, String, String)> {
+        self.pending_question()?;
+        self.answer = Some(reply.trim().to_string());
+        self.finish_next(Some(Ok(ToolOutput::text(ANSWERED))))
+    }
+
+    // Tools executed since the last request
+    pub fn executed_tools(&self) -> &[(String, String, String)] {
+        &self.executed
+    }
+
+    pub fn modified_files(&self) -> &[String] {
+        &self.modified
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.written
+    }
+
+    // Why the next queued edit needs the user's confirmation: it would
+    // leave a file, or the session's writes, over the write limits
+    pub fn write_limit_exceeded(&self) -> Option<String> {
+        if self.write_confirmed || dry_run::enabled() {
+            return None;
+        }
+        let (tool, param) = self.pending.front()?;
+        let size = app::projected_size(tool, param, &self.project_root)?;
+        self.write_limits
+            .exceeded(app::tool_path(tool, param)?, size, self.written)
+    }
+
+    // Lets the next queued edit through the write limits once
+    pub fn confirm_write(&mut self) {
+        self.write_confirmed = true;
+    }
+
+    pub fn iteration(&self) -> u32 {
+        self.iteration
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.success
+    }
+
+    // The model's latest reply still in the conversation
+    pub fn last_reply(&self) -> Option<&str> {
+        self.history
+            .iter()
+            .rev()
+            .find(|m| m.role == "assistant")
+            .map(|m| m.content.as_str())
+    }
+
+    // A corrective follow-up for a malformed reply is waiting to be sent
+    pub fn retry_due(&self) -> bool {
+        self.retry_due
+    }
+
+    pub fn plan(&self) -> Option<&Plan> {
+        self.plan.as_ref()
+    }
+
+    // Replaces the plan, starting its first unfinished step
+    pub fn set_plan(&mut self, plan: Option<Plan>) {
+        self.plan = plan.map(|mut plan| {
+            plan.start();
+            plan
+        });
+        if let Some(plan) = &self.plan {
+            self.events.emit(AgentEvent::PlanUpdated { plan: plan.clone() });
+        }
+    }
+
+    pub fn pinned_files(&self) -> &[String] {
+        &self.pinned
+    }
+
+    // Pinned files are sent with every request, ahead of older turns when
+    // the context window is short
+    pub fn pin(&mut self, path: &str) -> Result<(), ToolError> {
+        let path = file_tree::clean(path);
+        workspace::refuse_outside(&self.project_root, &path)?;
+        IgnoreRules::load(&self.project_root).refuse(&path)?;
+        let full = Path::new(&self.project_root).join(&path);
+        if !vfs::current().is_file(&full) {
+            return Err(ToolError::InvalidArgs(format!("{} is not a file", path)));
+        }
+        if !self.pinned.contains(&path) {
+            self.pinned.push(path);
+        }
+        Ok(())
+    }
+
+    pub fn unpin(&mut self, path: &str) -> bool {
+        let path = file_tree::normalize(path.trim());
+        let before = self.pinned.len();
+        self.pinned.retain(|p| *p != path);
+        self.pinned.len() < before
+    }
+
+    // Attaches an image to the next user message. Refused when the model
+    // user messages go to can't take images.
+    pub fn attach_image(&mut self, path: &str) -> Result<(), ToolError> {
+        let client = self.router.client(Role::Planner);
+        if !client.supports_images() {
+            return Err(ToolError::InvalidArgs(format!(
+                "{} doesn't take images; set LLM_VISION=on if it does",
+                client.model()
+            )));
+        }
+        let path = path.trim();
+        let resolved = Path::new(&self.project_root).join(path);
+        let resolved = if resolved.is_file() { resolved } else { Path::new(path).to_path_buf() };
+        self.images.push(Image::load(&resolved)?);
+        Ok(())
+    }
+
+    pub fn attached_images(&self) -> &[Image] {
+        &self.images
+    }
+
+    pub fn tpm_limiter(&self) -> &TPMLimiter {
+        &self.tpm_limiter
+    }
+
+    // Continues a saved conversation in place of the current one, dropping
+    // the tool queue, plan, pins and per-file read state
+    pub fn restore(&mut self, history: Vec<ChatMessage>, iteration: u32) {
+        self.history = history;
+        self.iteration = iteration;
+        self.pending.clear();
+        self.executed.clear();
+        self.seen.clear();
+        self.in_context.clear();
+        self.pinned.clear();
+        self.trimmed = 0;
+        self.sent_from = 0;
+        self.success = false;
+        self.parse_retries = 0;
+        self.retry_due = false;
+        self.tools_failed = false;
+        self.edited_by_user = false;
+        self.plan = None;
+        self.subtask = None;
+    }
+
+    pub fn push_user(&mut self, message: impl Into<String>) {
+        let text = message.into();
+        if self.retrieval > 0 {
+            self.retrieved = semantic::prompt_section(&self.project_root, &text, self.retrieval);
+        }
+        // Advice about the last task's failures doesn't carry over
+        self.advice.clear();
+        self.events.emit(AgentEvent::UserMessage { text: text.clone() });
+        let images = std::mem::take(&mut self.images);
+        self.history.push(ChatMessage::user(text).with_images(&images));
+    }
+
+    // Runs a command the user typed after '!' the way execute_command runs,
+    // through the project's hooks, sandbox and audit log, and adds its
+    // output to the conversation for the model to see
+    pub fn run_user_command(&mut self, command: &str) -> Result<ToolOutput, ToolError> {
+        let output = app::execute_tool("execute_command", command, &self.project_root);
+        self.history.push(ChatMessage::system(format!(
+            "The user ran `{}`:\n{}",
+            command,
+            app::result_text(&output)
+        )));
+        self.trim_history();
+        output
+    }
+
+    // "role: model" for the next request
+    pub fn next_model(&self) -> String {
+        let role = router::select_role(&self.history, self.tools_failed);
+        format!("{}: {}", role.name(), self.router.client(role).model())
+    }
+
+    // Switches the limiter to the [limits.*] profile of the next request's
+    // model, so its wait_time is the one that request will see
+    pub fn select_limits(&mut self) {
+        let role = router::select_role(&self.history, self.tools_failed);
+        self.select_limits_for(role);
+    }
+
+    fn select_limits_for(&mut self, role: Role) {
+        let client = self.router.client(role);
+        let profile = LimitProfiles::load(&self.project_root).for_model(client.provider(), client.model());
+        self.tpm_limiter.select(profile);
+    }
+
+    // Sends the conversation to the model and queues the tool calls in its
+    // reply. Ok(None) when interrupted.
+    pub async fn request(&mut self) -> Result<Option<Response>, AgentError> {
+        if !self.retry_due {
+            self.advance_subtasks();
+            self.iteration += 1;
+            self.events.emit(AgentEvent::IterationStarted {
+                iteration: self.iteration,
+            });
+        }
+        self.retry_due = false;
+        self.executed.clear();
+        self.pending.clear();
+
+        // The project section follows the active root, which switch_project
+        // may have moved to a project of another kind
+        let mut prompt = self.prompt.clone();
+        let project = ProjectKind::detect(&self.project_root).prompt_section();
+        if !project.is_empty() {
+            prompt = format!("{}\n\n{}", prompt, project);
+        }
+        // Cut to the routed model's context window
+        let role = router::select_role(&self.history, self.tools_failed);
+        let budget = ContextBudget::for_model(self.router.client(role).model());
+        let conventions = conventions::prompt_section(&self.project_root, &budget);
+        if !conventions.is_empty() {
+            prompt = format!("{}\n\n{}", prompt, conventions);
+        }
+        if !self.retrieved.is_empty() {
+            prompt = format!("{}\n\n{}", prompt, self.retrieved);
+        }
+        let errors = error_context::prompt_section(
+            &self.project_root,
+            &self.error_locations,
+            self.error_radius,
+            &budget,
+        );
+        if !errors.is_empty() {
+            prompt = format!("{}\n\n{}", prompt, errors);
+        }
+        if !self.advice.is_empty() {
+            prompt = format!("{}\n\n{}", prompt, self.advice);
+        }
+        if let Some(workspace) = &self.workspace {
+            prompt = format!("{}\n\n{}", prompt, workspace.map(&self.project_root));
+        }
+        if let Some(plan) = &self.plan {
+            prompt = format!("{}\n\n{}", prompt, plan.prompt_section());
+        }
+        let mut messages = budget.fit(
+            app::build_messages(&prompt, &self.project_root, &self.history),
+            &self.pinned_contents(),
+        );
+        let whole = messages
+            .iter()
+            .rev()
+            .zip(self.history.iter().rev())
+            .take_while(|(sent, kept)| sent.content == kept.content)
+            .count();
+        self.sent_from = self.trimmed + self.history.len() - whole;
+        // An editor model without vision gets a note in place of the images
+        if !self.router.client(role).supports_images() {
+            for message in messages.iter_mut().filter(|m| !m.images.is_empty()) {
+                message.content.push_str(&format!(
+                    "\n[{} image(s) attached, not shown to this model]",
+                    message.images.len()
+                ));
+                message.images.clear();
+            }
+        }
+        let filters = Filters::load(&self.project_root);
+        self.filter_prompt(&filters, &mut messages)?;
+        let input_tokens = app::count_message_tokens(&messages);
+
+        // Abandoned like the request itself if Ctrl+C is pressed meanwhile
+        self.select_limits_for(role);
+        tokio::select! {
+            _ = self.tpm_limiter.wait_if_needed() => {}
+            _ = interrupt::wait() => return Ok(None),
+        }
+        self.tools_failed = false;
+        self.events.emit(AgentEvent::LlmRequest {
+            role,
+            model: self.router.client(role).model().to_string(),
+            input_tokens,
+        });
+
+        // Abandoned if Ctrl+C is pressed while waiting
+        let config = serde_json::json!({});
+        let generation = tokio::select! {
+            resp = self.router.generate(role, &messages, &config) => resp,
+            _ = interrupt::wait() => return Ok(None),
+        };
+        let generation = match generation {
+            Ok(generation) => generation,
+            Err(e) => {
+                self.events.emit(AgentEvent::Error {
+                    message: e.to_string(),
+                });
+                return Err(e.into());
+            }
+        };
+
+        // Reasoning is shown but kept out of the history sent back
+        let (reasoning, text) = app::split_reasoning(&app::filter_thinking_tokens(&generation.text));
+        // A blocked reply never reaches the parser
+        let (text, blocked) = match filters.apply_response(&text, &self.project_root) {
+            Ok(text) => (text, None),
+            Err(reason) => {
+                self.events.emit(AgentEvent::Error {
+                    message: format!("Reply blocked by a content filter: {}", reason),
+                });
+                (format!("[reply blocked by a content filter: {}]", reason), Some(reason))
+            }
+        };
+        // The server's own count when it reports one
+        let (output_tokens, total_tokens) = match generation.usage {
+            Some(usage) => (usage.completion_tokens, usage.total_tokens),
+            None => {
+                let output =
+                    app::count_tokens(&text) + reasoning.as_deref().map_or(0, app::count_tokens);
+                (output, input_tokens + output)
+            }
+        };
+        self.tpm_limiter.add_token_usage(total_tokens);
+        self.history.push(ChatMessage::assistant(text.clone()));
+        self.events.emit(AgentEvent::LlmResponse {
+            role,
+            model: generation.model.clone(),
+            text: text.clone(),
+            reasoning,
+            fingerprint: generation.fingerprint.clone(),
+            fallbacks: generation.fallbacks.clone(),
+            output_tokens,
+        });
+
+        let parsed = match blocked {
+            Some(reason) => {
+                self.history.push(ChatMessage::user(format!(
+                    "Your reply was blocked by the project's content filter ({}). Don't repeat \
+                     it; reach the goal another way.",
+                    reason
+                )));
+                Ok(Vec::new())
+            }
+            None => self.parser.parse(&text),
+        };
+        let parse_error = match parsed {
+            Ok(mut tools) => {
+                // A question goes first, so the calls made alongside it can
+                // wait for the answer
+                if let Some(i) = tools.iter().position(|(tool, _)| tool == "ask_user") {
+                    let question = tools.remove(i);
+                    tools.insert(0, question);
+                }
+                self.pending = tools.into();
+                self.parse_retries = 0;
+                None
+            }
+            Err(err) => {
+                self.events.emit(AgentEvent::Error {
+                    message: format!("Could not parse tool call: {}", err),
+                });
+                if self.parse_retries < self.parser.max_retries {
+                    self.parse_retries += 1;
+                    self.retry_due = true;
+                    self.history
+                        .push(ChatMessage::user(self.parser.error_feedback(&err)));
+                } else {
+                    self.parse_retries = 0;
+                }
+                Some(err)
+            }
+        };
+
+        if self.pending.is_empty() {
+            self.trim_history();
+        }
+
+        Ok(Some(Response {
+            role,
+            model: generation.model,
+            text,
+            fallbacks: generation.fallbacks,
+            input_tokens,
+            output_tokens,
+            parse_error,
+        }))
+    }
+
+    // A prompt blocked by the project's filters isn't sent at all
+    fn filter_prompt(&mut self, filters: &Filters, messages: &mut [ChatMessage]) -> Result<(), AgentError> {
+        filters
+            .apply_prompt(messages, &self.project_root)
+            .map_err(|reason| {
+                self.events.emit(AgentEvent::Error {
+                    message: format!("Prompt blocked by a content filter: {}", reason),
+                });
+                AgentError::Blocked(reason)
+            })
+    }
+
+    // Queues a tool call that didn't come from the model, e.g. /commit
+    pub fn queue_tool(&mut self, tool: impl Into<String>, param: impl Into<String>) {
+        self.pending.push_back((tool.into(), param.into()));
+    }
+
+    // Asks the model for a commit message describing the changes to the
+    // modified files, outside the conversation
+    pub async fn draft_commit_message(&mut self) -> Result<String, AgentError> {
+        let diff = git::diff(&self.modified, &self.project_root)?;
+        let mut messages = git::commit_message_request(&diff);
+        let filters = Filters::load(&self.project_root);
+        self.filter_prompt(&filters, &mut messages)?;
+        let input_tokens = app::count_message_tokens(&messages);
+
+        self.select_limits_for(Role::Planner);
+        self.tpm_limiter.wait_if_needed().await;
+        let config = serde_json::json!({});
+        let generation = self.router.generate(Role::Planner, &messages, &config).await?;
+        let text = filters
+            .apply_response(&generation.text, &self.project_root)
+            .map_err(AgentError::Blocked)?;
+        let message = git::clean_message(&text);
+        self.tpm_limiter
+            .add_token_usage(input_tokens + app::count_tokens(&message));
+        Ok(message)
+    }
+
+    // Asks the planner for a numbered plan of the task; nothing is added to
+    // the conversation until the plan is approved with set_plan
+    pub async fn draft_plan(&mut self, task: &str) -> Result<Plan, AgentError> {
+        let mut messages = plan::plan_request(task, &self.project_root);
+        let filters = Filters::load(&self.project_root);
+        self.filter_prompt(&filters, &mut messages)?;
+        let input_tokens = app::count_message_tokens(&messages);
+
+        self.select_limits_for(Role::Planner);
+        self.tpm_limiter.wait_if_needed().await;
+        let config = serde_json::json!({});
+        let generation = self.router.generate(Role::Planner, &messages, &config).await?;
+        let (_, text) = app::split_reasoning(&app::filter_thinking_tokens(&generation.text));
+        let text = filters
+            .apply_response(&text, &self.project_root)
+            .map_err(AgentError::Blocked)?;
+        self.tpm_limiter
+            .add_token_usage(input_tokens + app::count_tokens(&generation.text));
+        Plan::parse(task, &text).ok_or_else(|| {
+            AgentError::Plan(format!("it has no numbered steps:\n{}", text.trim()))
+        })
+    }
+
+    // Replaces the next queued edit with the file content the user settled
+    // on, written whole when it runs
+    pub fn edit_next(&mut self, content: &str) -> Result<(), ToolError> {
+        let path = self
+            .pending
+            .front()
+            .filter(|(tool, _)| app::is_edit_tool(tool))
+            .and_then(|(tool, param)| app::tool_path(tool, param))
+            .map(str::to_string)
+            .ok_or_else(|| ToolError::InvalidArgs("the next tool is not an edit".to_string()))?;
+        self.pending[0] = (
+            "write_file_delta".to_string(),
+            format!("{}:::\n<<<<<<< CURRENT\n=======\n{}\n>>>>>>> NEW", path, content),
+        );
+        self.edited_by_user = true;
+        Ok(())
+    }
+
+    // Drops the next queued tool without running it; the model is told it
+    // was skipped
+    pub fn skip_next(&mut self) -> Option<(String, String)> {
+        let (tool, param) = self.pending.pop_front()?;
+        let result = "✗ Skipped by the user".to_string();
+        self.events.emit(AgentEvent::ToolExecuted {
+            tool: tool.clone(),
+            param: param.clone(),
+            result: result.clone(),
+            ok: false,
+            status: None,
+        });
+
+        self.executed.push((tool.clone(), param.clone(), result));
+        if self.pending.is_empty() {
+            self.finish_tools();
+        }
+        Some((tool, param))
+    }
+
+    // Executes the next queued tool; once the queue is empty the results
+    // are added to the conversation
+    pub fn execute_next(&mut self) -> Option<(String, String, String)> {
+        self.finish_next(None)
+    }
+
+    // The next queued tool with the project root to run it in, when running
+    // it is all there is to do: the engine doesn't refuse it, answer it from
+    // the conversation or handle it itself. Frontends can run it on a worker
+    // thread and hand the result to complete_next.
+    pub fn next_call(&self) -> Option<(String, String, String)> {
+        let (tool, param) = self.pending.front()?;
+        if self.write_limit_exceeded().is_some() || !self.is_plain_call(tool, param) {
+            return None;
+        }
+        Some((tool.clone(), param.clone(), self.project_root.clone()))
+    }
+
+    // Finishes the next queued tool with the result of running it elsewhere
+    pub fn complete_next(
+        &mut self,
+        output: Result<ToolOutput, ToolError>,
+    ) -> Option<(String, String, String)> {
+        self.finish_next(Some(output))
+    }
+
+    // Runs every queued tool and returns how many ran. Consecutive reads run
+    // side by side, PARALLEL_READS at a time; edits and commands run alone,
+    // in order, so each sees what the calls before it did.
+    pub async fn execute_many(&mut self) -> usize {
+        let mut executed = 0;
+        while !self.pending.is_empty() {
+            let reads: Vec<(String, String)> = self
+                .pending
+                .iter()
+                .take_while(|(tool, param)| {
+                    is_independent_read(tool) && self.is_plain_call(tool, param)
+                })
+                .take(PARALLEL_READS)
+                .cloned()
+                .collect();
+            if reads.len() < 2 {
+                self.execute_next();
+                executed += 1;
+                continue;
+            }
+
+            let tasks: Vec<_> = reads
+                .into_iter()
+                .map(|(tool, param)| {
+                    let root = self.project_root.clone();
+                    // The blocking thread reads through this thread's files
+                    let fs = vfs::current();
+                    tokio::task::spawn_blocking(move || {
+                        let _fs = vfs::install(fs);
+                        app::execute_tool(&tool, &param, &root)
+                    })
+                })
+                .collect();
+            for task in tasks {
+                let output = task
+                    .await
+                    .unwrap_or_else(|e| Err(ToolError::InvalidArgs(format!("the read failed: {}", e))));
+                self.finish_next(Some(output));
+                executed += 1;
+            }
+        }
+        executed
+    }
+
+    // Executes the next queued tool, or takes the result of a plain call
+    // that was already run
+    fn finish_next(
+        &mut self,
+        ran: Option<Result<ToolOutput, ToolError>>,
+    ) -> Option<(String, String, String)> {
+        let over_limit = self.write_limit_exceeded();
+        self.write_confirmed = false;
+        let (tool, param) = self.pending.pop_front()?;
+
+        // Edits over the write limits or on top of content the model hasn't
+        // seen are refused; git_commit commits the files this engine
+        // modified
+        let mut output = if let Some(output) = ran {
+            output
+        } else if let Some(reason) = over_limit {
+            Err(ToolError::WriteLimit(reason))
+        } else if let Some(path) = self.stale_path(&tool, &param) {
+            Err(ToolError::Stale(path))
+        } else if let Some(path) = self.cached_read(&tool, &param) {
+            Ok(ToolOutput::text(format!(
+                "cached: {} hasn't changed since you read it; its content is in the tool results above",
+                path
+            )))
+        } else if tool == "switch_project" {
+            self.switch_project(&param)
+        } else if tool == "step_done" {
+            self.step_done(&param)
+        } else if tool == "ask_user" {
+            self.ask_user(&param)
+        } else if tool == "git_commit" {
+            app::execute_tool(
+                &tool,
+                &git::encode_commit(&self.modified, &param),
+                &self.project_root,
+            )
+        } else {
+            app::execute_tool(&tool, &param, &self.project_root)
+        };
+
+        // With the quality gate on, success also needs clippy and rustfmt to
+        // be clean; what is left goes back to the model with the result
+        let mut succeeded =
+            matches!(&output, Ok(o) if app::is_success(&tool, &param, o, &self.project_root));
+        if succeeded && quality::gate_enabled() {
+            if let (Some(report), Ok(output)) =
+                (quality::gate_report(&self.project_root), output.as_mut())
+            {
+                output.text.push_str("\n\n");
+                output.text.push_str(&report);
+                succeeded = false;
+            }
+        }
+        let mut result = app::result_text(&output);
+        if std::mem::take(&mut self.edited_by_user) {
+            result.push_str(" (the user rewrote this change before it was applied; read the file before editing it again)");
+        }
+        self.events.emit(AgentEvent::ToolExecuted {
+            tool: tool.clone(),
+            param: param.clone(),
+            result: result.clone(),
+            ok: output.is_ok(),
+            status: output.as_ref().ok().map(ToolOutput::status),
+        });
+
+        self.failures.record(&tool, &param, &output);
+        if let Err(e @ ToolError::Stale(_)) = &output {
+            self.events.emit(AgentEvent::Error {
+                message: e.to_string(),
+            });
+        }
+
+        match &output {
+            Ok(output) => {
+                if let Some(path) = app::tool_path(&tool, &param)
+                    .filter(|_| app::is_edit_tool(&tool) && !dry_run::enabled())
+                {
+                    let path = Path::new(&self.project_root).join(path);
+                    self.written += vfs::current().len(&path).unwrap_or(0);
+                }
+                self.track_changes(&tool, &param, output);
+                self.note_build_output(&tool, &param, output);
+                if tool == "read_file" {
+                    self.note_in_context(&param, &output.text);
+                }
+                if tool == "git_commit" && output.exit_code == Some(0) {
+                    self.modified.clear();
+                }
+                if succeeded {
+                    self.success = true;
+                    self.events.emit(AgentEvent::Success);
+                }
+            }
+            // The tool ran before its after hook failed, so an edit did
+            // change the file
+            Err(ToolError::AfterHook { .. }) => {
+                self.tools_failed = true;
+                self.track_changes(&tool, &param, &ToolOutput::text(""));
+            }
+            Err(_) => self.tools_failed = true,
+        }
+
+        self.executed
+            .push((tool.clone(), param.clone(), result.clone()));
+        // The model asked because it didn't know how to go on, so the calls
+        // it made alongside the question wait for the answer
+        if tool == "ask_user" && output.is_ok() {
+            for (tool, param) in std::mem::take(&mut self.pending) {
+                let result = "✗ Not run: the user was asked a question first".to_string();
+                self.events.emit(AgentEvent::ToolExecuted {
+                    tool: tool.clone(),
+                    param: param.clone(),
+                    result: result.clone(),
+                    ok: false,
+                    status: None,
+                });
+                self.executed.push((tool, param, result));
+            }
+        }
+        if self.pending.is_empty() {
+            self.finish_tools();
+        }
+
+        Some((tool, param, result))
+    }
+
+    // The errors of a failed build put the code around them into the
+    // system prompt of the following requests, so the model needn't read
+    // it first; a passing build clears them
+    pub fn note_build_output(&mut self, tool: &str, param: &str, output: &ToolOutput) {
+        if !error_context::is_build(tool, param) {
+            return;
+        }
+        let locations = error_context::error_locations(&output.raw());
+        if !locations.is_empty() || output.exit_code == Some(0) {
+            self.error_locations = locations;
+        }
+    }
+
+    // One request plus all of its tools, including corrective follow-ups
+    // for malformed replies. Returns the number of tools executed, or None
+    // when interrupted; fails once the parse retries are used up.
+    pub async fn step(&mut self) -> Result<Option<usize>, AgentError> {
+        loop {
+            let Some(response) = self.request().await? else {
+                return Ok(None);
+            };
+            match response.parse_error {
+                Some(_) if self.retry_due && !interrupt::is_interrupted() => {}
+                Some(err) if !self.retry_due => return Err(AgentError::Parse(err)),
+                _ => break,
+            }
+        }
+
+        Ok(Some(self.execute_many().await))
+    }
+
+    // Steps until the success condition is met (true), or the model stops
+    // calling tools, the iteration limit is reached or the run is
+    // interrupted (false)
+    pub async fn run_to_completion(&mut self, max_iterations: u32) -> Result<bool, AgentError> {
+        while self.iteration < max_iterations && !interrupt::is_interrupted() {
+            match self.step().await? {
+                _ if self.success => return Ok(true),
+                Some(0) | None => return Ok(false),
+                Some(_) => {}
+            }
+        }
+        Ok(self.success)
+    }
+
+    // Remembers the content of files read or changed, so later edits can
+    // tell whether they were changed outside the agent, and collects the
+    // changed files for git_commit
+    fn track_changes(&mut self, tool: &str, param: &str, output: &ToolOutput) {
+        let changed = app::modified_paths(tool, param, output);
+        let read = (tool == "read_file").then(|| app::tool_path(tool, param)).flatten();
+        for path in read.into_iter().chain(changed.iter().map(String::as_str)) {
+            let path = file_tree::normalize(path);
+            if let Some(hash) = self.content_hash(&path) {
+                self.seen.insert(path, hash);
+            }
+        }
+        if !dry_run::enabled() {
+            for path in changed {
+                let normalized = file_tree::normalize(&path);
+                if !self.modified.contains(&normalized) {
+                    self.modified.push(normalized);
+                }
+                semantic::note_edit(&self.project_root, &path);
+                self.events.emit(AgentEvent::FileModified { path });
+            }
+        }
+    }
+
+    // Later tools resolve paths against the new root
+    fn switch_project(&mut self, name: &str) -> Result<ToolOutput, ToolError> {
+        let workspace = self.workspace.as_ref().ok_or_else(|| {
+            ToolError::InvalidArgs("switch_project only works in a workspace".to_string())
+        })?;
+        let project = workspace.find(name).ok_or_else(|| {
+            ToolError::InvalidArgs(format!(
+                "unknown project \"{}\"; the workspace has: {}",
+                name.trim(),
+                workspace.names().join(", ")
+            ))
+        })?;
+        let (name, root) = (project.name.clone(), project.root.clone());
+
+        self.project_root = root.clone();
+        // Paths seen so far belong to the old root
+        self.seen.clear();
+        self.in_context.clear();
+        self.modified.clear();
+        self.events.emit(AgentEvent::ProjectSwitched {
+            name: name.clone(),
+            root: root.clone(),
+        });
+        Ok(ToolOutput::text(format!("✓ Switched to {} at {}", name, root)))
+    }
+
+    // Binary or unreadable pinned files are skipped
+    fn pinned_contents(&self) -> Vec<(String, String)> {
+        self.pinned
+            .iter()
+            .filter_map(|path| {
+                match file_cache::read(&Path::new(&self.project_root).join(path)) {
+                    Ok(FileContent::Text(content)) => Some((path.clone(), content)),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    // Starts the nested run of the current plan step, or stops one that is
+    // over its budget. The rest of the plan then continues in the main
+    // session, so the step isn't retried with the same budget.
+    fn advance_subtasks(&mut self) {
+        let Some(budget) = self.subtasks else {
+            return;
+        };
+        if let Some(subtask) = &self.subtask {
+            let iterations = self.iteration - subtask.started_iteration;
+            let tokens = self.tpm_limiter.get_total_tokens() - subtask.started_tokens;
+            if iterations >= budget.iterations || tokens >= budget.tokens {
+                let step = subtask.step + 1;
+                self.finish_subtask(false);
+                self.subtasks = None;
+                self.events.emit(AgentEvent::Error {
+                    message: format!(
+                        "Sub-task {} ran out of its budget; continuing in the main session",
+                        step
+                    ),
+                });
+            }
+            return;
+        }
+        let Some(plan) = &self.plan else {
+            return;
+        };
+        let Some(step) = plan.current() else {
+            return;
+        };
+        let brief = plan::subtask_brief(plan, step);
+        self.events.emit(AgentEvent::SubtaskStarted {
+            step: step + 1,
+            text: plan.steps[step].text.clone(),
+        });
+        self.subtask = Some(Subtask {
+            step,
+            started_iteration: self.iteration,
+            started_tokens: self.tpm_limiter.get_total_tokens(),
+            parent_history: std::mem::take(&mut self.history),
+        });
+        self.in_context.clear();
+        self.history.push(ChatMessage::user(brief));
+    }
+
+    // Puts the main session's history back with a note on how the step
+    // went; the nested run's own messages are dropped
+    fn finish_subtask(&mut self, completed: bool) {
+        let Some(subtask) = self.subtask.take() else {
+            return;
+        };
+        let iterations = self.iteration - subtask.started_iteration;
+        let tokens = self.tpm_limiter.get_total_tokens() - subtask.started_tokens;
+        let (text, summary) = match &self.plan {
+            Some(plan) => {
+                let step = &plan.steps[subtask.step];
+                (step.text.clone(), step.summary.clone().unwrap_or_default())
+            }
+            None => (String::new(), String::new()),
+        };
+        let outcome = match (completed, summary.is_empty()) {
+            (true, true) => "finished".to_string(),
+            (true, false) => format!("finished: {}", summary),
+            (false, _) => "stopped unfinished after running out of its budget".to_string(),
+        };
+        self.history = subtask.parent_history;
+        self.in_context.clear();
+        self.history.push(ChatMessage::user(format!(
+            "Sub-task {} ({}) {} [{} iteration(s), {} tokens]",
+            subtask.step + 1,
+            text,
+            outcome,
+            iterations,
+            tokens
+        )));
+        self.events.emit(AgentEvent::SubtaskFinished {
+            step: subtask.step + 1,
+            completed,
+            summary,
+            iterations,
+            tokens,
+        });
+    }
+
+    // Asks the user through the asker; the answer follows the tool results
+    // as a user message
+    fn ask_user(&mut self, question: &str) -> Result<ToolOutput, ToolError> {
+        let reply = self.asker.as_mut().and_then(|ask| ask(question.trim()));
+        match reply.filter(|reply| !reply.trim().is_empty()) {
+            Some(reply) => {
+                self.answer = Some(reply.trim().to_string());
+                Ok(ToolOutput::text(ANSWERED))
+            }
+            None => Err(ToolError::NoOneToAsk),
+        }
+    }
+
+    // Moves the plan on to its next step; the summary the model passes is
+    // kept in the conversation with the call
+    fn step_done(&mut self, summary: &str) -> Result<ToolOutput, ToolError> {
+        let plan = self.plan.as_mut().ok_or_else(|| {
+            ToolError::InvalidArgs("step_done only works in plan mode".to_string())
+        })?;
+        let result = plan.complete_current(summary);
+        self.events.emit(AgentEvent::PlanUpdated { plan: plan.clone() });
+        Ok(ToolOutput::text(format!("✓ {}", result)))
+    }
+
+    // A call app::execute_tool can run as is, away from the engine
+    fn is_plain_call(&self, tool: &str, param: &str) -> bool {
+        !matches!(tool, "switch_project" | "step_done" | "git_commit" | "ask_user")
+            && self.stale_path(tool, param).is_none()
+            && self.cached_read(tool, param).is_none()
+    }
+
+    // A whole-file read whose result the model was last sent in full, of a
+    // file that hasn't changed since
+    fn cached_read(&self, tool: &str, param: &str) -> Option<String> {
+        if tool != "read_file" || param.contains(":::") {
+            return None;
+        }
+        let path = file_tree::normalize(param.trim());
+        let (hash, index) = self.in_context.get(&path)?;
+        (*index >= self.sent_from.max(self.trimmed) && self.content_hash(&path) == Some(*hash)).then_some(path)
+    }
+
+    // Remembers a read that returned the whole file verbatim and goes to the
+    // model uncut; its results message is the next one in the history
+    fn note_in_context(&mut self, param: &str, text: &str) {
+        if param.contains(":::") || self.result_limit.is_some_and(|l| text.chars().count() > l) {
+            return;
+        }
+        let path = file_tree::normalize(param.trim());
+        let hash = file_cache::hash_bytes(text.as_bytes());
+        if self.content_hash(&path) == Some(hash) {
+            self.in_context
+                .insert(path, (hash, self.trimmed + self.history.len()));
+        }
+    }
+
+    // The file an edit tool would change, if it changed on disk since the
+    // model last read or wrote it
+    fn stale_path(&self, tool: &str, param: &str) -> Option<String> {
+        if !app::is_edit_tool(tool) {
+            return None;
+        }
+        let path = file_tree::normalize(app::tool_path(tool, param)?);
+        let seen = self.seen.get(&path)?;
+        (self.content_hash(&path) != Some(*seen)).then_some(path)
+    }
+
+    fn content_hash(&self, path: &str) -> Option<u64> {
+        file_cache::hash(&Path::new(&self.project_root).join(path))
+    }
+
+    fn finish_tools(&mut self) {
+        if !self.executed.is_empty() {
+            let results: Vec<String> = self
+                .executed
+                .iter()
+                .map(|(tool, param, result)| match self.result_limit {
+                    Some(limit) => format!(
+                        "{}: {} -> {}",
+                        tool,
+                        truncate(param, 30),
+                        truncate(result, limit)
+                    ),
+                    None => format!("{}: {}", tool, result),
+                })
+                .collect();
+
+            self.history
+                .push(ChatMessage::tool_results(results.join("\n")));
+        }
+        self.advice = self.failures.take_advice();
+        if let Some(answer) = self.answer.take() {
+            self.events.emit(AgentEvent::UserMessage { text: answer.clone() });
+            self.history.push(ChatMessage::user(answer));
+        }
+
+        // step_done ended the nested run's step
+        let step_finished = match (&self.subtask, &self.plan) {
+            (Some(subtask), Some(plan)) => plan.current() != Some(subtask.step),
+            _ => false,
+        };
+        if step_finished {
+            self.finish_subtask(true);
+        }
+
+        self.trim_history();
+    }
+
+    fn trim_history(&mut self) {
+        if self.history.len() > HISTORY_LEN {
+            let excess = self.history.len() - HISTORY_LEN;
+            self.history.drain(0..excess);
+            self.trimmed += excess;
+        }
+    }
+}
+
+fn truncate(text: &str, limit: usize) -> String {
+    match text.char_indices().nth(limit) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+// Tools that only read files, so several can run at once
+fn is_independent_read(tool: &str) -> bool {
+    matches!(tool, "read_file" | "outline")
+}
@@ -0,0 +1,171 @@
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    env,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+use crate::error::LlmError;
+use crate::http;
+
+// Token audience of Azure OpenAI
+const SCOPE: &str = "https://cognitiveservices.azure.com/.default";
+const RESOURCE: &str = "https://cognitiveservices.azure.com";
+const DEFAULT_AUTHORITY: &str = "https://login.microsoftonline.com";
+const IMDS_ENDPOINT: &str = "http://169.254.169.254/metadata/identity/oauth2/token";
+// Tokens are renewed this long before they expire
+const REFRESH_MARGIN: Duration = Duration::from_secs(300);
+
+// Where Entra ID tokens come from, read from the variables azure_identity
+// uses: AZURE_TENANT_ID, AZURE_CLIENT_ID and AZURE_CLIENT_SECRET for a
+// service principal, otherwise the managed identity (AZURE_CLIENT_ID picks
+// a user-assigned one)
+#[derive(Clone, Debug)]
+pub enum TokenSource {
+    ClientSecret {
+        authority: String,
+        tenant: String,
+        client_id: String,
+        secret: String,
+    },
+    // IDENTITY_ENDPOINT and IDENTITY_HEADER on App Service and Functions,
+    // the instance metadata service on VMs and AKS
+    ManagedIdentity {
+        endpoint: Option<(String, String)>,
+        client_id: Option<String>,
+    },
+}
+
+impl TokenSource {
+    pub fn from_env() -> Self {
+        let var = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+        match (var("AZURE_TENANT_ID"), var("AZURE_CLIENT_ID"), var("AZURE_CLIENT_SECRET")) {
+            (Some(tenant), Some(client_id), Some(secret)) => Self::ClientSecret {
+                authority: var("AZURE_AUTHORITY_HOST")
+                    .unwrap_or_else(|| DEFAULT_AUTHORITY.to_string()),
+                tenant,
+                client_id,
+                secret,
+            },
+            (_, client_id, _) => Self::ManagedIdentity {
+                endpoint: var("IDENTITY_ENDPOINT").zip(var("IDENTITY_HEADER")),
+                client_id,
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    // A number from the login endpoint, a string from managed identity
+    expires_in: Value,
+}
+
+// Bearer tokens for Azure OpenAI, fetched on first use and renewed shortly
+// before they expire
+pub struct EntraCredential {
+    source: TokenSource,
+    client: Client,
+    token: Mutex<Option<(String, Instant)>>,
+}
+
+impl EntraCredential {
+    pub fn new(source: TokenSource, client: Client) -> Self {
+        Self {
+            source,
+            client,
+            token: Mutex::new(None),
+        }
+    }
+
+    pub async fn token(&self) -> Result<String, LlmError> {
+        let mut cached = self.token.lock().await;
+        if let Some((token, expires)) = cached.as_ref() {
+            if Instant::now() + REFRESH_MARGIN < *expires {
+                return Ok(token.clone());
+            }
+        }
+        let response = self.fetch().await?;
+        let expires_in = match &response.expires_in {
+            Value::Number(n) => n.as_u64(),
+            Value::String(s) => s.parse().ok(),
+            _ => None,
+        }
+        .unwrap_or(3600);
+        let expires = Instant::now() + Duration::from_secs(expires_in);
+        *cached = Some((response.access_token.clone(), expires));
+        Ok(response.access_token)
+    }
+
+    // After a 401 the next request fetches a new token
+    pub async fn invalidate(&self) {
+        *self.token.lock().await = None;
+    }
+
+    fn endpoint(&self) -> String {
+        match &self.source {
+            TokenSource::ClientSecret { authority, tenant, .. } => {
+                format!("{}/{}/oauth2/v2.0/token", authority.trim_end_matches('/'), tenant)
+            }
+            TokenSource::ManagedIdentity { endpoint: Some((url, _)), .. } => url.clone(),
+            TokenSource::ManagedIdentity { endpoint: None, .. } => IMDS_ENDPOINT.to_string(),
+        }
+    }
+
+    async fn fetch(&self) -> Result<TokenResponse, LlmError> {
+        let request = match &self.source {
+            TokenSource::ClientSecret {
+                client_id, secret, ..
+            } => self
+                .client
+                .post(self.endpoint())
+                .form(&[
+                    ("grant_type", "client_credentials"),
+                    ("client_id", client_id),
+                    ("client_secret", secret),
+                    ("scope", SCOPE),
+                ]),
+            TokenSource::ManagedIdentity { endpoint, client_id } => {
+                let mut query = vec![("resource", RESOURCE)];
+                if let Some(client_id) = client_id {
+                    query.push(("client_id", client_id));
+                }
+                match endpoint {
+                    Some((url, header)) => self
+                        .client
+                        .get(url)
+                        .header("X-IDENTITY-HEADER", header)
+                        .query(&[("api-version", "2019-08-01")])
+                        .query(&query),
+                    None => self
+                        .client
+                        .get(IMDS_ENDPOINT)
+                        .header("Metadata", "true")
+                        .query(&[("api-version", "2018-02-01")])
+                        .query(&query),
+                }
+            }
+        };
+
+        let response = request
+            .timeout(Duration::from_secs(30))
+            .send()
+            .await
+            .map_err(|e| match http::connection_error(&self.endpoint(), e) {
+                LlmError::Connection(message) => LlmError::Auth(message),
+                other => other,
+            })?;
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let body = response.text().await.unwrap_or_default();
+            return Err(LlmError::Auth(format!("{} from the token endpoint: {}", status, body)));
+        }
+        response
+            .json()
+            .await
+            .map_err(|e| LlmError::Auth(format!("unexpected token response: {}", e)))
+    }
+}
@@ -0,0 +1,205 @@
+use std::io;
+use thiserror::Error;
+
+use crate::exit;
+
+#[derive(Debug, Error)]
+pub enum LlmError {
+    #[error("{0} not set")]
+    MissingConfig(String),
+    #[error("{0}")]
+    InvalidConfig(String),
+    #[error("Could not reach {0}")]
+    Connection(String),
+    #[error("Request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("API error ({status}): {body}")]
+    Api { status: u16, body: String },
+    #[error("No response from AI")]
+    EmptyResponse,
+    #[error("Replay failed: {0}")]
+    Replay(String),
+    #[error("Entra ID authentication failed: {0}")]
+    Auth(String),
+}
+
+impl LlmError {
+    // Errors another model might not hit and the same model will hit again:
+    // auth, missing deployment, content filter. Rate limits and timeouts are
+    // worth retrying on the same model.
+    pub fn is_non_retryable(&self) -> bool {
+        match self {
+            LlmError::MissingConfig(_)
+            | LlmError::InvalidConfig(_)
+            | LlmError::Replay(_)
+            | LlmError::Auth(_) => true,
+            LlmError::Api { status, body } => {
+                let body = body.to_lowercase();
+                matches!(status, 401 | 403 | 404)
+                    || ["deploymentnotfound", "model_not_found", "content_filter", "invalid_api_key"]
+                        .iter()
+                        .any(|marker| body.contains(marker))
+            }
+            LlmError::Http(_) | LlmError::Connection(_) | LlmError::EmptyResponse => false,
+        }
+    }
+}
+
+// A tool call that couldn't be carried out. The message goes back to the
+// model so it can correct the call.
+#[derive(Debug, Error)]
+pub enum ToolError {
+    #[error("Unknown tool: {0}")]
+    UnknownTool(String),
+    #[error("Invalid {0} format")]
+    InvalidFormat(&'static str),
+    #[error("Invalid arguments: {0}")]
+    InvalidArgs(String),
+    #[error("Invalid line range \"{0}\", expected START-END")]
+    InvalidRange(String),
+    #[error("Line range {start}-{end} is outside {path} ({lines} lines)")]
+    OutOfRange {
+        path: String,
+        start: usize,
+        end: usize,
+        lines: usize,
+    },
+    #[error("{0} is outside the project root")]
+    OutsideProject(String),
+    #[error("{0} is excluded by .gitignore or .botcoderignore")]
+    Ignored(String),
+    #[error("{0} is read-only in this project ([write] read_only in .botcoder.toml)")]
+    ReadOnly(String),
+    #[error("{0} is turned off: this is a read-only session")]
+    ReadOnlySession(String),
+    #[error("{0} is turned off: this run only writes a patch, edit the files instead")]
+    PatchOnly(String),
+    #[error("{0} is turned off: this run only writes a patch and leaves the project as it is, so no commands run on it")]
+    CommandsOff(String),
+    #[error("Write refused: {0}. Write less, or ask the user to confirm it")]
+    WriteLimit(String),
+    #[error("Refusing to edit a binary or non-UTF-8 file\n{0}")]
+    Binary(String),
+    #[error("Error reading {path}: {source}")]
+    Read {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Error writing {path}: {source}")]
+    Write {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Could not find content in {path} (hunk {hunk} of {hunks}, no changes written)\nSearching for:\n{search}")]
+    NoMatch {
+        path: String,
+        hunk: usize,
+        hunks: usize,
+        search: String,
+    },
+    #[error("Anchor \"{anchor}\" not found in {path}")]
+    AnchorNotFound { path: String, anchor: String },
+    #[error("Anchor \"{anchor}\" matches {matches} lines in {path}; use a more specific anchor")]
+    AmbiguousAnchor {
+        path: String,
+        anchor: String,
+        matches: usize,
+    },
+    #[error("Error executing command: {0}")]
+    Spawn(#[source] io::Error),
+    #[error("Git error: {0}")]
+    Git(#[from] git2::Error),
+    #[error("{0} was changed outside the agent since it was last read; read it again before editing it")]
+    Stale(String),
+    #[error("Request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    // A proxy or CA bundle setting the HTTP client can't use
+    #[error(transparent)]
+    HttpSetup(LlmError),
+    #[error("{0} was not found on crates.io")]
+    UnknownCrate(String),
+    #[error("fetch_url is disabled; set BOTCODER_FETCH_DOMAINS to the domains it may read")]
+    FetchDisabled,
+    #[error("{0} is not on an allowed domain (BOTCODER_FETCH_DOMAINS)")]
+    DomainNotAllowed(String),
+    #[error("No one can answer questions in this run; make a reasonable assumption, say which, and go on")]
+    NoOneToAsk,
+    #[error("Embeddings request failed: {0}")]
+    Embeddings(String),
+    #[error("Hook `{command}` failed, so {tool} did not run:\n{output}")]
+    BeforeHook {
+        tool: String,
+        command: String,
+        output: String,
+    },
+    #[error("{tool} ran, but the hook `{command}` after it failed:\n{output}")]
+    AfterHook {
+        tool: String,
+        command: String,
+        output: String,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum GitHubError {
+    #[error("Nothing to open a pull request for: no files were modified")]
+    NothingChanged,
+    #[error("git {step} failed:\n{output}")]
+    Git { step: &'static str, output: String },
+    #[error("Request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("GitHub API error ({status}): {body}")]
+    Api { status: u16, body: String },
+    #[error(transparent)]
+    Agent(#[from] AgentError),
+}
+
+// Errors that end a run, as opposed to tool errors that are reported to the
+// model
+#[derive(Debug, Error)]
+pub enum AgentError {
+    #[error(transparent)]
+    Llm(#[from] LlmError),
+    #[error(transparent)]
+    Tool(#[from] ToolError),
+    #[error("Could not parse tool call: {0}")]
+    Parse(String),
+    #[error("Could not read a plan from the reply: {0}")]
+    Plan(String),
+    #[error("Blocked by a content filter: {0}")]
+    Blocked(String),
+}
+
+impl AgentError {
+    // Process exit status, one of the stable codes in exit.rs
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            AgentError::Llm(_) => exit::PROVIDER_ERROR,
+            AgentError::Blocked(_) => exit::POLICY_VIOLATION,
+            AgentError::Tool(e) if e.is_policy_violation() => exit::POLICY_VIOLATION,
+            AgentError::Tool(_) => exit::ERROR,
+            AgentError::Parse(_) | AgentError::Plan(_) => exit::GAVE_UP,
+        }
+    }
+}
+
+impl ToolError {
+    // Refused by a project rule rather than failed
+    pub fn is_policy_violation(&self) -> bool {
+        matches!(
+            self,
+            ToolError::OutsideProject(_)
+                | ToolError::Ignored(_)
+                | ToolError::ReadOnly(_)
+                | ToolError::ReadOnlySession(_)
+                | ToolError::PatchOnly(_)
+                | ToolError::CommandsOff(_)
+                | ToolError::WriteLimit(_)
+                | ToolError::FetchDisabled
+                | ToolError::DomainNotAllowed(_)
+                | ToolError::BeforeHook { .. }
+        )
+    }
+}
@@ -0,0 +1,144 @@
+use std::{env, fs, path::Path};
+
+use crate::app;
+use crate::context::ContextBudget;
+use crate::fetch;
+use crate::ignore_rules::IgnoreRules;
+
+const DEFAULT_RADIUS: usize = 20;
+// Errors past these are usually follow-on errors of the first ones
+const MAX_LOCATIONS: usize = 8;
+const MAX_FILES: usize = 4;
+// Never more than this share of the model's context window
+const WINDOW_SHARE: usize = 8;
+
+// Lines shown either side of each error, BOTCODER_ERROR_CONTEXT_LINES
+// (default 20); 0 turns the section off
+pub fn radius() -> usize {
+    env::var("BOTCODER_ERROR_CONTEXT_LINES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_RADIUS)
+}
+
+// Tool calls that build the project, whose success means earlier errors
+// are gone
+pub fn is_build(tool: &str, param: &str) -> bool {
+    match tool {
+        "run_tests" | "cargo_clippy" => true,
+        "execute_command" => param.trim_start().starts_with("cargo "),
+        _ => false,
+    }
+}
+
+// Where cargo reported errors, as (path, line) in the order reported.
+// Reads both the default format ("error[E0425]: ..." then "  --> src/a.rs:3:5")
+// and --message-format short ("src/a.rs:3:5: error[E0425]: ..."); warnings
+// are skipped.
+pub fn error_locations(output: &str) -> Vec<(String, usize)> {
+    let mut locations = Vec::new();
+    let mut in_error = false;
+    for line in output.lines() {
+        let trimmed = line.trim_start();
+        if line.starts_with("error") {
+            in_error = !line.starts_with("error: could not compile")
+                && !line.starts_with("error: aborting");
+            continue;
+        }
+        if line.starts_with("warning") {
+            in_error = false;
+            continue;
+        }
+        let location = match trimmed.strip_prefix("--> ") {
+            Some(location) if in_error => Some(location),
+            Some(_) => None,
+            None => line
+                .split_once(": error")
+                .map(|(location, _)| location)
+                .filter(|location| !location.contains(' ')),
+        };
+        if let Some(location) = location.and_then(parse_location) {
+            if !locations.contains(&location) {
+                locations.push(location);
+            }
+        }
+    }
+    locations
+}
+
+// "src/a.rs:3:5" as ("src/a.rs", 3)
+fn parse_location(text: &str) -> Option<(String, usize)> {
+    let mut parts = text.trim().rsplitn(3, ':');
+    let _column: usize = parts.next()?.parse().ok()?;
+    let line = parts.next()?.parse().ok()?;
+    let path = parts.next()?;
+    (line > 0 && !path.is_empty()).then(|| (path.replace('\\', "/"), line))
+}
+
+// The code around each error as the files are now, merged where regions
+// overlap, cut to an eighth of the context window. Files outside the
+// project, like dependencies in the cargo registry, and ignored files are
+// left out. Empty when there is nothing to show.
+pub fn prompt_section(
+    root: &str,
+    locations: &[(String, usize)],
+    radius: usize,
+    budget: &ContextBudget,
+) -> String {
+    if radius == 0 {
+        return String::new();
+    }
+    let rules = IgnoreRules::load(root);
+    let mut files: Vec<(&str, Vec<(usize, usize)>)> = Vec::new();
+    for (path, line) in locations.iter().take(MAX_LOCATIONS) {
+        if Path::new(path).is_absolute() || path.contains("..") || rules.is_ignored(path, false) {
+            continue;
+        }
+        let region = (line.saturating_sub(radius).max(1), line + radius);
+        match files.iter().position(|(p, _)| p == path) {
+            Some(i) => files[i].1.push(region),
+            None if files.len() < MAX_FILES => files.push((path, vec![region])),
+            None => {}
+        }
+    }
+
+    let mut sections = Vec::new();
+    for (path, mut regions) in files {
+        let Ok(content) = fs::read_to_string(Path::new(root).join(path)) else {
+            continue;
+        };
+        let lines: Vec<&str> = content.lines().collect();
+        regions.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::new();
+        for (start, end) in regions {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        for (start, end) in merged {
+            let end = end.min(lines.len());
+            if start > end {
+                continue;
+            }
+            sections.push(format!(
+                "== {}:{}-{} ==\n{}",
+                path,
+                start,
+                end,
+                app::number_lines(&lines[start - 1..end], start, lines.len())
+            ));
+        }
+    }
+    if sections.is_empty() {
+        return String::new();
+    }
+
+    format!(
+        "SOURCE AROUND THE COMPILER ERRORS\nThe code at each error the last build reported, as the files are now; no need to read these lines again.\n\n{}",
+        fetch::truncate_tokens(
+            sections.join("\n").trim_end(),
+            budget.available() as usize / WINDOW_SHARE
+        )
+    )
+}
@@ -0,0 +1,189 @@
+use serde::Serialize;
+use std::sync::{
+    mpsc::{self, Receiver, Sender},
+    Arc, Mutex,
+};
+
+use crate::app::ToolStatus;
+use crate::plan::Plan;
+use crate::router::Role;
+
+// Everything that happens during a run. The engine only emits these;
+// printing, drawing and logging are done by observers.
+#[derive(Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AgentEvent {
+    IterationStarted {
+        iteration: u32,
+    },
+    UserMessage {
+        text: String,
+    },
+    LlmRequest {
+        role: Role,
+        model: String,
+        input_tokens: u32,
+    },
+    LlmResponse {
+        role: Role,
+        model: String,
+        text: String,
+        // The model's reasoning, split off the reply and not kept in history
+        reasoning: Option<String>,
+        // The server's system_fingerprint, where it sends one
+        fingerprint: Option<String>,
+        // One note per fallback model taken
+        fallbacks: Vec<String>,
+        output_tokens: u32,
+    },
+    ToolExecuted {
+        tool: String,
+        param: String,
+        result: String,
+        // False when the tool call failed; a command that ran and exited
+        // non-zero still counts as ok
+        ok: bool,
+        // Exit status, timing and output sizes of a call that ran
+        #[serde(skip_serializing_if = "Option::is_none")]
+        status: Option<ToolStatus>,
+    },
+    FileModified {
+        path: String,
+    },
+    // switch_project made another workspace project the active one
+    ProjectSwitched {
+        name: String,
+        root: String,
+    },
+    // A plan was approved or step_done moved it on
+    PlanUpdated {
+        plan: Plan,
+    },
+    // --subtasks: a nested run started for plan step `step` (from 1), or
+    // ended and was rolled up into the main session
+    SubtaskStarted {
+        step: usize,
+        text: String,
+    },
+    SubtaskFinished {
+        step: usize,
+        completed: bool,
+        summary: String,
+        iterations: u32,
+        tokens: u32,
+    },
+    Success,
+    Error {
+        message: String,
+    },
+}
+
+pub trait AgentObserver: Send {
+    fn on_event(&mut self, event: &AgentEvent);
+}
+
+impl<F: FnMut(&AgentEvent) + Send> AgentObserver for F {
+    fn on_event(&mut self, event: &AgentEvent) {
+        self(event)
+    }
+}
+
+#[derive(Default)]
+pub struct EventBus {
+    observers: Vec<Box<dyn AgentObserver>>,
+}
+
+impl EventBus {
+    pub fn subscribe(&mut self, observer: impl AgentObserver + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    pub fn emit(&mut self, event: AgentEvent) {
+        for observer in &mut self.observers {
+            observer.on_event(&event);
+        }
+    }
+}
+
+// Forwards events to another thread or loop, e.g. the TUI's event loop
+pub struct ChannelObserver(Sender<AgentEvent>);
+
+impl AgentObserver for ChannelObserver {
+    fn on_event(&mut self, event: &AgentEvent) {
+        let _ = self.0.send(event.clone());
+    }
+}
+
+pub fn channel() -> (ChannelObserver, Receiver<AgentEvent>) {
+    let (tx, rx) = mpsc::channel();
+    (ChannelObserver(tx), rx)
+}
+
+// One JSON object per line on stdout
+pub struct JsonObserver;
+
+impl AgentObserver for JsonObserver {
+    fn on_event(&mut self, event: &AgentEvent) {
+        if let Ok(line) = serde_json::to_string(event) {
+            println!("{}", line);
+        }
+    }
+}
+
+// Full transcript of the session, unlike the engine's history which is
+// trimmed to the last few turns
+#[derive(Clone, Default)]
+pub struct SessionRecorder {
+    transcript: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl SessionRecorder {
+    // (role, content) pairs in order
+    pub fn transcript(&self) -> Vec<(String, String)> {
+        self.transcript.lock().map(|t| t.clone()).unwrap_or_default()
+    }
+}
+
+impl AgentObserver for SessionRecorder {
+    fn on_event(&mut self, event: &AgentEvent) {
+        // A "model" entry each time the backend's fingerprint changes, so
+        // deterministic runs against different backends can be told apart
+        if let AgentEvent::LlmResponse {
+            model,
+            fingerprint: Some(fingerprint),
+            ..
+        } = event
+        {
+            let entry = format!("{} (fingerprint {})", model, fingerprint);
+            if let Ok(mut transcript) = self.transcript.lock() {
+                let last = transcript.iter().rev().find(|(role, _)| role == "model");
+                if last.map(|(_, content)| content) != Some(&entry) {
+                    transcript.push(("model".to_string(), entry));
+                }
+            }
+        }
+        let entry = match event {
+            AgentEvent::UserMessage { text } => ("user", text.clone()),
+            AgentEvent::LlmResponse { text, .. } => ("assistant", text.clone()),
+            AgentEvent::ToolExecuted {
+                tool,
+                param,
+                result,
+                ..
+            } => ("tool", format!("{}: {}\n{}", tool, param, result)),
+            _ => return,
+        };
+        if let Ok(mut transcript) = self.transcript.lock() {
+            transcript.push((entry.0.to_string(), entry.1));
+        }
+    }
+}
+
+// "**role**: content" paragraphs, for saving a transcript as Markdown
+pub fn transcript_markdown(transcript: &[(String, String)]) -> String {
+    transcript
+        .iter()
+        .map(|(role, content)| format!("**{}**: {}", role, content))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
@@ -0,0 +1,18 @@
+// Exit statuses of the headless modes (fix, run, ask), kept stable so CI
+// pipelines can gate on them
+pub const SUCCESS: i32 = 0;
+// Anything else, like a check command that could not start
+pub const ERROR: i32 = 1;
+// Stopped short of success: interrupted, or the model's replies could not
+// be used
+pub const GAVE_UP: i32 = 2;
+// The iteration limit was reached first
+pub const BUDGET_EXCEEDED: i32 = 3;
+// A project rule refused to go on: a content filter, a before hook, a path
+// outside the project or ignored, or uncommitted changes with
+// BOTCODER_DIRTY=refuse
+pub const POLICY_VIOLATION: i32 = 4;
+// The model provider failed or isn't configured
+pub const PROVIDER_ERROR: i32 = 5;
+// Another instance holds the project's session lock
+pub const LOCKED: i32 = 7;
@@ -0,0 +1,198 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+
+use crate::app::{self, ToolOutput};
+use crate::commands;
+use crate::error::ToolError;
+use crate::error_context;
+
+// Edits that failed to match this many times in a row on one file switch
+// the model to line-based edits
+const EDIT_SWITCH_AFTER: u32 = 2;
+// Builds or test runs failing this many times in a row get a step back
+const STUCK_AFTER: u32 = 3;
+
+// Why a tool call failed, as far as the follow-up advice is concerned
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    // A CHANGE block, anchor or line range that didn't fit the file
+    DeltaMismatch,
+    CompileError,
+    TestFailure,
+    CommandNotFound,
+    PermissionDenied,
+    Other,
+}
+
+// The kind of failure a tool result is, or None when it succeeded
+pub fn classify(
+    tool: &str,
+    param: &str,
+    output: &Result<ToolOutput, ToolError>,
+) -> Option<FailureKind> {
+    let output = match output {
+        Ok(output) => output,
+        Err(e) => return Some(classify_error(e)),
+    };
+    let code = output.exit_code?;
+    let streams = output.raw();
+    if error_context::is_build(tool, param) && !error_context::error_locations(&streams).is_empty()
+        || streams.contains("error: could not compile")
+    {
+        return Some(FailureKind::CompileError);
+    }
+    if code == 0 {
+        return None;
+    }
+    let lower = streams.to_lowercase();
+    Some(if code == 127 || lower.contains("command not found") {
+        FailureKind::CommandNotFound
+    } else if code == 126 || lower.contains("permission denied") {
+        FailureKind::PermissionDenied
+    } else if tool == "run_tests" || streams.contains("test result: FAILED") {
+        FailureKind::TestFailure
+    } else {
+        FailureKind::Other
+    })
+}
+
+fn classify_error(error: &ToolError) -> FailureKind {
+    match error {
+        ToolError::NoMatch { .. }
+        | ToolError::AnchorNotFound { .. }
+        | ToolError::AmbiguousAnchor { .. }
+        | ToolError::OutOfRange { .. }
+        | ToolError::Stale(_) => FailureKind::DeltaMismatch,
+        ToolError::ReadOnly(_)
+        | ToolError::ReadOnlySession(_)
+        | ToolError::PatchOnly(_)
+        | ToolError::CommandsOff(_)
+        | ToolError::OutsideProject(_)
+        | ToolError::Ignored(_) => FailureKind::PermissionDenied,
+        ToolError::Read { source, .. } | ToolError::Write { source, .. }
+            if source.kind() == io::ErrorKind::PermissionDenied =>
+        {
+            FailureKind::PermissionDenied
+        }
+        ToolError::Spawn(e) if e.kind() == io::ErrorKind::NotFound => FailureKind::CommandNotFound,
+        ToolError::Spawn(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            FailureKind::PermissionDenied
+        }
+        _ => FailureKind::Other,
+    }
+}
+
+// What a failure is about: the file for edits and reads, the program for
+// commands, nothing for builds and tests
+fn subject(kind: FailureKind, tool: &str, param: &str) -> String {
+    match (kind, app::tool_path(tool, param)) {
+        (FailureKind::CompileError | FailureKind::TestFailure, _) => String::new(),
+        (_, Some(path)) => path.to_string(),
+        _ if tool == "execute_command" => {
+            let (command, _) = commands::decode(param);
+            command.split_whitespace().next().unwrap_or("").to_string()
+        }
+        _ => tool.to_string(),
+    }
+}
+
+// Failures seen in a row, by kind and subject, turned into advice for the
+// next request. Every failure used to go back to the model as it was, so
+// it kept retrying the same CHANGE block or missing program.
+#[derive(Default)]
+pub struct FailureTracker {
+    streaks: HashMap<(FailureKind, String), u32>,
+    // This batch's failures, in order
+    latest: Vec<(FailureKind, String)>,
+}
+
+impl FailureTracker {
+    pub fn record(&mut self, tool: &str, param: &str, output: &Result<ToolOutput, ToolError>) {
+        match classify(tool, param, output) {
+            Some(FailureKind::Other) => {}
+            Some(kind) => {
+                let key = (kind, subject(kind, tool, param));
+                *self.streaks.entry(key.clone()).or_default() += 1;
+                if !self.latest.contains(&key) {
+                    self.latest.push(key);
+                }
+            }
+            // An edit that applies ends its file's streak; a passing build
+            // or test run ends the build and test streaks
+            None => {
+                if let Some(path) = app::tool_path(tool, param).filter(|_| app::is_edit_tool(tool)) {
+                    self.streaks.remove(&(FailureKind::DeltaMismatch, path.to_string()));
+                }
+                if error_context::is_build(tool, param) {
+                    self.streaks.remove(&(FailureKind::CompileError, String::new()));
+                    if tool == "run_tests" || param.contains("test") {
+                        self.streaks.remove(&(FailureKind::TestFailure, String::new()));
+                    }
+                }
+            }
+        }
+    }
+
+    // Advice for the failures of the batch just finished, for the next
+    // system prompt; empty when it had none worth advice. Starts the next
+    // batch.
+    pub fn take_advice(&mut self) -> String {
+        let advice: Vec<String> = self
+            .latest
+            .drain(..)
+            .map(|(kind, subject)| {
+                let count = self.streaks.get(&(kind, subject.clone())).copied().unwrap_or(1);
+                advice(kind, &subject, count)
+            })
+            .collect();
+        if advice.is_empty() {
+            return String::new();
+        }
+        format!(
+            "AFTER THE LAST FAILURES\n{}",
+            advice
+                .iter()
+                .map(|line| format!("- {}", line))
+                .collect::<Vec<_>>()
+                .join("\n")
+        )
+    }
+}
+
+fn advice(kind: FailureKind, subject: &str, count: u32) -> String {
+    match kind {
+        FailureKind::DeltaMismatch if count >= EDIT_SWITCH_AFTER => format!(
+            "Edits to {} failed to match {} times in a row. Stop retrying CHANGE blocks: read it again with read_file(\"{}\", numbered) and replace the lines by number with EDIT_LINES.",
+            subject, count, subject
+        ),
+        FailureKind::DeltaMismatch => format!(
+            "An edit to {} didn't match. The CURRENT text must be copied exactly, whitespace included, from the latest content of the file; read it again if unsure.",
+            subject
+        ),
+        FailureKind::CompileError if count >= STUCK_AFTER => format!(
+            "The build has failed {} times in a row. Step back: re-read the code around the first error and consider undoing the last change instead of adding more fixes on top.",
+            count
+        ),
+        FailureKind::CompileError => {
+            "The build fails. Fix the first error first; later ones often follow from it.".to_string()
+        }
+        FailureKind::TestFailure if count >= STUCK_AFTER => format!(
+            "Tests have failed {} times in a row. Run only the failing test with run_tests(\"name\") and read both it and the code it covers before changing anything else.",
+            count
+        ),
+        FailureKind::TestFailure => {
+            "Tests fail. Read the failing test and the code it exercises before changing either; don't change what a test expects unless the task asks for it.".to_string()
+        }
+        FailureKind::CommandNotFound => format!(
+            "`{}` isn't installed here. Don't run it again; use another tool or a command that exists.",
+            subject
+        ),
+        FailureKind::PermissionDenied => format!(
+            "Permission was denied for {}. Don't retry it; leave it alone and tell the user if the task needs it.",
+            subject
+        ),
+        FailureKind::Other => String::new(),
+    }
+}
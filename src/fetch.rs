@@ -0,0 +1,314 @@
+use reqwest::{redirect, Url};
+use std::{env, io::Read, thread, time::Duration};
+
+use crate::app::{self, ToolOutput};
+use crate::error::ToolError;
+use crate::http;
+
+const DEFAULT_TOKENS: usize = 4_000;
+// Pages larger than this are cut before conversion
+const MAX_DOWNLOAD: usize = 2 * 1024 * 1024;
+
+// Elements whose content is never worth reading
+const SKIPPED: [&str; 9] = [
+    "head", "script", "style", "noscript", "svg", "nav", "footer", "template", "iframe",
+];
+const BLOCKS: [&str; 13] = [
+    "p", "div", "section", "article", "main", "header", "table", "ul", "ol", "dl", "blockquote",
+    "figure", "details",
+];
+
+// fetch_url is off unless BOTCODER_FETCH_DOMAINS lists the domains it may
+// read, comma-separated; subdomains of a listed domain are allowed too.
+// BOTCODER_FETCH_TOKENS caps the text returned (default 4000 tokens).
+#[derive(Clone)]
+pub struct FetchConfig {
+    domains: Vec<String>,
+    tokens: usize,
+}
+
+impl FetchConfig {
+    pub fn from_env() -> Option<Self> {
+        let domains: Vec<String> = env::var("BOTCODER_FETCH_DOMAINS")
+            .ok()?
+            .split(',')
+            .map(|d| d.trim().trim_start_matches("*.").to_lowercase())
+            .filter(|d| !d.is_empty())
+            .collect();
+        if domains.is_empty() {
+            return None;
+        }
+        let tokens = env::var("BOTCODER_FETCH_TOKENS")
+            .ok()
+            .and_then(|t| t.parse().ok())
+            .unwrap_or(DEFAULT_TOKENS);
+        Some(Self { domains, tokens })
+    }
+
+    pub fn allows(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_lowercase();
+        matches!(url.scheme(), "http" | "https")
+            && self
+                .domains
+                .iter()
+                .any(|d| host == *d || host.ends_with(&format!(".{}", d)))
+    }
+}
+
+// fetch_url: the page as readable text, with headings, lists and code
+// blocks kept in Markdown form
+pub fn fetch_url(param: &str) -> Result<ToolOutput, ToolError> {
+    let config = FetchConfig::from_env().ok_or(ToolError::FetchDisabled)?;
+    let url = Url::parse(param.trim())
+        .map_err(|e| ToolError::InvalidArgs(format!("{}: {}", param.trim(), e)))?;
+    if !config.allows(&url) {
+        return Err(ToolError::DomainNotAllowed(url.to_string()));
+    }
+
+    // The blocking client runs its own runtime, which can't live on one of
+    // tokio's threads
+    let tokens = config.tokens;
+    let (final_url, content_type, body) = thread::spawn(move || download(config, url))
+        .join()
+        .map_err(|_| ToolError::InvalidArgs("fetch_url panicked".to_string()))??;
+
+    let text = if content_type.contains("html") {
+        html_to_text(&body)
+    } else {
+        body
+    };
+    Ok(ToolOutput::text(format!(
+        "{}\n\n{}",
+        final_url,
+        truncate_tokens(text.trim(), tokens)
+    )))
+}
+
+fn download(config: FetchConfig, url: Url) -> Result<(Url, String, String), ToolError> {
+    let redirects = config.clone();
+    let client = http::blocking_builder()
+        .map_err(ToolError::HttpSetup)?
+        .timeout(Duration::from_secs(20))
+        .user_agent(concat!("botcoder/", env!("CARGO_PKG_VERSION")))
+        // Redirects must stay on allowed domains as well
+        .redirect(redirect::Policy::custom(move |attempt| {
+            if redirects.allows(attempt.url()) && attempt.previous().len() < 5 {
+                attempt.follow()
+            } else {
+                attempt.stop()
+            }
+        }))
+        .build()?;
+
+    let response = client.get(url).send()?.error_for_status()?;
+    let final_url = response.url().clone();
+    if !config.allows(&final_url) {
+        return Err(ToolError::DomainNotAllowed(final_url.to_string()));
+    }
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("text/html")
+        .to_lowercase();
+    // Read up to the cap and no further, however large the page
+    let mut bytes = Vec::new();
+    response
+        .take(MAX_DOWNLOAD as u64)
+        .read_to_end(&mut bytes)
+        .map_err(|source| ToolError::Read {
+            path: final_url.to_string(),
+            source,
+        })?;
+    let body = String::from_utf8_lossy(&bytes).into_owned();
+    Ok((final_url, content_type, body))
+}
+
+// A small HTML to Markdown-ish conversion: drops scripts, styles and page
+// chrome, turns headings into "#" lines, list items into "- " lines and
+// <pre> into fenced blocks, and collapses the remaining whitespace
+pub fn html_to_text(html: &str) -> String {
+    let mut out = String::new();
+    if let Some(title) = element_text(html, "title") {
+        out.push_str(&format!("# {}\n\n", title));
+    }
+
+    let mut rest = html;
+    let mut skipping: Option<String> = None;
+    let mut pre = 0usize;
+    while let Some(start) = rest.find('<') {
+        if skipping.is_none() {
+            push_text(&mut out, &rest[..start], pre > 0);
+        }
+        let tag_start = &rest[start..];
+        if let Some(comment) = tag_start.strip_prefix("<!--") {
+            rest = comment.find("-->").map(|end| &comment[end + 3..]).unwrap_or("");
+            continue;
+        }
+        let Some(end) = tag_start.find('>') else {
+            rest = "";
+            break;
+        };
+        let tag = &tag_start[1..end];
+        rest = &tag_start[end + 1..];
+
+        let closing = tag.starts_with('/');
+        let self_closing = tag.ends_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+
+        if let Some(skipped) = &skipping {
+            if closing && name == *skipped {
+                skipping = None;
+            }
+            continue;
+        }
+        match name.as_str() {
+            name if SKIPPED.contains(&name) && !closing && !self_closing => {
+                skipping = Some(name.to_string());
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                out.push_str("\n\n");
+                if !closing {
+                    let level = name[1..].parse().unwrap_or(1);
+                    out.push_str(&format!("{} ", "#".repeat(level)));
+                }
+            }
+            "pre" if !closing => {
+                pre += 1;
+                out.push_str("\n\n```\n");
+            }
+            "pre" => {
+                pre = pre.saturating_sub(1);
+                out.push_str("\n```\n\n");
+            }
+            "code" if pre == 0 => out.push('`'),
+            "li" if !closing => out.push_str("\n- "),
+            "br" | "tr" | "dt" => out.push('\n'),
+            "td" | "th" | "dd" if !closing => out.push(' '),
+            name if BLOCKS.contains(&name) => out.push_str("\n\n"),
+            _ => {}
+        }
+    }
+    if skipping.is_none() {
+        push_text(&mut out, rest, pre > 0);
+    }
+
+    // Trailing spaces and runs of blank lines left over from the markup
+    let mut text = String::new();
+    let mut blank = 0;
+    for line in out.lines().map(str::trim_end) {
+        if line.trim().is_empty() {
+            blank += 1;
+            if blank > 1 {
+                continue;
+            }
+        } else {
+            blank = 0;
+        }
+        text.push_str(line);
+        text.push('\n');
+    }
+    text.trim().to_string()
+}
+
+fn push_text(out: &mut String, text: &str, preformatted: bool) {
+    let text = decode_entities(text);
+    if preformatted {
+        out.push_str(&text);
+        return;
+    }
+    let words = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let at_break = out.is_empty() || out.ends_with([' ', '\n']);
+    if text.starts_with(char::is_whitespace) && !at_break {
+        out.push(' ');
+    }
+    out.push_str(&words);
+    if text.ends_with(char::is_whitespace) && !words.is_empty() {
+        out.push(' ');
+    }
+}
+
+fn element_text(html: &str, name: &str) -> Option<String> {
+    let start = html.find(&format!("<{}", name))?;
+    let open_end = start + html[start..].find('>')? + 1;
+    let close = open_end + html[open_end..].find(&format!("</{}>", name))?;
+    let text = decode_entities(&html[open_end..close]);
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!text.is_empty()).then_some(text)
+}
+
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        let entity = &rest[start..];
+        let decoded = entity.find(';').filter(|&end| end <= 10).and_then(|end| {
+            let c = match &entity[1..end] {
+                "amp" => '&',
+                "lt" => '<',
+                "gt" => '>',
+                "quot" => '"',
+                "apos" | "#39" => '\'',
+                "nbsp" => ' ',
+                code => {
+                    let number = code.strip_prefix('#')?;
+                    let value = match number.strip_prefix(['x', 'X']) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => number.parse().ok()?,
+                    };
+                    char::from_u32(value)?
+                }
+            };
+            Some((c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &entity[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &entity[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+// Cuts the text at a line boundary once it passes the token budget
+pub fn truncate_tokens(text: &str, budget: usize) -> String {
+    if app::count_tokens(text) as usize <= budget {
+        return text.to_string();
+    }
+    let mut out = String::new();
+    for line in text.lines() {
+        if app::count_tokens(&out) as usize + app::count_tokens(line) as usize > budget {
+            break;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    if out.is_empty() {
+        // One long line, e.g. minified text
+        out = text.chars().take(budget * 4).collect();
+    }
+    format!(
+        "{}\n[truncated to about {} tokens of {}]",
+        out.trim_end(),
+        budget,
+        app::count_tokens(text)
+    )
+}
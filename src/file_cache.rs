@@ -0,0 +1,116 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs::{self, Metadata},
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::SystemTime,
+};
+
+use crate::binary::{self, FileContent};
+use crate::vfs;
+
+// Past this much cached text the cache starts over
+const MAX_BYTES: usize = 64 * 1024 * 1024;
+
+// Text files read this session, by canonical path. An entry is used while
+// the file keeps the size and modification time it was read at; edits made
+// through the tools drop it right away.
+static CACHE: OnceLock<Mutex<HashMap<PathBuf, Entry>>> = OnceLock::new();
+
+struct Entry {
+    len: u64,
+    modified: Option<SystemTime>,
+    // Of the bytes on disk, not the decoded text, so read() and hash() agree
+    // on UTF-16 and other re-encoded files
+    hash: u64,
+    text: String,
+}
+
+impl Entry {
+    fn is_fresh(&self, metadata: &Metadata) -> bool {
+        self.len == metadata.len() && self.modified == metadata.modified().ok()
+    }
+}
+
+// Like binary::read, but answered from the cache when the file is unchanged
+pub fn read(path: &Path) -> io::Result<FileContent> {
+    if !vfs::current().on_disk(path) {
+        return binary::read(path);
+    }
+    let metadata = fs::metadata(path)?;
+    let key = key(path);
+    if let Some(entry) = cache().get(&key).filter(|e| e.is_fresh(&metadata)) {
+        return Ok(FileContent::Text(entry.text.clone()));
+    }
+
+    let bytes = fs::read(path)?;
+    let hash = hash_bytes(&bytes);
+    let content = binary::decode(path, bytes);
+    if let FileContent::Text(text) = &content {
+        store(key, &metadata, hash, text.clone());
+    }
+    Ok(content)
+}
+
+// Hash of the file's bytes, None when it can't be read
+pub fn hash(path: &Path) -> Option<u64> {
+    let fs = vfs::current();
+    if !fs.on_disk(path) {
+        return fs.read(path).ok().map(|bytes| hash_bytes(&bytes));
+    }
+    let metadata = fs::metadata(path).ok()?;
+    let key = key(path);
+    if let Some(entry) = cache().get(&key).filter(|e| e.is_fresh(&metadata)) {
+        return Some(entry.hash);
+    }
+
+    let bytes = fs::read(path).ok()?;
+    let hash = hash_bytes(&bytes);
+    if let FileContent::Text(text) = binary::decode(path, bytes) {
+        store(key, &metadata, hash, text);
+    }
+    Some(hash)
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Called after every write made through the tools
+pub fn invalidate(path: &Path) {
+    cache().remove(&key(path));
+}
+
+fn store(key: PathBuf, metadata: &Metadata, hash: u64, text: String) {
+    let mut cache = cache();
+    let cached: usize = cache.values().map(|e| e.text.len()).sum();
+    if cached + text.len() > MAX_BYTES {
+        cache.clear();
+    }
+    cache.insert(
+        key,
+        Entry {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            hash,
+            text,
+        },
+    );
+}
+
+// The same file is reached through different spellings of its path, e.g.
+// "./src/lib.rs" and "src/lib.rs"
+fn key(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn cache() -> std::sync::MutexGuard<'static, HashMap<PathBuf, Entry>> {
+    CACHE
+        .get_or_init(Mutex::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,110 @@
+use std::{fs, path::Path};
+
+use crate::ignore_rules::IgnoreRules;
+
+const MAX_ENTRIES: usize = 2000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FileMark {
+    Read,
+    Modified,
+}
+
+impl FileMark {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            FileMark::Read => "R",
+            FileMark::Modified => "M",
+        }
+    }
+}
+
+pub struct TreeEntry {
+    // Path relative to the project root, using '/' separators
+    pub path: String,
+    pub name: String,
+    pub depth: usize,
+    pub is_dir: bool,
+}
+
+pub fn scan(root: &str) -> Vec<TreeEntry> {
+    let mut entries = Vec::new();
+    let rules = IgnoreRules::load(root);
+    walk(Path::new(root), "", 0, &rules, &mut entries);
+    entries
+}
+
+fn walk(
+    dir: &Path,
+    prefix: &str,
+    depth: usize,
+    rules: &IgnoreRules,
+    entries: &mut Vec<TreeEntry>,
+) {
+    let Ok(read_dir) = fs::read_dir(dir) else {
+        return;
+    };
+
+    let mut children: Vec<(String, bool)> = read_dir
+        .filter_map(|e| e.ok())
+        .map(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            let is_dir = e.file_type().map(|t| t.is_dir()).unwrap_or(false);
+            (name, is_dir)
+        })
+        .filter(|(name, is_dir)| !(*is_dir && name.starts_with('.')))
+        .collect();
+
+    // Directories first, then files, each alphabetically
+    children.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+    for (name, is_dir) in children {
+        if entries.len() >= MAX_ENTRIES {
+            return;
+        }
+
+        let path = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{}/{}", prefix, name)
+        };
+        if rules.is_ignored(&path, is_dir) {
+            continue;
+        }
+
+        entries.push(TreeEntry {
+            path: path.clone(),
+            name: name.clone(),
+            depth,
+            is_dir,
+        });
+
+        if is_dir {
+            walk(&dir.join(&name), &path, depth + 1, rules, entries);
+        }
+    }
+}
+
+// Normalizes a tool path argument ("./src/main.rs") to a tree path
+pub fn normalize(path: &str) -> String {
+    path.trim().trim_start_matches("./").replace('\\', "/")
+}
+
+// normalize() with "." and ".." resolved without touching the disk, so
+// "src/../migrations/1.sql" is matched as "migrations/1.sql"; a ".." that
+// climbs out of the path is kept for workspace::refuse_outside to reject
+pub fn clean(path: &str) -> String {
+    let path = normalize(path);
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." if !parts.is_empty() => {}
+            "." => {}
+            ".." if parts.last().is_some_and(|last| !matches!(*last, ".." | "")) => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
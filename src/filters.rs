@@ -0,0 +1,166 @@
+use regex::Regex;
+use serde::Deserialize;
+use std::{io::Write, process::Stdio};
+
+use crate::app;
+use crate::config::{self, CONFIG_FILE};
+use crate::llm::ChatMessage;
+
+// Filters over what is sent to the model ([[filters.prompt]]) and what
+// comes back ([[filters.response]]), run in order before a reply reaches the
+// parser. A rule is a regex whose matches are replaced, or that blocks the
+// text when action = "block", or a script that gets the text on stdin and
+// prints the filtered text; a script exiting non-zero blocks it.
+//
+//   [[filters.response]]
+//   pattern = "rm\\s+-rf\\s+/"
+//   action = "block"
+//   message = "no recursive deletes from the root"
+#[derive(Default)]
+pub struct Filters {
+    prompt: Vec<Filter>,
+    response: Vec<Filter>,
+}
+
+enum Filter {
+    Regex {
+        regex: Regex,
+        replace: String,
+        block: Option<String>,
+    },
+    Script(String),
+}
+
+#[derive(Default, Deserialize)]
+struct Rules {
+    #[serde(default)]
+    prompt: Vec<Rule>,
+    #[serde(default)]
+    response: Vec<Rule>,
+}
+
+#[derive(Deserialize)]
+struct Rule {
+    pattern: Option<String>,
+    #[serde(default)]
+    replace: String,
+    #[serde(default)]
+    action: Action,
+    message: Option<String>,
+    script: Option<String>,
+}
+
+#[derive(Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    #[default]
+    Replace,
+    Block,
+}
+
+impl Filters {
+    // No filters without a .botcoder.toml; an invalid file or rule is
+    // skipped with a warning
+    pub fn load(root: &str) -> Self {
+        let rules: Rules = config::section(root, "filters");
+        Self {
+            prompt: compile(rules.prompt),
+            response: compile(rules.response),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.prompt.is_empty() && self.response.is_empty()
+    }
+
+    // Filters the content of every outgoing message; Err with the reason
+    // when a rule blocks one
+    pub fn apply_prompt(&self, messages: &mut [ChatMessage], root: &str) -> Result<(), String> {
+        if self.prompt.is_empty() {
+            return Ok(());
+        }
+        for message in messages {
+            message.content = apply(&self.prompt, &message.content, "prompt", root)?;
+        }
+        Ok(())
+    }
+
+    pub fn apply_response(&self, text: &str, root: &str) -> Result<String, String> {
+        apply(&self.response, text, "response", root)
+    }
+}
+
+fn compile(rules: Vec<Rule>) -> Vec<Filter> {
+    rules
+        .into_iter()
+        .filter_map(|rule| match (rule.pattern, rule.script) {
+            (Some(pattern), None) => match Regex::new(&pattern) {
+                Ok(regex) => Some(Filter::Regex {
+                    block: (rule.action == Action::Block).then(|| {
+                        rule.message
+                            .unwrap_or_else(|| format!("matches the filter /{}/", pattern))
+                    }),
+                    regex,
+                    replace: rule.replace,
+                }),
+                Err(e) => {
+                    log::warn!("Ignoring filter /{}/ in {}: {}", pattern, CONFIG_FILE, e);
+                    None
+                }
+            },
+            (None, Some(script)) => Some(Filter::Script(script)),
+            _ => {
+                log::warn!("Ignoring a filter in {} without exactly one of pattern or script", CONFIG_FILE);
+                None
+            }
+        })
+        .collect()
+}
+
+fn apply(filters: &[Filter], text: &str, stage: &str, root: &str) -> Result<String, String> {
+    let mut text = text.to_string();
+    for filter in filters {
+        text = match filter {
+            Filter::Regex {
+                regex,
+                block: Some(message),
+                ..
+            } if regex.is_match(&text) => return Err(message.clone()),
+            Filter::Regex { regex, replace, .. } => regex.replace_all(&text, replace.as_str()).into_owned(),
+            Filter::Script(script) => run_script(script, &text, stage, root)?,
+        };
+    }
+    Ok(text)
+}
+
+// BOTCODER_FILTER_STAGE tells the script whether it sees a prompt or a
+// response
+fn run_script(script: &str, text: &str, stage: &str, root: &str) -> Result<String, String> {
+    let mut command = app::shell_command(script);
+    command
+        .current_dir(root)
+        .env("BOTCODER_FILTER_STAGE", stage)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("filter {} could not run: {}", script, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A script that exits without reading everything is fine
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("filter {} failed: {}", script, e))?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        let reason = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        Err(if reason.is_empty() {
+            format!("blocked by {}", script)
+        } else {
+            reason
+        })
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::HashSet;
+
+use crate::app::{self, OutputKind, ToolOutput};
+use crate::dry_run;
+use crate::engine::AgentEngine;
+use crate::error::{AgentError, ToolError};
+use crate::events::{AgentEvent, AgentObserver};
+use crate::glyphs;
+use crate::interrupt;
+use crate::project::ProjectKind;
+use crate::quality;
+use crate::test_runner;
+
+enum FixState {
+    Check,
+    Patch(Vec<String>),
+    Green,
+    GaveUp,
+}
+
+pub struct FixLoop<'a> {
+    engine: &'a mut AgentEngine,
+    max_iterations: u32,
+    iteration: u32,
+    reported: HashSet<String>,
+    // Status lines go to stderr, leaving stdout to a JSON event stream
+    json_output: bool,
+    // The build check and test commands, else the project kind's
+    commands: Option<(Option<String>, String)>,
+}
+
+// Prints engine events for fix mode's plain console output, or to stderr
+// where stdout carries the result of a one-shot run
+#[derive(Default)]
+pub struct ConsoleObserver {
+    stderr: bool,
+}
+
+impl ConsoleObserver {
+    pub fn on_stderr() -> Self {
+        Self { stderr: true }
+    }
+
+    fn say(&self, text: &str) {
+        if self.stderr {
+            eprintln!("{}", glyphs::plain(text));
+        } else {
+            glyphs::say(text);
+        }
+    }
+}
+
+impl AgentObserver for ConsoleObserver {
+    fn on_event(&mut self, event: &AgentEvent) {
+        match event {
+            AgentEvent::LlmResponse { fallbacks, .. } => {
+                for note in fallbacks {
+                    self.say(&format!("⚠ {}", note));
+                }
+            }
+            AgentEvent::ToolExecuted {
+                tool,
+                result,
+                status,
+                ..
+            } => {
+                // Commands by their exit status and time
+                let line = match status.as_ref().filter(|s| s.kind == OutputKind::Command) {
+                    Some(status) => status.summary(),
+                    None => result.lines().next().unwrap_or("").to_string(),
+                };
+                self.say(&format!("  {}: {}", tool, line));
+            }
+            AgentEvent::Error { message } => self.say(&format!("✗ {}", message)),
+            _ => {}
+        }
+    }
+}
+
+impl<'a> FixLoop<'a> {
+    pub fn new(engine: &'a mut AgentEngine, max_iterations: u32) -> Self {
+        Self {
+            engine,
+            max_iterations,
+            iteration: 0,
+            reported: HashSet::new(),
+            json_output: false,
+            commands: None,
+        }
+    }
+
+    pub fn with_json_output(mut self) -> Self {
+        self.json_output = true;
+        self
+    }
+
+    pub fn with_commands(mut self, check: Option<&str>, test: &str) -> Self {
+        self.commands = Some((check.map(str::to_string), test.to_string()));
+        self
+    }
+
+    // Patch iterations run so far
+    pub fn iterations(&self) -> u32 {
+        self.iteration
+    }
+
+    fn say(&self, text: &str) {
+        if self.json_output {
+            eprintln!("{}", glyphs::plain(text));
+        } else {
+            glyphs::say(text);
+        }
+    }
+
+    // Returns true when the project ends up green. Errors that another
+    // iteration can't fix, like a rejected API key, end the run.
+    pub async fn run(&mut self) -> Result<bool, AgentError> {
+        let mut state = FixState::Check;
+
+        loop {
+            if interrupt::is_interrupted() {
+                self.say(&format!(
+                    "⏹ Interrupted after {} iteration(s)",
+                    self.iteration
+                ));
+                return Ok(false);
+            }
+
+            state = match state {
+                FixState::Check => self.check()?,
+                FixState::Patch(diagnostics) => {
+                    if self.iteration >= self.max_iterations {
+                        FixState::GaveUp
+                    } else {
+                        self.patch(diagnostics).await?;
+                        if dry_run::enabled() {
+                            // Nothing was written, so checking again would
+                            // only report the same failures
+                            self.say("▸ Dry run: stopping after one iteration");
+                            return Ok(false);
+                        }
+                        FixState::Check
+                    }
+                }
+                FixState::Green => {
+                    self.say(&format!(
+                        "✓ Project is green after {} iteration(s)",
+                        self.iteration
+                    ));
+                    return Ok(true);
+                }
+                FixState::GaveUp => {
+                    self.say(&format!(
+                        "✗ Still failing after {} iteration(s), giving up",
+                        self.iteration
+                    ));
+                    return Ok(false);
+                }
+            };
+        }
+    }
+
+    fn check(&mut self) -> Result<FixState, AgentError> {
+        let (check_command, test_command) = self.commands.clone().unwrap_or_else(|| {
+            let kind = ProjectKind::detect(self.engine.project_root());
+            (kind.check_command().map(str::to_string), kind.test_command())
+        });
+        if let Some(check_command) = check_command {
+            self.say(&format!("▸ {}", check_command));
+            let output = self.run_check(&check_command)?;
+            self.engine.note_build_output("execute_command", &check_command, &output);
+            if output.exit_code != Some(0) {
+                return Ok(FixState::Patch(parse_check_diagnostics(&output.streams())));
+            }
+        }
+
+        self.say(&format!("▸ {}", test_command));
+        let output = self.run_check(&test_command)?;
+        if output.exit_code != Some(0) {
+            return Ok(FixState::Patch(parse_test_failures(&output.streams())));
+        }
+
+        if quality::gate_enabled() {
+            self.say("▸ Quality gate: cargo clippy, cargo fmt --check");
+            let failures = quality::gate_failures(self.engine.project_root())?;
+            if !failures.is_empty() {
+                return Ok(FixState::Patch(failures));
+            }
+        }
+
+        Ok(FixState::Green)
+    }
+
+    // The checks themselves run even in dry-run mode; they don't touch the
+    // source tree
+    fn run_check(&self, command: &str) -> Result<ToolOutput, ToolError> {
+        if dry_run::enabled() {
+            app::run_command(command, self.engine.project_root())
+        } else {
+            app::execute_tool("execute_command", command, self.engine.project_root())
+        }
+    }
+
+    async fn patch(&mut self, diagnostics: Vec<String>) -> Result<(), AgentError> {
+        self.iteration += 1;
+        self.say(&format!(
+            "▸ Iteration {}/{}: {} failure(s)",
+            self.iteration,
+            self.max_iterations,
+            diagnostics.len()
+        ));
+
+        let message = self.failure_message(&diagnostics);
+        self.engine.push_user(message);
+
+        // Other errors are reported through the engine's events; the next
+        // check decides whether to try again
+        match self.engine.step().await {
+            Err(AgentError::Llm(e)) if e.is_non_retryable() => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    // Diagnostics already sent in an earlier turn are only listed by their
+    // first line so the model isn't fed the same wall of text every turn
+    fn failure_message(&mut self, diagnostics: &[String]) -> String {
+        let mut new = Vec::new();
+        let mut repeated = Vec::new();
+
+        for diagnostic in diagnostics {
+            let key = diagnostic.lines().next().unwrap_or("").trim().to_string();
+            if self.reported.insert(key.clone()) {
+                new.push(diagnostic.clone());
+            } else {
+                repeated.push(key);
+            }
+        }
+
+        let mut message = String::from(if quality::gate_enabled() {
+            "The build, tests or quality gate are failing. Fix the project so that it builds, \
+             all tests pass, clippy reports no warnings and rustfmt has nothing to change."
+        } else {
+            "The build or tests are failing. Fix the project so that it builds and all tests pass."
+        });
+        if !new.is_empty() {
+            message.push_str("\n\nNew failures:\n");
+            message.push_str(&new.join("\n\n"));
+        }
+        if !repeated.is_empty() {
+            message.push_str("\n\nStill failing (already reported):\n");
+            for key in repeated {
+                message.push_str(&format!("- {}\n", key));
+            }
+        }
+        message
+    }
+}
+
+fn parse_check_diagnostics(output: &str) -> Vec<String> {
+    let diagnostics: Vec<String> = output
+        .lines()
+        .filter(|line| line.contains("error"))
+        .filter(|line| {
+            !line.starts_with("error: could not compile") && !line.starts_with("error: aborting")
+        })
+        .map(|line| line.trim().to_string())
+        .collect();
+
+    if diagnostics.is_empty() {
+        vec![tail(output, 40)]
+    } else {
+        diagnostics
+    }
+}
+
+fn parse_test_failures(output: &str) -> Vec<String> {
+    let failures = test_runner::parse_failures(output);
+
+    if failures.is_empty() {
+        // Tests failed to build; report compiler errors instead
+        parse_check_diagnostics(output)
+    } else {
+        failures
+    }
+}
+
+fn tail(text: &str, lines: usize) -> String {
+    let all: Vec<&str> = text.lines().collect();
+    all[all.len().saturating_sub(lines)..].join("\n")
+}
@@ -0,0 +1,528 @@
+use git2::{BranchType, DiffFormat, DiffOptions, Repository, Sort, StatusOptions};
+use std::{
+    env, fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+use crate::app::{self, ToolOutput};
+use crate::diff;
+use crate::dry_run;
+use crate::error::ToolError;
+use crate::llm::ChatMessage;
+
+// Diff sent to the model when drafting a commit message is cut to this many
+// characters
+const MAX_DIFF_CHARS: usize = 12_000;
+
+// What to do with uncommitted changes found when a run starts, from
+// BOTCODER_DIRTY
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DirtyPolicy {
+    // Warn and carry on (default)
+    Allow,
+    Refuse,
+    // Stash the changes, leaving a clean tree
+    Stash,
+    // Save the changes in a stash entry but keep them in the tree
+    Checkpoint,
+}
+
+impl DirtyPolicy {
+    pub fn from_env() -> Self {
+        match env::var("BOTCODER_DIRTY").as_deref() {
+            Ok("refuse") => DirtyPolicy::Refuse,
+            Ok("stash") => DirtyPolicy::Stash,
+            Ok("checkpoint") => DirtyPolicy::Checkpoint,
+            _ => DirtyPolicy::Allow,
+        }
+    }
+}
+
+// Lines of a git_diff result before it is cut off
+const DIFF_TOOL_LINES: usize = 400;
+const DEFAULT_LOG_COUNT: usize = 10;
+
+const COMMIT_PROMPT: &str = "Write a git commit message in the Conventional Commits format \
+     (type(scope): summary, e.g. \"fix(parser): handle empty replies\") for the diff below. \
+     Keep the summary under 72 characters. Add a short body after a blank line only if the \
+     change needs explaining. Reply with the message only, no code fences or commentary.";
+
+// git_commit parameter: the files to commit, one per line, then ":::" and
+// the message. The engine fills in the files it modified.
+pub fn encode_commit(files: &[String], message: &str) -> String {
+    format!("{}:::{}", files.join("\n"), message)
+}
+
+// Stages the files and commits just them, leaving anything else already
+// staged out of the commit
+pub fn commit(param: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    let (files, message) = param
+        .split_once(":::")
+        .ok_or(ToolError::InvalidFormat("git_commit"))?;
+    let files: Vec<&str> = files.lines().filter(|f| !f.trim().is_empty()).collect();
+    let message = message.trim();
+    if files.is_empty() {
+        return Err(ToolError::InvalidArgs(
+            "no files were modified this session".to_string(),
+        ));
+    }
+    if message.is_empty() {
+        return Err(ToolError::InvalidArgs("empty commit message".to_string()));
+    }
+
+    if dry_run::enabled() {
+        return Ok(ToolOutput::text(format!(
+            "Would commit {} file(s): {}",
+            files.len(),
+            message
+        )));
+    }
+
+    let add = git(root, |cmd| {
+        cmd.arg("add").arg("--").args(&files);
+    })?;
+    if !add.status.success() {
+        return Ok(app::command_output(&add));
+    }
+    let commit = git(root, |cmd| {
+        cmd.args(["commit", "-m", message, "--"]).args(&files);
+    })?;
+    Ok(app::command_output(&commit))
+}
+
+// Creates the branch from HEAD and switches to it, carrying uncommitted
+// changes along
+pub fn create_branch(name: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    let output = git(root, |cmd| {
+        cmd.args(["checkout", "-b", name]);
+    })?;
+    Ok(app::command_output(&output))
+}
+
+pub fn push(remote: &str, branch: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    let output = git(root, |cmd| {
+        cmd.args(["push", "-u", remote, branch]);
+    })?;
+    Ok(app::command_output(&output))
+}
+
+// Changes to the files against HEAD, with files git doesn't track yet shown
+// as added
+pub fn diff(files: &[String], root: &str) -> Result<String, ToolError> {
+    if files.is_empty() {
+        return Ok(String::new());
+    }
+
+    let tracked = git(root, |cmd| {
+        cmd.args(["diff", "HEAD", "--"]).args(files);
+    })?;
+    let mut out = String::from_utf8_lossy(&tracked.stdout).into_owned();
+
+    let untracked = git(root, |cmd| {
+        cmd.args(["ls-files", "--others", "--exclude-standard", "--"])
+            .args(files);
+    })?;
+    for file in String::from_utf8_lossy(&untracked.stdout).lines() {
+        let content = fs::read_to_string(Path::new(root).join(file)).unwrap_or_default();
+        out.push_str(&diff::unified(file, "", &content));
+    }
+    Ok(out)
+}
+
+// Request asking the model for a commit message describing the diff
+pub fn commit_message_request(diff: &str) -> Vec<ChatMessage> {
+    let diff = match diff.char_indices().nth(MAX_DIFF_CHARS) {
+        Some((end, _)) => format!("{}\n[diff truncated]", &diff[..end]),
+        None => diff.to_string(),
+    };
+    vec![
+        ChatMessage::system(COMMIT_PROMPT),
+        ChatMessage::user(diff),
+    ]
+}
+
+// The model's reply without code fences or surrounding quotes
+pub fn clean_message(reply: &str) -> String {
+    reply
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("```"))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .trim_matches('"')
+        .trim()
+        .to_string()
+}
+
+pub fn is_repository(root: &str) -> bool {
+    Repository::discover(root).is_ok()
+}
+
+// The top directory of the repository holding root
+pub fn workdir(root: &str) -> Option<String> {
+    Repository::discover(root)
+        .ok()
+        .and_then(|repo| repo.workdir().map(|dir| dir.to_string_lossy().into_owned()))
+}
+
+// Checks out HEAD of root's repository in a new worktree at path, on a new
+// branch or, without one, detached
+pub fn add_worktree(root: &str, path: &Path, branch: Option<&str>) -> Result<(), String> {
+    let output = git(root, |cmd| {
+        cmd.args(["worktree", "add", "-q"]);
+        match branch {
+            Some(branch) => cmd.args(["-b", branch]),
+            None => cmd.arg("--detach"),
+        };
+        cmd.arg(path).arg("HEAD");
+    })
+    .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(())
+}
+
+// Deletes the worktree at path, changes and all
+pub fn remove_worktree(root: &str, path: &Path) -> Result<(), String> {
+    let output = git(root, |cmd| {
+        cmd.args(["worktree", "remove", "--force"]).arg(path);
+    })
+    .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(())
+}
+
+pub fn head_commit(root: &str) -> Option<String> {
+    let repo = Repository::discover(root).ok()?;
+    let commit = repo.head().ok()?.peel_to_commit().ok()?;
+    Some(commit.id().to_string())
+}
+
+// Every change in the working tree at root since the commit `base`:
+// committed, staged or not, new files and command output included, but not
+// botcoder's own .botcoder/ state. Stages everything to get it.
+pub fn patch(root: &str, base: &str) -> Result<String, String> {
+    const PATHSPEC: [&str; 3] = ["--", ":/", ":(top,exclude,glob)**/.botcoder/**"];
+    let output = git(root, |cmd| {
+        cmd.args(["add", "-A"]).args(PATHSPEC);
+    })
+    .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    let output = git(root, |cmd| {
+        cmd.args(["diff", "--cached", "--binary", base]).args(PATHSPEC);
+    })
+    .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Applies the patch file to root's working tree, all of it or nothing
+pub fn apply(root: &str, patch: &Path) -> Result<(), String> {
+    let output = git(root, |cmd| {
+        cmd.args(["apply", "--binary"]).arg(patch);
+    })
+    .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    Ok(())
+}
+
+// Uncommitted changes in the project, including untracked files, leaving
+// out botcoder's own .botcoder directory. Empty outside a repository.
+pub fn dirty_files(root: &str) -> Vec<String> {
+    let Ok(repo) = Repository::discover(root) else {
+        return Vec::new();
+    };
+    let mut options = StatusOptions::new();
+    options.include_untracked(true);
+    let Ok(statuses) = repo.statuses(Some(&mut options)) else {
+        return Vec::new();
+    };
+    statuses
+        .iter()
+        .filter_map(|entry| entry.path().map(str::to_string))
+        .filter(|path| !path.starts_with(".botcoder/") && !path.contains("/.botcoder/"))
+        .collect()
+}
+
+// Applies the policy to the uncommitted changes before a run. Returns a
+// note for the user, or the list of files when the policy refuses them.
+pub fn prepare_worktree(root: &str, policy: DirtyPolicy) -> Result<Option<String>, Vec<String>> {
+    let dirty = dirty_files(root);
+    if dirty.is_empty() {
+        return Ok(None);
+    }
+
+    let note = match policy {
+        DirtyPolicy::Refuse => return Err(dirty),
+        DirtyPolicy::Allow => format!(
+            "⚠ {} file(s) have uncommitted changes; the agent's edits will mix with them",
+            dirty.len()
+        ),
+        DirtyPolicy::Stash | DirtyPolicy::Checkpoint => {
+            let keep = policy == DirtyPolicy::Checkpoint;
+            let message = if keep {
+                "botcoder checkpoint"
+            } else {
+                "botcoder: stashed before run"
+            };
+            match stash(root, &dirty, message, keep) {
+                Ok(()) if keep => format!(
+                    "✓ Checkpointed {} uncommitted file(s) in the git stash",
+                    dirty.len()
+                ),
+                Ok(()) => format!("✓ Stashed {} uncommitted file(s)", dirty.len()),
+                Err(e) => format!("⚠ Could not stash uncommitted changes: {}", e),
+            }
+        }
+    };
+    Ok(Some(note))
+}
+
+// Stashes the files, untracked ones included; with keep the stash is
+// applied straight back so the tree is unchanged
+pub fn stash(root: &str, files: &[String], message: &str, keep: bool) -> Result<(), String> {
+    let repo_root = workdir(root).unwrap_or_else(|| root.to_string());
+
+    let push = git(&repo_root, |cmd| {
+        cmd.args(["stash", "push", "--include-untracked", "-m", message, "--"])
+            .args(files);
+    })
+    .map_err(|e| e.to_string())?;
+    if !push.status.success() {
+        return Err(String::from_utf8_lossy(&push.stderr).trim().to_string());
+    }
+    if keep {
+        let apply = git(&repo_root, |cmd| {
+            cmd.args(["stash", "apply", "--index"]);
+        })
+        .map_err(|e| e.to_string())?;
+        if !apply.status.success() {
+            return Err(String::from_utf8_lossy(&apply.stderr).trim().to_string());
+        }
+    }
+    Ok(())
+}
+
+// The read tools below use libgit2, so they work without a git binary.
+// Paths are relative to the repository, which may contain the project.
+
+// git_status: current branch, how it compares to its upstream, and changed
+// files grouped into staged, unstaged and untracked
+pub fn status(root: &str) -> Result<ToolOutput, ToolError> {
+    let repo = Repository::discover(root)?;
+    let mut out = format!("branch: {}\n", branch_line(&repo));
+
+    let mut options = StatusOptions::new();
+    options.include_untracked(true).recurse_untracked_dirs(true);
+    let statuses = repo.statuses(Some(&mut options))?;
+
+    let (mut staged, mut unstaged, mut untracked, mut conflicted) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for entry in statuses.iter() {
+        let path = entry.path().unwrap_or("").to_string();
+        let status = entry.status();
+        if status.is_conflicted() {
+            conflicted.push(path);
+            continue;
+        }
+        if status.is_wt_new() {
+            untracked.push(path.clone());
+        }
+        let index = if status.is_index_new() {
+            Some('A')
+        } else if status.is_index_deleted() {
+            Some('D')
+        } else if status.is_index_renamed() {
+            Some('R')
+        } else if status.is_index_modified() || status.is_index_typechange() {
+            Some('M')
+        } else {
+            None
+        };
+        if let Some(code) = index {
+            staged.push(format!("{} {}", code, path));
+        }
+        let worktree = if status.is_wt_deleted() {
+            Some('D')
+        } else if status.is_wt_renamed() {
+            Some('R')
+        } else if status.is_wt_modified() || status.is_wt_typechange() {
+            Some('M')
+        } else {
+            None
+        };
+        if let Some(code) = worktree {
+            unstaged.push(format!("{} {}", code, path));
+        }
+    }
+
+    if staged.is_empty() && unstaged.is_empty() && untracked.is_empty() && conflicted.is_empty() {
+        out.push_str("working tree clean\n");
+    }
+    for (title, files) in [
+        ("conflicted", conflicted),
+        ("staged", staged),
+        ("unstaged", unstaged),
+        ("untracked", untracked),
+    ] {
+        if !files.is_empty() {
+            out.push_str(&format!("{}:\n", title));
+            for file in files {
+                out.push_str(&format!("  {}\n", file));
+            }
+        }
+    }
+    Ok(ToolOutput::text(out))
+}
+
+// "main (ahead 1, behind 2 of origin/main)"
+fn branch_line(repo: &Repository) -> String {
+    let Ok(head) = repo.head() else {
+        return "(no commits yet)".to_string();
+    };
+    if repo.head_detached().unwrap_or(false) {
+        let id = head.target().map(|id| id.to_string()).unwrap_or_default();
+        return format!("(detached at {})", short_id(&id));
+    }
+    let name = head.shorthand().unwrap_or("HEAD").to_string();
+
+    let upstream = repo
+        .find_branch(&name, BranchType::Local)
+        .and_then(|branch| branch.upstream());
+    let Ok(upstream) = upstream else {
+        return name;
+    };
+    let upstream_name = upstream.name().ok().flatten().unwrap_or("upstream").to_string();
+    match (head.target(), upstream.get().target()) {
+        (Some(local), Some(remote)) => match repo.graph_ahead_behind(local, remote) {
+            Ok((ahead, behind)) => format!(
+                "{} (ahead {}, behind {} of {})",
+                name, ahead, behind, upstream_name
+            ),
+            Err(_) => name,
+        },
+        _ => name,
+    }
+}
+
+// git_diff: staged and unstaged changes against HEAD, including new files,
+// optionally limited to one path
+pub fn diff_tool(path: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    let repo = Repository::discover(root)?;
+    let head = repo.head().ok().and_then(|head| head.peel_to_tree().ok());
+
+    let mut options = DiffOptions::new();
+    options
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .show_untracked_content(true);
+    let path = path.trim();
+    if !path.is_empty() {
+        options.pathspec(repo_path(&repo, root, path));
+    }
+    let diff = repo.diff_tree_to_workdir_with_index(head.as_ref(), Some(&mut options))?;
+
+    let mut out = String::new();
+    diff.print(DiffFormat::Patch, |_, _, line| {
+        if matches!(line.origin(), '+' | '-' | ' ') {
+            out.push(line.origin());
+        }
+        out.push_str(&String::from_utf8_lossy(line.content()));
+        true
+    })?;
+
+    if out.is_empty() {
+        return Ok(ToolOutput::text("No changes"));
+    }
+    let lines: Vec<&str> = out.lines().collect();
+    if lines.len() > DIFF_TOOL_LINES {
+        return Ok(ToolOutput::text(format!(
+            "{}\n[{} more lines; pass a path to git_diff to see one file]",
+            lines[..DIFF_TOOL_LINES].join("\n"),
+            lines.len() - DIFF_TOOL_LINES
+        )));
+    }
+    Ok(ToolOutput::text(out))
+}
+
+// git_log: the latest commits on HEAD, newest first, one per line as
+// "<id> <date> <author>: <summary>"
+pub fn log(count: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    let count = match count.trim() {
+        "" => DEFAULT_LOG_COUNT,
+        n => n
+            .parse()
+            .map_err(|_| ToolError::InvalidArgs(format!("git_log count \"{}\" is not a number", n)))?,
+    };
+
+    let repo = Repository::discover(root)?;
+    if repo.head().is_err() {
+        return Ok(ToolOutput::text("No commits yet"));
+    }
+    let mut walk = repo.revwalk()?;
+    walk.push_head()?;
+    walk.set_sorting(Sort::TIME)?;
+
+    let mut out = String::new();
+    for id in walk.take(count) {
+        let commit = repo.find_commit(id?)?;
+        out.push_str(&format!(
+            "{} {} {}: {}\n",
+            short_id(&commit.id().to_string()),
+            date(commit.time().seconds()),
+            commit.author().name().unwrap_or("unknown"),
+            commit.summary().unwrap_or("")
+        ));
+    }
+    Ok(ToolOutput::text(out))
+}
+
+// A project-relative path as seen from the repository root
+fn repo_path(repo: &Repository, root: &str, path: &str) -> String {
+    let prefix = repo.workdir().and_then(|workdir| {
+        let root = Path::new(root).canonicalize().ok()?;
+        let workdir = workdir.canonicalize().ok()?;
+        Some(root.strip_prefix(workdir).ok()?.to_path_buf())
+    });
+    match prefix {
+        Some(prefix) if !prefix.as_os_str().is_empty() => {
+            prefix.join(path).to_string_lossy().replace('\\', "/")
+        }
+        _ => path.to_string(),
+    }
+}
+
+fn short_id(id: &str) -> &str {
+    &id[..id.len().min(8)]
+}
+
+// YYYY-MM-DD (UTC) from a Unix timestamp
+fn date(seconds: i64) -> String {
+    // Days to civil date, from Howard Hinnant's date algorithms
+    let z = seconds.div_euclid(86_400) + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn git(root: &str, args: impl FnOnce(&mut Command)) -> Result<Output, ToolError> {
+    let mut cmd = Command::new("git");
+    args(&mut cmd);
+    cmd.current_dir(root).output().map_err(ToolError::Spawn)
+}
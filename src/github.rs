@@ -0,0 +1,125 @@
+use reqwest::header::{ACCEPT, USER_AGENT};
+use serde_json::{json, Value};
+use std::env;
+
+use crate::app::ToolOutput;
+use crate::engine::AgentEngine;
+use crate::error::{AgentError, GitHubError, ToolError};
+use crate::git;
+
+const DEFAULT_API: &str = "https://api.github.com";
+// Diff quoted in the pull request body is cut to this many characters
+const MAX_BODY_DIFF: usize = 50_000;
+
+// Opens a pull request once a run succeeds. Enabled by BOTCODER_GITHUB_REPO
+// ("owner/name"); the token comes from BOTCODER_GITHUB_TOKEN or GITHUB_TOKEN.
+// Pushing uses the remote's own git credentials.
+pub struct GitHubConfig {
+    repo: String,
+    token: String,
+    // BOTCODER_GITHUB_BASE, default "main"
+    base: String,
+    // BOTCODER_GITHUB_REMOTE, default "origin"
+    remote: String,
+    // GITHUB_API_URL, for GitHub Enterprise
+    api: String,
+}
+
+impl GitHubConfig {
+    pub fn from_env() -> Option<Self> {
+        let repo = env::var("BOTCODER_GITHUB_REPO").ok()?;
+        let token = env::var("BOTCODER_GITHUB_TOKEN")
+            .or_else(|_| env::var("GITHUB_TOKEN"))
+            .ok()?;
+        Some(Self {
+            repo,
+            token,
+            base: env::var("BOTCODER_GITHUB_BASE").unwrap_or_else(|_| "main".to_string()),
+            remote: env::var("BOTCODER_GITHUB_REMOTE").unwrap_or_else(|_| "origin".to_string()),
+            api: env::var("GITHUB_API_URL").unwrap_or_else(|_| DEFAULT_API.to_string()),
+        })
+    }
+
+    // Commits the files the engine modified to a new botcoder/<session>
+    // branch, pushes it and opens a pull request against the base branch.
+    // The working tree is left on the new branch. Returns the PR's URL.
+    pub async fn open_pull_request(
+        &self,
+        engine: &mut AgentEngine,
+        session: &str,
+        summary: &str,
+    ) -> Result<String, GitHubError> {
+        let files = engine.modified_files().to_vec();
+        if files.is_empty() {
+            return Err(GitHubError::NothingChanged);
+        }
+        let root = engine.project_root().to_string();
+        let diff = git::diff(&files, &root).map_err(AgentError::from)?;
+        let message = engine.draft_commit_message().await?;
+
+        let branch = format!("botcoder/{}", session);
+        check("checkout", git::create_branch(&branch, &root))?;
+        check("commit", git::commit(&git::encode_commit(&files, &message), &root))?;
+        check("push", git::push(&self.remote, &branch, &root))?;
+
+        let title = message.lines().next().unwrap_or("botcoder changes");
+        let response = reqwest::Client::new()
+            .post(format!("{}/repos/{}/pulls", self.api, self.repo))
+            .bearer_auth(&self.token)
+            .header(USER_AGENT, "botcoder")
+            .header(ACCEPT, "application/vnd.github+json")
+            .json(&json!({
+                "title": title,
+                "head": branch,
+                "base": self.base,
+                "body": pull_request_body(session, summary, &message, &diff),
+            }))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(GitHubError::Api {
+                status: status.as_u16(),
+                body: response.text().await.unwrap_or_default(),
+            });
+        }
+        let created: Value = response.json().await?;
+        Ok(created["html_url"].as_str().unwrap_or_default().to_string())
+    }
+}
+
+fn check(
+    step: &'static str,
+    output: Result<ToolOutput, ToolError>,
+) -> Result<(), GitHubError> {
+    let output = output.map_err(AgentError::from)?;
+    if output.exit_code == Some(0) {
+        Ok(())
+    } else {
+        Err(GitHubError::Git {
+            step,
+            output: output.text,
+        })
+    }
+}
+
+fn pull_request_body(session: &str, summary: &str, message: &str, diff: &str) -> String {
+    let diff = match diff.char_indices().nth(MAX_BODY_DIFF) {
+        Some((end, _)) => format!("{}\n[diff truncated]", &diff[..end]),
+        None => diff.to_string(),
+    };
+    // The commit message body, if the model wrote one
+    let details = message.lines().skip(1).collect::<Vec<_>>().join("\n");
+
+    let mut sections = vec![summary.to_string()];
+    if !details.trim().is_empty() {
+        sections.push(details.trim().to_string());
+    }
+    sections.push(format!(
+        "<details><summary>Diff</summary>\n\n```diff\n{}\n```\n\n</details>",
+        diff.trim_end()
+    ));
+    sections.push(format!("Opened by botcoder session `{}`.", session));
+    sections.join("\n\n")
+}
@@ -0,0 +1,107 @@
+use ratatui::buffer::Buffer;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Plain-text mode for screen readers and dumb terminals. All box drawing,
+// emoji and other symbols are mapped to ASCII here, for both the console
+// output and the final TUI frame, rather than at every call site.
+static ASCII: AtomicBool = AtomicBool::new(false);
+
+const SPINNER: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+const ASCII_SPINNER: [&str; 4] = ["|", "/", "-", "\\"];
+
+pub fn set_ascii(enabled: bool) {
+    ASCII.store(enabled, Ordering::Relaxed);
+}
+
+pub fn ascii() -> bool {
+    ASCII.load(Ordering::Relaxed)
+}
+
+pub fn spinner_frames() -> &'static [&'static str] {
+    if ascii() {
+        &ASCII_SPINNER
+    } else {
+        &SPINNER
+    }
+}
+
+// Console text, with status symbols spelled out in ASCII mode
+pub fn plain(text: &str) -> String {
+    if !ascii() {
+        return text.to_string();
+    }
+
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '✓' | '✔' => out.push_str("OK"),
+            '✗' | '❌' => out.push_str("FAIL"),
+            '⏹' => out.push_str("STOPPED"),
+            '⏸' => out.push_str("PAUSED"),
+            '⚠' => out.push_str("WARNING"),
+            c if is_symbol(c) => {
+                if let Some(a) = ascii_char(c) {
+                    out.push(a);
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn say(text: &str) {
+    println!("{}", plain(text));
+}
+
+// Rewrites a rendered frame so it only contains ASCII symbols
+pub fn asciify(buffer: &mut Buffer) {
+    for cell in buffer.content.iter_mut() {
+        let mut chars = cell.symbol().chars();
+        let (Some(c), rest) = (chars.next(), chars.as_str()) else {
+            continue;
+        };
+        if c.is_ascii() && rest.is_empty() {
+            continue;
+        }
+        if is_symbol(c) {
+            let replacement = ascii_char(c).unwrap_or(' ');
+            cell.set_char(replacement);
+        }
+    }
+}
+
+fn is_symbol(c: char) -> bool {
+    matches!(c as u32,
+        0x2190..=0x21FF   // arrows
+        | 0x2300..=0x23FF // misc technical (⏸ ⏹)
+        | 0x2500..=0x25FF // box drawing, blocks, geometric shapes
+        | 0x2600..=0x27BF // misc symbols, dingbats
+        | 0x2800..=0x28FF // braille (spinner)
+        | 0xFE0F          // emoji variation selector
+        | 0x1F300..=0x1FAFF)
+}
+
+fn ascii_char(c: char) -> Option<char> {
+    let mapped = match c {
+        '│' | '┃' | '║' => '|',
+        '─' | '━' | '═' => '-',
+        '┌' | '┐' | '└' | '┘' | '├' | '┤' | '┬' | '┴' | '┼' | '╭' | '╮' | '╰' | '╯' => {
+            '+'
+        }
+        '█' | '▉' | '▊' | '▋' | '▌' | '▍' | '▎' | '▏' | '▇' | '▆' | '▅' | '▄' | '▃' | '▂' | '▁' => {
+            '#'
+        }
+        '▲' | '↑' => '^',
+        '▼' | '↓' | '▾' => 'v',
+        '▸' | '▶' | '→' => '>',
+        '◀' | '←' => '<',
+        '○' => 'o',
+        '✓' | '✔' => '+',
+        '✗' | '❌' => 'x',
+        '⚡' | '⚠' => '!',
+        '\u{FE0F}' => return None,
+        _ => ' ',
+    };
+    Some(mapped)
+}
@@ -0,0 +1,184 @@
+use ratatui::{
+    style::{Color, Style},
+    text::{Line, Span},
+};
+use std::{
+    cell::RefCell,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::OnceLock,
+};
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{Theme, ThemeSet},
+    parsing::{SyntaxReference, SyntaxSet},
+};
+
+const CACHE_SIZE: usize = 8;
+
+struct Assets {
+    syntaxes: SyntaxSet,
+    themes: ThemeSet,
+}
+
+thread_local! {
+    // Highlighting runs on every frame, so recently rendered texts are cached
+    static CACHE: RefCell<Vec<(u64, Vec<Line<'static>>)>> = const { RefCell::new(Vec::new()) };
+}
+
+fn assets() -> &'static Assets {
+    static ASSETS: OnceLock<Assets> = OnceLock::new();
+    ASSETS.get_or_init(|| Assets {
+        syntaxes: SyntaxSet::load_defaults_newlines(),
+        themes: ThemeSet::load_defaults(),
+    })
+}
+
+// Maps a fence tag or file extension to a bundled syntax
+fn find_syntax(lang: &str) -> Option<&'static SyntaxReference> {
+    let syntaxes = &assets().syntaxes;
+    let lang = match lang.trim().to_lowercase().as_str() {
+        "" => return None,
+        "rust" => "rs".to_string(),
+        // syntect ships no TOML grammar; properties files are close enough
+        "toml" | "lock" => "properties".to_string(),
+        "patch" | "delta" => "diff".to_string(),
+        "shell" | "console" => "sh".to_string(),
+        other => other.to_string(),
+    };
+
+    syntaxes
+        .find_syntax_by_token(&lang)
+        .or_else(|| syntaxes.find_syntax_by_extension(&lang))
+}
+
+// Language of a file path, for highlighting read_file results
+pub fn lang_for_path(path: &str) -> Option<String> {
+    path.trim()
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_string())
+        .filter(|ext| !ext.contains('/'))
+}
+
+// Highlights fenced code blocks in `text`; when `lang` is given the whole text
+// is treated as code in that language. Other lines use `plain`, as does
+// everything when no syntax theme is set.
+pub fn highlight(
+    text: &str,
+    lang: Option<&str>,
+    plain: Style,
+    syntax_theme: Option<&str>,
+) -> Vec<Line<'static>> {
+    let Some(theme) = syntax_theme.and_then(|name| assets().themes.themes.get(name)) else {
+        return text
+            .lines()
+            .map(|l| Line::from(Span::styled(l.to_string(), plain)))
+            .collect();
+    };
+
+    let mut hasher = DefaultHasher::new();
+    (text, lang, syntax_theme).hash(&mut hasher);
+    let key = hasher.finish();
+
+    if let Some(lines) = CACHE.with(|c| {
+        c.borrow()
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, l)| l.clone())
+    }) {
+        return lines;
+    }
+
+    let lines = match lang.and_then(find_syntax) {
+        Some(syntax) => highlight_block(text.lines(), syntax, plain, theme),
+        None => highlight_fenced(text, plain, theme),
+    };
+
+    CACHE.with(|c| {
+        let mut cache = c.borrow_mut();
+        if cache.len() >= CACHE_SIZE {
+            cache.remove(0);
+        }
+        cache.push((key, lines.clone()));
+    });
+
+    lines
+}
+
+fn highlight_fenced(text: &str, plain: Style, theme: &Theme) -> Vec<Line<'static>> {
+    let mut lines = Vec::new();
+    let mut fence: Option<(Option<&SyntaxReference>, Vec<&str>)> = None;
+
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if let Some(tag) = trimmed.strip_prefix("```") {
+            match fence.take() {
+                Some((syntax, body)) => {
+                    lines.extend(render_fence(syntax, body, plain, theme));
+                    lines.push(Line::from(Span::styled(line.to_string(), plain)));
+                }
+                None => {
+                    lines.push(Line::from(Span::styled(line.to_string(), plain)));
+                    fence = Some((find_syntax(tag), Vec::new()));
+                }
+            }
+        } else if let Some((_, body)) = fence.as_mut() {
+            body.push(line);
+        } else {
+            lines.push(Line::from(Span::styled(line.to_string(), plain)));
+        }
+    }
+
+    // Unterminated fence (e.g. a truncated response)
+    if let Some((syntax, body)) = fence {
+        lines.extend(render_fence(syntax, body, plain, theme));
+    }
+
+    lines
+}
+
+fn render_fence(
+    syntax: Option<&SyntaxReference>,
+    body: Vec<&str>,
+    plain: Style,
+    theme: &Theme,
+) -> Vec<Line<'static>> {
+    match syntax {
+        Some(syntax) => highlight_block(body.into_iter(), syntax, plain, theme),
+        None => body
+            .into_iter()
+            .map(|l| Line::from(Span::styled(l.to_string(), plain)))
+            .collect(),
+    }
+}
+
+fn highlight_block<'a>(
+    lines: impl Iterator<Item = &'a str>,
+    syntax: &SyntaxReference,
+    plain: Style,
+    theme: &Theme,
+) -> Vec<Line<'static>> {
+    let assets = assets();
+    let mut highlighter = HighlightLines::new(syntax, theme);
+
+    lines
+        .map(|line| {
+            let with_newline = format!("{}\n", line);
+            match highlighter.highlight_line(&with_newline, &assets.syntaxes) {
+                Ok(regions) => Line::from(
+                    regions
+                        .into_iter()
+                        .map(|(style, piece)| {
+                            let fg = style.foreground;
+                            Span::styled(
+                                piece.trim_end_matches('\n').to_string(),
+                                plain.fg(Color::Rgb(fg.r, fg.g, fg.b)),
+                            )
+                        })
+                        .collect::<Vec<_>>(),
+                ),
+                Err(_) => Line::from(Span::styled(line.to_string(), plain)),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,82 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::app::{self, ToolOutput};
+use crate::config;
+use crate::error::ToolError;
+
+// Shell commands run around tool calls, keyed by tool name or "*" for
+// every tool. "{path}" and "{tool}" in a command are replaced with the
+// call's file path (shell-quoted, since the model chose it) and tool name:
+//
+//   [hooks.after]
+//   write_file_delta = ["cargo fmt -- {path}"]
+#[derive(Default, Deserialize)]
+pub struct Hooks {
+    #[serde(default)]
+    before: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    after: HashMap<String, Vec<String>>,
+}
+
+impl Hooks {
+    // No hooks without a .botcoder.toml; an invalid one is skipped with a
+    // warning
+    pub fn load(root: &str) -> Self {
+        config::section(root, "hooks")
+    }
+
+    // A failing before hook keeps the tool from running
+    pub fn run_before(&self, tool: &str, param: &str, root: &str) -> Result<(), ToolError> {
+        for command in commands(&self.before, tool) {
+            let (command, output) = run(command, tool, param, root)?;
+            if output.exit_code != Some(0) {
+                return Err(ToolError::BeforeHook {
+                    tool: tool.to_string(),
+                    command,
+                    output: output.text,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Runs after a successful call; a failure is reported as the tool's
+    // error even though the tool itself ran
+    pub fn run_after(&self, tool: &str, param: &str, root: &str) -> Result<(), ToolError> {
+        for command in commands(&self.after, tool) {
+            let (command, output) = run(command, tool, param, root)?;
+            if output.exit_code != Some(0) {
+                return Err(ToolError::AfterHook {
+                    tool: tool.to_string(),
+                    command,
+                    output: output.text,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// The tool's own hooks first, then the ones for every tool
+fn commands<'a>(hooks: &'a HashMap<String, Vec<String>>, tool: &str) -> Vec<&'a String> {
+    hooks
+        .get(tool)
+        .into_iter()
+        .chain(hooks.get("*"))
+        .flatten()
+        .collect()
+}
+
+fn run(command: &str, tool: &str, param: &str, root: &str) -> Result<(String, ToolOutput), ToolError> {
+    let command = command
+        .replace("{path}", &shell_quote(app::tool_path(tool, param).unwrap_or("")))
+        .replace("{tool}", tool);
+    let output = app::run_command(&command, root)?;
+    Ok((command, output))
+}
+
+// One shell word, whatever the path contains
+fn shell_quote(path: &str) -> String {
+    format!("'{}'", path.replace('\'', "'\\''"))
+}
@@ -0,0 +1,119 @@
+use reqwest::{blocking, Certificate, Client, NoProxy, Proxy};
+use std::{env, error::Error as _, fs, time::Duration};
+
+use crate::error::LlmError;
+
+const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+// Long enough for a slow reasoning model's reply
+const DEFAULT_READ_TIMEOUT: u64 = 300;
+
+// The HTTP client for model and token requests:
+//   HTTPS_PROXY (or ALL_PROXY) routes through a proxy, skipping the hosts
+//   in NO_PROXY;
+//   BOTCODER_CA_BUNDLE (or SSL_CERT_FILE) adds the PEM root certificates of
+//   e.g. a TLS-inspecting proxy;
+//   BOTCODER_CONNECT_TIMEOUT and BOTCODER_READ_TIMEOUT are in seconds
+pub fn client() -> Result<Client, LlmError> {
+    let mut builder = Client::builder()
+        .connect_timeout(connect_timeout())
+        .read_timeout(read_timeout());
+    if let Some(proxy) = configured_proxy()? {
+        builder = builder.proxy(proxy);
+    }
+    for certificate in root_certificates()? {
+        builder = builder.add_root_certificate(certificate);
+    }
+    builder.build().map_err(setup_error)
+}
+
+// The same settings for the blocking client of tools that run off tokio's
+// threads (fetch_url, embeddings). It has no read timeout, so
+// BOTCODER_READ_TIMEOUT bounds each whole request; callers may set their own.
+pub fn blocking_builder() -> Result<blocking::ClientBuilder, LlmError> {
+    let mut builder = blocking::Client::builder()
+        .connect_timeout(connect_timeout())
+        .timeout(read_timeout());
+    if let Some(proxy) = configured_proxy()? {
+        builder = builder.proxy(proxy);
+    }
+    for certificate in root_certificates()? {
+        builder = builder.add_root_certificate(certificate);
+    }
+    Ok(builder)
+}
+
+fn setup_error(error: reqwest::Error) -> LlmError {
+    LlmError::InvalidConfig(format!("Could not set up the HTTP client: {}", error))
+}
+
+fn configured_proxy() -> Result<Option<Proxy>, LlmError> {
+    let Some(url) = proxy() else {
+        return Ok(None);
+    };
+    let proxy = Proxy::https(&url)
+        .map_err(|e| LlmError::InvalidConfig(format!("Invalid HTTPS_PROXY {}: {}", url, e)))?
+        .no_proxy(NoProxy::from_env());
+    Ok(Some(proxy))
+}
+
+fn root_certificates() -> Result<Vec<Certificate>, LlmError> {
+    match var("BOTCODER_CA_BUNDLE").or_else(|| var("SSL_CERT_FILE")) {
+        Some(path) => ca_bundle(&path),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn connect_timeout() -> Duration {
+    Duration::from_secs(seconds("BOTCODER_CONNECT_TIMEOUT", DEFAULT_CONNECT_TIMEOUT))
+}
+
+fn read_timeout() -> Duration {
+    Duration::from_secs(seconds("BOTCODER_READ_TIMEOUT", DEFAULT_READ_TIMEOUT))
+}
+
+// The certificates of a PEM bundle
+pub fn ca_bundle(path: &str) -> Result<Vec<Certificate>, LlmError> {
+    let pem = fs::read(path)
+        .map_err(|e| LlmError::InvalidConfig(format!("Could not read CA bundle {}: {}", path, e)))?;
+    Certificate::from_pem_bundle(&pem)
+        .map_err(|e| LlmError::InvalidConfig(format!("Invalid CA bundle {}: {}", path, e)))
+}
+
+// A failed request with its causes spelled out, e.g. "connection refused"
+// or a certificate error, and the proxy it went through
+pub fn connection_error(url: &str, error: reqwest::Error) -> LlmError {
+    let timeout = error.is_timeout();
+    let error = error.without_url();
+    let mut causes = vec![error.to_string()];
+    let mut source = error.source();
+    while let Some(cause) = source {
+        causes.push(cause.to_string());
+        source = cause.source();
+    }
+    causes.dedup();
+    let route = match proxy() {
+        Some(proxy) => format!("via proxy {}", proxy),
+        None => "no proxy set (HTTPS_PROXY)".to_string(),
+    };
+    let hint = if timeout {
+        "; raise BOTCODER_CONNECT_TIMEOUT or BOTCODER_READ_TIMEOUT if the endpoint is slow"
+    } else {
+        ""
+    };
+    let host = url.split('?').next().unwrap_or(url);
+    LlmError::Connection(format!("{}: {}, {}{}", host, causes.join(": "), route, hint))
+}
+
+fn proxy() -> Option<String> {
+    ["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]
+        .iter()
+        .find_map(|name| var(name))
+}
+
+fn var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.trim().is_empty())
+}
+
+fn seconds(name: &str, default: u64) -> u64 {
+    var(name).and_then(|v| v.parse().ok()).unwrap_or(default)
+}
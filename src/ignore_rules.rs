@@ -0,0 +1,57 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::Path;
+
+use crate::error::ToolError;
+
+// Always excluded, whether or not the project has a .gitignore
+const DEFAULT_RULES: [&str; 3] = ["target/", "node_modules/", ".git/"];
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".botcoderignore"];
+
+// Paths the agent shouldn't see: the project's root .gitignore and
+// .botcoderignore (same syntax) plus build output directories
+pub struct IgnoreRules {
+    matcher: Gitignore,
+}
+
+impl IgnoreRules {
+    pub fn load(root: &str) -> Self {
+        let root = Path::new(root);
+        let mut builder = GitignoreBuilder::new(root);
+        for rule in DEFAULT_RULES {
+            let _ = builder.add_line(None, rule);
+        }
+        for file in IGNORE_FILES {
+            let path = root.join(file);
+            if path.exists() {
+                if let Some(e) = builder.add(&path) {
+                    log::warn!("Skipping invalid rules in {}: {}", path.display(), e);
+                }
+            }
+        }
+
+        Self {
+            matcher: builder.build().unwrap_or_else(|_| Gitignore::empty()),
+        }
+    }
+
+    // path is relative to the project root
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        let path = Path::new(path.trim().trim_start_matches("./"));
+        let path = match path.strip_prefix(self.matcher.path()) {
+            Ok(relative) => relative,
+            Err(_) if path.has_root() => return false,
+            Err(_) => path,
+        };
+        self.matcher
+            .matched_path_or_any_parents(path, is_dir)
+            .is_ignore()
+    }
+
+    // Fails reads of an ignored path
+    pub fn refuse(&self, path: &str) -> Result<(), ToolError> {
+        if self.is_ignored(path, false) {
+            return Err(ToolError::Ignored(path.trim().to_string()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use std::{fs, path::Path};
+
+use crate::error::ToolError;
+
+// Larger images are refused rather than sent; providers cap them at 20 MB
+const MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+// Rough cost of one image in a request, for budgets and rate limits
+pub const IMAGE_TOKENS: u32 = 1_000;
+
+// An image attached to the next user message, e.g. a mockup or screenshot
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub path: String,
+    pub media_type: &'static str,
+    pub data: String,
+}
+
+impl Image {
+    // Reads a PNG, JPEG, GIF or WebP file; the type is taken from its
+    // content, not its extension
+    pub fn load(path: &Path) -> Result<Self, ToolError> {
+        let display = path.display().to_string();
+        let bytes = fs::read(path).map_err(|source| ToolError::Read {
+            path: display.clone(),
+            source,
+        })?;
+        let Some(media_type) = media_type(&bytes) else {
+            return Err(ToolError::InvalidArgs(format!(
+                "{} is not a PNG, JPEG, GIF or WebP image",
+                display
+            )));
+        };
+        if bytes.len() > MAX_IMAGE_BYTES {
+            return Err(ToolError::InvalidArgs(format!(
+                "{} is {} bytes; images are limited to {}",
+                display,
+                bytes.len(),
+                MAX_IMAGE_BYTES
+            )));
+        }
+        Ok(Self {
+            path: display,
+            media_type,
+            data: STANDARD.encode(&bytes),
+        })
+    }
+
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.media_type, self.data)
+    }
+}
+
+fn media_type(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG") {
+        Some("image/png")
+    } else if bytes.starts_with(b"\xFF\xD8\xFF") {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"GIF8") {
+        Some("image/gif")
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
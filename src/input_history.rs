@@ -0,0 +1,131 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+};
+
+use crate::overlay;
+
+const HISTORY_FILE: &str = ".botcoder/input_history.jsonl";
+// Entries read back at startup
+const MAX_ENTRIES: usize = 500;
+
+// What was typed into the message box, oldest first, kept across runs in
+// .botcoder/input_history.jsonl (one JSON string per line, as entries can
+// span lines). ↑ and ↓ walk through it, Ctrl+R searches it.
+#[derive(Default)]
+pub struct InputHistory {
+    path: Option<PathBuf>,
+    entries: Vec<String>,
+    // The entry shown while browsing, and the unsent text it replaced
+    position: Option<usize>,
+    draft: String,
+}
+
+// Ctrl+R: the text searched for and the entry it found
+#[derive(Default)]
+pub struct HistorySearch {
+    pub query: String,
+    pub matched: Option<usize>,
+}
+
+impl InputHistory {
+    pub fn load(project_root: &str) -> Self {
+        let path = overlay::state_path(project_root, HISTORY_FILE);
+        let mut entries: Vec<String> = fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        let excess = entries.len().saturating_sub(MAX_ENTRIES);
+        entries.drain(..excess);
+        Self {
+            path: Some(path),
+            entries,
+            ..Self::default()
+        }
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    // Records a sent entry, unless blank or a repeat of the last one, and
+    // stops browsing
+    pub fn push(&mut self, entry: &str) {
+        self.position = None;
+        self.draft.clear();
+        if entry.trim().is_empty() || self.entries.last().is_some_and(|last| last == entry) {
+            return;
+        }
+        self.entries.push(entry.to_string());
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let line = serde_json::to_string(entry).unwrap_or_default();
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", line));
+        if let Err(e) = written {
+            log::warn!("Could not write {}: {}", path.display(), e);
+        }
+    }
+
+    pub fn is_browsing(&self) -> bool {
+        self.position.is_some()
+    }
+
+    // The entry before the one shown; `current` is kept to come back to
+    pub fn older(&mut self, current: &str) -> Option<String> {
+        let position = match self.position {
+            None if self.entries.is_empty() => return None,
+            None => {
+                self.draft = current.to_string();
+                self.entries.len() - 1
+            }
+            Some(position) => position.saturating_sub(1),
+        };
+        self.position = Some(position);
+        Some(self.entries[position].clone())
+    }
+
+    // The entry after the one shown, or the unsent text past the last one
+    pub fn newer(&mut self) -> String {
+        match self.position {
+            Some(position) if position + 1 < self.entries.len() => {
+                self.position = Some(position + 1);
+                self.entries[position + 1].clone()
+            }
+            _ => {
+                self.position = None;
+                std::mem::take(&mut self.draft)
+            }
+        }
+    }
+
+    // Finds the newest entry containing the query, older than the current
+    // match when `older` is set, ignoring case
+    pub fn search(&self, search: &mut HistorySearch, older: bool) {
+        let end = match search.matched {
+            Some(matched) if older => matched,
+            _ => self.entries.len(),
+        };
+        let query = search.query.to_lowercase();
+        let found = self.entries[..end]
+            .iter()
+            .rposition(|entry| entry.to_lowercase().contains(&query));
+        // Nothing older keeps the current match
+        if found.is_some() || !older {
+            search.matched = found;
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+}
@@ -0,0 +1,169 @@
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    process::{Child, Command, Output, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::sanitize;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static INPUT_PAUSED: AtomicBool = AtomicBool::new(false);
+// Where command output goes line by line as it arrives, for the TUI to tail
+// while the command runs
+static LIVE_OUTPUT: Mutex<Option<Sender<String>>> = Mutex::new(None);
+
+pub fn trigger() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// Resolves once Ctrl+C has been pressed; used to race in-flight LLM requests
+pub async fn wait() {
+    while !is_interrupted() {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+// Outside raw mode Ctrl+C arrives as SIGINT
+pub fn install_signal_handler() {
+    tokio::spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            trigger();
+        }
+    });
+}
+
+// Stops the input thread reading the terminal while another program, such
+// as $EDITOR, owns it. Pausing waits out a poll already in progress.
+pub fn pause_input(paused: bool) {
+    INPUT_PAUSED.store(paused, Ordering::SeqCst);
+    if paused {
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+// In raw mode Ctrl+C arrives as a key event, so terminal input is read on its
+// own thread where it can be noticed even while the main loop is busy
+pub fn spawn_input_thread() -> Receiver<Event> {
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || loop {
+        if INPUT_PAUSED.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(50));
+            continue;
+        }
+        match event::poll(Duration::from_millis(50)) {
+            Ok(true) => {
+                let Ok(ev) = event::read() else { break };
+                if let Event::Key(key) = &ev {
+                    if key.kind == KeyEventKind::Press
+                        && key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL)
+                    {
+                        trigger();
+                    }
+                }
+                if tx.send(ev).is_err() {
+                    break;
+                }
+            }
+            Ok(false) => {}
+            Err(_) => break,
+        }
+    });
+
+    rx
+}
+
+// Sends the lines of every command's stdout and stderr to `sink` as they
+// come, besides collecting them; None stops it
+pub fn stream_output(sink: Option<Sender<String>>) {
+    *LIVE_OUTPUT.lock().unwrap_or_else(|e| e.into_inner()) = sink;
+}
+
+// Like Command::output, but kills the child if Ctrl+C is pressed meanwhile
+pub fn output(mut cmd: Command) -> io::Result<Output> {
+    let child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    wait_for(child)
+}
+
+// output() with the given bytes written to the child's stdin
+pub fn output_with_stdin(mut cmd: Command, input: Vec<u8>) -> io::Result<Output> {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // Written on its own thread so a child that doesn't read its input
+        // can't block us on a full pipe
+        thread::spawn(move || {
+            let _ = stdin.write_all(&input);
+        });
+    }
+    wait_for(child)
+}
+
+fn wait_for(mut child: Child) -> io::Result<Output> {
+    let stdout = child.stdout.take().map(read_in_background);
+    let stderr = child.stderr.take().map(read_in_background);
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if is_interrupted() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "interrupted by user",
+            ));
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.and_then(|h| h.join().ok()).unwrap_or_default(),
+        stderr: stderr.and_then(|h| h.join().ok()).unwrap_or_default(),
+    })
+}
+
+fn read_in_background<R: Read + Send + 'static>(reader: R) -> thread::JoinHandle<Vec<u8>> {
+    let sink = LIVE_OUTPUT.lock().unwrap_or_else(|e| e.into_inner()).clone();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(reader);
+        let mut buf = Vec::new();
+        let Some(sink) = sink else {
+            let _ = reader.read_to_end(&mut buf);
+            return buf;
+        };
+        loop {
+            let start = buf.len();
+            match reader.read_until(b'\n', &mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    let line = String::from_utf8_lossy(&buf[start..]);
+                    let _ = sink.send(sanitize::clean(line.trim_end()));
+                }
+            }
+        }
+        buf
+    })
+}
@@ -0,0 +1,126 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::git;
+
+const PATCH_DIR: &str = ".botcoder/patches";
+
+// Numbers the worktrees of this process
+static CHECKOUTS: AtomicUsize = AtomicUsize::new(0);
+
+// What becomes of an isolated run's changes when it succeeds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Isolation {
+    // Applied to the checkout (--isolate)
+    Merge,
+    // Saved to .botcoder/patches/<run>.patch (--isolate-patch)
+    Patch,
+}
+
+// A temporary git worktree a fix or run works in instead of the checkout,
+// checked out from HEAD. Only a successful run's changes reach the checkout,
+// merged or as a patch; a failed one leaves it untouched.
+pub struct IsolatedCheckout {
+    // The top of the original repository and of the worktree
+    repo: String,
+    path: PathBuf,
+    // The project directory inside the worktree
+    root: String,
+    base: String,
+    isolation: Isolation,
+}
+
+impl IsolatedCheckout {
+    pub fn create(project_root: &str, isolation: Isolation) -> Result<Self, String> {
+        let repo = git::workdir(project_root)
+            .ok_or_else(|| format!("{} is not in a git repository", project_root))?;
+        let base = git::head_commit(&repo).ok_or("the repository has no commits yet")?;
+        let subdir = Path::new(project_root)
+            .canonicalize()
+            .ok()
+            .zip(Path::new(&repo).canonicalize().ok())
+            .and_then(|(root, repo)| root.strip_prefix(repo).map(Path::to_path_buf).ok())
+            .unwrap_or_default();
+        let path = env::temp_dir().join(format!(
+            "botcoder-isolated-{}-{}",
+            process::id(),
+            CHECKOUTS.fetch_add(1, Ordering::SeqCst)
+        ));
+        git::add_worktree(&repo, &path, None)?;
+        let root = if subdir.as_os_str().is_empty() {
+            path.clone()
+        } else {
+            path.join(subdir)
+        };
+        Ok(Self {
+            repo,
+            root: root.to_string_lossy().into_owned(),
+            path,
+            base,
+            isolation,
+        })
+    }
+
+    // Where the engine works
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    // Brings a successful run's changes back to the checkout at
+    // project_root, then deletes the worktree. Everything that changed in
+    // the worktree counts, whether the agent edited it, a command wrote it
+    // or it was committed there. A failed run's changes are kept as a patch
+    // in the temp directory. Returns a note for the user.
+    pub fn finish(self, succeeded: bool, project_root: &str, run: &str) -> String {
+        let note = self.bring_back(succeeded, project_root, run);
+        match git::remove_worktree(&self.repo, &self.path) {
+            Ok(()) => note,
+            Err(e) => format!("{}\n⚠ Could not remove the worktree {}: {}", note, self.path.display(), e),
+        }
+    }
+
+    fn bring_back(&self, succeeded: bool, project_root: &str, run: &str) -> String {
+        let patch = match git::patch(&self.root, &self.base) {
+            Ok(patch) if patch.is_empty() => {
+                return "✓ No changes to bring back; the checkout is untouched".to_string()
+            }
+            Ok(patch) => patch,
+            Err(e) => return format!("✗ Could not collect the changes: {}", e),
+        };
+
+        let path = if succeeded {
+            Path::new(project_root).join(PATCH_DIR).join(format!("{}.patch", run))
+        } else {
+            env::temp_dir().join(format!("botcoder-{}.patch", run))
+        };
+        let files = patch.lines().filter(|line| line.starts_with("diff --git ")).count();
+        let dir = path.parent().unwrap_or(Path::new("."));
+        if let Err(e) = fs::create_dir_all(dir).and_then(|_| fs::write(&path, &patch)) {
+            return format!("✗ Could not save the patch {}: {}", path.display(), e);
+        }
+        if !succeeded {
+            return format!(
+                "✓ The checkout is untouched; the failed run's changes are in {}",
+                path.display()
+            );
+        }
+        if self.isolation == Isolation::Patch {
+            return format!("✓ Saved the changes to {} file(s) as {}", files, path.display());
+        }
+        match git::apply(&self.repo, &path) {
+            Ok(()) => {
+                let _ = fs::remove_file(&path);
+                format!("✓ Merged the changes to {} file(s) into the checkout", files)
+            }
+            Err(e) => format!(
+                "✗ Could not merge the changes into the checkout ({}); they are in {}",
+                e,
+                path.display()
+            ),
+        }
+    }
+}
@@ -0,0 +1,82 @@
+// botcoder as a library: AgentEngine runs the agent loop, the rest are the
+// pieces it and the bundled frontends are built from
+pub mod app;
+pub mod audit;
+pub mod bench;
+pub mod bedrock;
+pub mod binary;
+pub mod cargo_tools;
+pub mod cli;
+pub mod clipboard;
+pub mod commands;
+pub mod completion;
+pub mod config;
+pub mod context;
+pub mod conventions;
+pub mod crates;
+pub mod deterministic;
+pub mod diff;
+pub mod dry_run;
+pub mod editor;
+pub mod encoding;
+pub mod engine;
+pub mod entra;
+pub mod error;
+pub mod error_context;
+pub mod events;
+pub mod exit;
+pub mod failures;
+pub mod fetch;
+pub mod file_cache;
+pub mod file_tree;
+pub mod filters;
+pub mod fix;
+pub mod git;
+pub mod github;
+pub mod glyphs;
+pub mod highlight;
+pub mod hooks;
+pub mod http;
+pub mod ignore_rules;
+pub mod image;
+pub mod input_history;
+pub mod interrupt;
+pub mod isolation;
+pub mod llm;
+pub mod limits;
+pub mod lock;
+pub mod mock;
+pub mod notify;
+pub mod oneshot;
+pub mod openai_compat;
+pub mod orchestrator;
+pub mod outline;
+pub mod overlay;
+pub mod parser;
+pub mod plan;
+pub mod plugins;
+pub mod project;
+pub mod quality;
+pub mod queue;
+pub mod rename;
+pub mod replay;
+pub mod router;
+pub mod sanitize;
+pub mod sandbox;
+pub mod semantic;
+pub mod server;
+pub mod sessions;
+pub mod test_runner;
+pub mod theme;
+pub mod timeline;
+pub mod tool_registry;
+pub mod tpm_limiter;
+pub mod ui;
+pub mod vfs;
+pub mod webhooks;
+pub mod workspace;
+pub mod write_scope;
+
+pub use engine::AgentEngine;
+pub use error::{AgentError, LlmError, ToolError};
+pub use events::{AgentEvent, AgentObserver};
@@ -0,0 +1,125 @@
+use serde::Deserialize;
+use std::{collections::BTreeMap, env};
+
+use crate::config;
+use crate::llm;
+
+const DEFAULT_TPM: u32 = 20_000;
+const DEFAULT_MIN_INTERVAL: u64 = 10;
+
+// The rate limits of one deployment
+#[derive(Clone, Debug, PartialEq)]
+pub struct LimitProfile {
+    // The [limits.<name>] it came from, "env" for the LLM_* variables
+    pub name: String,
+    pub tpm: u32,
+    pub rpm: u32,
+    // Requests in flight at once
+    pub burst: usize,
+    // Seconds between requests
+    pub min_interval: u64,
+}
+
+impl LimitProfile {
+    // LLM_TPM, LLM_RPM, LLM_MAX_CONCURRENT and LLM_MIN_INTERVAL
+    pub fn from_env() -> Self {
+        let tpm = var("LLM_TPM").unwrap_or(DEFAULT_TPM);
+        Self {
+            name: "env".to_string(),
+            tpm,
+            rpm: llm::rpm_from_env(tpm),
+            burst: var("LLM_MAX_CONCURRENT").unwrap_or(llm::DEFAULT_MAX_CONCURRENT),
+            min_interval: var("LLM_MIN_INTERVAL").unwrap_or(DEFAULT_MIN_INTERVAL),
+        }
+    }
+}
+
+// Named profiles picked by the model a request goes to, so deployments with
+// different quotas can be mixed without touching .env:
+//
+//   [limits.gpt4o]
+//   models = ["gpt-4o*"]
+//   provider = "azure"
+//   tpm = 450000
+//   rpm = 2700
+//
+// A limit a profile leaves out keeps its LLM_* value; rpm then follows tpm.
+#[derive(Default)]
+pub struct LimitProfiles {
+    profiles: Vec<(String, Rule)>,
+}
+
+#[derive(Deserialize)]
+struct Rule {
+    #[serde(default)]
+    models: Vec<String>,
+    provider: Option<String>,
+    tpm: Option<u32>,
+    rpm: Option<u32>,
+    burst: Option<usize>,
+    min_interval: Option<u64>,
+}
+
+impl LimitProfiles {
+    // No profiles without a .botcoder.toml; an invalid one is skipped with a
+    // warning
+    pub fn load(root: &str) -> Self {
+        let limits: BTreeMap<String, Rule> = config::section(root, "limits");
+        Self {
+            profiles: limits.into_iter().collect(),
+        }
+    }
+
+    // The first profile, by name, whose provider and model patterns match;
+    // a profile without models matches every model of its provider
+    pub fn for_model(&self, provider: &str, model: &str) -> Option<LimitProfile> {
+        let (name, rule) = self.profiles.iter().find(|(_, rule)| {
+            rule.provider.as_deref().is_none_or(|p| p.eq_ignore_ascii_case(provider))
+                && (rule.models.is_empty() || rule.models.iter().any(|m| matches(m, model)))
+        })?;
+        let defaults = LimitProfile::from_env();
+        let tpm = rule.tpm.unwrap_or(defaults.tpm);
+        Some(LimitProfile {
+            name: name.clone(),
+            tpm,
+            rpm: rule.rpm.unwrap_or_else(|| match rule.tpm {
+                Some(tpm) => llm::default_rpm(tpm),
+                None => defaults.rpm,
+            }),
+            burst: rule.burst.unwrap_or(defaults.burst),
+            min_interval: rule.min_interval.unwrap_or(defaults.min_interval),
+        })
+    }
+}
+
+// The profile for a client, from the .botcoder.toml of PROJECT_PATH
+pub fn for_client(provider: &str, model: &str) -> LimitProfile {
+    let root = env::var("PROJECT_PATH").unwrap_or_else(|_| ".".to_string());
+    LimitProfiles::load(&root)
+        .for_model(provider, model)
+        .unwrap_or_else(LimitProfile::from_env)
+}
+
+// A model name against a pattern where * stands for any text
+fn matches(pattern: &str, model: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = model.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+fn var<T: std::str::FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok().and_then(|v| v.parse().ok())
+}
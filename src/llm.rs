@@ -2,117 +2,473 @@ use async_trait::async_trait;
 use dotenvy::dotenv;
 use log::{error, info};
 use reqwest::Client;
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde::{ser::SerializeStruct, Deserialize, Serialize, Serializer};
+use serde_json::{json, Value};
 use std::collections::VecDeque;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
+
+use crate::bedrock::BedrockClient;
+use crate::deterministic;
+use crate::entra::{EntraCredential, TokenSource};
+use crate::error::LlmError;
+use crate::http;
+use crate::openai_compat::OpenAICompatClient;
+use crate::image::Image;
+use crate::limits::{self, LimitProfile};
 
 #[async_trait]
 pub trait LLMProvider: Send + Sync {
+    // Model or deployment name, shown in the UI and fallback notes
+    fn model(&self) -> &str;
+
+    // Matched against the provider of [limits.*] profiles, e.g. "azure"
+    fn provider(&self) -> &str {
+        ""
+    }
+
+    // Whether user messages may carry images
+    fn supports_images(&self) -> bool {
+        false
+    }
+
+    // Token usage the server reported for the latest reply
+    fn last_usage(&self) -> Option<Usage> {
+        None
+    }
+
+    // system_fingerprint of the latest reply, where the server sends one
+    fn last_fingerprint(&self) -> Option<String> {
+        None
+    }
+
     async fn generate(
         &self,
         prompt: &str,
         config: &Value,
-    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>>;
+    ) -> Result<String, LlmError>;
+
+    async fn generate_chat(
+        &self,
+        messages: &[ChatMessage],
+        config: &Value,
+    ) -> Result<String, LlmError>;
 }
 
+// Longest reply requested from the model
+pub const MAX_REPLY_TOKENS: u32 = 6_000;
+// Reasoning models spend part of the completion on hidden reasoning
+pub const MAX_REASONING_REPLY_TOKENS: u32 = 25_000;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AzureOpenAIConfig {
     pub endpoint: String,
-    pub api_key: String,
+    // None with Entra ID authentication (<PREFIX>_AUTH=entra)
+    pub api_key: Option<String>,
     pub api_version: String,
     pub deployment: String,
+    pub options: ModelOptions,
 }
 
+// Sampling parameters are left out for reasoning models, which reject them
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChatCompletionRequest {
     pub messages: Vec<ChatMessage>,
-    pub temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub max_tokens: Option<u32>,
-    pub top_p: f32,
-    pub frequency_penalty: f32,
-    pub presence_penalty: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_completion_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reasoning_effort: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
     pub model: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl ChatCompletionRequest {
+    // Reasoning models get max_completion_tokens and no sampling parameters
+    pub fn new(
+        messages: Vec<ChatMessage>,
+        model: &str,
+        options: &ModelOptions,
+        temperature: f32,
+        max_tokens: Option<u32>,
+    ) -> Self {
+        if options.reasoning {
+            Self {
+                messages,
+                temperature: None,
+                max_tokens: None,
+                max_completion_tokens: max_tokens.map(|_| MAX_REASONING_REPLY_TOKENS),
+                reasoning_effort: options.reasoning_effort.clone(),
+                top_p: None,
+                frequency_penalty: None,
+                presence_penalty: None,
+                seed: deterministic::seed(),
+                model: model.to_string(),
+            }
+        } else {
+            Self {
+                messages,
+                temperature: Some(deterministic::temperature(temperature)),
+                max_tokens,
+                max_completion_tokens: None,
+                reasoning_effort: None,
+                top_p: Some(1.0),
+                frequency_penalty: Some(0.0),
+                presence_penalty: Some(0.0),
+                seed: deterministic::seed(),
+                model: model.to_string(),
+            }
+        }
+    }
+
+    // Rough count for the rate limiter before the real usage is known
+    pub fn estimated_tokens(&self) -> u32 {
+        self.messages
+            .iter()
+            .map(|msg| msg.content.len() / 4)
+            .sum::<usize>() as u32
+            + 100
+    }
+}
+
+// What the model behind a prefix can do, detected from its name unless
+// <PREFIX>_REASONING or <PREFIX>_VISION is on or off
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ModelOptions {
+    // o1/o3-style model: no sampling parameters, max_completion_tokens
+    // instead of max_tokens
+    pub reasoning: bool,
+    // "low", "medium" or "high"; the model's default when unset
+    pub reasoning_effort: Option<String>,
+    // Takes images in user messages
+    pub vision: bool,
+}
+
+impl ModelOptions {
+    pub fn from_env(var: impl Fn(&str) -> Result<String, std::env::VarError>, model: &str) -> Self {
+        let switch = |name: &str, detected: bool| match var(name).as_deref() {
+            Ok("on" | "1" | "true") => true,
+            Ok("off" | "0" | "false") => false,
+            _ => detected,
+        };
+        Self {
+            reasoning: switch("REASONING", is_reasoning_model(model)),
+            reasoning_effort: var("REASONING_EFFORT").ok().filter(|e| !e.is_empty()),
+            vision: switch("VISION", is_vision_model(model)),
+        }
+    }
+}
+
+// The provider behind an env prefix: <PREFIX>_PROVIDER=openai for any
+// OpenAI-compatible endpoint, bedrock for Amazon Bedrock, Azure OpenAI
+// otherwise
+pub fn client_from_env(prefix: &str) -> Result<Box<dyn LLMProvider>, LlmError> {
+    dotenv().ok();
+    client_from(prefix, &env_var)
+}
+
+// As client_from_env, with the settings looked up in vars instead
+pub fn client_from(prefix: &str, vars: Vars) -> Result<Box<dyn LLMProvider>, LlmError> {
+    let provider = prefixed(vars, prefix)("PROVIDER").unwrap_or_default();
+    match provider.as_str() {
+        "openai" => Ok(Box::new(OpenAICompatClient::from_vars(prefix, vars)?)),
+        "bedrock" => Ok(Box::new(BedrockClient::from_vars(prefix, vars)?)),
+        "" | "azure" => Ok(Box::new(AzureOpenAIClient::from_vars(prefix, vars)?)),
+        other => Err(LlmError::InvalidConfig(format!(
+            "Unknown {}_PROVIDER \"{}\"; expected azure, openai or bedrock",
+            prefix, other
+        ))),
+    }
+}
+
+// Settings by variable name, e.g. LLM_MODEL
+pub type Vars<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+pub fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+// <PREFIX>_<NAME>, falling back to LLM_<NAME>
+pub(crate) fn prefixed<'a>(
+    vars: Vars<'a>,
+    prefix: &'a str,
+) -> impl Fn(&str) -> Result<String, std::env::VarError> + 'a {
+    move |name| {
+        vars(&format!("{}_{}", prefix, name))
+            .or_else(|| vars(&format!("LLM_{}", name)))
+            .ok_or(std::env::VarError::NotPresent)
+    }
+}
+
+// o1, o3, o4-mini, gpt-5 and the like, by model or deployment name
+pub fn is_reasoning_model(model: &str) -> bool {
+    let name = model.rsplit('/').next().unwrap_or(model).to_lowercase();
+    ["o1", "o3", "o4", "gpt-5"].iter().any(|prefix| name.starts_with(prefix))
+}
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct ChatMessage {
     pub role: String,
     pub content: String,
+    // Data URLs of attached images, sent as image_url content parts
+    #[serde(default)]
+    pub images: Vec<String>,
+}
+
+impl ChatMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new("system", content)
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new("user", content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new("assistant", content)
+    }
+
+    fn new(role: &str, content: impl Into<String>) -> Self {
+        Self {
+            role: role.to_string(),
+            content: content.into(),
+            images: Vec::new(),
+        }
+    }
+
+    pub fn with_images(mut self, images: &[Image]) -> Self {
+        self.images.extend(images.iter().map(Image::data_url));
+        self
+    }
+
+    // Tool output goes back as a user turn: the API's "tool" role is only
+    // valid in reply to native function calls, which this protocol doesn't use
+    pub fn tool_results(content: impl Into<String>) -> Self {
+        Self::user(format!("Tool Results:\n{}", content.into()))
+    }
+
+    pub fn is_tool_results(&self) -> bool {
+        self.role == "user" && self.content.starts_with("Tool Results:")
+    }
 }
 
+// Plain text content, or a list of text and image parts when images are
+// attached
+impl Serialize for ChatMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut message = serializer.serialize_struct("ChatMessage", 2)?;
+        message.serialize_field("role", &self.role)?;
+        if self.images.is_empty() {
+            message.serialize_field("content", &self.content)?;
+        } else {
+            let parts: Vec<Value> = std::iter::once(json!({"type": "text", "text": self.content}))
+                .chain(self.images.iter().map(|url| {
+                    json!({"type": "image_url", "image_url": {"url": url}})
+                }))
+                .collect();
+            message.serialize_field("content", &parts)?;
+        }
+        message.end()
+    }
+}
+
+// Models that take image input, by model or deployment name
+pub fn is_vision_model(model: &str) -> bool {
+    let name = model.rsplit('/').next().unwrap_or(model).to_lowercase();
+    let text_only = ["o1-mini", "o1-preview", "o3-mini"];
+    let vision = ["gpt-4o", "gpt-4.1", "gpt-4-turbo", "gpt-5", "o1", "o3", "o4", "claude", "gemini"];
+    !text_only.iter().any(|prefix| name.starts_with(prefix))
+        && vision.iter().any(|prefix| name.starts_with(prefix))
+}
+
+// OpenAI-compatible servers leave out some of the fields Azure sends
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChatCompletionResponse {
+    #[serde(default)]
     pub id: String,
+    #[serde(default)]
     pub object: String,
+    #[serde(default)]
     pub created: u64,
     pub choices: Vec<ChatChoice>,
+    #[serde(default)]
     pub usage: Usage,
+    // Backend configuration the reply came from, for deterministic runs
+    #[serde(default)]
+    pub system_fingerprint: Option<String>,
+}
+
+impl ChatCompletionResponse {
+    // The first choice's content. Separate reasoning is passed on as a
+    // <think> block, the same way models that reason inline return it; the
+    // engine splits it off.
+    pub fn into_text(self) -> Result<String, LlmError> {
+        let Some(choice) = self.choices.into_iter().next() else {
+            return Err(LlmError::EmptyResponse);
+        };
+        let content = choice.message.content.unwrap_or_default();
+        match choice.message.reasoning_content.filter(|r| !r.trim().is_empty()) {
+            Some(reasoning) => Ok(format!("<think>{}</think>\n{}", reasoning, content)),
+            None => Ok(content),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChatChoice {
+    #[serde(default)]
     pub index: u32,
-    pub message: ChatMessage,
+    pub message: ResponseMessage,
     pub finish_reason: Option<String>,
 }
 
+// Reasoning models may leave content empty, and some OpenAI-compatible
+// servers return their reasoning next to it
 #[derive(Debug, Serialize, Deserialize)]
+pub struct ResponseMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: Option<String>,
+    // "reasoning" on OpenRouter
+    #[serde(default, alias = "reasoning")]
+    pub reasoning_content: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
 pub struct Usage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
 }
 
+// Requests in flight at once when no limit is given
+pub const DEFAULT_MAX_CONCURRENT: usize = 4;
+
+// Azure grants 6 requests per minute for every 1000 tokens per minute
+pub fn default_rpm(max_tpm: u32) -> u32 {
+    (max_tpm / 1000 * 6).max(1)
+}
+
+// LLM_RPM, or the rate Azure pairs with the TPM limit
+pub fn rpm_from_env(max_tpm: u32) -> u32 {
+    std::env::var("LLM_RPM")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_else(|| default_rpm(max_tpm))
+}
+
 pub struct RateLimiter {
     max_tpm: u32,
+    max_rpm: u32,
     requests: Arc<Mutex<VecDeque<(Instant, u32)>>>,
+    // Requests in flight, so a burst doesn't open more connections than
+    // the deployment accepts
+    in_flight: Arc<Semaphore>,
     total_tokens_used: Arc<Mutex<u32>>,
+    last_usage: std::sync::Mutex<Option<Usage>>,
+    last_fingerprint: std::sync::Mutex<Option<String>>,
 }
 
 impl RateLimiter {
     pub fn new(max_tpm: u32) -> Self {
+        Self::with_limits(max_tpm, default_rpm(max_tpm), DEFAULT_MAX_CONCURRENT)
+    }
+
+    pub fn with_limits(max_tpm: u32, max_rpm: u32, max_concurrent: usize) -> Self {
         Self {
             max_tpm,
+            max_rpm: max_rpm.max(1),
             requests: Arc::new(Mutex::new(VecDeque::new())),
+            in_flight: Arc::new(Semaphore::new(max_concurrent.max(1))),
             total_tokens_used: Arc::new(Mutex::new(0)),
+            last_usage: std::sync::Mutex::new(None),
+            last_fingerprint: std::sync::Mutex::new(None),
         }
     }
 
-    pub async fn wait_if_needed(&self, estimated_tokens: u32) {
-        let mut requests = self.requests.lock().await;
-        let now = Instant::now();
-        let one_minute_ago = now - Duration::from_secs(60);
+    // Waits for a free request slot and for room in both the token and the
+    // request window; the request counts as in flight until the permit is
+    // dropped
+    pub async fn wait_if_needed(&self, estimated_tokens: u32) -> OwnedSemaphorePermit {
+        let permit = self
+            .in_flight
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("the request semaphore is never closed");
 
-        while let Some((time, _)) = requests.front() {
-            if *time < one_minute_ago {
-                requests.pop_front();
+        let mut requests = self.requests.lock().await;
+        loop {
+            let now = Instant::now();
+            let one_minute_ago = now - Duration::from_secs(60);
+            requests.retain(|(time, _)| *time >= one_minute_ago);
+
+            let current_tpm: u32 = requests.iter().map(|(_, tokens)| tokens).sum();
+            let limit = if requests.len() as u32 >= self.max_rpm {
+                "RPM"
+            } else if current_tpm + estimated_tokens > self.max_tpm && !requests.is_empty() {
+                "TPM"
             } else {
                 break;
-            }
+            };
+            let Some((oldest_time, _)) = requests.front() else {
+                break;
+            };
+            let wait_time = Duration::from_secs(60).saturating_sub(now.duration_since(*oldest_time))
+                + Duration::from_millis(100);
+            info!("{} limit reached, waiting {}ms", limit, wait_time.as_millis());
+            tokio::time::sleep(wait_time).await;
         }
 
-        let current_tpm: u32 = requests.iter().map(|(_, tokens)| tokens).sum();
+        requests.push_back((Instant::now(), estimated_tokens));
+        *self.total_tokens_used.lock().await += estimated_tokens;
+        permit
+    }
+
+    pub fn from_profile(profile: &LimitProfile) -> Self {
+        Self::with_limits(profile.tpm, profile.rpm, profile.burst)
+    }
 
-        if current_tpm + estimated_tokens > self.max_tpm {
-            if let Some((oldest_time, _)) = requests.front() {
-                let elapsed = now.duration_since(*oldest_time);
-                if elapsed < Duration::from_secs(60) {
-                    let wait_time = Duration::from_secs(60) - elapsed + Duration::from_millis(100);
-                    info!("TPM limit reached, waiting {}ms", wait_time.as_millis());
-                    tokio::time::sleep(wait_time).await;
+    // Replaces the estimate for the latest request with the usage the
+    // server reported; servers that report none leave it as it is
+    pub async fn record_usage(&self, usage: Usage) {
+        let reported = usage.total_tokens > 0;
+        if let Ok(mut last) = self.last_usage.lock() {
+            *last = reported.then_some(usage);
+        }
+        if !reported {
+            return;
+        }
+        info!("Actual token usage: {}", usage.total_tokens);
+        let mut requests = self.requests.lock().await;
+        if let Some(back) = requests.back_mut() {
+            back.1 = usage.total_tokens;
+        }
+    }
 
-                    let now = Instant::now();
-                    let one_minute_ago = now - Duration::from_secs(60);
-                    requests.retain(|(time, _)| *time >= one_minute_ago);
-                }
-            }
+    pub fn last_usage(&self) -> Option<Usage> {
+        self.last_usage.lock().ok().and_then(|last| *last)
+    }
+
+    pub fn record_fingerprint(&self, fingerprint: Option<String>) {
+        if let Ok(mut last) = self.last_fingerprint.lock() {
+            *last = fingerprint;
         }
+    }
 
-        requests.push_back((now, estimated_tokens));
-        *self.total_tokens_used.lock().await += estimated_tokens;
+    pub fn last_fingerprint(&self) -> Option<String> {
+        self.last_fingerprint.lock().ok().and_then(|last| last.clone())
     }
 
     pub async fn get_current_tpm(&self) -> u32 {
@@ -127,6 +483,12 @@ impl RateLimiter {
             .sum()
     }
 
+    pub async fn get_current_rpm(&self) -> u32 {
+        let requests = self.requests.lock().await;
+        let one_minute_ago = Instant::now() - Duration::from_secs(60);
+        requests.iter().filter(|(time, _)| *time >= one_minute_ago).count() as u32
+    }
+
     pub async fn get_total_tokens(&self) -> u32 {
         *self.total_tokens_used.lock().await
     }
@@ -134,36 +496,53 @@ impl RateLimiter {
 
 pub struct AzureOpenAIClient {
     config: AzureOpenAIConfig,
+    // Bearer tokens in place of the api-key header
+    entra: Option<EntraCredential>,
     client: Client,
     rate_limiter: Arc<RateLimiter>,
 }
 
 impl AzureOpenAIClient {
-    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        dotenv().ok();
+    pub fn new() -> Result<Self, LlmError> {
+        Self::from_env_prefix("LLM")
+    }
 
-        let endpoint = std::env::var("LLM_URL").map_err(|_| "LLM_URL not set")?;
-        let api_key = std::env::var("LLM_KEY").map_err(|_| "LLM_KEY not set")?;
-        let api_version =
-            std::env::var("LLM_VERSION").unwrap_or_else(|_| "2024-05-01-preview".to_string());
-        let deployment = std::env::var("LLM_MODEL").unwrap_or_else(|_| "gpt-4".to_string());
+    // Reads <PREFIX>_URL, <PREFIX>_KEY, <PREFIX>_VERSION and <PREFIX>_MODEL,
+    // falling back to the LLM_* variables for anything not set.
+    // <PREFIX>_AUTH=entra authenticates with Entra ID instead of a key.
+    pub fn from_env_prefix(prefix: &str) -> Result<Self, LlmError> {
+        dotenv().ok();
+        Self::from_vars(prefix, &env_var)
+    }
 
-        let tpm_limit: u32 = std::env::var("LLM_TPM")
-            .unwrap_or_else(|_| "20000".to_string())
-            .parse()
-            .unwrap_or(20000);
+    pub fn from_vars(prefix: &str, vars: Vars) -> Result<Self, LlmError> {
+        let var = prefixed(vars, prefix);
+        let endpoint = var("URL").map_err(|_| LlmError::MissingConfig(format!("{}_URL", prefix)))?;
+        let entra = matches!(var("AUTH").as_deref(), Ok("entra" | "aad"));
+        let api_key = if entra {
+            None
+        } else {
+            Some(var("KEY").map_err(|_| LlmError::MissingConfig(format!("{}_KEY", prefix)))?)
+        };
+        let api_version = var("VERSION").unwrap_or_else(|_| "2024-05-01-preview".to_string());
+        let deployment = var("MODEL").unwrap_or_else(|_| "gpt-4".to_string());
+        let options = ModelOptions::from_env(&var, &deployment);
+        let rate_limiter = RateLimiter::from_profile(&limits::for_client("azure", &deployment));
 
         let config = AzureOpenAIConfig {
             endpoint,
             api_key,
             api_version,
             deployment,
+            options,
         };
 
+        let client = http::client()?;
         Ok(Self {
             config,
-            client: Client::new(),
-            rate_limiter: Arc::new(RateLimiter::new(tpm_limit)),
+            entra: entra.then(|| EntraCredential::new(TokenSource::from_env(), client.clone())),
+            client,
+            rate_limiter: Arc::new(rate_limiter),
         })
     }
 
@@ -176,103 +555,115 @@ impl AzureOpenAIClient {
         messages: Vec<ChatMessage>,
         temperature: f32,
         max_tokens: Option<u32>,
-    ) -> Result<ChatCompletionResponse, Box<dyn std::error::Error>> {
+    ) -> Result<ChatCompletionResponse, LlmError> {
         let url = format!(
             "{}/chat/completions?api-version={}",
             self.config.endpoint, self.config.api_version
         );
 
-        let request_body = ChatCompletionRequest {
+        let request_body = ChatCompletionRequest::new(
             messages,
+            &self.config.deployment,
+            &self.config.options,
             temperature,
             max_tokens,
-            top_p: 1.0,
-            frequency_penalty: 0.0,
-            presence_penalty: 0.0,
-            model: self.config.deployment.clone(),
-        };
-
-        let estimated_tokens = request_body
-            .messages
-            .iter()
-            .map(|msg| msg.content.len() / 4)
-            .sum::<usize>() as u32
-            + 100;
-
-        self.rate_limiter.wait_if_needed(estimated_tokens).await;
+        );
+        let _permit = self
+            .rate_limiter
+            .wait_if_needed(request_body.estimated_tokens())
+            .await;
 
         info!("Sending request to Azure OpenAI");
 
-        let response = self
-            .client
-            .post(&url)
-            .header("api-key", &self.config.api_key)
+        let request = self.client.post(&url);
+        let request = match (&self.entra, &self.config.api_key) {
+            (Some(entra), _) => request.bearer_auth(entra.token().await?),
+            (None, Some(key)) => request.header("api-key", key),
+            (None, None) => request,
+        };
+        let response = request
             .header("Content-Type", "application/json")
             .json(&request_body)
             .send()
-            .await?;
+            .await
+            .map_err(|e| http::connection_error(&url, e))?;
 
         if !response.status().is_success() {
-            let error_text = response.text().await?;
-            error!("Azure OpenAI API error: {}", error_text);
-            return Err(format!("API error: {}", error_text).into());
+            let status = response.status().as_u16();
+            if let (401, Some(entra)) = (status, &self.entra) {
+                entra.invalidate().await;
+            }
+            let body = response.text().await?;
+            error!("Azure OpenAI API error ({}): {}", status, body);
+            return Err(LlmError::Api { status, body });
         }
 
         let completion_response: ChatCompletionResponse = response.json().await?;
-
-        if let Some(usage) = Some(&completion_response.usage) {
-            let actual_tokens = usage.total_tokens;
-            info!("Actual token usage: {}", actual_tokens);
-
-            let mut requests = self.rate_limiter.requests.lock().await;
-            if let Some(back) = requests.back_mut() {
-                back.1 = actual_tokens;
-            }
-        }
+        self.rate_limiter.record_usage(completion_response.usage).await;
+        self.rate_limiter
+            .record_fingerprint(completion_response.system_fingerprint.clone());
 
         Ok(completion_response)
     }
 
-    pub async fn simple_chat(&self, prompt: &str) -> Result<String, Box<dyn std::error::Error>> {
+    pub async fn simple_chat(&self, prompt: &str) -> Result<String, LlmError> {
         let messages = vec![
-            ChatMessage {
-                role: "system".to_string(),
-                content: "You are a helpful AI coding assistant.".to_string(),
-            },
-            ChatMessage {
-                role: "user".to_string(),
-                content: prompt.to_string(),
-            },
+            ChatMessage::system("You are a helpful AI coding assistant."),
+            ChatMessage::user(prompt),
         ];
 
-        let response = self.chat_completions(messages, 0.7, Some(6000)).await?;
+        self.chat(messages).await
+    }
 
-        if let Some(choice) = response.choices.first() {
-            Ok(choice.message.content.clone())
-        } else {
-            Err(" No response from AI".into())
-        }
+    pub async fn chat(
+        &self,
+        messages: Vec<ChatMessage>,
+    ) -> Result<String, LlmError> {
+        self.chat_completions(messages, 0.7, Some(MAX_REPLY_TOKENS))
+            .await?
+            .into_text()
     }
 }
 
 #[async_trait]
 impl LLMProvider for AzureOpenAIClient {
+    fn model(&self) -> &str {
+        &self.config.deployment
+    }
+
+    fn provider(&self) -> &str {
+        "azure"
+    }
+
+    fn supports_images(&self) -> bool {
+        self.config.options.vision
+    }
+
+    fn last_usage(&self) -> Option<Usage> {
+        self.rate_limiter.last_usage()
+    }
+
+    fn last_fingerprint(&self) -> Option<String> {
+        self.rate_limiter.last_fingerprint()
+    }
+
     async fn generate(
         &self,
         prompt: &str,
         _config: &Value,
-    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    ) -> Result<String, LlmError> {
         info!("Generating response...");
 
-        match self.simple_chat(prompt).await {
-            Ok(content) => Ok(content),
-            Err(e) => {
-                let err = std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("Generation failed: {}", e),
-                );
-                Err(Box::new(err))
-            }
-        }
+        self.simple_chat(prompt).await
+    }
+
+    async fn generate_chat(
+        &self,
+        messages: &[ChatMessage],
+        _config: &Value,
+    ) -> Result<String, LlmError> {
+        info!("Generating response from {} messages...", messages.len());
+
+        self.chat(messages.to_vec()).await
     }
 }
@@ -0,0 +1,128 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::PathBuf,
+    process::{self, Command},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::overlay;
+
+// Held by the running instance so a second agent on the same project
+// doesn't apply deltas on top of the first one's edits
+const LOCK_FILE: &str = ".botcoder/lock";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub host: String,
+    // Unix time the lock was taken
+    pub started: u64,
+}
+
+impl LockHolder {
+    fn current() -> Self {
+        Self {
+            pid: process::id(),
+            host: hostname(),
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        }
+    }
+
+    // A lock left behind by a process on this host that has since exited
+    fn is_stale(&self) -> bool {
+        self.host == hostname() && !process_alive(self.pid)
+    }
+}
+
+// Removed again when dropped. std::process::exit skips destructors, so drop
+// it before exiting.
+pub struct SessionLock {
+    path: PathBuf,
+}
+
+impl SessionLock {
+    // Takes the lock unless another live instance holds it, in which case
+    // the holder is returned. force takes it over regardless.
+    pub fn acquire(root: &str, force: bool) -> Result<Self, LockHolder> {
+        let path = overlay::state_path(root, LOCK_FILE);
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    let holder = serde_json::to_string(&LockHolder::current()).unwrap_or_default();
+                    let _ = file.write_all(holder.as_bytes());
+                    return Ok(Self { path });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let holder = fs::read_to_string(&path)
+                        .ok()
+                        .and_then(|content| serde_json::from_str::<LockHolder>(&content).ok());
+                    match holder {
+                        // Unreadable locks are treated as stale
+                        Some(holder) if !force && !holder.is_stale() => return Err(holder),
+                        _ => {
+                            log::warn!("Taking over the session lock at {}", path.display());
+                            if let Err(e) = fs::remove_file(&path) {
+                                log::warn!("Could not remove {}: {}", path.display(), e);
+                                return Ok(Self::none());
+                            }
+                        }
+                    }
+                }
+                // Read-only project or similar: run without a lock
+                Err(e) => {
+                    log::warn!("Could not create {}: {}", path.display(), e);
+                    return Ok(Self::none());
+                }
+            }
+        }
+    }
+
+    fn none() -> Self {
+        Self {
+            path: PathBuf::new(),
+        }
+    }
+}
+
+impl Drop for SessionLock {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().is_empty() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn hostname() -> String {
+    env::var("HOSTNAME")
+        .or_else(|_| env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn process_alive(pid: u32) -> bool {
+    if cfg!(target_os = "windows") {
+        Command::new("tasklist")
+            .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+            .output()
+            .map(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()))
+            .unwrap_or(true)
+    } else {
+        Command::new("kill")
+            .args(["-0", &pid.to_string()])
+            .output()
+            .map(|output| output.status.success())
+            .unwrap_or(true)
+    }
+}
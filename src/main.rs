@@ -1,104 +1,917 @@
 use color_eyre::eyre::Result;
 use crossterm::{
-    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    event::{
+        DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture, Event,
+        KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use dotenvy::dotenv;
-use ratatui::{backend::CrosstermBackend, Terminal};
+use ratatui::{backend::CrosstermBackend, layout::Rect, Terminal};
 use std::{
     env, fs,
-    io::{self, stdout},
-    time::Duration,
+    io::{self, stdout, IsTerminal, Read, Write},
+    path::Path,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-mod app;
-mod llm;
-mod tpm_limiter;
-mod ui;
-
-use app::AppState;
-use llm::{AzureOpenAIClient, LLMProvider};
-use tpm_limiter::TPMLimiter;
-use ui::draw_ui;
+use botcoder::app::{self, AppState, DetailView, Focus, RunMode, RunningTool, TimelineView, ToolOutput};
+use botcoder::cli::{self, Mode, SessionsCommand};
+use botcoder::file_tree::{self, FileMark};
+use botcoder::mock::MockProvider;
+use botcoder::events::{self, JsonObserver, SessionRecorder};
+use botcoder::fix::{ConsoleObserver, FixLoop};
+use botcoder::git::{self, DirtyPolicy};
+use botcoder::limits::LimitProfile;
+use botcoder::lock::SessionLock;
+use botcoder::oneshot::{self, RunEnd};
+use botcoder::orchestrator::{self, Overview, SessionFactory};
+use botcoder::github::GitHubConfig;
+use botcoder::input_history::{HistorySearch, InputHistory};
+use botcoder::isolation::IsolatedCheckout;
+use botcoder::parser::ResponseParser;
+use botcoder::plan::{PlanStep, StepStatus, SubtaskBudget};
+use botcoder::replay::Replay;
+use botcoder::router::ModelRouter;
+use botcoder::semantic;
+use botcoder::queue::{QueueReport, TaskQueue};
+use botcoder::sessions::SavedSession;
+use botcoder::theme::{Theme, THEME_NAMES};
+use botcoder::timeline::{self, Timeline};
+use botcoder::tpm_limiter::TPMLimiter;
+use botcoder::ui::{self, draw_ui};
+use botcoder::webhooks::{self, RunOutcome, RunSummary, Webhooks};
+use botcoder::workspace::Workspace;
+use botcoder::{
+    audit, bench, binary, clipboard, completion, deterministic, dry_run, editor, exit, glyphs, highlight, interrupt, notify, overlay, quality, queue, replay, server, sessions, write_scope, AgentEngine, AgentError, AgentEvent, ToolError,
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
     dotenv().ok();
 
-    // Setup terminal
-    enable_raw_mode()?;
-    let mut stdout = stdout();
-    execute!(stdout, EnterAlternateScreen)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+    let args = cli::parse_args();
+    glyphs::set_ascii(args.ascii || env::var("BOTCODER_ASCII").is_ok());
+    dry_run::set_enabled(args.dry_run);
+    write_scope::set_read_only(args.read_only);
+    overlay::set_enabled(args.patch_output.is_some());
+    deterministic::set_enabled(args.deterministic);
 
-    // Create app state
-    let mut app = AppState::default();
+    // fix is its build and tests, which don't run in a patch-only session
+    if args.patch_output.is_some() && matches!(args.mode, Mode::Fix { .. }) {
+        glyphs::say("✗ fix runs the build and tests on the project, which --patch-output turns off; use --isolate-patch instead");
+        std::process::exit(exit::ERROR);
+    }
+    // Task checks, queue checkpoints and reports all work on the project itself
+    if args.patch_output.is_some() {
+        let mode = match args.mode {
+            Mode::Queue { .. } => Some("queue"),
+            Mode::Parallel { .. } => Some("parallel"),
+            Mode::Bench { .. } => Some("bench"),
+            _ => None,
+        };
+        if let Some(mode) = mode {
+            glyphs::say(&format!("✗ {} runs task checks and writes to the project, which --patch-output turns off", mode));
+            std::process::exit(exit::ERROR);
+        }
+    }
 
-    let client = AzureOpenAIClient::new()
-        .map_err(|e| color_eyre::eyre::eyre!("Failed to create AzureOpenAIClient: {}", e))?;
+    if let Mode::VerifyAudit = args.mode {
+        let project_root = env::var("PROJECT_PATH").unwrap_or_else(|_| ".".to_string());
+        match audit::verify(&project_root) {
+            Ok(entries) => glyphs::say(&format!("✓ Audit log intact ({} entries)", entries)),
+            Err(e) => {
+                glyphs::say(&format!("✗ Audit log verification failed: {}", e));
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
 
-    let prompt = fs::read_to_string("prompt.txt").unwrap_or_else(|_| {
+    if let Mode::Sessions(command) = &args.mode {
+        let project_root = env::var("PROJECT_PATH").unwrap_or_else(|_| ".".to_string());
+        let result = match command {
+            SessionsCommand::List => {
+                let saved = sessions::list(&project_root);
+                if saved.is_empty() {
+                    glyphs::say("No saved sessions; /save <name> saves one");
+                }
+                for meta in saved {
+                    glyphs::say(&sessions::describe_meta(&meta));
+                }
+                Ok(())
+            }
+            SessionsCommand::Show(name) => {
+                sessions::load(&project_root, name).map(|s| println!("{}", s.describe()))
+            }
+            SessionsCommand::Delete(name) => sessions::delete(&project_root, name)
+                .map(|()| glyphs::say(&format!("✓ Deleted session {}", name))),
+        };
+        if let Err(e) = result {
+            glyphs::say(&format!("✗ {}", e));
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Mode::Replay { session, speed, step } = &args.mode {
+        let project_root = env::var("PROJECT_PATH").unwrap_or_else(|_| ".".to_string());
+        if session.is_empty() {
+            glyphs::say("✗ No session given: botcoder replay <session>");
+            std::process::exit(1);
+        }
+        let records = match replay::load(&project_root, session) {
+            Ok(records) => records,
+            Err(e) => {
+                glyphs::say(&format!("✗ {}", e));
+                std::process::exit(1);
+            }
+        };
+        let mut replay = Replay::new(session.as_str(), &records);
+        replay.speed = *speed;
+        replay.paused = *step;
+
+        let mut app = AppState {
+            file_tree: file_tree::scan(&project_root),
+            theme: if args.no_color {
+                Theme::plain()
+            } else {
+                Theme::from_env()
+            },
+            replay: Some(replay),
+            ..AppState::default()
+        };
+        let mut terminal = enter_terminal()?;
+        let result = replay_session(&mut terminal, &mut app, &project_root);
+        restore_terminal();
+        terminal.show_cursor()?;
+        return result;
+    }
+
+    let mut prompt = fs::read_to_string("prompt.txt").unwrap_or_else(|_| {
         "You are a helpful AI coding assistant.".to_string()
     });
+    if args.patch_output.is_some() {
+        prompt.push_str("\n\nYour edits are collected into a patch for review instead of being written to disk: reads show them, but commands, builds and tests are turned off. Work from reading the code.");
+    }
+    if args.read_only {
+        prompt.push_str("\n\nThis is a read-only session: read, search and run commands to analyse the project, but don't edit files or commit.");
+    }
 
     let project_root = env::var("PROJECT_PATH").unwrap_or_else(|_| ".".to_string());
 
-    let tpm_limit: u32 = env::var("LLM_TPM")
-        .unwrap_or_else(|_| "20000".to_string())
-        .parse()
-        .unwrap_or(20000);
+    // Bench tasks run on copies of their snapshots, so neither the lock nor
+    // the worktree check applies
+    let config = EngineConfig {
+        prompt: prompt.clone(),
+        replay: args.replay.clone(),
+        record: args.record.clone(),
+        // [limits.*] profiles in .botcoder.toml take over per model
+        limits: LimitProfile::from_env(),
+        share: 1,
+        workspace: Some(args.workspace),
+        subtasks: args.subtasks,
+    };
+    // Bench tasks and parallel sessions get plain engines
+    let plain = EngineConfig {
+        record: None,
+        workspace: None,
+        subtasks: false,
+        ..config.clone()
+    };
+
+    if let Mode::Bench { dir, max_iterations, baseline } = &args.mode {
+        interrupt::install_signal_handler();
+        let code = run_bench(dir, *max_iterations, baseline.as_deref(), args.json, &plain, &project_root).await;
+        if code != exit::SUCCESS {
+            std::process::exit(code);
+        }
+        return Ok(());
+    }
+
+    let lock = match SessionLock::acquire(&project_root, args.force_lock) {
+        Ok(lock) => lock,
+        Err(holder) => {
+            let held = format!(
+                "Another botcoder (pid {} on {}) is working on {}",
+                holder.pid, holder.host, project_root
+            );
+            // Only the chat mode has someone at the terminal to ask
+            match args.mode {
+                Mode::Chat if confirm(&format!("{}. Take over its lock? [y/N] ", held)) => {
+                    SessionLock::acquire(&project_root, true).unwrap_or_else(|_| unreachable!())
+                }
+                _ => {
+                    glyphs::say(&format!("✗ {}; use --force-lock to take over", held));
+                    std::process::exit(exit::LOCKED);
+                }
+            }
+        }
+    };
+
+    // Nothing is stashed in a dry run
+    let dirty_policy = match DirtyPolicy::from_env() {
+        DirtyPolicy::Stash | DirtyPolicy::Checkpoint if args.dry_run => DirtyPolicy::Allow,
+        policy => policy,
+    };
+    match git::prepare_worktree(&project_root, dirty_policy) {
+        Ok(Some(note)) => glyphs::say(&note),
+        Ok(None) => {}
+        Err(files) => {
+            glyphs::say(&format!(
+                "✗ Refusing to start with uncommitted changes (BOTCODER_DIRTY=refuse) in {} file(s):",
+                files.len()
+            ));
+            for file in files {
+                glyphs::say(&format!("  {}", file));
+            }
+            drop(lock);
+            std::process::exit(exit::POLICY_VIOLATION);
+        }
+    }
 
-    let min_interval_secs: u64 = env::var("LLM_MIN_INTERVAL")
-        .unwrap_or_else(|_| "10".to_string())
-        .parse()
-        .unwrap_or(10);
+    // With --isolate, fix and run work in a temporary worktree and the
+    // checkout only gets the changes of a successful run
+    let isolated = match args.isolate {
+        Some(isolation) if matches!(args.mode, Mode::Fix { .. } | Mode::Run { .. }) && !args.dry_run => {
+            match IsolatedCheckout::create(&project_root, isolation) {
+                Ok(checkout) => {
+                    eprintln!("{}", glyphs::plain(&format!("✓ Working in the isolated worktree {}", checkout.root())));
+                    Some(checkout)
+                }
+                Err(e) => {
+                    glyphs::say(&format!("✗ Could not isolate the run: {}", e));
+                    drop(lock);
+                    std::process::exit(exit::ERROR);
+                }
+            }
+        }
+        _ => None,
+    };
+
+    // Serve mode builds one engine per session
+    let new_engine = {
+        let config = config.clone();
+        let project_root = isolated.as_ref().map_or(project_root.clone(), |c| c.root().to_string());
+        move || build_engine(&config, &project_root)
+    };
 
-    let mut tpm_limiter = TPMLimiter::new(tpm_limit, min_interval_secs);
-    app.stats.max_tpm = tpm_limit;
+    // Keeps the semantic search index current while the session runs
+    let _index_watcher = semantic::IndexWatcher::from_env(&project_root);
+
+    if let Mode::Serve { port, max_iterations } = args.mode {
+        interrupt::install_signal_handler();
+        let result = server::serve(port, project_root, max_iterations, Arc::new(new_engine)).await;
+        if let Err(e) = result {
+            eprintln!("Server error: {}", e);
+            drop(lock);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Mode::Queue { file, max_iterations } = &args.mode {
+        interrupt::install_signal_handler();
+        let code = run_queue(file, *max_iterations, args.json, &config, &project_root).await;
+        drop(lock);
+        std::process::exit(code);
+    }
+
+    if let Mode::Parallel { file, sessions, max_iterations } = &args.mode {
+        interrupt::install_signal_handler();
+        // Each session works on its own worktree with its share of the limits
+        let config = EngineConfig {
+            share: *sessions as u32,
+            ..plain
+        };
+        let code = run_parallel(file, *sessions, *max_iterations, &args, config, &project_root).await;
+        drop(lock);
+        std::process::exit(code?);
+    }
+
+    let mut engine = match new_engine() {
+        Ok(engine) => engine,
+        Err(e) => {
+            eprintln!("Failed to set up the model client: {}", e);
+            drop(lock);
+            std::process::exit(e.exit_code());
+        }
+    };
+
+    for image in &args.images {
+        if let Err(e) = engine.attach_image(image) {
+            eprintln!("Could not attach {}: {}", image, e);
+            drop(lock);
+            std::process::exit(AgentError::from(e).exit_code());
+        }
+    }
+
+    // Console runs started at a terminal take the model's questions there
+    if matches!(args.mode, Mode::Fix { .. } | Mode::Run { .. }) && io::stdin().is_terminal() {
+        engine = engine.with_asker(oneshot::ask_on_terminal);
+    }
+
+    if let Mode::Fix { max_iterations } = args.mode {
+        interrupt::install_signal_handler();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let id = format!("fix-{:x}", timestamp);
+        let session = SessionRecorder::default();
+        engine.subscribe(session.clone());
+        // Logged like a chat session, for botcoder replay
+        engine.subscribe(Timeline::new(&project_root, &id));
+        let mut fix = if args.json {
+            engine.subscribe(JsonObserver);
+            FixLoop::new(&mut engine, max_iterations).with_json_output()
+        } else {
+            engine.subscribe(ConsoleObserver::default());
+            FixLoop::new(&mut engine, max_iterations)
+        };
+        let result = fix.run().await;
+        let iterations = fix.iterations();
+        let code = match &result {
+            Ok(true) => exit::SUCCESS,
+            Ok(false) if interrupt::is_interrupted() => exit::GAVE_UP,
+            Ok(false) => exit::BUDGET_EXCEEDED,
+            Err(e) => {
+                glyphs::say(&format!("✗ {}", e));
+                e.exit_code()
+            }
+        };
+        if let (Ok(true), false, Some(github)) =
+            (&result, args.dry_run || overlay::enabled(), GitHubConfig::from_env())
+        {
+            let summary = format!(
+                "Fixes the build and tests (green after {} iteration(s)).",
+                iterations
+            );
+            match github.open_pull_request(&mut engine, &id, &summary).await {
+                Ok(url) => glyphs::say(&format!("✓ Opened pull request {}", url)),
+                Err(e) => glyphs::say(&format!("✗ Could not open a pull request: {}", e)),
+            }
+        }
+        let task = "Fix the build and tests";
+        let summary = match &result {
+            Ok(true) => RunSummary::new(RunOutcome::Succeeded, &id, task),
+            Ok(false) if interrupt::is_interrupted() => {
+                RunSummary::new(RunOutcome::Failed, &id, task).with_error("Interrupted")
+            }
+            Ok(false) => RunSummary::new(RunOutcome::BudgetExceeded, &id, task),
+            Err(e) => RunSummary::new(RunOutcome::Failed, &id, task).with_error(e.to_string()),
+        };
+        let criteria = if quality::gate_enabled() {
+            "build, tests, clippy and rustfmt pass"
+        } else {
+            "build and tests pass"
+        };
+        let summary = summary
+            .with_usage(iterations, engine.tpm_limiter().get_total_tokens())
+            .with_files(engine.modified_files())
+            .with_success_check(criteria, matches!(result, Ok(true)))
+            .with_exit_code(code);
+        finish_headless_run(&project_root, summary, &session, args.result.as_deref()).await;
+        write_patch_output(args.patch_output.as_deref(), engine.project_root());
+        if let Some(checkout) = isolated {
+            glyphs::say(&checkout.finish(code == exit::SUCCESS, &project_root, &id));
+        }
+        drop(lock);
+        std::process::exit(code);
+    }
+
+    if let Mode::Run { task, max_iterations } = &args.mode {
+        interrupt::install_signal_handler();
+        let mut task = task.clone().unwrap_or_default();
+        if task.is_empty() {
+            if let Err(e) = io::stdin().read_to_string(&mut task) {
+                eprintln!("Could not read the task from stdin: {}", e);
+            }
+        }
+        if task.trim().is_empty() {
+            eprintln!("No task given: botcoder run \"<task>\", or pipe it in with botcoder run -");
+            drop(lock);
+            std::process::exit(exit::ERROR);
+        }
+        // stdout is left to the result, or the JSON events with --json
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let id = format!("run-{:x}", timestamp);
+        let session = SessionRecorder::default();
+        engine.subscribe(session.clone());
+        engine.subscribe(Timeline::new(&project_root, &id));
+        if args.json {
+            engine.subscribe(JsonObserver);
+        } else {
+            engine.subscribe(ConsoleObserver::on_stderr());
+        }
+        let task = task.trim();
+        let result = oneshot::run(&mut engine, task, *max_iterations).await;
+        let code = match &result {
+            Ok(end) => {
+                if !args.json {
+                    println!("{}", engine.last_reply().unwrap_or_default().trim());
+                }
+                match end {
+                    RunEnd::OutOfIterations => eprintln!(
+                        "{}",
+                        glyphs::plain(&format!("✗ Stopped at the iteration limit ({})", max_iterations))
+                    ),
+                    RunEnd::OutOfTokens => eprintln!("{}", glyphs::plain("✗ Stopped at the token limit")),
+                    RunEnd::Interrupted => eprintln!("{}", glyphs::plain("⏹ Interrupted")),
+                    RunEnd::Success | RunEnd::Answered => {}
+                }
+                end.exit_code()
+            }
+            Err(e) => {
+                eprintln!("{}", glyphs::plain(&format!("✗ {}", e)));
+                e.exit_code()
+            }
+        };
+        let summary = match &result {
+            Ok(RunEnd::Success | RunEnd::Answered) => RunSummary::new(RunOutcome::Succeeded, &id, task),
+            Ok(RunEnd::OutOfIterations | RunEnd::OutOfTokens) => {
+                RunSummary::new(RunOutcome::BudgetExceeded, &id, task)
+            }
+            Ok(RunEnd::Interrupted) => {
+                RunSummary::new(RunOutcome::Failed, &id, task).with_error("Interrupted")
+            }
+            Err(e) => RunSummary::new(RunOutcome::Failed, &id, task).with_error(e.to_string()),
+        };
+        let summary = summary
+            .with_usage(engine.iteration(), engine.tpm_limiter().get_total_tokens())
+            .with_files(engine.modified_files())
+            .with_success_check(app::success_criteria(), engine.succeeded())
+            .with_exit_code(code);
+        finish_headless_run(&project_root, summary, &session, args.result.as_deref()).await;
+        write_patch_output(args.patch_output.as_deref(), engine.project_root());
+        if let Some(checkout) = isolated {
+            let note = checkout.finish(code == exit::SUCCESS, &project_root, &id);
+            eprintln!("{}", glyphs::plain(&note));
+        }
+        drop(lock);
+        std::process::exit(code);
+    }
+
+    // The conversation only carries a short summary of each tool result
+    let mut engine = engine.with_result_limit(50);
+    let (ui_observer, agent_events) = events::channel();
+    let session = SessionRecorder::default();
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let timeline = Timeline::new(&project_root, &format!("tui-{:x}", timestamp));
+    engine.subscribe(ui_observer);
+    engine.subscribe(session.clone());
+    engine.subscribe(timeline.clone());
+
+    let mut terminal = enter_terminal()?;
+    let events = interrupt::spawn_input_thread();
+
+    // Create app state
+    let mut app = AppState::default();
+    app.stats.max_tpm = config.limits.tpm;
+    app.stats.max_rpm = config.limits.rpm;
+    app.file_tree = file_tree::scan(&project_root);
+    app.input_history = InputHistory::load(&project_root);
+    app.theme = if args.no_color {
+        Theme::plain()
+    } else {
+        Theme::from_env()
+    };
+    app.plan_mode = args.plan || args.subtasks;
+    app.timeline = Some(timeline);
 
     // Main loop
-    let result = run_app(
-        &mut terminal,
-        &mut app,
-        &client,
-        &prompt,
-        &project_root,
-        &mut tpm_limiter,
-    )
-    .await;
+    let result = run_app(&mut terminal, &mut app, &mut engine, &events, &agent_events).await;
 
     // Restore terminal
-    disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    restore_terminal();
     terminal.show_cursor()?;
 
     if let Err(err) = result {
         eprintln!("Error: {:?}", err);
     }
+    write_patch_output(args.patch_output.as_deref(), engine.project_root());
+
+    if interrupt::is_interrupted() {
+        offer_to_save_session(&session.transcript());
+    }
 
     Ok(())
 }
 
+// botcoder bench: the benchmark tasks in dir, compared with the baseline
+// report or the last one; returns the exit code
+async fn run_bench(
+    dir: &str,
+    max_iterations: u32,
+    baseline: Option<&str>,
+    json: bool,
+    config: &EngineConfig,
+    project_root: &str,
+) -> i32 {
+    let new_engine = |root: &str| build_engine(config, root);
+    let report = match bench::run(Path::new(dir), max_iterations, new_engine).await {
+        Ok(report) => report,
+        Err(e) => {
+            glyphs::say(&format!("✗ {}", e));
+            return e.exit_code();
+        }
+    };
+    let baseline = match baseline {
+        Some(path) => match bench::load_report(Path::new(path)) {
+            Ok(report) => Some(report),
+            Err(e) => {
+                eprintln!("{}", glyphs::plain(&format!("✗ {}", e)));
+                None
+            }
+        },
+        None => bench::previous_report(project_root, report.started),
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).unwrap_or_default());
+    } else {
+        print!("{}", glyphs::plain(&report.render()));
+        if let Some(baseline) = &baseline {
+            print!("{}", glyphs::plain(&report.compare(baseline)));
+        }
+    }
+    match report.save(project_root) {
+        Ok(path) => eprintln!("{}", glyphs::plain(&format!("✓ Saved the report to {}", path.display()))),
+        Err(e) => eprintln!("{}", glyphs::plain(&format!("✗ Could not save the report: {}", e))),
+    }
+    exit::SUCCESS
+}
+
+// botcoder queue: the tasks one after another, checkpointed in between;
+// returns the exit code
+async fn run_queue(file: &str, max_iterations: u32, json: bool, config: &EngineConfig, project_root: &str) -> i32 {
+    let queue = match TaskQueue::load(Path::new(file)) {
+        Ok(queue) => queue,
+        Err(e) => {
+            glyphs::say(&format!("✗ {}", e));
+            return exit::ERROR;
+        }
+    };
+    // Progress goes to stderr, leaving stdout to the report
+    let new_task_engine = || {
+        build_engine(config, project_root).map(|mut engine| {
+            if !json {
+                engine.subscribe(ConsoleObserver::on_stderr());
+            }
+            engine
+        })
+    };
+    match queue::run(&queue, project_root, max_iterations, new_task_engine).await {
+        Ok(report) => show_report(&report, json, project_root, queue::REPORT_DIR),
+        Err(e) => {
+            glyphs::say(&format!("✗ {}", e));
+            e.exit_code()
+        }
+    }
+}
+
+// botcoder parallel: the tasks at once, each session in its own worktree;
+// returns the exit code
+async fn run_parallel(
+    file: &str,
+    sessions: usize,
+    max_iterations: u32,
+    args: &cli::Args,
+    config: EngineConfig,
+    project_root: &str,
+) -> Result<i32> {
+    let queue = match TaskQueue::load(Path::new(file)) {
+        Ok(queue) => queue,
+        Err(e) => {
+            glyphs::say(&format!("✗ {}", e));
+            return Ok(exit::ERROR);
+        }
+    };
+    let new_session_engine: Arc<SessionFactory> = Arc::new(move |root: &str| build_engine(&config, root));
+    // The overview screen takes over the terminal; otherwise progress
+    // goes to stderr
+    let show = io::stdout().is_terminal() && !args.json;
+    let overview = if show { Overview::quiet() } else { Overview::default() };
+    let run = tokio::spawn(orchestrator::run(
+        queue,
+        project_root.to_string(),
+        sessions,
+        max_iterations,
+        orchestrator::worktree_dir(project_root),
+        new_session_engine,
+        overview.clone(),
+    ));
+    if show {
+        let theme = if args.no_color { Theme::plain() } else { Theme::from_env() };
+        let mut terminal = enter_terminal()?;
+        let drawn = watch_sessions(&mut terminal, &run, &overview, &theme);
+        restore_terminal();
+        terminal.show_cursor()?;
+        drawn?;
+    }
+    Ok(match run.await {
+        Ok(Ok(report)) => show_report(&report, args.json, project_root, orchestrator::REPORT_DIR),
+        Ok(Err(e)) => {
+            glyphs::say(&format!("✗ {}", e));
+            e.exit_code()
+        }
+        Err(e) => {
+            glyphs::say(&format!("✗ {}", e));
+            exit::ERROR
+        }
+    })
+}
+
+// Prints a queue's report and saves it in dir; returns the exit code
+fn show_report(report: &QueueReport, json: bool, project_root: &str, dir: &str) -> i32 {
+    if json {
+        println!("{}", serde_json::to_string_pretty(report).unwrap_or_default());
+    } else {
+        print!("{}", glyphs::plain(&report.render()));
+    }
+    match report.save_in(project_root, dir) {
+        Ok(path) => eprintln!("{}", glyphs::plain(&format!("✓ Saved the report to {}", path.display()))),
+        Err(e) => eprintln!("{}", glyphs::plain(&format!("✗ Could not save the report: {}", e))),
+    }
+    report.exit_code()
+}
+
+// What every engine of a run is built from
+#[derive(Clone)]
+struct EngineConfig {
+    prompt: String,
+    replay: Option<String>,
+    record: Option<String>,
+    limits: LimitProfile,
+    // Sessions running side by side, which split the rate limits
+    share: u32,
+    // Workspace::load's cargo_members, or None for no workspace
+    workspace: Option<bool>,
+    subtasks: bool,
+}
+
+fn build_engine(config: &EngineConfig, root: &str) -> std::result::Result<AgentEngine, AgentError> {
+    let router = match &config.replay {
+        Some(fixture) => MockProvider::from_file(fixture).map(ModelRouter::single)?,
+        None => ModelRouter::from_env()?,
+    };
+    let router = match &config.record {
+        Some(fixture) => router.with_recorder(fixture),
+        None => router,
+    };
+    let limiter = match config.share {
+        1 => TPMLimiter::from_profile(config.limits.clone()),
+        share => TPMLimiter::from_profile(config.limits.clone()).with_share(share),
+    };
+    let engine = AgentEngine::new(router, config.prompt.clone(), root, limiter, ResponseParser::from_env());
+    let engine = match config.workspace.and_then(|members| Workspace::load(root, members)) {
+        Some(workspace) => engine.with_workspace(workspace),
+        None => engine,
+    };
+    Ok(if config.subtasks {
+        engine.with_subtasks(SubtaskBudget::from_env())
+    } else {
+        engine
+    })
+}
+
+// --patch-output: writes the edits collected in the overlay as one patch
+fn write_patch_output(path: Option<&str>, project_root: &str) {
+    let Some(path) = path else {
+        return;
+    };
+    match overlay::save_patch(project_root, Path::new(path)) {
+        Ok(summary) => eprintln!("{}", glyphs::plain(&summary)),
+        Err(e) => eprintln!("{}", glyphs::plain(&format!("✗ Could not write {}: {}", path, e))),
+    }
+}
+
+// Writes result.json for CI to gate on, then tells any webhooks; the
+// transcript is only saved for the webhooks to point at
+async fn finish_headless_run(
+    project_root: &str,
+    mut summary: RunSummary,
+    session: &SessionRecorder,
+    result_path: Option<&str>,
+) {
+    let webhooks = Webhooks::from_env();
+    if !webhooks.is_empty() {
+        summary.transcript =
+            webhooks::save_transcript(project_root, &summary.session, &session.transcript());
+    }
+    let path = match result_path {
+        Some(path) => Path::new(path).to_path_buf(),
+        None => overlay::state_path(project_root, webhooks::RESULT_FILE),
+    };
+    if let Err(e) = summary.save(&path) {
+        eprintln!("Could not write {}: {}", path.display(), e);
+    }
+    if !webhooks.is_empty() {
+        webhooks.notify(&summary).await;
+    }
+}
+
+// Matches listed when Tab can't complete any further
+const COMPLETIONS_SHOWN: usize = 40;
+
+// Tab with a message typed completes its last word; several matches are
+// listed in the thoughts panel
+fn complete_input(app: &mut AppState) {
+    let completion = completion::complete(&app.chat_input, &app.file_tree);
+    if completion.input != app.chat_input || completion.candidates.is_empty() {
+        app.chat_input = completion.input;
+        return;
+    }
+    let more = completion.candidates.len().saturating_sub(COMPLETIONS_SHOWN);
+    app.current_thoughts = format!(
+        "Completions:\n{}{}",
+        completion.candidates[..completion.candidates.len() - more].join("\n"),
+        if more > 0 { format!("\n… and {} more", more) } else { String::new() }
+    );
+    app.thoughts_scroll = 0;
+}
+
+// Ctrl+R: typing narrows the search, Ctrl+R again finds an older match,
+// Enter or a movement key keeps the match in the message box and Esc or
+// Ctrl+G leaves it as it was
+fn handle_history_search_key(app: &mut AppState, key: KeyEvent) {
+    let Some(search) = app.history_search.as_mut() else {
+        return;
+    };
+    let control = key.modifiers.contains(KeyModifiers::CONTROL);
+    match key.code {
+        KeyCode::Char('r') if control => app.input_history.search(search, true),
+        KeyCode::Esc => app.history_search = None,
+        KeyCode::Char('g') if control => app.history_search = None,
+        KeyCode::Char(c) => {
+            search.query.push(c);
+            app.input_history.search(search, false);
+        }
+        KeyCode::Backspace => {
+            search.query.pop();
+            app.input_history.search(search, false);
+        }
+        KeyCode::Enter | KeyCode::Left | KeyCode::Right | KeyCode::Up | KeyCode::Down => {
+            if let Some(entry) = search.matched.and_then(|i| app.input_history.get(i)) {
+                app.chat_input = entry.to_string();
+            }
+            app.history_search = None;
+        }
+        _ => {}
+    }
+}
+
+// Hands the terminal to $EDITOR for the text and takes it back
+fn with_editor(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    text: &str,
+    name: &str,
+) -> Result<String, ToolError> {
+    interrupt::pause_input(true);
+    restore_terminal();
+    let edited = editor::edit(text, name);
+    let _ = enable_raw_mode();
+    let _ = execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture, EnableBracketedPaste);
+    let _ = terminal.clear();
+    interrupt::pause_input(false);
+    edited
+}
+
+// E while paused: the file the next edit would produce opens in $EDITOR, and
+// what is saved there is written instead, one step at a time
+fn edit_pending(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    app: &mut AppState,
+    engine: &mut AgentEngine,
+) {
+    app.thoughts_scroll = 0;
+    let Some((tool, param)) = engine.pending_tools().front().cloned() else {
+        return;
+    };
+    let proposed = match app::proposed_content(&tool, &param, engine.project_root()) {
+        Ok(proposed) => proposed,
+        Err(e) => {
+            app.current_thoughts = format!("❌ Error: {}", e);
+            return;
+        }
+    };
+    let path = app::tool_path(&tool, &param).unwrap_or_default().to_string();
+    let name = Path::new(&path)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "proposed.txt".to_string());
+    let result = with_editor(terminal, &proposed, &name).and_then(|content| {
+        if content == proposed {
+            return Ok(false);
+        }
+        engine.edit_next(&content).map(|()| true)
+    });
+    app.current_thoughts = match result {
+        Ok(true) => {
+            app.run_mode = RunMode::Step;
+            format!("✎ Applying your version of {}", path)
+        }
+        Ok(false) => format!("{} unchanged; S applies the proposed edit, R resumes", path),
+        Err(e) => format!("❌ Error: {}", e),
+    };
+}
+
+// Sets up the terminal, making sure a panic doesn't leave it in raw mode
+fn enter_terminal() -> Result<Terminal<CrosstermBackend<io::Stdout>>> {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore_terminal();
+        default_hook(info);
+    }));
+
+    enable_raw_mode()?;
+    let mut stdout = stdout();
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture, EnableBracketedPaste)?;
+    let backend = CrosstermBackend::new(stdout);
+    Ok(Terminal::new(backend)?)
+}
+
+fn restore_terminal() {
+    let _ = disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen, DisableMouseCapture, DisableBracketedPaste);
+}
+
+fn offer_to_save_session(transcript: &[(String, String)]) {
+    if transcript.is_empty() {
+        return;
+    }
+
+    if !confirm("Interrupted. Save this session's conversation? [y/N] ") {
+        return;
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let path = format!("botcoder-session-{}.md", timestamp);
+
+    match fs::write(&path, events::transcript_markdown(transcript)) {
+        Ok(_) => glyphs::say(&format!("Session saved to {}", path)),
+        Err(e) => eprintln!("Failed to save session: {}", e),
+    }
+}
+
+fn confirm(question: &str) -> bool {
+    print!("{}", question);
+    let _ = io::stdout().flush();
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).is_ok() && answer.trim().eq_ignore_ascii_case("y")
+}
+
 async fn run_app(
     terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
     app: &mut AppState,
-    client: &AzureOpenAIClient,
-    prompt: &str,
-    project_root: &str,
-    tpm_limiter: &mut TPMLimiter,
+    engine: &mut AgentEngine,
+    events: &Receiver<Event>,
+    agent_events: &Receiver<AgentEvent>,
 ) -> Result<()> {
-    // Start first iteration automatically
-    process_iteration(app, client, prompt, project_root, tpm_limiter).await?;
+    // Start first iteration automatically, except in plan mode where the
+    // first message is planned before anything runs
+    if app.plan_mode {
+        app.current_thoughts =
+            "Plan mode: describe the task and a plan is drafted for your review first".to_string();
+    } else {
+        process_iteration(app, engine, agent_events).await?;
+    }
 
     let mut last_update = std::time::Instant::now();
-    let spinner_frames = vec!["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+    let spinner_frames = glyphs::spinner_frames();
     let mut spinner_index = 0;
+    let pace = app::tool_pace();
+    // When the last tool ran, or the queue last stood empty
+    let mut last_step = Instant::now();
+    // Tools run on a worker thread so the screen and keys stay live; their
+    // commands' output is tailed in the Tool Execution panel
+    let mut running: Option<tokio::task::JoinHandle<Result<ToolOutput, ToolError>>> = None;
+    let (live_tx, live_output) = mpsc::channel();
+    interrupt::stream_output(Some(live_tx));
+    // Files added or removed outside the agent, as the index watcher sees them
+    let mut tree_changes = semantic::tree_changes();
 
     loop {
-        terminal.draw(|f| draw_ui(f, app, &spinner_frames[spinner_index]))?;
+        if semantic::tree_changes() != tree_changes {
+            tree_changes = semantic::tree_changes();
+            app.file_tree = file_tree::scan(engine.project_root());
+        }
+        terminal.draw(|f| draw_ui(f, app, spinner_frames[spinner_index]))?;
 
         // Update spinner every 80ms for fluid animation
         if last_update.elapsed() > Duration::from_millis(80) {
@@ -106,47 +919,332 @@ async fn run_app(
             last_update = std::time::Instant::now();
         }
 
-        if app.should_quit {
+        if app.should_quit || interrupt::is_interrupted() {
             break;
         }
 
-        if event::poll(Duration::from_millis(50))? {
-            match event::read()? {
+        for line in live_output.try_iter() {
+            if let Some(tool) = app.running_tool.as_mut() {
+                tool.push_line(line);
+            }
+        }
+        if let Some(task) = running.take_if(|task| task.is_finished()) {
+            let output = task.await.unwrap_or_else(|e| {
+                Err(ToolError::Spawn(io::Error::other(e.to_string())))
+            });
+            app.running_tool = None;
+            engine.complete_next(output);
+            last_step = Instant::now();
+            apply_agent_events(app, agent_events, engine.project_root());
+            if app.run_mode == RunMode::Step {
+                app.run_mode = RunMode::Paused;
+            }
+        }
+
+        // Tools are executed one at a time so the run can be paused or
+        // stepped between them
+        if engine.pending_tools().is_empty() {
+            last_step = Instant::now();
+        }
+        if !engine.pending_tools().is_empty()
+            && running.is_none()
+            && app.run_mode != RunMode::Paused
+            && app.approval.is_none()
+            && app.question.is_none()
+            && (last_step.elapsed() >= pace || app.run_mode == RunMode::Step)
+        {
+            if let Some(reason) = engine.write_limit_exceeded() {
+                app.current_thoughts = format!(
+                    "⚠ {}\n\nPress Y to write it anyway, N to skip",
+                    reason
+                );
+                app.approval = Some(reason);
+                app.thoughts_scroll = 0;
+                notify::alert("Approval needed: large write");
+                continue;
+            }
+            if let Some(question) = engine.pending_question() {
+                app.question = Some(question.to_string());
+                app.current_thoughts = question.trim().to_string();
+                app.thoughts_scroll = 0;
+                notify::alert("Question from the agent");
+                continue;
+            }
+            let next = engine.pending_tools().front();
+            if let Some((_, message)) = next.filter(|(tool, _)| tool == "git_commit") {
+                app.approval = Some(message.clone());
+                app.current_thoughts = format!(
+                    "Commit {} file(s) with this message?\n\n{}\n\nPress Y to commit, N to skip",
+                    engine.modified_files().len(),
+                    message
+                );
+                app.thoughts_scroll = 0;
+                notify::alert("Approval needed: commit");
+                continue;
+            }
+            if let Some((tool, param, root)) = engine.next_call() {
+                app.running_tool = Some(RunningTool::new(&tool, &param));
+                running = Some(tokio::task::spawn_blocking(move || {
+                    app::execute_tool(&tool, &param, &root)
+                }));
+                continue;
+            }
+            engine.execute_next();
+            last_step = Instant::now();
+            apply_agent_events(app, agent_events, engine.project_root());
+            if app.run_mode == RunMode::Step {
+                app.run_mode = RunMode::Paused;
+            }
+        }
+        let was_processing = app.processing;
+        // A question waits for the message box, like a reply would
+        app.processing = (!engine.pending_tools().is_empty() && app.question.is_none())
+            || app.rate_limited.is_some();
+        if was_processing && !app.processing && !engine.retry_due() && app.question.is_none() {
+            notify::alert(if app.success_achieved {
+                "Task complete"
+            } else {
+                "Waiting for your reply"
+            });
+        }
+
+        if engine.retry_due() && app.run_mode != RunMode::Paused && app.rate_limited.is_none() {
+            app.processing = true;
+            process_iteration(app, engine, agent_events).await?;
+            continue;
+        }
+
+        if app.rate_limited.is_some_and(|(until, _)| Instant::now() >= until) {
+            process_iteration(app, engine, agent_events).await?;
+            continue;
+        }
+
+        if let Ok(event) = events.recv_timeout(Duration::from_millis(50)) {
+            match event {
+                Event::Key(key) if key.kind == KeyEventKind::Press && app.detail.is_some() => {
+                    handle_detail_key(app, key.code);
+                }
+                Event::Key(key) if key.kind == KeyEventKind::Press && app.timeline_view.is_some() => {
+                    handle_timeline_key(app, key.code);
+                }
+                Event::Key(key) if key.kind == KeyEventKind::Press && app.approval.is_some() => {
+                    handle_approval_key(app, engine, agent_events, key.code);
+                }
+                Event::Key(key) if key.kind == KeyEventKind::Press && app.plan_review => {
+                    let approved = handle_plan_key(app, key.code);
+                    if approved {
+                        approve_plan(app, engine, agent_events).await?;
+                    }
+                }
+                Event::Key(key) if key.kind == KeyEventKind::Press && app.history_search.is_some() => {
+                    handle_history_search_key(app, key);
+                }
+                Event::Mouse(mouse) => {
+                    let size = terminal.size()?;
+                    let screen = Rect::new(0, 0, size.width, size.height);
+                    let panels_area = ui::outer_layout(screen, ui::input_height(app))[1];
+                    handle_mouse(app, mouse, panels_area);
+                }
+                // Bracketed paste: a multi-line paste stays one message
+                Event::Paste(text) if !app.processing => {
+                    app.chat_input.push_str(&text.replace("\r\n", "\n").replace('\r', "\n"));
+                }
                 Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    if key.code == KeyCode::Enter
+                        && !key.modifiers.contains(KeyModifiers::ALT)
+                        && !app.processing
+                    {
+                        app.input_history.push(&app.chat_input);
+                    }
                     match key.code {
+                        KeyCode::Esc if app.rate_limited.is_some() => {
+                            app.rate_limited = None;
+                            app.current_thoughts =
+                                "Request cancelled; send a message to try again".to_string();
+                        }
+                        // Esc leaves the question to the agent
+                        KeyCode::Esc if app.question.is_some() && app.chat_input.is_empty() => {
+                            app.question = None;
+                            engine.answer_question("No answer; decide for yourself, say what you chose and go on.");
+                            apply_agent_events(app, agent_events, engine.project_root());
+                            app.processing = true;
+                            process_iteration(app, engine, agent_events).await?;
+                        }
                         KeyCode::Char('q') | KeyCode::Esc => {
                             app.should_quit = true;
                         }
                         KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
                             app.should_quit = true;
                         }
-                        KeyCode::Enter => {
-                            if !app.chat_input.trim().is_empty() && !app.processing {
-                                let user_message = app.chat_input.clone();
-                                app.chat_input.clear();
-                                app.conversation_history
-                                    .push(format!("User: {}", user_message));
-                                app.processing = true;
-                                process_iteration(app, client, prompt, project_root, tpm_limiter)
-                                    .await?;
-                                app.processing = false;
-                            }
+                        KeyCode::Char('r')
+                            if key.modifiers.contains(KeyModifiers::CONTROL) && !app.processing =>
+                        {
+                            app.history_search = Some(HistorySearch::default());
+                        }
+                        KeyCode::Enter if key.modifiers.contains(KeyModifiers::ALT) && !app.processing => {
+                            app.chat_input.push('\n');
                         }
-                        KeyCode::Char(c) => {
-                            if !app.processing {
-                                app.chat_input.push(c);
+                        KeyCode::Tab if !app.chat_input.is_empty() && !app.processing => {
+                            complete_input(app);
+                        }
+                        KeyCode::Tab => {
+                            app.focus = match app.focus {
+                                Focus::Files => Focus::Thoughts,
+                                Focus::Thoughts => Focus::Tools,
+                                Focus::Tools => Focus::Files,
+                            };
+                        }
+                        KeyCode::Enter
+                            if app.focus == Focus::Tools && app.chat_input.is_empty() =>
+                        {
+                            if let Some((tool, param, result, _)) =
+                                app.current_tools.get(app.selected_tool)
+                            {
+                                let lang = match tool.as_str() {
+                                    "read_file" => app::tool_path(tool, param)
+                                        .and_then(highlight::lang_for_path),
+                                    _ => None,
+                                };
+                                app.detail = Some(DetailView {
+                                    title: format!("{}: {}", tool, param),
+                                    content: result.clone(),
+                                    lang,
+                                    scroll: 0,
+                                });
                             }
                         }
-                        KeyCode::Backspace => {
-                            if !app.processing {
-                                app.chat_input.pop();
+                        KeyCode::Enter
+                            if app.focus == Focus::Files && app.chat_input.is_empty() =>
+                        {
+                            open_selected_file(app, engine.project_root());
+                        }
+                        KeyCode::Enter if app.chat_input.trim() == "/commit" && !app.processing => {
+                            app.chat_input.clear();
+                            commit_command(app, engine).await;
+                        }
+                        KeyCode::Enter
+                            if (app.chat_input.trim() == "/edit" || app.chat_input.starts_with("/edit "))
+                                && !app.processing =>
+                        {
+                            let draft = app.chat_input.trim()["/edit".len()..].trim().to_string();
+                            app.chat_input.clear();
+                            match with_editor(terminal, &draft, "message.md") {
+                                Ok(message) if !message.trim().is_empty() => {
+                                    if app.plan_mode && app.plan.is_none() {
+                                        draft_plan(app, engine, message.trim()).await;
+                                    } else {
+                                        engine.push_user(message.trim_end());
+                                        app.processing = true;
+                                        process_iteration(app, engine, agent_events).await?;
+                                    }
+                                }
+                                Ok(_) => app.current_thoughts = "Empty message, nothing sent".to_string(),
+                                Err(e) => app.current_thoughts = format!("❌ Error: {}", e),
                             }
+                            app.thoughts_scroll = 0;
+                        }
+                        KeyCode::Enter
+                            if app.chat_input.trim().starts_with('!') && !app.processing =>
+                        {
+                            let command = app.chat_input.trim()[1..].trim().to_string();
+                            app.chat_input.clear();
+                            run_shell(app, engine, &command);
+                        }
+                        KeyCode::Enter if app.chat_input.trim().starts_with('/') => {
+                            let command = app.chat_input.trim().to_string();
+                            app.chat_input.clear();
+                            handle_command(app, engine, &command);
+                        }
+                        // The answer goes back to the model, which carries on
+                        KeyCode::Enter
+                            if app.question.is_some() && !app.chat_input.trim().is_empty() =>
+                        {
+                            let answer = std::mem::take(&mut app.chat_input);
+                            app.question = None;
+                            engine.answer_question(&answer);
+                            apply_agent_events(app, agent_events, engine.project_root());
+                            app.processing = true;
+                            process_iteration(app, engine, agent_events).await?;
+                        }
+                        KeyCode::Enter
+                            if app.plan_mode
+                                && app.plan.is_none()
+                                && !app.chat_input.trim().is_empty()
+                                && !app.processing =>
+                        {
+                            let task = app.chat_input.trim().to_string();
+                            app.chat_input.clear();
+                            draft_plan(app, engine, &task).await;
+                        }
+                        KeyCode::Enter
+                            if !app.chat_input.trim().is_empty() && !app.processing =>
+                        {
+                            let user_message = app.chat_input.clone();
+                            app.chat_input.clear();
+                            engine.push_user(user_message);
+                            app.processing = true;
+                            process_iteration(app, engine, agent_events).await?;
                         }
-                        KeyCode::Up => {
-                            if app.thoughts_scroll > 0 {
-                                app.thoughts_scroll = app.thoughts_scroll.saturating_sub(1);
+                        KeyCode::Char('p') if !engine.pending_tools().is_empty() => {
+                            app.run_mode = RunMode::Paused;
+                        }
+                        KeyCode::Char('s')
+                            if app.run_mode == RunMode::Paused
+                                && !engine.pending_tools().is_empty() =>
+                        {
+                            app.run_mode = RunMode::Step;
+                        }
+                        KeyCode::Char('r')
+                            if app.run_mode == RunMode::Paused
+                                && !engine.pending_tools().is_empty() =>
+                        {
+                            app.run_mode = RunMode::Running;
+                        }
+                        KeyCode::Char('e')
+                            if app.run_mode == RunMode::Paused
+                                && app.running_tool.is_none()
+                                && !engine.pending_tools().is_empty() =>
+                        {
+                            edit_pending(terminal, app, engine);
+                        }
+                        KeyCode::Char(c) if !app.processing => {
+                            app.chat_input.push(c);
+                        }
+                        KeyCode::Backspace if !app.processing => {
+                            app.chat_input.pop();
+                        }
+                        KeyCode::Up if app.focus == Focus::Tools => {
+                            app.selected_tool = app.selected_tool.saturating_sub(1);
+                        }
+                        KeyCode::Down if app.focus == Focus::Tools => {
+                            let last = app.current_tools.len().saturating_sub(1);
+                            app.selected_tool = (app.selected_tool + 1).min(last);
+                        }
+                        KeyCode::Up if app.focus == Focus::Files => {
+                            app.selected_file = app.selected_file.saturating_sub(1);
+                        }
+                        KeyCode::Down if app.focus == Focus::Files => {
+                            let last = app.file_tree.len().saturating_sub(1);
+                            app.selected_file = (app.selected_file + 1).min(last);
+                        }
+                        // ↑ at the top of the thoughts, or with a message
+                        // typed, goes back through the sent messages
+                        KeyCode::Up
+                            if !app.processing
+                                && (app.thoughts_scroll == 0
+                                    || !app.chat_input.is_empty()
+                                    || app.input_history.is_browsing()) =>
+                        {
+                            if let Some(entry) = app.input_history.older(&app.chat_input) {
+                                app.chat_input = entry;
                             }
                         }
+                        KeyCode::Down if app.input_history.is_browsing() => {
+                            app.chat_input = app.input_history.newer();
+                        }
+                        KeyCode::Up if app.thoughts_scroll > 0 => {
+                            app.thoughts_scroll = app.thoughts_scroll.saturating_sub(1);
+                        }
                         KeyCode::Down => {
                             let max_scroll = app.current_thoughts.lines().count().saturating_sub(10);
                             if app.thoughts_scroll < max_scroll as u32 {
@@ -171,104 +1269,749 @@ async fn run_app(
     Ok(())
 }
 
-async fn process_iteration(
-    app: &mut AppState,
-    client: &AzureOpenAIClient,
-    prompt: &str,
-    project_root: &str,
-    tpm_limiter: &mut TPMLimiter,
-) -> Result<()> {
-    app.iteration += 1;
+// Slash commands are handled locally and never sent to the LLM
+fn handle_command(app: &mut AppState, engine: &mut AgentEngine, command: &str) {
+    let (name, arg) = command
+        .split_once(' ')
+        .map(|(n, a)| (n, a.trim()))
+        .unwrap_or((command, ""));
+
+    app.current_thoughts = match name {
+        "/theme" if arg.is_empty() => format!(
+            "Current theme: {}\nAvailable: {}",
+            app.theme.name,
+            THEME_NAMES.join(", ")
+        ),
+        "/theme" => match Theme::by_name(arg) {
+            Some(theme) => {
+                app.theme = theme;
+                format!("Theme switched to {}", theme.name)
+            }
+            None => format!(
+                "Unknown theme '{}'. Available: {}",
+                arg,
+                THEME_NAMES.join(", ")
+            ),
+        },
+        "/pin" if arg.is_empty() => match engine.pinned_files() {
+            [] => "No pinned files. /pin <path> sends a file with every request".to_string(),
+            files => format!("Pinned files:\n{}", files.join("\n")),
+        },
+        "/pin" => match engine.pin(arg) {
+            Ok(()) => format!("📌 {} is sent with every request", arg),
+            Err(e) => format!("❌ Error: {}", e),
+        },
+        "/image" if arg.is_empty() => match engine.attached_images() {
+            [] => "No images attached. /image <path> sends one with your next message".to_string(),
+            images => format!(
+                "Attached to your next message:\n{}",
+                images.iter().map(|i| i.path.as_str()).collect::<Vec<_>>().join("\n")
+            ),
+        },
+        "/image" => match engine.attach_image(arg) {
+            Ok(()) => format!("🖼 {} goes with your next message", arg),
+            Err(e) => format!("❌ Error: {}", e),
+        },
+        "/timeline" => match app.timeline.as_ref().map(|t| t.records()) {
+            Some(records) if !records.is_empty() => {
+                let selected = records.len() - 1;
+                app.timeline_view = Some(TimelineView { records, selected });
+                app.current_thoughts.clone()
+            }
+            _ => "No iterations yet".to_string(),
+        },
+        "/search" if arg.is_empty() => {
+            "/search <text> finds it in this session's messages and tool results".to_string()
+        }
+        "/search" => {
+            let records = app.timeline.as_ref().map(|t| t.records()).unwrap_or_default();
+            search_results(arg, &timeline::search(&records, arg))
+        }
+        "/save" if arg.is_empty() => {
+            "/save <name> saves this conversation to continue later".to_string()
+        }
+        "/save" => {
+            let turns = app.timeline.as_ref().map(|t| t.records()).unwrap_or_default();
+            match sessions::save(engine, arg, turns) {
+                Ok(path) => {
+                    app.session_name = Some(arg.to_string());
+                    format!("💾 Saved as {} ({})", arg, path.display())
+                }
+                Err(e) => format!("❌ Error: {}", e),
+            }
+        }
+        "/load" if arg.is_empty() => match sessions::list(engine.project_root()).as_slice() {
+            [] => "No saved sessions; /save <name> saves one".to_string(),
+            saved => format!(
+                "Saved sessions, /load <name> continues one:\n{}",
+                saved.iter().map(sessions::describe_meta).collect::<Vec<_>>().join("\n")
+            ),
+        },
+        "/load" => match sessions::load(engine.project_root(), arg) {
+            Ok(saved) => {
+                continue_session(app, engine, &saved);
+                format!(
+                    "📂 Loaded {}: {} message(s)\n{}",
+                    arg,
+                    engine.history().len(),
+                    sessions::describe_meta(&saved.meta)
+                )
+            }
+            Err(e) => format!("❌ Error: {}", e),
+        },
+        "/fork" if arg.is_empty() => {
+            "/fork <iteration> [name] continues from that iteration in a new session, keeping this one".to_string()
+        }
+        "/fork" => match fork(app, engine, arg) {
+            Ok(branch) => format!(
+                "🌿 Forked {} at #{}: {} message(s)\n{}",
+                branch.meta.parent.as_deref().unwrap_or_default(),
+                engine.iteration(),
+                engine.history().len(),
+                sessions::describe_meta(&branch.meta)
+            ),
+            Err(e) => format!("❌ Error: {}", e),
+        },
+        "/copy" => copy_command(engine, arg),
+        "/paste" => match clipboard::paste() {
+            Ok(text) if text.trim().is_empty() => "The clipboard is empty".to_string(),
+            Ok(text) => {
+                app.chat_input.push_str(&text);
+                format!("📋 Pasted {} line(s) into the message box; Enter sends them", text.lines().count())
+            }
+            Err(e) => format!("❌ Error: {}", e),
+        },
+        "/unpin" if engine.unpin(arg) => format!("{} is no longer pinned", arg),
+        "/unpin" => format!("{} is not pinned", arg),
+        _ => format!("Unknown command: {}", name),
+    };
+    app.thoughts_scroll = 0;
+}
+
+// Puts a saved or forked session in place of the current conversation
+fn continue_session(app: &mut AppState, engine: &mut AgentEngine, saved: &SavedSession) {
+    saved.restore(engine);
+    if let Some(timeline) = &app.timeline {
+        timeline.replace(&saved.turns);
+    }
+    app.session_name = Some(saved.meta.name.clone());
+    app.iteration = engine.iteration();
     app.current_tools.clear();
+    app.selected_tool = 0;
+    app.plan = None;
+    app.success_achieved = false;
+}
 
-    let context = if app.conversation_history.is_empty() {
-        format!("{}\n\nProject: {}\n\nConversation:", prompt, project_root)
-    } else {
-        format!(
-            "{}\n\nProject: {}\n\nConversation History:\n{}\n\nNext:",
-            prompt,
-            project_root,
-            app.conversation_history.join("\n\n")
-        )
+// /fork <iteration> [name]: saves the current session, under its timeline's
+// name if it has none yet, then continues from the iteration in a branch
+// named <session>-<iteration> unless named
+fn fork(app: &mut AppState, engine: &mut AgentEngine, arg: &str) -> Result<SavedSession, ToolError> {
+    let mut words = arg.split_whitespace();
+    let turn: u32 = words
+        .next()
+        .and_then(|w| w.trim_start_matches('#').parse().ok())
+        .ok_or_else(|| ToolError::InvalidArgs("usage: /fork <iteration> [name]".to_string()))?;
+    let Some(timeline) = &app.timeline else {
+        return Err(ToolError::InvalidArgs("this session has no timeline".to_string()));
     };
+    let parent = app.session_name.clone().unwrap_or_else(|| {
+        timeline
+            .path()
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "session".to_string())
+    });
+    sessions::save(engine, &parent, timeline.records())?;
+    let name = words.next().map(str::to_string).unwrap_or_else(|| format!("{}-{}", parent, turn));
+    let branch = sessions::load(engine.project_root(), &parent)?.fork(&name, turn)?;
+    sessions::write(&branch)?;
+    continue_session(app, engine, &branch);
+    Ok(branch)
+}
 
-    app.stats.input_tokens = app::count_tokens(&context);
-    app.current_thoughts = "🤔 Thinking...".to_string();
+// /copy: the model's last reply; /copy diff: the session's changes
+fn copy_command(engine: &AgentEngine, arg: &str) -> String {
+    let (what, text) = match arg {
+        "" => (
+            "the last reply",
+            engine.last_reply().unwrap_or_default().to_string(),
+        ),
+        "diff" => match git::diff(engine.modified_files(), engine.project_root()) {
+            Ok(diff) => ("the diff", diff),
+            Err(e) => return format!("❌ Error: {}", e),
+        },
+        _ => return "/copy copies the last reply, /copy diff the session's changes".to_string(),
+    };
+    if text.trim().is_empty() {
+        return format!("Nothing to copy: {} is empty", what);
+    }
+    match clipboard::copy(&text) {
+        Ok(via) => format!("📋 Copied {} ({} line(s)) via {}", what, text.lines().count(), via),
+        Err(e) => format!("❌ Error: {}", e),
+    }
+}
+
+fn open_selected_file(app: &mut AppState, project_root: &str) {
+    let Some(entry) = app.file_tree.get(app.selected_file) else {
+        return;
+    };
+    if entry.is_dir {
+        return;
+    }
+
+    let content = match binary::read(&Path::new(project_root).join(&entry.path)) {
+        Ok(binary::FileContent::Text(content)) => content,
+        Ok(binary::FileContent::Binary(description)) => description,
+        Err(e) => format!("Error reading file: {}", e),
+    };
+    app.detail = Some(DetailView {
+        title: entry.path.clone(),
+        content,
+        lang: highlight::lang_for_path(&entry.path),
+        scroll: 0,
+    });
+}
+
+// Click focuses a panel, dragging a border resizes the columns next to it
+// and the wheel scrolls the focused panel
+fn handle_mouse(app: &mut AppState, mouse: MouseEvent, panels_area: Rect) {
+    let panels = ui::panel_layout(panels_area, &app.panel_widths);
+    let inside = mouse.row >= panels_area.y && mouse.row < panels_area.bottom();
+
+    match mouse.kind {
+        MouseEventKind::Down(MouseButton::Left) if inside && app.detail.is_none() => {
+            let splitter = panels
+                .iter()
+                .take(panels.len() - 1)
+                .position(|p| mouse.column + 1 >= p.right() && mouse.column <= p.right());
 
-    // Rate limiting
-    tpm_limiter.wait_if_needed();
-    app.stats.current_tpm = tpm_limiter.get_current_tpm();
+            if splitter.is_some() {
+                app.dragging = splitter;
+            } else if let Some(index) = panels.iter().position(|p| {
+                mouse.column >= p.x && mouse.column < p.right()
+            }) {
+                app.focus = match index {
+                    0 => Focus::Files,
+                    2 => Focus::Tools,
+                    _ => Focus::Thoughts,
+                };
+            }
+        }
+        MouseEventKind::Drag(MouseButton::Left) => {
+            if let Some(index) = app.dragging {
+                app.resize_panels(index, mouse.column, panels_area.x, panels_area.width);
+            }
+        }
+        MouseEventKind::Up(MouseButton::Left) => app.dragging = None,
+        MouseEventKind::ScrollUp => scroll_focused(app, false),
+        MouseEventKind::ScrollDown => scroll_focused(app, true),
+        _ => {}
+    }
+}
 
-    // LLM Request
-    match client.generate(&context, &serde_json::json!({})).await {
-        Ok(resp) => {
-            let raw_response = resp;
-            let response = app::filter_thinking_tokens(&raw_response);
-            app.current_thoughts = response.clone();
+fn scroll_focused(app: &mut AppState, down: bool) {
+    if let Some(detail) = app.detail.as_mut() {
+        let max_scroll = detail.content.lines().count().saturating_sub(1) as u16;
+        detail.scroll = if down {
+            (detail.scroll + 3).min(max_scroll)
+        } else {
+            detail.scroll.saturating_sub(3)
+        };
+        return;
+    }
 
-            let output_tokens = app::count_tokens(&response);
-            let total_tokens = app.stats.input_tokens + output_tokens;
+    match app.focus {
+        Focus::Files => {
+            let last = app.file_tree.len().saturating_sub(1);
+            app.selected_file = if down {
+                (app.selected_file + 1).min(last)
+            } else {
+                app.selected_file.saturating_sub(1)
+            };
+        }
+        Focus::Tools => {
+            let last = app.current_tools.len().saturating_sub(1);
+            app.selected_tool = if down {
+                (app.selected_tool + 1).min(last)
+            } else {
+                app.selected_tool.saturating_sub(1)
+            };
+        }
+        Focus::Thoughts => {
+            let max_scroll = app.current_thoughts.lines().count().saturating_sub(10) as u32;
+            app.thoughts_scroll = if down {
+                (app.thoughts_scroll + 3).min(max_scroll)
+            } else {
+                app.thoughts_scroll.saturating_sub(3)
+            };
+        }
+    }
+}
 
-            tpm_limiter.add_token_usage(total_tokens);
-            app.stats.output_tokens = output_tokens;
-            app.stats.total_tokens = tpm_limiter.get_total_tokens();
-            app.stats.current_tpm = tpm_limiter.get_current_tpm();
+// Y runs the queued git_commit or over-limit edit, N or Esc tells the model
+// it was skipped
+fn handle_approval_key(
+    app: &mut AppState,
+    engine: &mut AgentEngine,
+    agent_events: &Receiver<AgentEvent>,
+    code: KeyCode,
+) {
+    let result = match code {
+        KeyCode::Char('y') | KeyCode::Char('Y') => {
+            engine.confirm_write();
+            engine.execute_next().map(|(_, _, result)| result)
+        }
+        KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => engine.skip_next().map(|(tool, _)| {
+            if tool == "git_commit" { "Commit skipped" } else { "Write skipped" }.to_string()
+        }),
+        _ => return,
+    };
+    app.approval = None;
+    apply_agent_events(app, agent_events, engine.project_root());
+    app.current_thoughts = result.unwrap_or_default();
+    app.thoughts_scroll = 0;
+}
 
-            app.conversation_history
-                .push(format!("Assistant: {}", response));
+// /commit: the model drafts a message for the session's changes, which is
+// then queued as a git_commit for approval
+async fn commit_command(app: &mut AppState, engine: &mut AgentEngine) {
+    app.thoughts_scroll = 0;
+    if engine.modified_files().is_empty() {
+        app.current_thoughts = "Nothing to commit: no files were modified this session".to_string();
+        return;
+    }
+    if let Some(notice) = rate_limit_notice(engine) {
+        app.current_thoughts = notice;
+        return;
+    }
 
-            let tools = app::extract_tools(&response);
+    match engine.draft_commit_message().await {
+        Ok(message) => engine.queue_tool("git_commit", message),
+        Err(e) => app.current_thoughts = format!("❌ Error: {}", e),
+    }
+}
 
-            // Execute tools
-            for (tool, param) in tools {
-                let result = app::execute_tool(&tool, &param, project_root);
-                app.current_tools
-                    .push((tool.clone(), param.clone(), result.clone()));
+// !<command> runs it in the project; the model sees its output with the
+// next message
+fn run_shell(app: &mut AppState, engine: &mut AgentEngine, command: &str) {
+    app.thoughts_scroll = 0;
+    if command.is_empty() {
+        app.current_thoughts = "!<command> runs it in the project and shows the model its output".to_string();
+        return;
+    }
+    app.current_thoughts = match engine.run_user_command(command) {
+        Ok(output) => format!("$ {}\n{}", command, output.text),
+        Err(e) => format!("$ {}\n❌ Error: {}", command, e),
+    };
+}
 
-                // Check for success condition
-                if tool == "execute_command" && param.contains("cargo run") {
-                    if result.contains("exit_code: 0") && !result.to_lowercase().contains("error") {
-                        app.success_achieved = true;
-                    }
+// Drafts aren't deferred like requests, so they are refused while the rate
+// limit is reached rather than freezing the screen
+fn rate_limit_notice(engine: &AgentEngine) -> Option<String> {
+    engine.tpm_limiter().wait_time().map(|(wait, limit)| {
+        format!(
+            "⏳ The {} allows another request in {}s; try again then",
+            limit,
+            wait.as_secs_f32().ceil()
+        )
+    })
+}
+
+const PLAN_REVIEW_HELP: &str = "Review the plan: ↑↓ select a step, Enter edits it, A adds a step \
+     after it, D deletes it. Y approves the plan and starts work, N discards it.";
+
+// --plan: the model drafts the steps for the task, which the user then
+// reviews before anything runs
+async fn draft_plan(app: &mut AppState, engine: &mut AgentEngine, task: &str) {
+    app.thoughts_scroll = 0;
+    if let Some(notice) = rate_limit_notice(engine) {
+        app.current_thoughts = notice;
+        app.chat_input = task.to_string();
+        return;
+    }
+    match engine.draft_plan(task).await {
+        Ok(plan) => {
+            app.plan = Some(plan);
+            app.plan_review = true;
+            app.selected_step = 0;
+            app.current_thoughts = PLAN_REVIEW_HELP.to_string();
+        }
+        Err(e) => {
+            app.current_thoughts = format!("❌ Error: {}", e);
+            app.chat_input = task.to_string();
+        }
+    }
+    app.stats.set_total_tokens(engine.tpm_limiter().get_total_tokens());
+    app.stats.current_tpm = engine.tpm_limiter().get_current_tpm();
+    app.stats.current_rpm = engine.tpm_limiter().get_current_rpm();
+}
+
+// Keys while a drafted plan is reviewed; a step being edited takes the
+// typed text. True once the user approves the plan.
+fn handle_plan_key(app: &mut AppState, code: KeyCode) -> bool {
+    let Some(plan) = app.plan.as_mut() else {
+        app.plan_review = false;
+        return false;
+    };
+
+    if let Some(index) = app.editing_step {
+        match code {
+            // An emptied step is dropped, as long as one is left
+            KeyCode::Enter => {
+                let text = app.chat_input.trim().to_string();
+                if !text.is_empty() {
+                    plan.steps[index].text = text;
+                } else if plan.steps.len() > 1 {
+                    plan.steps.remove(index);
+                }
+                app.editing_step = None;
+                app.chat_input.clear();
+            }
+            KeyCode::Esc => {
+                if plan.steps[index].text.is_empty() && plan.steps.len() > 1 {
+                    plan.steps.remove(index);
                 }
+                app.editing_step = None;
+                app.chat_input.clear();
             }
+            KeyCode::Char(c) => app.chat_input.push(c),
+            KeyCode::Backspace => {
+                app.chat_input.pop();
+            }
+            _ => {}
+        }
+        app.selected_step = app.selected_step.min(plan.steps.len() - 1);
+        return false;
+    }
 
-            if !app.current_tools.is_empty() {
-                let tool_summary: Vec<String> = app
-                    .current_tools
-                    .iter()
-                    .map(|(t, p, r)| {
-                        format!(
-                            "{}: {} -> {}",
-                            t,
-                            if p.len() > 30 {
-                                format!("{}...", &p[..30])
-                            } else {
-                                p.clone()
-                            },
-                            if r.len() > 50 {
-                                format!("{}...", &r[..50])
-                            } else {
-                                r.clone()
-                            }
-                        )
-                    })
-                    .collect();
+    match code {
+        KeyCode::Up => app.selected_step = app.selected_step.saturating_sub(1),
+        KeyCode::Down => app.selected_step = (app.selected_step + 1).min(plan.steps.len() - 1),
+        KeyCode::Enter | KeyCode::Char('e') | KeyCode::Char('E') => {
+            app.chat_input = plan.steps[app.selected_step].text.clone();
+            app.editing_step = Some(app.selected_step);
+        }
+        KeyCode::Char('a') | KeyCode::Char('A') => {
+            let index = app.selected_step + 1;
+            plan.steps.insert(
+                index,
+                PlanStep {
+                    text: String::new(),
+                    status: StepStatus::Pending,
+                    summary: None,
+                },
+            );
+            app.selected_step = index;
+            app.editing_step = Some(index);
+            app.chat_input.clear();
+        }
+        KeyCode::Char('d') | KeyCode::Char('D') if plan.steps.len() > 1 => {
+            plan.steps.remove(app.selected_step);
+            app.selected_step = app.selected_step.min(plan.steps.len() - 1);
+        }
+        KeyCode::Char('y') | KeyCode::Char('Y') => return true,
+        // The task goes back to the message box to be reworded
+        KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+            app.chat_input = plan.task.clone();
+            app.plan = None;
+            app.plan_review = false;
+            app.current_thoughts =
+                "Plan discarded. Edit the task and press Enter to draft a new one".to_string();
+        }
+        _ => {}
+    }
+    false
+}
 
-                app.conversation_history
-                    .push(format!("Tool Results:\n{}", tool_summary.join("\n")));
-            }
+// The engine takes over the plan and the task starts at step 1
+async fn approve_plan(
+    app: &mut AppState,
+    engine: &mut AgentEngine,
+    agent_events: &Receiver<AgentEvent>,
+) -> Result<()> {
+    let Some(plan) = app.plan.clone() else {
+        return Ok(());
+    };
+    app.plan_review = false;
+    let task = plan.task.clone();
+    engine.set_plan(Some(plan));
+    engine.push_user(task);
+    app.processing = true;
+    process_iteration(app, engine, agent_events).await
+}
 
-            // Keep only last 10 conversation items
-            if app.conversation_history.len() > 10 {
-                app.conversation_history
-                    .drain(0..app.conversation_history.len() - 10);
+const SEARCH_RESULTS: usize = 100;
+
+// Matches grouped under their iteration, the first SEARCH_RESULTS of them
+fn search_results(query: &str, matches: &[timeline::SearchMatch]) -> String {
+    if matches.is_empty() {
+        return format!("🔍 No matches for \"{}\"", query);
+    }
+    let mut text = format!("🔍 {} match(es) for \"{}\"\n", matches.len(), query);
+    let mut iteration = None;
+    for m in matches.iter().take(SEARCH_RESULTS) {
+        if iteration != Some(m.iteration) {
+            text.push_str(&format!("\n#{}\n", m.iteration));
+            iteration = Some(m.iteration);
+        }
+        let line: String = m.line.chars().take(160).collect();
+        text.push_str(&format!("  {}: {}\n", m.source, line));
+    }
+    if matches.len() > SEARCH_RESULTS {
+        text.push_str(&format!("\n… {} more; narrow the search\n", matches.len() - SEARCH_RESULTS));
+    }
+    text.push_str("\n/timeline opens an iteration in full");
+    text
+}
+
+// ↑↓ pick an iteration, Enter opens its reply and tool results
+fn handle_timeline_key(app: &mut AppState, code: KeyCode) {
+    let Some(view) = app.timeline_view.as_mut() else {
+        return;
+    };
+    match code {
+        KeyCode::Up => view.selected = view.selected.saturating_sub(1),
+        KeyCode::Down => view.selected = (view.selected + 1).min(view.records.len().saturating_sub(1)),
+        KeyCode::Enter => {
+            if let Some(record) = view.records.get(view.selected) {
+                app.detail = Some(DetailView {
+                    title: format!("Iteration #{} ({})", record.iteration, record.model),
+                    content: record.details(),
+                    lang: None,
+                    scroll: 0,
+                });
             }
         }
-        Err(err) => {
-            app.current_thoughts = format!("❌ Error: {}", err);
+        KeyCode::Char('q') | KeyCode::Esc => app.timeline_view = None,
+        _ => {}
+    }
+}
+
+fn handle_detail_key(app: &mut AppState, code: KeyCode) {
+    let Some(detail) = app.detail.as_mut() else {
+        return;
+    };
+    let max_scroll = detail.content.lines().count().saturating_sub(1) as u16;
+
+    match code {
+        KeyCode::Char('q') | KeyCode::Esc => app.detail = None,
+        KeyCode::Up => detail.scroll = detail.scroll.saturating_sub(1),
+        KeyCode::Down => detail.scroll = (detail.scroll + 1).min(max_scroll),
+        KeyCode::PageUp => detail.scroll = detail.scroll.saturating_sub(20),
+        KeyCode::PageDown => detail.scroll = (detail.scroll + 20).min(max_scroll),
+        KeyCode::Home => detail.scroll = 0,
+        KeyCode::End => detail.scroll = max_scroll,
+        _ => {}
+    }
+}
+
+async fn process_iteration(
+    app: &mut AppState,
+    engine: &mut AgentEngine,
+    agent_events: &Receiver<AgentEvent>,
+) -> Result<()> {
+    // Held back while the rate limit is reached so the event loop keeps
+    // running; run_app sends it when the wait is over
+    engine.select_limits();
+    app.stats.max_tpm = engine.tpm_limiter().max_tpm();
+    app.stats.max_rpm = engine.tpm_limiter().max_rpm();
+    if let Some((wait, limit)) = engine.tpm_limiter().wait_time() {
+        app.rate_limited = Some((Instant::now() + wait, limit));
+        return Ok(());
+    }
+    app.rate_limited = None;
+    app.current_thoughts = "🤔 Thinking...".to_string();
+    app.active_model = engine.next_model();
+
+    let response = engine.request().await;
+    apply_agent_events(app, agent_events, engine.project_root());
+    if let Ok(None) = response {
+        app.current_thoughts = "⏹ Interrupted".to_string();
+    }
+
+    app.stats.set_total_tokens(engine.tpm_limiter().get_total_tokens());
+    app.stats.current_tpm = engine.tpm_limiter().get_current_tpm();
+    app.stats.current_rpm = engine.tpm_limiter().get_current_rpm();
+    Ok(())
+}
+
+// The TUI is an observer of the engine: its panels are updated from the
+// events of each request and tool
+// Plays the replay's steps as they come due, or one per s/→ when paused.
+// Nothing runs: the recorded events are fed to the UI as the engine sent
+// them.
+fn replay_session(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    app: &mut AppState,
+    project_root: &str,
+) -> Result<()> {
+    let events = interrupt::spawn_input_thread();
+    let (replayed, agent_events) = mpsc::channel();
+    let mut next_step = Instant::now();
+
+    while !app.should_quit {
+        terminal.draw(|f| draw_ui(f, app, ""))?;
+
+        let Some(replay) = app.replay.as_mut() else {
+            break;
+        };
+        if !replay.paused && Instant::now() >= next_step {
+            next_step = Instant::now() + replay.delay();
+            play_step(app, &replayed, &agent_events, project_root);
+        }
+
+        let Ok(Event::Key(key)) = events.recv_timeout(Duration::from_millis(50)) else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let Some(replay) = app.replay.as_mut() else {
+            break;
+        };
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => app.should_quit = true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => app.should_quit = true,
+            KeyCode::Char(' ') => {
+                replay.paused = !replay.paused;
+                next_step = Instant::now();
+            }
+            KeyCode::Char('s') | KeyCode::Right => {
+                replay.paused = true;
+                play_step(app, &replayed, &agent_events, project_root);
+            }
+            KeyCode::Char('+') | KeyCode::Char('=') => replay.faster(),
+            KeyCode::Char('-') => replay.slower(),
+            KeyCode::Up => app.thoughts_scroll = app.thoughts_scroll.saturating_sub(1),
+            KeyCode::Down => {
+                let max_scroll = app.current_thoughts.lines().count().saturating_sub(10);
+                if app.thoughts_scroll < max_scroll as u32 {
+                    app.thoughts_scroll += 1;
+                }
+            }
+            _ => {}
         }
     }
+    Ok(())
+}
+
+// botcoder parallel's overview, until every session has ended. Q or Esc
+// interrupts them all; each stops after its current step.
+fn watch_sessions<T>(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    run: &tokio::task::JoinHandle<T>,
+    overview: &Overview,
+    theme: &Theme,
+) -> Result<()> {
+    let events = interrupt::spawn_input_thread();
+    let spinner_frames = glyphs::spinner_frames();
+    let mut spinner_index = 0;
+    while !run.is_finished() {
+        let sessions = overview.sessions();
+        terminal.draw(|f| ui::draw_overview(f, &sessions, theme, spinner_frames[spinner_index]))?;
+        spinner_index = (spinner_index + 1) % spinner_frames.len();
 
+        let Ok(Event::Key(key)) = events.recv_timeout(Duration::from_millis(100)) else {
+            continue;
+        };
+        if key.kind == KeyEventKind::Press && matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+            interrupt::trigger();
+        }
+    }
     Ok(())
 }
+
+fn play_step(
+    app: &mut AppState,
+    replayed: &Sender<AgentEvent>,
+    agent_events: &Receiver<AgentEvent>,
+    project_root: &str,
+) {
+    let Some(step) = app.replay.as_mut().and_then(|replay| replay.next_step()) else {
+        return;
+    };
+    for event in step {
+        if matches!(event, AgentEvent::LlmResponse { .. }) {
+            app.thoughts_scroll = 0;
+        }
+        let _ = replayed.send(event);
+    }
+    apply_agent_events(app, agent_events, project_root);
+}
+
+fn apply_agent_events(app: &mut AppState, agent_events: &Receiver<AgentEvent>, project_root: &str) {
+    while let Ok(event) = agent_events.try_recv() {
+        match event {
+            AgentEvent::IterationStarted { iteration } => {
+                app.iteration = iteration;
+                app.current_tools.clear();
+                app.selected_tool = 0;
+            }
+            AgentEvent::LlmRequest {
+                role,
+                model,
+                input_tokens,
+            } => {
+                app.active_model = format!("{}: {}", role.name(), model);
+                app.stats.input_tokens = input_tokens;
+            }
+            AgentEvent::LlmResponse {
+                role,
+                model,
+                text,
+                reasoning,
+                fallbacks,
+                output_tokens,
+                ..
+            } => {
+                app.active_model = format!("{}: {}", role.name(), model);
+                if let Some(reasoning) = reasoning {
+                    app.reasoning = reasoning;
+                }
+                app.current_thoughts = fallbacks
+                    .iter()
+                    .map(|note| format!("⚠ {}\n", note))
+                    .chain(std::iter::once(text))
+                    .collect();
+                app.stats.output_tokens = output_tokens;
+            }
+            AgentEvent::ToolExecuted {
+                tool,
+                param,
+                result,
+                ok,
+                status,
+            } => {
+                let read_path = app::tool_path(&tool, &param).filter(|_| !app::is_edit_tool(&tool));
+                if let Some(path) = read_path.filter(|_| ok) {
+                    app.file_marks
+                        .entry(file_tree::normalize(path))
+                        .or_insert(FileMark::Read);
+                }
+                app.current_tools.push((tool, param, result, status));
+            }
+            AgentEvent::FileModified { path } => {
+                let path = file_tree::normalize(&path);
+                if !app.file_tree.iter().any(|e| e.path == path) {
+                    app.file_tree = file_tree::scan(project_root);
+                }
+                app.file_marks.insert(path, FileMark::Modified);
+            }
+            AgentEvent::Success => app.success_achieved = true,
+            AgentEvent::Error { message } if app.current_thoughts.starts_with("🤔") => {
+                app.current_thoughts = format!("❌ Error: {}", message);
+            }
+            AgentEvent::Error { message } => {
+                app.current_thoughts.push_str(&format!("\n\n⚠ {}", message));
+            }
+            AgentEvent::ProjectSwitched { root, .. } => {
+                app.file_tree = file_tree::scan(&root);
+                app.file_marks.clear();
+                app.selected_file = 0;
+            }
+            AgentEvent::PlanUpdated { plan } => app.plan = Some(plan),
+            AgentEvent::SubtaskStarted { .. } | AgentEvent::SubtaskFinished { .. } => {}
+            AgentEvent::UserMessage { .. } => {}
+        }
+    }
+}
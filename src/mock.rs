@@ -0,0 +1,134 @@
+use async_trait::async_trait;
+use serde_json::Value;
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::error::LlmError;
+use crate::llm::{ChatMessage, LLMProvider};
+
+// Replays canned responses in order instead of calling a model, for tests
+// and offline runs. Fixtures are JSON arrays of response texts, as written
+// by --record. Clones share the same queue.
+#[derive(Clone)]
+pub struct MockProvider {
+    model: String,
+    responses: Arc<Mutex<VecDeque<String>>>,
+    // Returned before any response, as a failing endpoint would
+    errors: Arc<Mutex<VecDeque<LlmError>>>,
+    // Every conversation sent to the provider, for assertions
+    requests: Arc<Mutex<Vec<Vec<ChatMessage>>>>,
+}
+
+impl MockProvider {
+    pub fn new<S: Into<String>>(responses: impl IntoIterator<Item = S>) -> Self {
+        Self {
+            model: "mock".to_string(),
+            responses: Arc::new(Mutex::new(responses.into_iter().map(Into::into).collect())),
+            errors: Arc::new(Mutex::new(VecDeque::new())),
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    // Reports another model name, to tell mocks apart in a fallback chain
+    pub fn named(mut self, model: impl Into<String>) -> Self {
+        self.model = model.into();
+        self
+    }
+
+    // The next requests fail with these errors, in order
+    pub fn failing(self, errors: impl IntoIterator<Item = LlmError>) -> Self {
+        if let Ok(mut queue) = self.errors.lock() {
+            queue.extend(errors);
+        }
+        self
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, LlmError> {
+        let path = path.as_ref();
+        let fixture = fs::read_to_string(path)
+            .map_err(|e| LlmError::Replay(format!("{}: {}", path.display(), e)))?;
+        let responses: Vec<String> = serde_json::from_str(&fixture)
+            .map_err(|e| LlmError::Replay(format!("{}: {}", path.display(), e)))?;
+        Ok(Self::new(responses))
+    }
+
+    pub fn requests(&self) -> Vec<Vec<ChatMessage>> {
+        self.requests.lock().map(|r| r.clone()).unwrap_or_default()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.responses.lock().map(|r| r.len()).unwrap_or(0)
+    }
+}
+
+#[async_trait]
+impl LLMProvider for MockProvider {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn supports_images(&self) -> bool {
+        true
+    }
+
+    async fn generate(&self, prompt: &str, config: &Value) -> Result<String, LlmError> {
+        self.generate_chat(&[ChatMessage::user(prompt)], config)
+            .await
+    }
+
+    async fn generate_chat(
+        &self,
+        messages: &[ChatMessage],
+        _config: &Value,
+    ) -> Result<String, LlmError> {
+        if let Ok(mut requests) = self.requests.lock() {
+            requests.push(messages.to_vec());
+        }
+        if let Some(error) = self.errors.lock().ok().and_then(|mut errors| errors.pop_front()) {
+            return Err(error);
+        }
+        self.responses
+            .lock()
+            .ok()
+            .and_then(|mut responses| responses.pop_front())
+            .ok_or_else(|| LlmError::Replay("no recorded response left".to_string()))
+    }
+}
+
+// Captures the responses of a real run into a fixture for MockProvider.
+// The file is rewritten after every response so an interrupted run still
+// leaves a usable fixture.
+pub struct Recorder {
+    path: PathBuf,
+    responses: Mutex<Vec<String>>,
+}
+
+impl Recorder {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            responses: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn record(&self, response: &str) {
+        let Ok(mut responses) = self.responses.lock() else {
+            return;
+        };
+        responses.push(response.to_string());
+
+        if let Some(parent) = self.path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let written = serde_json::to_string_pretty(&*responses)
+            .map_err(std::io::Error::other)
+            .and_then(|fixture| fs::write(&self.path, fixture));
+        if let Err(e) = written {
+            log::error!("Failed to write fixture {}: {}", self.path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use notify_rust::Notification;
+use std::{
+    env,
+    io::{self, Write},
+    thread,
+};
+
+// BOTCODER_NOTIFY=off disables the bell and desktop notifications
+pub fn enabled() -> bool {
+    !matches!(
+        env::var("BOTCODER_NOTIFY").as_deref(),
+        Ok("off" | "0" | "false")
+    )
+}
+
+// Rings the terminal bell and shows a desktop notification, for when the
+// agent needs attention while its window is in the background
+pub fn alert(body: &str) {
+    if !enabled() {
+        return;
+    }
+
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(b"\x07");
+    let _ = stdout.flush();
+
+    // Talking to the notification daemon can block; no daemon, no
+    // notification
+    let body = body.to_string();
+    thread::spawn(move || {
+        if let Err(e) = Notification::new().summary("BotCoder").body(&body).show() {
+            log::debug!("Desktop notification failed: {}", e);
+        }
+    });
+}
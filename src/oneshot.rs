@@ -0,0 +1,76 @@
+use std::io;
+
+use crate::engine::AgentEngine;
+use crate::error::AgentError;
+use crate::exit;
+use crate::glyphs;
+use crate::interrupt;
+
+// How a `botcoder run` or `botcoder ask` ended
+#[derive(Debug, PartialEq, Eq)]
+pub enum RunEnd {
+    // A tool met the success condition, e.g. the tests pass
+    Success,
+    // The model replied without calling any more tools
+    Answered,
+    OutOfIterations,
+    // A token budget was used up first
+    OutOfTokens,
+    Interrupted,
+}
+
+impl RunEnd {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RunEnd::Success | RunEnd::Answered => exit::SUCCESS,
+            RunEnd::OutOfIterations | RunEnd::OutOfTokens => exit::BUDGET_EXCEEDED,
+            RunEnd::Interrupted => exit::GAVE_UP,
+        }
+    }
+}
+
+// Sends the task and steps until the model is done, at most max_iterations
+// requests (retries for malformed replies aside)
+pub async fn run(
+    engine: &mut AgentEngine,
+    task: &str,
+    max_iterations: u32,
+) -> Result<RunEnd, AgentError> {
+    run_within(engine, task, max_iterations, None).await
+}
+
+// run, also stopping once the engine has used max_tokens tokens
+pub async fn run_within(
+    engine: &mut AgentEngine,
+    task: &str,
+    max_iterations: u32,
+    max_tokens: Option<u32>,
+) -> Result<RunEnd, AgentError> {
+    engine.push_user(task);
+    for _ in 0..max_iterations {
+        if interrupt::is_interrupted() {
+            return Ok(RunEnd::Interrupted);
+        }
+        match engine.step().await? {
+            None => return Ok(RunEnd::Interrupted),
+            _ if engine.succeeded() => return Ok(RunEnd::Success),
+            Some(0) => return Ok(RunEnd::Answered),
+            Some(_) => {}
+        }
+        if max_tokens.is_some_and(|max| engine.tpm_limiter().get_total_tokens() >= max) {
+            return Ok(RunEnd::OutOfTokens);
+        }
+    }
+    Ok(RunEnd::OutOfIterations)
+}
+
+// Puts an ask_user question to whoever started the run at a terminal; an
+// empty line or the end of input leaves it to the model
+pub fn ask_on_terminal(question: &str) -> Option<String> {
+    eprintln!("\n{}", glyphs::plain(&format!("❓ {}", question)));
+    eprint!("> ");
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).ok()?;
+    let answer = answer.trim();
+    (!answer.is_empty()).then(|| answer.to_string())
+}
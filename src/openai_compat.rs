@@ -0,0 +1,152 @@
+use async_trait::async_trait;
+use dotenvy::dotenv;
+use log::{error, info};
+use reqwest::Client;
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::error::LlmError;
+use crate::http;
+use crate::limits;
+use crate::llm::{
+    self, ChatCompletionRequest, ChatCompletionResponse, ChatMessage, LLMProvider, ModelOptions,
+    RateLimiter, Usage, Vars, MAX_REPLY_TOKENS,
+};
+
+// Any endpoint shaped like OpenAI's /v1/chat/completions: OpenRouter,
+// vLLM, LM Studio, Groq, OpenAI itself
+pub struct OpenAICompatClient {
+    // e.g. https://openrouter.ai/api/v1
+    base_url: String,
+    api_key: Option<String>,
+    // "Authorization" sends "Bearer <key>", any other header the bare key
+    auth_header: String,
+    model: String,
+    options: ModelOptions,
+    client: Client,
+    rate_limiter: Arc<RateLimiter>,
+}
+
+impl OpenAICompatClient {
+    // Reads <PREFIX>_URL, <PREFIX>_MODEL, <PREFIX>_KEY (optional for local
+    // servers) and <PREFIX>_AUTH_HEADER, falling back to the LLM_* variables
+    pub fn from_env_prefix(prefix: &str) -> Result<Self, LlmError> {
+        dotenv().ok();
+        Self::from_vars(prefix, &llm::env_var)
+    }
+
+    pub fn from_vars(prefix: &str, vars: Vars) -> Result<Self, LlmError> {
+        let var = llm::prefixed(vars, prefix);
+
+        let base_url = var("URL").map_err(|_| LlmError::MissingConfig(format!("{}_URL", prefix)))?;
+        let model = var("MODEL").map_err(|_| LlmError::MissingConfig(format!("{}_MODEL", prefix)))?;
+        let options = ModelOptions::from_env(&var, &model);
+        let rate_limiter = RateLimiter::from_profile(&limits::for_client("openai", &model));
+
+        Ok(Self {
+            base_url,
+            api_key: var("KEY").ok().filter(|k| !k.is_empty()),
+            auth_header: var("AUTH_HEADER").unwrap_or_else(|_| "Authorization".to_string()),
+            model,
+            options,
+            client: http::client()?,
+            rate_limiter: Arc::new(rate_limiter),
+        })
+    }
+
+    // The base URL, or the full completions URL as given
+    fn url(&self) -> String {
+        let base = self.base_url.trim_end_matches('/');
+        if base.ends_with("/chat/completions") {
+            base.to_string()
+        } else {
+            format!("{}/chat/completions", base)
+        }
+    }
+
+    pub async fn chat_completions(
+        &self,
+        messages: Vec<ChatMessage>,
+        temperature: f32,
+        max_tokens: Option<u32>,
+    ) -> Result<ChatCompletionResponse, LlmError> {
+        let url = self.url();
+        let request_body =
+            ChatCompletionRequest::new(messages, &self.model, &self.options, temperature, max_tokens);
+        let _permit = self
+            .rate_limiter
+            .wait_if_needed(request_body.estimated_tokens())
+            .await;
+
+        info!("Sending request to {}", url);
+
+        let request = self.client.post(&url);
+        let request = match &self.api_key {
+            Some(key) if self.auth_header.eq_ignore_ascii_case("authorization") => {
+                request.bearer_auth(key)
+            }
+            Some(key) => request.header(self.auth_header.as_str(), key),
+            None => request,
+        };
+        let response = request
+            .json(&request_body)
+            .send()
+            .await
+            .map_err(|e| http::connection_error(&url, e))?;
+
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let body = response.text().await?;
+            error!("API error from {} ({}): {}", url, status, body);
+            return Err(LlmError::Api { status, body });
+        }
+
+        let completion_response: ChatCompletionResponse = response.json().await?;
+        self.rate_limiter.record_usage(completion_response.usage).await;
+        self.rate_limiter
+            .record_fingerprint(completion_response.system_fingerprint.clone());
+
+        Ok(completion_response)
+    }
+}
+
+#[async_trait]
+impl LLMProvider for OpenAICompatClient {
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn provider(&self) -> &str {
+        "openai"
+    }
+
+    fn supports_images(&self) -> bool {
+        self.options.vision
+    }
+
+    fn last_usage(&self) -> Option<Usage> {
+        self.rate_limiter.last_usage()
+    }
+
+    fn last_fingerprint(&self) -> Option<String> {
+        self.rate_limiter.last_fingerprint()
+    }
+
+    async fn generate(&self, prompt: &str, config: &Value) -> Result<String, LlmError> {
+        let messages = [
+            ChatMessage::system("You are a helpful AI coding assistant."),
+            ChatMessage::user(prompt),
+        ];
+        self.generate_chat(&messages, config).await
+    }
+
+    async fn generate_chat(
+        &self,
+        messages: &[ChatMessage],
+        _config: &Value,
+    ) -> Result<String, LlmError> {
+        self.chat_completions(messages.to_vec(), 0.7, Some(MAX_REPLY_TOKENS))
+            .await?
+            .into_text()
+    }
+}
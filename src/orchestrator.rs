@@ -0,0 +1,299 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::Semaphore;
+
+use crate::engine::AgentEngine;
+use crate::error::{AgentError, ToolError};
+use crate::events::AgentEvent;
+use crate::git;
+use crate::glyphs;
+use crate::interrupt;
+use crate::queue::{self, QueueReport, QueuedTask, TaskOutcome, TaskQueue, TaskReport};
+
+const DEFAULT_SESSIONS: usize = 4;
+// Under the project; saved with QueueReport::save_in
+pub const REPORT_DIR: &str = ".botcoder/parallel";
+
+// `botcoder parallel tasks.toml` runs the tasks of a queue file (see
+// queue.rs) side by side instead of one after another, at most
+// BOTCODER_SESSIONS (default 4) at once. Each task gets its own git
+// worktree, checked out from HEAD on the branch botcoder/<started>/<name>,
+// and an equal share of the rate limits. A task that succeeds is committed
+// on its branch; every worktree is kept for review and merging.
+pub fn sessions() -> usize {
+    env::var("BOTCODER_SESSIONS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_SESSIONS)
+}
+
+// Where the worktrees go: BOTCODER_WORKTREES, or <repo>-worktrees next to
+// the repository so they don't show up in its own file listings
+pub fn worktree_dir(root: &str) -> PathBuf {
+    if let Ok(dir) = env::var("BOTCODER_WORKTREES") {
+        return PathBuf::from(dir);
+    }
+    let repo = PathBuf::from(git::workdir(root).unwrap_or_else(|| root.to_string()));
+    let repo = repo.canonicalize().unwrap_or(repo);
+    let name = repo.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    repo.with_file_name(format!("{}-worktrees", name))
+}
+
+// Builds a session's engine on its worktree (or the project directory in
+// it)
+pub type SessionFactory = dyn Fn(&str) -> Result<AgentEngine, AgentError> + Send + Sync;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Queued,
+    Running,
+    Done(TaskOutcome),
+}
+
+// One session as the overview shows it
+#[derive(Clone, Debug)]
+pub struct SessionView {
+    pub name: String,
+    pub branch: String,
+    pub worktree: String,
+    pub phase: Phase,
+    pub iteration: u32,
+    pub tokens: u32,
+    // The tool the session ran last, or its error once it failed
+    pub activity: String,
+    pub started: Option<Instant>,
+    pub elapsed: Duration,
+}
+
+// The state of every session, shared between the sessions and whoever
+// displays it. Quiet leaves progress to the display instead of stderr.
+#[derive(Clone, Default)]
+pub struct Overview {
+    sessions: Arc<Mutex<Vec<SessionView>>>,
+    quiet: bool,
+}
+
+impl Overview {
+    pub fn quiet() -> Self {
+        Self {
+            quiet: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn sessions(&self) -> Vec<SessionView> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        for session in &mut sessions {
+            if let (Phase::Running, Some(started)) = (session.phase, session.started) {
+                session.elapsed = started.elapsed();
+            }
+        }
+        sessions
+    }
+
+    fn push(&self, session: SessionView) {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner()).push(session);
+    }
+
+    fn update(&self, index: usize, change: impl FnOnce(&mut SessionView)) {
+        if let Some(session) = self.sessions.lock().unwrap_or_else(|e| e.into_inner()).get_mut(index) {
+            change(session);
+        }
+    }
+
+    fn finish(&self, index: usize, report: &TaskReport) {
+        self.update(index, |session| {
+            session.phase = Phase::Done(report.outcome);
+            session.elapsed = Duration::from_millis(report.wall_ms);
+            session.iteration = report.iterations;
+            session.tokens = report.tokens;
+            if let Some(error) = &report.error {
+                session.activity = error.clone();
+            }
+        });
+        if !self.quiet {
+            eprintln!(
+                "{}",
+                glyphs::plain(&format!(
+                    "{} {}: {:?}, {} iteration(s), {} tokens, {:.1}s{}",
+                    if report.outcome == TaskOutcome::Succeeded { "✓" } else { "✗" },
+                    report.name,
+                    report.outcome,
+                    report.iterations,
+                    report.tokens,
+                    report.wall_ms as f64 / 1000.0,
+                    report.checkpoint.as_ref().map(|c| format!(", {}", c)).unwrap_or_default()
+                ))
+            );
+        }
+    }
+}
+
+// Runs the queue's tasks on worktrees under `worktrees`, `sessions` at a
+// time, and reports them in the file's order. Tasks start in order; with
+// stop_on_failure, those not yet started when one fails are skipped.
+pub async fn run(
+    queue: TaskQueue,
+    root: String,
+    sessions: usize,
+    max_iterations: u32,
+    worktrees: PathBuf,
+    new_engine: Arc<SessionFactory>,
+    overview: Overview,
+) -> Result<QueueReport, AgentError> {
+    let repo = git::workdir(&root)
+        .ok_or_else(|| ToolError::InvalidArgs(format!("{} is not in a git repository", root)))?;
+    // The project may be a directory inside the repository
+    let subdir = Path::new(&root)
+        .canonicalize()
+        .ok()
+        .zip(Path::new(&repo).canonicalize().ok())
+        .and_then(|(root, repo)| root.strip_prefix(repo).map(Path::to_path_buf).ok())
+        .unwrap_or_default();
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    let checkout = |task: &QueuedTask| worktrees.join(started.to_string()).join(branch_name(&task.name));
+    for task in &queue.tasks {
+        // Joining an empty path would add a trailing slash
+        let worktree = if subdir.as_os_str().is_empty() {
+            checkout(task)
+        } else {
+            checkout(task).join(&subdir)
+        };
+        overview.push(SessionView {
+            name: task.name.clone(),
+            branch: format!("botcoder/{}/{}", started, branch_name(&task.name)),
+            worktree: worktree.to_string_lossy().into_owned(),
+            phase: Phase::Queued,
+            iteration: 0,
+            tokens: 0,
+            activity: String::new(),
+            started: None,
+            elapsed: Duration::ZERO,
+        });
+    }
+
+    let slots = Arc::new(Semaphore::new(sessions.max(1)));
+    let runtime = tokio::runtime::Handle::current();
+    let mut running = Vec::new();
+    for (index, task) in queue.tasks.iter().enumerate() {
+        let slot = slots.clone().acquire_owned().await.expect("the semaphore is never closed");
+        let failed = overview.sessions().iter().any(|s| {
+            matches!(s.phase, Phase::Done(outcome) if outcome != TaskOutcome::Succeeded)
+        });
+        let view = overview.sessions()[index].clone();
+        let mut report = queue::skipped(&task.name);
+        if interrupt::is_interrupted() || queue.stop_on_failure && failed {
+            overview.finish(index, &report);
+            running.push(Session::Ended(report));
+            continue;
+        }
+        if let Err(e) = git::add_worktree(&repo, &checkout(task), Some(&view.branch)) {
+            report.outcome = TaskOutcome::Failed;
+            report.error = Some(format!("Could not create the worktree: {}", e));
+            overview.finish(index, &report);
+            running.push(Session::Ended(report));
+            continue;
+        }
+        let mut engine = new_engine(&view.worktree)?;
+        engine.subscribe(watch(overview.clone(), index));
+        overview.update(index, |session| {
+            session.phase = Phase::Running;
+            session.started = Some(Instant::now());
+        });
+        if !overview.quiet {
+            eprintln!(
+                "{}",
+                glyphs::plain(&format!("▸ {}: {} ({})", task.name, task.instruction, view.worktree))
+            );
+        }
+
+        // Tools block, so each session gets a blocking thread rather than a
+        // runtime worker
+        let (task, overview, runtime) = (task.clone(), overview.clone(), runtime.clone());
+        running.push(Session::Started(tokio::task::spawn_blocking(move || {
+            runtime.block_on(async move {
+                let mut report = queue::attempt(&task, &view.worktree, max_iterations, &mut engine).await;
+                report.checkpoint = checkpoint(&task, &view, &report);
+                overview.finish(index, &report);
+                drop(slot);
+                report
+            })
+        })));
+    }
+
+    let mut report = QueueReport {
+        started,
+        tasks: Vec::new(),
+    };
+    for (task, session) in queue.tasks.iter().zip(running) {
+        let result = match session {
+            Session::Started(session) => session.await.unwrap_or_else(|e| {
+                let mut report = queue::skipped(&task.name);
+                report.outcome = TaskOutcome::Failed;
+                report.error = Some(format!("The session crashed: {}", e));
+                report
+            }),
+            Session::Ended(report) => report,
+        };
+        report.tasks.push(result);
+    }
+    Ok(report)
+}
+
+enum Session {
+    Started(tokio::task::JoinHandle<TaskReport>),
+    // Skipped, or its worktree couldn't be created
+    Ended(TaskReport),
+}
+
+// Keeps the session's line of the overview current
+fn watch(overview: Overview, index: usize) -> impl FnMut(&AgentEvent) + Send {
+    move |event: &AgentEvent| match event {
+        AgentEvent::IterationStarted { iteration } => {
+            overview.update(index, |session| session.iteration = *iteration)
+        }
+        AgentEvent::LlmRequest { input_tokens, .. } => {
+            overview.update(index, |session| session.tokens += input_tokens)
+        }
+        AgentEvent::LlmResponse { output_tokens, .. } => {
+            overview.update(index, |session| session.tokens += output_tokens)
+        }
+        AgentEvent::ToolExecuted { tool, .. } => {
+            overview.update(index, |session| session.activity = tool.clone())
+        }
+        _ => {}
+    }
+}
+
+// Commits a successful task's changes on its branch; anything else stays
+// uncommitted in the worktree
+fn checkpoint(task: &QueuedTask, view: &SessionView, report: &TaskReport) -> Option<String> {
+    if report.files.is_empty() {
+        return None;
+    }
+    if report.outcome != TaskOutcome::Succeeded {
+        return Some(format!("left in {}", view.worktree));
+    }
+    let message = format!("botcoder: {}\n\n{}", task.name, task.instruction);
+    Some(match git::commit(&git::encode_commit(&report.files, &message), &view.worktree) {
+        Ok(output) if output.exit_code == Some(0) => format!("committed on {}", view.branch),
+        Ok(output) => format!("commit failed: {}", output.text.trim()),
+        Err(e) => format!("commit failed: {}", e),
+    })
+}
+
+// Task names as branch name parts
+fn branch_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+        .collect()
+}
@@ -0,0 +1,210 @@
+use proc_macro2::LineColumn;
+use syn::{spanned::Spanned, ImplItem, Item, TraitItem, Visibility};
+
+// Structural outline of a source file: item declarations with their line
+// numbers. Rust files are parsed with syn; anything else, or Rust that
+// doesn't parse, falls back to matching declaration keywords per line.
+const KEYWORDS: [&str; 12] = [
+    "fn ",
+    "struct ",
+    "enum ",
+    "trait ",
+    "impl ",
+    "impl<",
+    "mod ",
+    "type ",
+    "macro_rules!",
+    "class ",
+    "def ",
+    "function ",
+];
+
+const MODIFIERS: [&str; 7] = [
+    "pub(crate) ",
+    "pub(super) ",
+    "pub ",
+    "async ",
+    "unsafe ",
+    "const ",
+    "export ",
+];
+
+// (line, signature) pairs; items inside impls, traits and inline modules
+// are indented by two spaces per level
+pub fn outline(path: &str, content: &str) -> Vec<(usize, String)> {
+    if path.ends_with(".rs") {
+        if let Ok(file) = syn::parse_file(content) {
+            let lines: Vec<&str> = content.lines().collect();
+            let mut items = Vec::new();
+            for item in &file.items {
+                push_item(&lines, item, 0, &mut items);
+            }
+            return items;
+        }
+    }
+
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| is_item(line))
+        .map(|(i, line)| (i + 1, header(line)))
+        .collect()
+}
+
+pub fn format_outline(items: &[(usize, String)]) -> String {
+    items
+        .iter()
+        .map(|(line, signature)| format!("{:>5}: {}\n", line, signature))
+        .collect()
+}
+
+fn push_item(lines: &[&str], item: &Item, depth: usize, out: &mut Vec<(usize, String)>) {
+    let indent = "  ".repeat(depth);
+    match item {
+        Item::Fn(f) => {
+            let start = start_of(&f.vis, f.sig.span().start());
+            out.push((
+                start.line,
+                format!("{}{}", indent, source(lines, start, f.sig.span().end())),
+            ));
+        }
+        Item::Impl(i) => {
+            let start = i.impl_token.span.start();
+            out.push((
+                start.line,
+                format!("{}{}", indent, source(lines, start, i.self_ty.span().end())),
+            ));
+            for member in &i.items {
+                if let ImplItem::Fn(f) = member {
+                    let start = start_of(&f.vis, f.sig.span().start());
+                    let signature = source(lines, start, f.sig.span().end());
+                    out.push((start.line, format!("{}  {}", indent, signature)));
+                }
+            }
+        }
+        Item::Trait(t) if is_public(&t.vis) => {
+            let start = start_of(&t.vis, t.trait_token.span.start());
+            out.push((
+                start.line,
+                format!("{}{}", indent, header(lines[start.line - 1])),
+            ));
+            for member in &t.items {
+                if let TraitItem::Fn(f) = member {
+                    let start = f.sig.span().start();
+                    let signature = source(lines, start, f.sig.span().end());
+                    out.push((start.line, format!("{}  {}", indent, signature)));
+                }
+            }
+        }
+        Item::Mod(m) if is_public(&m.vis) || m.content.is_some() => {
+            let start = start_of(&m.vis, m.mod_token.span.start());
+            out.push((
+                start.line,
+                format!("{}{}", indent, header(lines[start.line - 1])),
+            ));
+            if let Some((_, items)) = &m.content {
+                for item in items {
+                    push_item(lines, item, depth + 1, out);
+                }
+            }
+        }
+        Item::Struct(s) if is_public(&s.vis) => {
+            let start = start_of(&s.vis, s.struct_token.span.start());
+            out.push((
+                start.line,
+                format!("{}{}", indent, header(lines[start.line - 1])),
+            ));
+        }
+        Item::Enum(e) if is_public(&e.vis) => {
+            let start = start_of(&e.vis, e.enum_token.span.start());
+            out.push((
+                start.line,
+                format!("{}{}", indent, header(lines[start.line - 1])),
+            ));
+        }
+        Item::Type(t) if is_public(&t.vis) => {
+            let start = start_of(&t.vis, t.type_token.span.start());
+            out.push((
+                start.line,
+                format!("{}{}", indent, header(lines[start.line - 1])),
+            ));
+        }
+        Item::Const(c) if is_public(&c.vis) => {
+            let start = start_of(&c.vis, c.const_token.span.start());
+            out.push((
+                start.line,
+                format!("{}{}", indent, source(lines, start, c.ty.span().end())),
+            ));
+        }
+        Item::Static(s) if is_public(&s.vis) => {
+            let start = start_of(&s.vis, s.static_token.span.start());
+            out.push((
+                start.line,
+                format!("{}{}", indent, source(lines, start, s.ty.span().end())),
+            ));
+        }
+        Item::Macro(m) if m.ident.is_some() => {
+            let start = m.mac.path.span().start();
+            out.push((
+                start.line,
+                format!("{}{}", indent, header(lines[start.line - 1])),
+            ));
+        }
+        _ => {}
+    }
+}
+
+fn is_public(vis: &Visibility) -> bool {
+    !matches!(vis, Visibility::Inherited)
+}
+
+// Items start at their visibility when they have one, after any attributes
+fn start_of(vis: &Visibility, fallback: LineColumn) -> LineColumn {
+    if is_public(vis) {
+        vis.span().start()
+    } else {
+        fallback
+    }
+}
+
+// Source text between two positions, on one line
+fn source(lines: &[&str], start: LineColumn, end: LineColumn) -> String {
+    let mut text = String::new();
+    for line in start.line..=end.line {
+        let Some(source) = lines.get(line - 1) else {
+            break;
+        };
+        let chars: Vec<char> = source.chars().collect();
+        let from = if line == start.line { start.column } else { 0 };
+        let to = if line == end.line {
+            end.column.min(chars.len())
+        } else {
+            chars.len()
+        };
+        if from < to {
+            text.extend(&chars[from..to]);
+            text.push(' ');
+        }
+    }
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace("( ", "(")
+        .replace(" )", ")")
+        .replace(",)", ")")
+}
+
+fn is_item(line: &str) -> bool {
+    let mut rest = line.trim_start();
+    while let Some(modifier) = MODIFIERS.iter().find(|m| rest.starts_with(*m)) {
+        rest = &rest[modifier.len()..];
+    }
+    KEYWORDS.iter().any(|k| rest.starts_with(k))
+}
+
+// The declaration line without its body
+fn header(line: &str) -> String {
+    let line = line.trim();
+    let line = line.strip_suffix('{').unwrap_or(line).trim_end();
+    line.to_string()
+}
@@ -0,0 +1,117 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, LazyLock,
+    },
+};
+
+use crate::diff;
+use crate::encoding;
+use crate::file_cache;
+use crate::vfs::{self, OverlayFs, Vfs};
+
+// --patch-output <file>: the edit tools write to an in-memory overlay
+// instead of the project. Reads through the tools see the overlay, the tree
+// on disk is never modified, and at the end the overlay is written out as
+// one patch. Commands, which would run on the tree without the edits, are
+// turned off, and botcoder's own state goes to the temp directory.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// File contents by absolute path, as they would be written
+static FS: LazyLock<Arc<OverlayFs>> = LazyLock::new(Default::default);
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// Drops every change collected so far
+pub fn clear() {
+    FS.changes.clear();
+}
+
+// The file system the tools use while the overlay is on
+pub fn fs() -> Arc<dyn Vfs> {
+    FS.clone()
+}
+
+// Where a .botcoder/ file of the project goes: in the project, or while
+// the overlay is on, in a directory of the temp directory kept per project
+pub fn state_path(root: &str, relative: &str) -> PathBuf {
+    if !enabled() {
+        return Path::new(root).join(relative);
+    }
+    let project = vfs::key(Path::new(root));
+    let id = file_cache::hash_bytes(project.to_string_lossy().as_bytes());
+    env::temp_dir()
+        .join(format!("botcoder-state-{:016x}", id))
+        .join(relative)
+}
+
+// Tools that run commands on the project; they would see the tree without
+// the overlay's edits and could change it
+pub fn runs_commands(tool: &str) -> bool {
+    matches!(
+        tool,
+        "execute_command" | "run_tests" | "cargo_clippy" | "cargo_tree" | "cargo_deps"
+    ) || crate::plugins::find(tool).is_some()
+}
+
+// The collected changes to files under root as a patch git apply and patch
+// -p1 take, paths relative to root; files the overlay leaves as they are on
+// disk are skipped
+pub fn patch(root: &str) -> String {
+    let root = vfs::key(Path::new(root));
+    let mut out = String::new();
+    for (path, bytes) in FS.changes.files() {
+        let Ok(relative) = path.strip_prefix(&root) else {
+            continue;
+        };
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let before = fs::read(path).ok();
+        let text = |bytes: &[u8]| encoding::decode(bytes).map(|(text, _)| text).unwrap_or_default();
+        let diff = diff::unified(
+            &relative,
+            &before.as_deref().map(text).unwrap_or_default(),
+            &text(&bytes),
+        );
+        // diff::unified starts with its own ---/+++ lines
+        let Some(hunks) = diff.splitn(3, '\n').nth(2).filter(|h| !h.is_empty()) else {
+            continue;
+        };
+        out.push_str(&format!("diff --git a/{0} b/{0}\n", relative));
+        match before {
+            Some(_) => out.push_str(&format!("--- a/{}\n", relative)),
+            None => out.push_str("new file mode 100644\n--- /dev/null\n"),
+        }
+        out.push_str(&format!("+++ b/{}\n{}", relative, hunks));
+    }
+    out
+}
+
+// Writes the patch to path; returns the summary to show: the files changed
+// and the lines added and removed
+pub fn save_patch(root: &str, path: &Path) -> io::Result<String> {
+    let patch = patch(root);
+    fs::write(path, &patch)?;
+    let files = patch.lines().filter(|line| line.starts_with("diff --git ")).count();
+    let added = patch
+        .lines()
+        .filter(|line| line.starts_with('+') && !line.starts_with("+++ "))
+        .count();
+    let removed = patch
+        .lines()
+        .filter(|line| line.starts_with('-') && !line.starts_with("--- "))
+        .count();
+    Ok(format!(
+        "✓ Wrote {} ({} file(s), +{} -{}); the project is unchanged",
+        path.display(),
+        files,
+        added,
+        removed
+    ))
+}
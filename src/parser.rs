@@ -0,0 +1,296 @@
+use serde_json::{Map, Value};
+use std::env;
+
+use crate::app;
+use crate::commands::{self, CommandOptions};
+use crate::plugins;
+use crate::tool_registry;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum ToolFormat {
+    // read_file("..") calls and CHANGE blocks anywhere in the reply
+    #[default]
+    Text,
+    // A single {"tool": .., "args": {..}} object per reply
+    Json,
+}
+
+impl ToolFormat {
+    pub fn from_env() -> Self {
+        match env::var("BOTCODER_TOOL_FORMAT").as_deref() {
+            Ok("json") => ToolFormat::Json,
+            _ => ToolFormat::Text,
+        }
+    }
+}
+
+const DEFAULT_MAX_RETRIES: u32 = 2;
+
+// Markers that show the model meant to call a tool in the text protocol
+const TOOL_MARKERS: [&str; 27] = [
+    "read_file",
+    "outline(",
+    "execute_command",
+    "run_tests",
+    "CHANGE:",
+    "<<<<<<< CURRENT",
+    "EDIT_LINES:",
+    "APPEND_TO_FILE:",
+    "INSERT_AFTER:",
+    "TOOL:",
+    "git_commit(",
+    "git_status(",
+    "git_diff(",
+    "git_log(",
+    "switch_project(",
+    "step_done(",
+    "cargo_add(",
+    "cargo_remove(",
+    "cargo_tree(",
+    "cargo_deps(",
+    "cargo_clippy(",
+    "cargo_fmt(",
+    "lookup_crate(",
+    "fetch_url(",
+    "semantic_search(",
+    "rename_symbol(",
+    "ask_user(",
+];
+
+#[derive(Clone, Copy)]
+pub struct ResponseParser {
+    pub format: ToolFormat,
+    // Corrective follow-ups sent in a row before giving up on a reply
+    pub max_retries: u32,
+}
+
+impl Default for ResponseParser {
+    fn default() -> Self {
+        Self {
+            format: ToolFormat::default(),
+            max_retries: DEFAULT_MAX_RETRIES,
+        }
+    }
+}
+
+impl ResponseParser {
+    pub fn from_env() -> Self {
+        Self {
+            format: ToolFormat::from_env(),
+            max_retries: env::var("BOTCODER_PARSE_RETRIES")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_MAX_RETRIES),
+        }
+    }
+
+    // The base prompt plus the protocol description for the chosen format,
+    // including any project tools
+    pub fn system_prompt(&self, prompt: &str) -> String {
+        match self.format {
+            ToolFormat::Text if plugins::registered().is_empty() => prompt.to_string(),
+            ToolFormat::Text => format!("{}\n\n{}", prompt, plugins::prompt_section()),
+            ToolFormat::Json => format!("{}\n\n{}", prompt, tool_registry::json_instructions()),
+        }
+    }
+
+    // Text mode only fails when the reply mentions a tool but no call
+    // matched, saying what was malformed where it can; JSON mode rejects
+    // anything that doesn't match the registry. Errors are worded for the
+    // model.
+    pub fn parse(&self, text: &str) -> Result<Vec<(String, String)>, String> {
+        match self.format {
+            ToolFormat::Text => {
+                let (tools, diagnostics) = app::extract_tools_checked(text);
+                if tools.is_empty() && !diagnostics.is_empty() {
+                    return Err(diagnostics.join("; "));
+                }
+                if tools.is_empty() && TOOL_MARKERS.iter().any(|m| text.contains(m)) {
+                    return Err(
+                        "the reply mentions a tool but no call matched the expected syntax"
+                            .to_string(),
+                    );
+                }
+                // The well-formed calls still run
+                for diagnostic in diagnostics {
+                    log::warn!("Skipped a malformed tool call: {}", diagnostic);
+                }
+                Ok(tools)
+            }
+            ToolFormat::Json => parse_json(text),
+        }
+    }
+
+    // Sent back to the model as the next user turn after a rejected reply
+    pub fn error_feedback(&self, error: &str) -> String {
+        let rules = match self.format {
+            ToolFormat::Text if plugins::registered().is_empty() => TEXT_RULES.to_string(),
+            ToolFormat::Text => format!("{}\n\n{}", TEXT_RULES, plugins::prompt_section()),
+            ToolFormat::Json => "Reply again with exactly one JSON object of the form \
+                 {\"tool\": \"<name>\", \"args\": {...}}."
+                .to_string(),
+        };
+        format!(
+            "Your last reply could not be parsed as a tool call: {}\n\n{}",
+            error, rules
+        )
+    }
+}
+
+const TEXT_RULES: &str = "Use exactly this syntax, one call per line:
+read_file(\"path/to/file\"), read_file(\"path/to/file\", numbered) or read_file(\"path/to/file\", 200-400)
+outline(\"path/to/file\")
+execute_command(\"command here\") or execute_command(\"cargo test\", cwd=\"crates/api\", env=\"RUST_LOG=debug\")
+run_tests() or run_tests(\"test_name_filter\")
+git_status(), git_diff() or git_diff(\"path/to/file\"), git_log() or git_log(20)
+git_commit(\"type(scope): summary\")
+cargo_add(\"serde --features derive\"), cargo_remove(\"serde\"), cargo_tree() or cargo_tree(\"-i serde\"), cargo_deps() or cargo_deps(\"serde\")
+cargo_clippy() or cargo_clippy(\"--all-targets\"), cargo_fmt() or cargo_fmt(\"check\")
+lookup_crate(\"serde\") or lookup_crate(\"serde@1.0.200\")
+fetch_url(\"https://doc.rust-lang.org/error_codes/E0502.html\")
+semantic_search(\"where retries are scheduled\")
+rename_symbol(\"old_name\", \"new_name\")
+ask_user(\"Should the cache be per user or global?\")
+
+Put a backslash before any double quote inside a quoted argument: execute_command(\"grep \\\"fn main\\\" src\")
+
+and for file changes:
+
+CHANGE: path/to/file
+<<<<<<< CURRENT
+old content here
+=======
+new content here
+>>>>>>> NEW
+
+or, to replace lines by number:
+
+EDIT_LINES: path/to/file 10-12
+replacement lines
+END_EDIT
+
+or, to add content without quoting existing code:
+
+APPEND_TO_FILE: path/to/file
+new lines
+END_APPEND
+
+INSERT_AFTER: path/to/file \"anchor text\"
+new lines
+END_INSERT";
+
+fn parse_json(text: &str) -> Result<Vec<(String, String)>, String> {
+    let json = json_body(text).ok_or("no JSON object found in the reply")?;
+    let value: Value = serde_json::from_str(json).map_err(|e| format!("invalid JSON ({})", e))?;
+    let object = value
+        .as_object()
+        .ok_or("the reply must be a JSON object, not an array or value")?;
+
+    for key in object.keys() {
+        if !matches!(key.as_str(), "tool" | "args" | "reasoning") {
+            return Err(format!(
+                "unexpected top-level field \"{}\"; only \"tool\", \"args\" and \"reasoning\" are allowed",
+                key
+            ));
+        }
+    }
+
+    let name = object
+        .get("tool")
+        .and_then(|v| v.as_str())
+        .ok_or("missing string field \"tool\"")?;
+    let empty = Map::new();
+    let args = match object.get("args") {
+        Some(Value::Object(args)) => args,
+        Some(_) => return Err("\"args\" must be a JSON object".to_string()),
+        None => &empty,
+    };
+
+    // Project tools take their arguments as a JSON object
+    if let Some(plugin) = plugins::find(name) {
+        plugin.validate(args)?;
+        return Ok(vec![(plugin.name, Value::Object(args.clone()).to_string())]);
+    }
+
+    let spec = tool_registry::find(name).ok_or_else(|| {
+        format!(
+            "unknown tool \"{}\"; available tools are: {}",
+            name,
+            tool_registry::names().join(", ")
+        )
+    })?;
+
+    for key in args.keys() {
+        if !spec.args.iter().any(|(arg, _, _)| arg == key) {
+            return Err(format!(
+                "{} does not take an argument named \"{}\"",
+                spec.name, key
+            ));
+        }
+    }
+
+    let mut values = Vec::new();
+    for (arg, required, _) in spec.args {
+        match args.get(*arg) {
+            Some(Value::String(s)) => values.push(s.clone()),
+            // {"RUST_LOG": "debug"} is taken as "RUST_LOG=debug"
+            Some(Value::Object(vars)) if spec.name == "execute_command" && *arg == "env" => {
+                let pairs: Vec<String> = vars
+                    .iter()
+                    .map(|(name, value)| match value.as_str() {
+                        Some(value) => format!("{}={}", name, value),
+                        None => format!("{}={}", name, value),
+                    })
+                    .collect();
+                values.push(pairs.join(" "));
+            }
+            Some(_) => return Err(format!("{}.{} must be a string", spec.name, arg)),
+            None if *required => {
+                return Err(format!("{} requires the argument \"{}\"", spec.name, arg))
+            }
+            None => values.push(String::new()),
+        }
+    }
+
+    // Encode the arguments the same way the text protocol does
+    let param = match spec.name {
+        "done" => return Ok(Vec::new()),
+        "read_file" => {
+            let mut options = Vec::new();
+            if !values[1].is_empty() {
+                options.push(values[1].as_str());
+            }
+            if values[2] == "true" {
+                options.push("numbered");
+            }
+            if options.is_empty() {
+                values[0].clone()
+            } else {
+                format!("{}:::{}", values[0], options.join(","))
+            }
+        }
+        "write_file_delta" => {
+            app::encode_delta(&values[0], &[(values[1].clone(), values[2].clone())])
+        }
+        "edit_lines" => format!("{}:::{}-{}\n{}", values[0], values[1], values[2], values[3]),
+        "append_to_file" => format!("{}:::{}", values[0], values[1]),
+        "execute_command" => commands::encode(
+            &values[0],
+            &CommandOptions::parse(&values[1], &values[2])
+                .map_err(|e| format!("execute_command.env: {}", e))?,
+        ),
+        "insert_after" => format!("{}:::{}\n{}", values[0], values[1], values[2]),
+        "rename_symbol" => format!("{}:::{}", values[0], values[1]),
+        "git_status" => String::new(),
+        _ => values.remove(0),
+    };
+
+    Ok(vec![(spec.name.to_string(), param)])
+}
+
+// The outermost {...} in the reply, tolerating code fences or stray prose
+fn json_body(text: &str) -> Option<&str> {
+    let start = text.find('{')?;
+    let end = text.rfind('}')?;
+    (end > start).then(|| &text[start..=end])
+}
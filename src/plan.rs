@@ -0,0 +1,200 @@
+use serde::Serialize;
+use std::env;
+
+use crate::file_tree;
+use crate::llm::ChatMessage;
+
+// Files listed to the planner so the steps name real paths
+const PLAN_FILES: usize = 200;
+const DEFAULT_SUBTASK_ITERATIONS: u32 = 15;
+const DEFAULT_SUBTASK_TOKENS: u32 = 60_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Pending,
+    Active,
+    Done,
+}
+
+impl StepStatus {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            StepStatus::Pending => "○",
+            StepStatus::Active => "▶",
+            StepStatus::Done => "✓",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PlanStep {
+    pub text: String,
+    pub status: StepStatus,
+    // What step_done reported when the step was finished
+    pub summary: Option<String>,
+}
+
+// Numbered steps drafted by the model for a task, edited and approved by
+// the user, then worked through one at a time with step_done
+#[derive(Clone, Debug, Serialize)]
+pub struct Plan {
+    pub task: String,
+    pub steps: Vec<PlanStep>,
+}
+
+impl Plan {
+    // The "1. ..." or "1) ..." lines of the model's reply; None without any
+    pub fn parse(task: &str, text: &str) -> Option<Self> {
+        let steps: Vec<PlanStep> = text
+            .lines()
+            .filter_map(|line| {
+                let line = line.trim();
+                let digits = line.find(|c: char| !c.is_ascii_digit())?;
+                let rest = line[digits..].strip_prefix(['.', ')'])?;
+                (digits > 0).then(|| rest.trim().trim_matches('*').trim())
+            })
+            .filter(|step| !step.is_empty())
+            .map(|step| PlanStep {
+                text: step.to_string(),
+                status: StepStatus::Pending,
+                summary: None,
+            })
+            .collect();
+        (!steps.is_empty()).then(|| Self {
+            task: task.to_string(),
+            steps,
+        })
+    }
+
+    // Index of the step being worked on, if any is left
+    pub fn current(&self) -> Option<usize> {
+        self.steps
+            .iter()
+            .position(|s| s.status == StepStatus::Active)
+            .or_else(|| self.steps.iter().position(|s| s.status == StepStatus::Pending))
+    }
+
+    // Makes the first unfinished step the active one
+    pub fn start(&mut self) {
+        if let Some(index) = self.current() {
+            self.steps[index].status = StepStatus::Active;
+        }
+    }
+
+    // step_done: finishes the active step and starts the next; the reply
+    // tells the model what comes next
+    pub fn complete_current(&mut self, summary: &str) -> String {
+        let Some(index) = self.current() else {
+            return "All steps of the plan are already done".to_string();
+        };
+        self.steps[index].status = StepStatus::Done;
+        let summary = summary.trim();
+        self.steps[index].summary = (!summary.is_empty()).then(|| summary.to_string());
+        self.start();
+        match self.current() {
+            Some(next) => format!(
+                "Step {} done. Now step {} of {}: {}",
+                index + 1,
+                next + 1,
+                self.steps.len(),
+                self.steps[next].text
+            ),
+            None => format!("Step {} done. All {} steps of the plan are done", index + 1, self.steps.len()),
+        }
+    }
+
+    // Added to the system prompt to keep every iteration on the current step
+    pub fn prompt_section(&self) -> String {
+        let mut out = format!("PLAN\nThe user approved this plan for the task \"{}\":\n", self.task);
+        for (i, step) in self.steps.iter().enumerate() {
+            let status = match step.status {
+                StepStatus::Pending => "",
+                StepStatus::Active => " (current)",
+                StepStatus::Done => " (done)",
+            };
+            out.push_str(&format!("{}. {}{}\n", i + 1, step.text, status));
+        }
+        match self.current() {
+            Some(index) => out.push_str(&format!(
+                "\nYou are on step {} of {}: {}\nWork only on this step. When it is finished, \
+                 call step_done(\"what was done\") to move on to the next one.",
+                index + 1,
+                self.steps.len(),
+                self.steps[index].text
+            )),
+            None => out.push_str("\nAll steps are done; check the result and finish the task."),
+        }
+        out
+    }
+}
+
+// --subtasks: each step of the plan runs as a nested agent run with a
+// history of its own, bounded by BOTCODER_SUBTASK_ITERATIONS (default 15)
+// and BOTCODER_SUBTASK_TOKENS (default 60000)
+#[derive(Clone, Copy, Debug)]
+pub struct SubtaskBudget {
+    pub iterations: u32,
+    pub tokens: u32,
+}
+
+impl SubtaskBudget {
+    pub fn from_env() -> Self {
+        let var = |name: &str, default: u32| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        };
+        Self {
+            iterations: var("BOTCODER_SUBTASK_ITERATIONS", DEFAULT_SUBTASK_ITERATIONS),
+            tokens: var("BOTCODER_SUBTASK_TOKENS", DEFAULT_SUBTASK_TOKENS),
+        }
+    }
+}
+
+// The first message of a step's nested run: the task and what the earlier
+// steps did, in place of the main session's history
+pub fn subtask_brief(plan: &Plan, index: usize) -> String {
+    let mut out = format!("Task: {}\n", plan.task);
+    let done: Vec<String> = plan.steps[..index]
+        .iter()
+        .enumerate()
+        .filter(|(_, step)| step.status == StepStatus::Done)
+        .map(|(i, step)| match &step.summary {
+            Some(summary) => format!("{}. {}: {}", i + 1, step.text, summary),
+            None => format!("{}. {}", i + 1, step.text),
+        })
+        .collect();
+    if !done.is_empty() {
+        out.push_str(&format!("\nEarlier steps, already done:\n{}\n", done.join("\n")));
+    }
+    out.push_str(&format!(
+        "\nYour sub-task is step {} of {}: {}\nRead what you need, make the change and call \
+         step_done(\"what changed\") when it is finished.",
+        index + 1,
+        plan.steps.len(),
+        plan.steps[index].text
+    ));
+    out
+}
+
+// The planning request: the task and the project's files, answered with
+// a numbered list and no tool calls
+pub fn plan_request(task: &str, project_root: &str) -> Vec<ChatMessage> {
+    let files: Vec<String> = file_tree::scan(project_root)
+        .into_iter()
+        .filter(|entry| !entry.is_dir)
+        .map(|entry| entry.path)
+        .take(PLAN_FILES)
+        .collect();
+    vec![
+        ChatMessage::system(format!(
+            "You plan coding tasks. Reply with a numbered plan of 3 to 8 concrete steps, \
+             one line each, naming the files involved, and nothing else. Don't call tools.\n\n\
+             Project files:\n{}",
+            files.join("\n")
+        )),
+        ChatMessage::user(task),
+    ]
+}
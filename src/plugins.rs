@@ -0,0 +1,195 @@
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::RwLock,
+};
+
+use crate::app::{self, ToolOutput};
+use crate::dry_run;
+use crate::error::ToolError;
+use crate::interrupt;
+use crate::tool_registry;
+
+// Project-specific tools. Each .botcoder/tools/<name>.json manifest
+// describes one executable, which gets the call as JSON on stdin:
+// {"tool": .., "args": {..}, "project_root": ..}
+const TOOLS_DIR: &str = ".botcoder/tools";
+
+static REGISTERED: RwLock<Vec<Plugin>> = RwLock::new(Vec::new());
+
+#[derive(Clone, Deserialize)]
+pub struct Plugin {
+    pub name: String,
+    pub description: String,
+    #[serde(default)]
+    pub args: Vec<PluginArg>,
+    // Relative to the tools directory; defaults to the manifest's file name
+    // without .json
+    #[serde(default)]
+    command: Option<String>,
+    #[serde(skip)]
+    executable: PathBuf,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct PluginArg {
+    pub name: String,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub description: String,
+}
+
+// Loads the project's manifests, replacing any registered before. Invalid
+// manifests and names taken by built-in tools are skipped with a warning.
+pub fn register(root: &str) {
+    let plugins = load(root);
+    if let Ok(mut registered) = REGISTERED.write() {
+        *registered = plugins;
+    }
+}
+
+pub fn registered() -> Vec<Plugin> {
+    REGISTERED.read().map(|r| r.clone()).unwrap_or_default()
+}
+
+pub fn find(name: &str) -> Option<Plugin> {
+    registered().into_iter().find(|p| p.name == name)
+}
+
+fn load(root: &str) -> Vec<Plugin> {
+    let dir = Path::new(root).join(TOOLS_DIR);
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+
+    let mut manifests: Vec<PathBuf> = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "json"))
+        .collect();
+    manifests.sort();
+
+    let mut plugins: Vec<Plugin> = Vec::new();
+    for manifest in manifests {
+        let plugin = fs::read_to_string(&manifest)
+            .map_err(|e| e.to_string())
+            .and_then(|text| serde_json::from_str::<Plugin>(&text).map_err(|e| e.to_string()));
+        let mut plugin = match plugin {
+            Ok(plugin) => plugin,
+            Err(e) => {
+                log::warn!("Skipping tool manifest {}: {}", manifest.display(), e);
+                continue;
+            }
+        };
+
+        if tool_registry::find(&plugin.name).is_some() || plugins.iter().any(|p| p.name == plugin.name) {
+            log::warn!(
+                "Skipping tool manifest {}: the name {} is already taken",
+                manifest.display(),
+                plugin.name
+            );
+            continue;
+        }
+
+        plugin.executable = match &plugin.command {
+            Some(command) => dir.join(command),
+            None => manifest.with_extension(""),
+        };
+        plugins.push(plugin);
+    }
+    plugins
+}
+
+// Text-protocol description of the registered plugins, for the system
+// prompt; empty without any
+pub fn prompt_section() -> String {
+    let plugins = registered();
+    if plugins.is_empty() {
+        return String::new();
+    }
+
+    let mut out = String::from(
+        "PROJECT TOOLS\n\
+         This project provides extra tools. Call one with a TOOL block holding a JSON \
+         object of its arguments (all strings):\n\n\
+         TOOL: name\n\
+         {\"arg\": \"value\"}\n\
+         END_TOOL\n\n\
+         Available project tools:\n",
+    );
+    for plugin in &plugins {
+        out.push_str(&plugin.describe());
+    }
+    out
+}
+
+impl Plugin {
+    // One "- name: description" line plus one line per argument
+    pub fn describe(&self) -> String {
+        let mut out = format!("- {}: {}\n", self.name, self.description);
+        for arg in &self.args {
+            let kind = if arg.required { "required" } else { "optional" };
+            out.push_str(&format!(
+                "    {} (string, {}): {}\n",
+                arg.name, kind, arg.description
+            ));
+        }
+        out
+    }
+
+    // Checks a JSON object of arguments against the manifest; errors are
+    // worded for the model
+    pub fn validate(&self, args: &Map<String, Value>) -> Result<(), String> {
+        for (key, value) in args {
+            if !self.args.iter().any(|a| &a.name == key) {
+                return Err(format!(
+                    "{} does not take an argument named \"{}\"",
+                    self.name, key
+                ));
+            }
+            if !value.is_string() {
+                return Err(format!("{}.{} must be a string", self.name, key));
+            }
+        }
+        match self.args.iter().find(|a| a.required && !args.contains_key(&a.name)) {
+            Some(missing) => Err(format!(
+                "{} requires the argument \"{}\"",
+                self.name, missing.name
+            )),
+            None => Ok(()),
+        }
+    }
+
+    // param is the JSON object of arguments
+    pub fn run(&self, param: &str, root: &str) -> Result<ToolOutput, ToolError> {
+        let args: Map<String, Value> = match serde_json::from_str(param.trim()) {
+            Ok(Value::Object(args)) => args,
+            Ok(Value::Null) | Err(_) if param.trim().is_empty() => Map::new(),
+            _ => return Err(ToolError::InvalidFormat("project tool")),
+        };
+        self.validate(&args).map_err(ToolError::InvalidArgs)?;
+
+        if dry_run::enabled() {
+            return Ok(ToolOutput::text(format!(
+                "Would run: {} {}",
+                self.executable.display(),
+                Value::Object(args)
+            )));
+        }
+
+        let input = json!({
+            "tool": self.name,
+            "args": args,
+            "project_root": root,
+        });
+        let mut command = Command::new(&self.executable);
+        command.current_dir(root);
+        let output = interrupt::output_with_stdin(command, input.to_string().into_bytes())
+            .map_err(ToolError::Spawn)?;
+        Ok(app::command_output(&output))
+    }
+}
@@ -0,0 +1,120 @@
+use std::{env, fs, path::Path};
+
+// The kind of project at the root, from its manifest. It picks the check,
+// test and run commands and the project section of the system prompt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProjectKind {
+    Rust,
+    Python,
+    TypeScript,
+    JavaScript,
+    Unknown,
+}
+
+impl ProjectKind {
+    // Cargo.toml wins in mixed repositories, e.g. a Rust crate with Python
+    // bindings
+    pub fn detect(root: &str) -> Self {
+        let root = Path::new(root);
+        if root.join("Cargo.toml").is_file() {
+            Self::Rust
+        } else if ["pyproject.toml", "setup.py", "setup.cfg", "requirements.txt"]
+            .iter()
+            .any(|file| root.join(file).is_file())
+        {
+            Self::Python
+        } else if root.join("package.json").is_file() {
+            let typescript = root.join("tsconfig.json").is_file()
+                || fs::read_to_string(root.join("package.json"))
+                    .is_ok_and(|manifest| manifest.contains("\"typescript\""));
+            if typescript {
+                Self::TypeScript
+            } else {
+                Self::JavaScript
+            }
+        } else {
+            Self::Unknown
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rust => "Rust",
+            Self::Python => "Python",
+            Self::TypeScript => "TypeScript",
+            Self::JavaScript => "JavaScript",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    // Fix mode's build check; None where there is nothing to compile
+    pub fn check_command(self) -> Option<&'static str> {
+        match self {
+            Self::Rust | Self::Unknown => Some("cargo check --message-format=short"),
+            Self::Python => Some("python -m compileall -q ."),
+            Self::TypeScript => Some("npx tsc --noEmit --pretty false"),
+            Self::JavaScript => None,
+        }
+    }
+
+    // BOTCODER_TEST_COMMAND overrides the default for the kind
+    pub fn test_command(self) -> String {
+        if let Ok(command) = env::var("BOTCODER_TEST_COMMAND") {
+            return command;
+        }
+        match self {
+            Self::Rust | Self::Unknown => "cargo test",
+            Self::Python => "python -m pytest -q",
+            Self::TypeScript | Self::JavaScript => "npm test --",
+        }
+        .to_string()
+    }
+
+    // Commands that count as running the program for the default
+    // success criterion
+    pub fn run_commands(self) -> &'static [&'static str] {
+        match self {
+            Self::Rust | Self::Unknown => &["cargo run"],
+            Self::Python => &["python ", "python3 ", "uv run", "poetry run"],
+            Self::TypeScript => &["npm start", "npm run", "npx ts-node", "npx tsx", "node "],
+            Self::JavaScript => &["npm start", "npm run", "node "],
+        }
+    }
+
+    // Added to the system prompt so the model uses the project's own
+    // tooling; empty when the kind is unknown
+    pub fn prompt_section(self) -> String {
+        let hints = match self {
+            Self::Rust => {
+                "Use the cargo tools (cargo_deps, cargo_add, cargo_clippy, cargo_fmt) for \
+                 dependencies and lints, and lookup_crate before relying on a crate's API."
+            }
+            Self::Python => {
+                "Manage dependencies in pyproject.toml or requirements.txt and install them \
+                 with execute_command (pip or the project's tool, e.g. uv or poetry). The \
+                 cargo tools and lookup_crate don't apply here."
+            }
+            Self::TypeScript => {
+                "Keep the code type-checking with tsc. Manage dependencies with \
+                 execute_command(\"npm install name\"). The cargo tools and lookup_crate \
+                 don't apply here."
+            }
+            Self::JavaScript => {
+                "Manage dependencies with execute_command(\"npm install name\"). The cargo \
+                 tools and lookup_crate don't apply here."
+            }
+            Self::Unknown => return String::new(),
+        };
+        let check = match self.check_command() {
+            Some(check) => format!("To check that it builds, run `{}`. ", check),
+            None => String::new(),
+        };
+        format!(
+            "PROJECT\nThis is a {} project. {}run_tests() runs `{}`. {}",
+            self.name(),
+            check,
+            self.test_command(),
+            hints
+        )
+    }
+}
@@ -0,0 +1,236 @@
+use serde_json::Value;
+use std::{collections::HashSet, env, path::Path};
+
+use crate::app::{self, ToolOutput};
+use crate::dry_run;
+use crate::error::ToolError;
+use crate::file_tree;
+
+// Clippy output without lints is cut to this many lines
+const RAW_LINES: usize = 40;
+
+// BOTCODER_QUALITY_GATE=on: a run only counts as successful once clippy
+// reports nothing and rustfmt has nothing left to change
+pub fn gate_enabled() -> bool {
+    matches!(
+        env::var("BOTCODER_QUALITY_GATE").as_deref(),
+        Ok("on" | "1" | "true")
+    )
+}
+
+pub struct Lint {
+    pub level: String,
+    // e.g. "clippy::needless_return" or "E0308"
+    pub code: Option<String>,
+    pub message: String,
+    // "path:line:column" of the primary span
+    pub location: String,
+    pub help: Vec<String>,
+}
+
+impl Lint {
+    // "src/main.rs:6:5 warning[clippy::needless_return] unneeded `return`
+    // statement", then one "help:" line per suggestion
+    pub fn render(&self) -> String {
+        let code = self
+            .code
+            .as_ref()
+            .map(|code| format!("[{}]", code))
+            .unwrap_or_default();
+        let mut out = format!("{} {}{} {}", self.location, self.level, code, self.message);
+        for help in &self.help {
+            out.push_str(&format!("\n  help: {}", help));
+        }
+        out
+    }
+}
+
+// cargo_clippy: lints for the project, one entry per lint with its
+// location and clippy's suggestions. Extra arguments go to cargo clippy,
+// e.g. "--all-targets" or "-- -W clippy::pedantic".
+pub fn clippy(args: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    let output = run_clippy(args, root)?;
+    let lints = parse_lints(&output.stdout);
+    let exit_code = output.exit_code.unwrap_or(-1);
+
+    let mut text = if lints.is_empty() && exit_code == 0 {
+        "clippy: no warnings".to_string()
+    } else if lints.is_empty() {
+        // Clippy itself failed, e.g. it isn't installed
+        tail(&output.streams(), RAW_LINES)
+    } else {
+        let errors = lints.iter().filter(|l| l.level == "error").count();
+        let rendered: Vec<String> = lints.iter().map(Lint::render).collect();
+        format!(
+            "clippy: {} error(s), {} warning(s)\n\n{}",
+            errors,
+            lints.len() - errors,
+            rendered.join("\n\n")
+        )
+    };
+    text.push_str(&format!("\nexit_code: {}", exit_code));
+    Ok(output.with_text(text))
+}
+
+// cargo_fmt: formats the project and lists the files rustfmt changed;
+// cargo_fmt("check") only lists the files that need formatting
+pub fn fmt(param: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    if param.trim() == "check" {
+        let output = app::run_command("cargo fmt --check -- -l", root)?;
+        let files = rust_files(&output.stdout, root);
+        let text = match (output.exit_code, files.is_empty()) {
+            (Some(0), _) => "rustfmt: all files are formatted".to_string(),
+            (_, false) => format!("Not formatted:\n  {}", files.join("\n  ")),
+            (_, true) => tail(&output.streams(), RAW_LINES),
+        };
+        return Ok(output.with_text(text));
+    }
+
+    if dry_run::enabled() {
+        return Ok(ToolOutput::text("Would run: cargo fmt"));
+    }
+    let output = app::run_command("cargo fmt -- -l", root)?;
+    let files = rust_files(&output.stdout, root);
+    let text = match (output.exit_code, files.is_empty()) {
+        (Some(0), true) => "rustfmt: nothing to change".to_string(),
+        (Some(0), false) => format!("Formatted:\n  {}", files.join("\n  ")),
+        // A syntax error rustfmt couldn't get past
+        _ => tail(&output.streams(), RAW_LINES),
+    };
+    Ok(output.with_text(text))
+}
+
+// Files listed in a successful cargo_fmt result, which the engine treats
+// as modified
+pub fn formatted_files(text: &str) -> Vec<String> {
+    match text.strip_prefix("Formatted:\n") {
+        Some(list) => list.lines().map(|line| line.trim().to_string()).collect(),
+        None => Vec::new(),
+    }
+}
+
+// Everything that keeps the quality gate closed: clippy lints across all
+// targets and files rustfmt would change, one entry each
+pub fn gate_failures(root: &str) -> Result<Vec<String>, ToolError> {
+    let output = run_clippy("--all-targets", root)?;
+    let lints = parse_lints(&output.stdout);
+    let mut failures: Vec<String> = lints.iter().map(Lint::render).collect();
+    if failures.is_empty() && output.exit_code != Some(0) {
+        failures.push(format!("cargo clippy failed:\n{}", tail(&output.streams(), RAW_LINES)));
+    }
+
+    let output = app::run_command("cargo fmt --check -- -l", root)?;
+    if output.exit_code != Some(0) {
+        for file in rust_files(&output.stdout, root) {
+            failures.push(format!("{} is not formatted; run cargo_fmt()", file));
+        }
+    }
+    Ok(failures)
+}
+
+// What the model is told when a run would have succeeded but the gate is
+// still closed; None once it is open
+pub fn gate_report(root: &str) -> Option<String> {
+    let failures = match gate_failures(root) {
+        Ok(failures) if failures.is_empty() => return None,
+        Ok(failures) => failures,
+        Err(e) => vec![format!("The quality gate could not run: {}", e)],
+    };
+    Some(format!(
+        "The task isn't done yet: clippy must report no warnings and rustfmt no changes.\n\n{}",
+        failures.join("\n\n")
+    ))
+}
+
+// Warnings and errors from cargo's JSON messages. Summary messages such as
+// "aborting due to 2 previous errors" have no span and are dropped, as are
+// repeats from building the same file for several targets.
+pub fn parse_lints(output: &str) -> Vec<Lint> {
+    let mut seen = HashSet::new();
+    let mut lints = Vec::new();
+
+    for line in output.lines().filter(|line| line.starts_with('{')) {
+        let Ok(value) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        if value["reason"] != "compiler-message" {
+            continue;
+        }
+        let message = &value["message"];
+        let level = message["level"].as_str().unwrap_or_default();
+        if !matches!(level, "warning" | "error") {
+            continue;
+        }
+        let Some(span) = message["spans"]
+            .as_array()
+            .and_then(|spans| spans.iter().find(|s| s["is_primary"] == true))
+        else {
+            continue;
+        };
+
+        let location = format!(
+            "{}:{}:{}",
+            span["file_name"].as_str().unwrap_or_default(),
+            span["line_start"],
+            span["column_start"]
+        );
+        let help = message["children"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|child| child["level"] == "help")
+            .filter_map(|child| {
+                let text = child["message"].as_str()?;
+                if text.starts_with("for further information") {
+                    return None;
+                }
+                // Attach the replacement when clippy suggests one
+                let replacement = child["spans"]
+                    .as_array()
+                    .and_then(|spans| spans.first())
+                    .and_then(|s| s["suggested_replacement"].as_str());
+                Some(match replacement {
+                    Some(r) if !r.is_empty() => format!("{}: `{}`", text, r),
+                    _ => text.to_string(),
+                })
+            })
+            .collect();
+
+        let lint = Lint {
+            level: level.to_string(),
+            code: message["code"]["code"].as_str().map(str::to_string),
+            message: message["message"].as_str().unwrap_or_default().to_string(),
+            location,
+            help,
+        };
+        if seen.insert(lint.render()) {
+            lints.push(lint);
+        }
+    }
+    lints
+}
+
+fn run_clippy(args: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    let command = format!("cargo clippy --message-format=json {}", args.trim());
+    app::run_command(command.trim_end(), root)
+}
+
+// rustfmt's -l lists absolute paths; these are made relative to the root
+fn rust_files(output: &str, root: &str) -> Vec<String> {
+    let root = Path::new(root).canonicalize().unwrap_or_else(|_| root.into());
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.ends_with(".rs"))
+        .map(|line| {
+            let path = Path::new(line);
+            let relative = path.strip_prefix(&root).unwrap_or(path);
+            file_tree::normalize(&relative.to_string_lossy())
+        })
+        .collect()
+}
+
+fn tail(text: &str, lines: usize) -> String {
+    let all: Vec<&str> = text.lines().collect();
+    all[all.len().saturating_sub(lines)..].join("\n")
+}
@@ -0,0 +1,386 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::app;
+use crate::engine::AgentEngine;
+use crate::error::{AgentError, ToolError};
+use crate::exit;
+use crate::git;
+use crate::glyphs;
+use crate::interrupt;
+use crate::oneshot::{self, RunEnd};
+
+pub const REPORT_DIR: &str = ".botcoder/queue";
+
+// `botcoder queue tasks.toml` runs a list of tasks one after another on the
+// project itself, e.g. a night of batch refactoring:
+//
+//   stop_on_failure = false      (optional)
+//
+//   [[tasks]]
+//   name = "settings"            (optional, default task-1, task-2, ...)
+//   instruction = "Rename Config to Settings everywhere"
+//   check = "cargo test"         (optional success criteria)
+//   max_iterations = 10          (optional, else --max-iterations)
+//   max_tokens = 200000          (optional)
+//
+// A .json file holds the same fields, or just the list of tasks.
+//
+// Every task starts a new conversation. In between, the project is
+// checkpointed in git: a task that succeeds has its changes committed, one
+// that fails has them stashed, so the next task starts from the last good
+// state.
+pub struct TaskQueue {
+    pub tasks: Vec<QueuedTask>,
+    pub stop_on_failure: bool,
+}
+
+#[derive(Clone)]
+pub struct QueuedTask {
+    pub name: String,
+    pub instruction: String,
+    // Succeeds when it exits 0 after the run; without one, when the model
+    // finishes or meets BOTCODER_SUCCESS within the budget
+    pub check: Option<String>,
+    pub max_iterations: Option<u32>,
+    pub max_tokens: Option<u32>,
+}
+
+#[derive(Default, Deserialize)]
+struct TaskFields {
+    name: Option<String>,
+    instruction: Option<String>,
+    check: Option<String>,
+    max_iterations: Option<u32>,
+    max_tokens: Option<u32>,
+}
+
+impl TaskQueue {
+    // A .json file is read as JSON, anything else as TOML. YAML isn't
+    // supported, and is refused by its extension rather than failing as TOML
+    pub fn load(path: &Path) -> Result<Self, ToolError> {
+        let extension = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        if matches!(extension.as_deref(), Some("yaml" | "yml")) {
+            return Err(ToolError::InvalidArgs(format!(
+                "{}: YAML task files aren't supported; use TOML or JSON",
+                path.display()
+            )));
+        }
+        let text = fs::read_to_string(path).map_err(|source| ToolError::Read {
+            path: path.display().to_string(),
+            source,
+        })?;
+        let parsed = if extension.as_deref() == Some("json") {
+            Self::parse_json(&text)
+        } else {
+            Self::parse(&text)
+        };
+        parsed.map_err(|e| ToolError::InvalidArgs(format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        Self::build(toml::from_str(text).map_err(|e| e.message().trim().to_string())?)
+    }
+
+    // The tasks may also be the whole document, as a list
+    pub fn parse_json(text: &str) -> Result<Self, String> {
+        Self::build(serde_json::from_str(text).map_err(|e| e.to_string())?)
+    }
+
+    fn build(doc: Value) -> Result<Self, String> {
+        let (list, stop_on_failure) = match doc {
+            Value::Array(list) => (list, false),
+            Value::Object(mut doc) => (
+                match doc.remove("tasks") {
+                    Some(Value::Array(list)) => list,
+                    _ => return Err("expected a list of tasks under `tasks`".to_string()),
+                },
+                doc.get("stop_on_failure").and_then(Value::as_bool).unwrap_or(false),
+            ),
+            _ => return Err("expected a list of tasks".to_string()),
+        };
+
+        let mut tasks = Vec::new();
+        for (i, item) in list.into_iter().enumerate() {
+            let number = i + 1;
+            let fields = match item {
+                Value::String(instruction) => TaskFields {
+                    instruction: Some(instruction),
+                    ..TaskFields::default()
+                },
+                item => serde_json::from_value(item).map_err(|e| format!("task {}: {}", number, e))?,
+            };
+            let instruction = fields.instruction.ok_or(format!("task {} has no instruction", number))?;
+            if instruction.trim().is_empty() {
+                return Err(format!("task {} has an empty instruction", number));
+            }
+            let name = fields.name.unwrap_or_else(|| format!("task-{}", number));
+            if tasks.iter().any(|t: &QueuedTask| t.name == name) {
+                return Err(format!("two tasks are named {}", name));
+            }
+            tasks.push(QueuedTask {
+                name,
+                instruction: instruction.trim().to_string(),
+                check: fields.check,
+                max_iterations: fields.max_iterations,
+                max_tokens: fields.max_tokens,
+            });
+        }
+        if tasks.is_empty() {
+            return Err("no tasks".to_string());
+        }
+        Ok(Self {
+            tasks,
+            stop_on_failure,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskOutcome {
+    Succeeded,
+    Failed,
+    // Out of iterations or tokens
+    OverBudget,
+    Interrupted,
+    // Not run, after an interruption or a failure with stop_on_failure
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaskReport {
+    pub name: String,
+    pub outcome: TaskOutcome,
+    pub iterations: u32,
+    pub tokens: u32,
+    pub wall_ms: u64,
+    pub files: Vec<String>,
+    // What was done with the task's changes: committed or stashed
+    pub checkpoint: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueueReport {
+    // Seconds since the epoch, also the report's file name
+    pub started: u64,
+    pub tasks: Vec<TaskReport>,
+}
+
+impl QueueReport {
+    // Success only if every task succeeded; a budget running out takes
+    // precedence over other failures
+    pub fn exit_code(&self) -> i32 {
+        if self.tasks.iter().all(|t| t.outcome == TaskOutcome::Succeeded) {
+            exit::SUCCESS
+        } else if self.tasks.iter().any(|t| t.outcome == TaskOutcome::OverBudget) {
+            exit::BUDGET_EXCEEDED
+        } else {
+            exit::GAVE_UP
+        }
+    }
+
+    // One line per task and the totals
+    pub fn render(&self) -> String {
+        let mut text = format!("Queue of {} task(s)\n", self.tasks.len());
+        for task in &self.tasks {
+            let symbol = match task.outcome {
+                TaskOutcome::Succeeded => "✓",
+                TaskOutcome::Skipped => "-",
+                TaskOutcome::Interrupted => "⏹",
+                TaskOutcome::Failed | TaskOutcome::OverBudget => "✗",
+            };
+            text.push_str(&format!(
+                "{} {:<24} {:<12} {:>3} iteration(s) {:>8} tokens {:>7.1}s {:>3} file(s){}{}\n",
+                symbol,
+                task.name,
+                format!("{:?}", task.outcome).to_lowercase(),
+                task.iterations,
+                task.tokens,
+                task.wall_ms as f64 / 1000.0,
+                task.files.len(),
+                task.checkpoint.as_ref().map(|c| format!(", {}", c)).unwrap_or_default(),
+                task.error.as_ref().map(|e| format!("  {}", e)).unwrap_or_default()
+            ));
+        }
+        let succeeded = self
+            .tasks
+            .iter()
+            .filter(|t| t.outcome == TaskOutcome::Succeeded)
+            .count();
+        text.push_str(&format!(
+            "{}/{} succeeded, {} tokens, {:.1}s\n",
+            succeeded,
+            self.tasks.len(),
+            self.tasks.iter().map(|t| t.tokens).sum::<u32>(),
+            self.tasks.iter().map(|t| t.wall_ms).sum::<u64>() as f64 / 1000.0
+        ));
+        text
+    }
+
+    // Saves to .botcoder/queue/<started>.json
+    pub fn save(&self, project_root: &str) -> io::Result<PathBuf> {
+        self.save_in(project_root, REPORT_DIR)
+    }
+
+    // Saves to <dir>/<started>.json under the project
+    pub fn save_in(&self, project_root: &str, dir: &str) -> io::Result<PathBuf> {
+        let dir = Path::new(project_root).join(dir);
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.json", self.started));
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(&path, json)?;
+        Ok(path)
+    }
+}
+
+// Runs the tasks in order in the project, each with a new engine from
+// `new_engine`. Failed tasks are recorded; only a model client that can't
+// be set up stops the queue.
+pub async fn run(
+    queue: &TaskQueue,
+    root: &str,
+    max_iterations: u32,
+    new_engine: impl Fn() -> Result<AgentEngine, AgentError>,
+) -> Result<QueueReport, AgentError> {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let checkpoints = git::is_repository(root);
+    if !checkpoints {
+        eprintln!("{}", glyphs::plain("⚠ Not a git repository: tasks won't be checkpointed"));
+    }
+
+    let mut report = QueueReport {
+        started,
+        tasks: Vec::new(),
+    };
+    for task in &queue.tasks {
+        let stop = interrupt::is_interrupted()
+            || queue.stop_on_failure
+                && report.tasks.iter().any(|t| t.outcome != TaskOutcome::Succeeded);
+        if stop {
+            report.tasks.push(skipped(&task.name));
+            continue;
+        }
+        eprintln!("{}", glyphs::plain(&format!("▸ {}: {}", task.name, task.instruction)));
+        let mut engine = new_engine()?;
+        let mut result = attempt(task, root, max_iterations, &mut engine).await;
+        if checkpoints && !result.files.is_empty() {
+            result.checkpoint = Some(checkpoint(task, root, &result));
+        }
+        // Progress goes to stderr, leaving stdout to the report
+        eprintln!(
+            "{}",
+            glyphs::plain(&format!(
+                "{} {}: {:?}, {} iteration(s), {} tokens, {:.1}s{}",
+                if result.outcome == TaskOutcome::Succeeded { "✓" } else { "✗" },
+                task.name,
+                result.outcome,
+                result.iterations,
+                result.tokens,
+                result.wall_ms as f64 / 1000.0,
+                result.checkpoint.as_ref().map(|c| format!(", {}", c)).unwrap_or_default()
+            ))
+        );
+        report.tasks.push(result);
+    }
+    Ok(report)
+}
+
+// A task that didn't run
+pub fn skipped(name: &str) -> TaskReport {
+    TaskReport {
+        name: name.to_string(),
+        outcome: TaskOutcome::Skipped,
+        iterations: 0,
+        tokens: 0,
+        wall_ms: 0,
+        files: Vec::new(),
+        checkpoint: None,
+        error: None,
+    }
+}
+
+// Runs one task in root and checks the result, leaving the changes as they
+// are
+pub async fn attempt(
+    task: &QueuedTask,
+    root: &str,
+    max_iterations: u32,
+    engine: &mut AgentEngine,
+) -> TaskReport {
+    let mut result = skipped(&task.name);
+    let start = Instant::now();
+    let end = oneshot::run_within(
+        engine,
+        &task.instruction,
+        task.max_iterations.unwrap_or(max_iterations),
+        task.max_tokens,
+    )
+    .await;
+    result.outcome = match (&end, &task.check) {
+        (Err(e), _) => {
+            result.error = Some(e.to_string());
+            TaskOutcome::Failed
+        }
+        (Ok(RunEnd::Interrupted), _) => TaskOutcome::Interrupted,
+        (Ok(RunEnd::OutOfIterations | RunEnd::OutOfTokens), _) => TaskOutcome::OverBudget,
+        (Ok(_), Some(check)) => {
+            let mut command = app::shell_command(check);
+            command.current_dir(root);
+            match interrupt::output(command) {
+                Ok(output) if output.status.success() => TaskOutcome::Succeeded,
+                Ok(_) => {
+                    result.error = Some(format!("`{}` failed", check));
+                    TaskOutcome::Failed
+                }
+                Err(e) => {
+                    result.error = Some(format!("`{}` could not run: {}", check, e));
+                    TaskOutcome::Failed
+                }
+            }
+        }
+        (Ok(_), None) => TaskOutcome::Succeeded,
+    };
+    result.wall_ms = start.elapsed().as_millis() as u64;
+    result.iterations = engine.iteration();
+    result.tokens = engine.tpm_limiter().get_total_tokens();
+    result.files = engine.modified_files().to_vec();
+    result
+}
+
+// Commits a task's changes when it succeeded and stashes them otherwise,
+// except after an interruption, when they are left for the user
+fn checkpoint(task: &QueuedTask, root: &str, result: &TaskReport) -> String {
+    match result.outcome {
+        TaskOutcome::Succeeded => {
+            let message = format!("botcoder queue: {}\n\n{}", task.name, task.instruction);
+            match git::commit(&git::encode_commit(&result.files, &message), root) {
+                Ok(output) if output.exit_code == Some(0) => "committed".to_string(),
+                Ok(output) => format!("commit failed: {}", output.text.trim()),
+                Err(e) => format!("commit failed: {}", e),
+            }
+        }
+        TaskOutcome::Interrupted => "left uncommitted".to_string(),
+        _ => {
+            let files: Vec<String> = result
+                .files
+                .iter()
+                .map(|file| Path::new(root).join(file).to_string_lossy().into_owned())
+                .collect();
+            let message = format!("botcoder queue: {} ({:?})", task.name, result.outcome);
+            match git::stash(root, &files, &message, false) {
+                Ok(()) => "reverted, changes in the git stash".to_string(),
+                Err(e) => format!("could not revert: {}", e),
+            }
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use ignore::WalkBuilder;
+use proc_macro2::{TokenStream, TokenTree};
+use std::path::Path;
+
+use crate::app::{self, ToolOutput};
+use crate::dry_run;
+use crate::encoding;
+use crate::error::ToolError;
+use crate::ignore_rules::IgnoreRules;
+use crate::write_scope::WriteScope;
+
+// Names that would turn a rename into a syntax error in one language or
+// another
+const KEYWORDS: [&str; 36] = [
+    "as", "async", "await", "break", "class", "const", "continue", "crate", "def", "else",
+    "enum", "fn", "for", "from", "function", "if", "impl", "import", "in", "let", "loop",
+    "match", "mod", "mut", "pub", "return", "self", "Self", "static", "struct", "super",
+    "trait", "type", "use", "var", "while",
+];
+
+// How a language writes its comments and strings. A scanner stands in for
+// tree-sitter here: it finds identifiers outside comments and strings, so
+// names inside `${...}` or f-string braces are missed, and it knows no
+// syntax beyond these flags (no C++ raw strings, for one).
+#[derive(Clone, Copy)]
+struct Syntax {
+    slash_comments: bool,
+    hash_comments: bool,
+    triple_quotes: bool,
+    // Backtick strings, which may span lines
+    backticks: bool,
+}
+
+const C_LIKE: Syntax = Syntax {
+    slash_comments: true,
+    hash_comments: false,
+    triple_quotes: false,
+    backticks: true,
+};
+const SCRIPT: Syntax = Syntax {
+    slash_comments: false,
+    hash_comments: true,
+    triple_quotes: true,
+    backticks: false,
+};
+
+enum Language {
+    Rust,
+    Lexed(Syntax),
+}
+
+fn language(path: &str) -> Option<Language> {
+    let extension = Path::new(path).extension()?.to_str()?;
+    match extension {
+        "rs" => Some(Language::Rust),
+        "js" | "jsx" | "mjs" | "cjs" | "ts" | "tsx" | "go" | "java" | "kt" | "scala" | "swift"
+        | "dart" | "c" | "h" | "cc" | "cpp" | "hpp" | "cs" => Some(Language::Lexed(C_LIKE)),
+        "py" | "rb" | "sh" => Some(Language::Lexed(SCRIPT)),
+        _ => None,
+    }
+}
+
+// rename_symbol: renames an identifier in every source file of the project,
+// e.g. "parse_config:::load_config". Strings, comments and longer names
+// containing it are left alone, as are ignored and protected files.
+pub fn rename_symbol(param: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    let (old, new) = param.split_once(":::").unwrap_or((param, ""));
+    let (old, new) = (old.trim(), new.trim());
+    for name in [old, new] {
+        if !is_identifier(name) {
+            return Err(ToolError::InvalidArgs(format!(
+                "rename_symbol needs two identifiers, got \"{}\"",
+                name
+            )));
+        }
+    }
+    if old == new {
+        return Err(ToolError::InvalidArgs(format!("{} is already named {}", old, new)));
+    }
+    if KEYWORDS.contains(&new) {
+        return Err(ToolError::InvalidArgs(format!("{} is a keyword", new)));
+    }
+
+    let scope = WriteScope::load(root);
+    let mut renamed = Vec::new();
+    let mut protected = Vec::new();
+    let mut unparsed = Vec::new();
+    let mut clashes = Vec::new();
+    let mut previews = Vec::new();
+    let dry_run = dry_run::enabled();
+    for path in source_files(root) {
+        let full = Path::new(root).join(&path);
+        let Ok((content, format)) = encoding::read(&full) else {
+            continue;
+        };
+        if !content.contains(old) {
+            continue;
+        }
+        let Some(offsets) = occurrences(&path, &content, old) else {
+            unparsed.push(path);
+            continue;
+        };
+        if offsets.is_empty() {
+            continue;
+        }
+        if scope.is_protected(&path) {
+            protected.push(path);
+            continue;
+        }
+        if occurrences(&path, &content, new).is_some_and(|found| !found.is_empty()) {
+            clashes.push(path.clone());
+        }
+        let after = replace_at(&content, &offsets, old.len(), new);
+        let output = app::save(&full, &content, after, format, String::new())?;
+        if dry_run {
+            previews.push(output.text);
+        }
+        renamed.push((path, offsets.len()));
+    }
+
+    if renamed.is_empty() && protected.is_empty() {
+        return Err(ToolError::InvalidArgs(format!(
+            "no identifier named {} was found outside strings and comments",
+            old
+        )));
+    }
+
+    let total: usize = renamed.iter().map(|(_, count)| count).sum();
+    let mut out = if !dry_run {
+        format!("Renamed {} to {} in {} file(s):", old, new, renamed.len())
+    } else {
+        format!("Would rename {} to {} in {} file(s) (dry run):", old, new, renamed.len())
+    };
+    for (path, count) in &renamed {
+        out.push_str(&format!("\n  {} ({})", path, count));
+    }
+    out.push_str(&format!("\n{} occurrence(s) in total", total));
+    if !protected.is_empty() {
+        out.push_str(&format!("\nNot changed, protected: {}", protected.join(", ")));
+    }
+    if !unparsed.is_empty() {
+        out.push_str(&format!("\nNot changed, could not be tokenized: {}", unparsed.join(", ")));
+    }
+    if !clashes.is_empty() {
+        out.push_str(&format!(
+            "\nCheck for clashes, {} was already used in: {}",
+            new,
+            clashes.join(", ")
+        ));
+    }
+    for preview in previews {
+        out.push_str("\n\n");
+        out.push_str(&preview);
+    }
+    Ok(ToolOutput::text(out))
+}
+
+// Files listed in a rename_symbol result, which the engine treats as
+// modified. A dry run lists none.
+pub fn renamed_files(text: &str) -> Vec<String> {
+    if !text.starts_with("Renamed ") {
+        return Vec::new();
+    }
+    text.lines()
+        .skip(1)
+        .map_while(|line| line.strip_prefix("  "))
+        .filter_map(|line| line.rsplit_once(" (").map(|(path, _)| path.to_string()))
+        .collect()
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+// Source files the agent may see, relative to the root
+fn source_files(root: &str) -> Vec<String> {
+    let rules = IgnoreRules::load(root);
+    let base = Path::new(root);
+    WalkBuilder::new(root)
+        .require_git(false)
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .filter_map(|entry| {
+            let path = entry.path().strip_prefix(base).ok()?.to_string_lossy().replace('\\', "/");
+            (language(&path).is_some() && !rules.is_ignored(&path, false)).then_some(path)
+        })
+        .collect()
+}
+
+// Byte offsets of the identifier in code, or None when a Rust file doesn't
+// tokenize
+fn occurrences(path: &str, content: &str, name: &str) -> Option<Vec<usize>> {
+    match language(path)? {
+        Language::Rust => rust_identifiers(content, name),
+        Language::Lexed(syntax) => Some(lexed_identifiers(content, name, syntax)),
+    }
+}
+
+fn rust_identifiers(content: &str, name: &str) -> Option<Vec<usize>> {
+    let tokens: TokenStream = content.parse().ok()?;
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(content.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+    let mut offsets = Vec::new();
+    collect_idents(tokens, name, &mut |line, column| {
+        let start = line_starts[line - 1];
+        let offset = content[start..]
+            .char_indices()
+            .nth(column)
+            .map_or(content.len(), |(i, _)| start + i);
+        // Doc comments come back as #[doc = "..."] attributes whose tokens
+        // point into the comment
+        if content[offset..].starts_with(name) {
+            offsets.push(offset);
+        }
+    });
+    offsets.sort_unstable();
+    offsets.dedup();
+    Some(offsets)
+}
+
+fn collect_idents(tokens: TokenStream, name: &str, found: &mut dyn FnMut(usize, usize)) {
+    for token in tokens {
+        match token {
+            TokenTree::Group(group) => collect_idents(group.stream(), name, found),
+            TokenTree::Ident(ident) if ident == name => {
+                let start = ident.span().start();
+                found(start.line, start.column);
+            }
+            _ => {}
+        }
+    }
+}
+
+// A scan that steps over comments and string literals and matches whole
+// identifiers
+fn lexed_identifiers(content: &str, name: &str, syntax: Syntax) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    let mut i = 0;
+    while let Some(c) = content[i..].chars().next() {
+        let rest = &content[i..];
+        if syntax.slash_comments && rest.starts_with("//") || syntax.hash_comments && c == '#' {
+            i += rest.find('\n').unwrap_or(rest.len());
+        } else if syntax.slash_comments && rest.starts_with("/*") {
+            i += rest[2..].find("*/").map_or(rest.len(), |end| end + 4);
+        } else if syntax.triple_quotes && (rest.starts_with("\"\"\"") || rest.starts_with("'''")) {
+            i += rest[3..].find(&rest[..3]).map_or(rest.len(), |end| end + 6);
+        } else if c == '"' || c == '\'' || syntax.backticks && c == '`' {
+            i += string_length(rest, c);
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let length = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+                .unwrap_or(rest.len());
+            if &rest[..length] == name {
+                offsets.push(i);
+            }
+            i += length;
+        } else if c.is_ascii_digit() {
+            // Number suffixes like 10u8 aren't identifiers
+            i += rest.find(|c: char| !c.is_alphanumeric() && c != '_').unwrap_or(rest.len());
+        } else {
+            i += c.len_utf8();
+        }
+    }
+    offsets
+}
+
+// Length of the string literal at the start of text, up to its closing
+// quote. Only backtick strings run past the end of the line.
+fn string_length(text: &str, quote: char) -> usize {
+    let mut escaped = false;
+    for (i, c) in text.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '\n' if quote != '`' => return i,
+            c if c == quote => return i + 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+fn replace_at(content: &str, offsets: &[usize], length: usize, with: &str) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut last = 0;
+    for &offset in offsets {
+        out.push_str(&content[last..offset]);
+        out.push_str(with);
+        last = offset + length;
+    }
+    out.push_str(&content[last..]);
+    out
+}
@@ -0,0 +1,150 @@
+use std::{collections::VecDeque, path::Path, time::Duration};
+
+use crate::app;
+use crate::error::ToolError;
+use crate::events::AgentEvent;
+use crate::router::Role;
+use crate::sessions;
+use crate::timeline::{self, IterationRecord, Outcome, Timeline};
+
+// Time between steps at 1×
+const STEP: Duration = Duration::from_millis(1000);
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 16.0;
+
+// `botcoder replay <session>` plays a recorded session back through the
+// TUI from its iteration log, without calling the model or running any
+// tool. Each step is what the engine reported at that point: the request,
+// the reply, then one tool at a time.
+pub struct Replay {
+    pub name: String,
+    steps: VecDeque<Vec<AgentEvent>>,
+    total: usize,
+    pub speed: f32,
+    pub paused: bool,
+}
+
+impl Replay {
+    pub fn new(name: impl Into<String>, records: &[IterationRecord]) -> Self {
+        let steps: VecDeque<Vec<AgentEvent>> = records.iter().flat_map(steps).collect();
+        Self {
+            name: name.into(),
+            total: steps.len(),
+            steps,
+            speed: 1.0,
+            paused: false,
+        }
+    }
+
+    // The events of the next step, None once played through
+    pub fn next_step(&mut self) -> Option<Vec<AgentEvent>> {
+        self.steps.pop_front()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn delay(&self) -> Duration {
+        STEP.div_f32(self.speed)
+    }
+
+    pub fn faster(&mut self) {
+        self.speed = (self.speed * 2.0).min(MAX_SPEED);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = (self.speed / 2.0).max(MIN_SPEED);
+    }
+
+    // For the header, e.g. "▶ Replay fix-6710a3c2 3/20 at 2×"
+    pub fn status(&self) -> String {
+        let state = if self.is_finished() {
+            "■"
+        } else if self.paused {
+            "⏸"
+        } else {
+            "▶"
+        };
+        format!(
+            "{} Replay {} {}/{} at {}×",
+            state,
+            self.name,
+            self.total - self.steps.len(),
+            self.total,
+            self.speed
+        )
+    }
+}
+
+// A saved session's turns, or a timeline log: by path, or by name under
+// .botcoder/timeline/ (fix-…, run-…, tui-…)
+pub fn load(project_root: &str, name: &str) -> Result<Vec<IterationRecord>, ToolError> {
+    let path = Path::new(name);
+    let timeline = if path.extension().is_some_and(|ext| ext == "jsonl") && path.is_file() {
+        path.to_path_buf()
+    } else {
+        Timeline::new(project_root, name).path().to_path_buf()
+    };
+    let records = if timeline.is_file() {
+        timeline::load(&timeline)
+    } else {
+        sessions::load(project_root, name)?.turns
+    };
+    if records.is_empty() {
+        return Err(ToolError::InvalidArgs(format!("{} has no recorded iterations to replay", name)));
+    }
+    Ok(records)
+}
+
+fn steps(record: &IterationRecord) -> Vec<Vec<AgentEvent>> {
+    // Logged as "planner: gpt-4o"
+    let (role, model) = match record.model.split_once(": ") {
+        Some(("editor", model)) => (Role::Editor, model),
+        Some((_, model)) => (Role::Planner, model),
+        None => (Role::Planner, record.model.as_str()),
+    };
+
+    let mut request = vec![AgentEvent::IterationStarted {
+        iteration: record.iteration,
+    }];
+    request.extend(record.user.iter().map(|text| AgentEvent::UserMessage { text: text.clone() }));
+    request.push(AgentEvent::LlmRequest {
+        role,
+        model: model.to_string(),
+        input_tokens: 0,
+    });
+    let mut steps = vec![
+        request,
+        vec![AgentEvent::LlmResponse {
+            role,
+            model: model.to_string(),
+            text: record.thoughts.clone(),
+            reasoning: None,
+            fingerprint: None,
+            fallbacks: Vec::new(),
+            output_tokens: record.tokens,
+        }],
+    ];
+    for tool in &record.tools {
+        let mut events = vec![AgentEvent::ToolExecuted {
+            tool: tool.tool.clone(),
+            param: tool.param.clone(),
+            result: tool.result.clone(),
+            ok: tool.ok,
+            status: None,
+        }];
+        if let Some(path) = app::tool_path(&tool.tool, &tool.param).filter(|_| tool.ok && app::is_edit_tool(&tool.tool)) {
+            events.push(AgentEvent::FileModified {
+                path: path.to_string(),
+            });
+        }
+        steps.push(events);
+    }
+    if record.outcome == Outcome::Success {
+        if let Some(last) = steps.last_mut() {
+            last.push(AgentEvent::Success);
+        }
+    }
+    steps
+}
@@ -0,0 +1,171 @@
+use log::warn;
+use serde::Serialize;
+use serde_json::Value;
+use std::env;
+
+use crate::error::LlmError;
+use crate::llm::{self, ChatMessage, LLMProvider, Usage};
+use crate::mock::Recorder;
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // Deciding what to do next
+    Planner,
+    // Producing CHANGE blocks once the relevant files are in context
+    Editor,
+}
+
+impl Role {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Role::Planner => "planner",
+            Role::Editor => "editor",
+        }
+    }
+}
+
+// Sends planning and editing steps to different models. The planner is
+// configured with LLM_PLANNER_* and the editor with LLM_EDITOR_*, both falling
+// back to LLM_*; without any LLM_EDITOR_* variable one client serves both.
+pub struct ModelRouter {
+    planner: Box<dyn LLMProvider>,
+    editor: Option<Box<dyn LLMProvider>>,
+    // Tried in order when the routed model fails with a non-retryable error
+    fallbacks: Vec<Box<dyn LLMProvider>>,
+    // Set by --record
+    recorder: Option<Recorder>,
+}
+
+pub struct Generation {
+    pub text: String,
+    pub model: String,
+    // One note per fallback taken, e.g. "gpt-4 failed (...), using gpt-4o-mini"
+    pub fallbacks: Vec<String>,
+    // Token usage the server reported, when it did
+    pub usage: Option<Usage>,
+    // The server's system_fingerprint, when it sent one
+    pub fingerprint: Option<String>,
+}
+
+impl ModelRouter {
+    pub fn from_env() -> Result<Self, LlmError> {
+        let planner = if has_prefix("LLM_PLANNER_") {
+            llm::client_from_env("LLM_PLANNER")?
+        } else {
+            llm::client_from_env("LLM")?
+        };
+        let editor = if has_prefix("LLM_EDITOR_") {
+            Some(llm::client_from_env("LLM_EDITOR")?)
+        } else {
+            None
+        };
+
+        // LLM_FALLBACKS lists env prefixes, e.g. "LLM_BACKUP,LLM_LOCAL" reading
+        // LLM_BACKUP_URL/_KEY/_MODEL/_VERSION
+        let fallbacks = env::var("LLM_FALLBACKS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|p| p.trim())
+            .filter(|p| !p.is_empty())
+            .map(llm::client_from_env)
+            .collect::<Result<Vec<_>, LlmError>>()?;
+
+        Ok(Self {
+            planner,
+            editor,
+            fallbacks,
+            recorder: None,
+        })
+    }
+
+    // One provider for both roles and no fallbacks, e.g. a MockProvider
+    pub fn single(provider: impl LLMProvider + 'static) -> Self {
+        Self {
+            planner: Box::new(provider),
+            editor: None,
+            fallbacks: Vec::new(),
+            recorder: None,
+        }
+    }
+
+    // Adds a model to try after the others, as LLM_FALLBACKS does
+    pub fn with_fallback(mut self, provider: impl LLMProvider + 'static) -> Self {
+        self.fallbacks.push(Box::new(provider));
+        self
+    }
+
+    // Saves every response to a fixture that MockProvider can replay
+    pub fn with_recorder(mut self, path: impl Into<std::path::PathBuf>) -> Self {
+        self.recorder = Some(Recorder::new(path));
+        self
+    }
+
+    pub fn client(&self, role: Role) -> &dyn LLMProvider {
+        match role {
+            Role::Editor => self.editor.as_deref().unwrap_or(self.planner.as_ref()),
+            Role::Planner => self.planner.as_ref(),
+        }
+    }
+}
+
+impl ModelRouter {
+    pub async fn generate(
+        &self,
+        role: Role,
+        messages: &[ChatMessage],
+        config: &Value,
+    ) -> Result<Generation, LlmError> {
+        let chain: Vec<&dyn LLMProvider> = std::iter::once(self.client(role))
+            .chain(self.fallbacks.iter().map(|f| f.as_ref()))
+            .collect();
+        let mut notes = Vec::new();
+
+        for (i, client) in chain.iter().enumerate() {
+            match client.generate_chat(messages, config).await {
+                Ok(text) => {
+                    if let Some(recorder) = &self.recorder {
+                        recorder.record(&text);
+                    }
+                    return Ok(Generation {
+                        text,
+                        model: client.model().to_string(),
+                        fallbacks: notes,
+                        usage: client.last_usage(),
+                        fingerprint: client.last_fingerprint(),
+                    })
+                }
+                Err(e) if i + 1 < chain.len() && e.is_non_retryable() => {
+                    let note = format!(
+                        "{} failed ({}), falling back to {}",
+                        client.model(),
+                        e,
+                        chain[i + 1].model()
+                    );
+                    warn!("{}", note);
+                    notes.push(note);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        unreachable!("the chain always starts with the routed model")
+    }
+}
+
+// Once the previous turn read files, the next step is most likely an edit;
+// new instructions, command output and failed tools go back to the planner
+pub fn select_role(history: &[ChatMessage], tools_failed: bool) -> Role {
+    match history.last() {
+        Some(last)
+            if last.is_tool_results() && last.content.contains("read_file:") && !tools_failed =>
+        {
+            Role::Editor
+        }
+        _ => Role::Planner,
+    }
+}
+
+fn has_prefix(prefix: &str) -> bool {
+    env::vars().any(|(k, _)| k.starts_with(prefix))
+}
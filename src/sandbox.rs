@@ -0,0 +1,92 @@
+use std::{collections::BTreeMap, fs, process::Command};
+
+use crate::llm::{self, Vars};
+use crate::write_scope;
+
+const WORKDIR: &str = "/workspace";
+
+// Container settings for running execute_command in isolation. Enabled by
+// setting BOTCODER_SANDBOX to "docker" or "podman".
+pub struct SandboxConfig {
+    pub runtime: String,
+    pub image: String,
+    pub network: bool,
+    pub mounts: Vec<String>,
+}
+
+impl SandboxConfig {
+    pub fn from_env() -> Option<Self> {
+        Self::from_vars(&llm::env_var)
+    }
+
+    pub fn from_vars(vars: Vars) -> Option<Self> {
+        let runtime = vars("BOTCODER_SANDBOX")?;
+        if runtime.is_empty() || runtime == "off" {
+            return None;
+        }
+
+        let image = vars("BOTCODER_SANDBOX_IMAGE").unwrap_or_else(|| "rust:latest".to_string());
+        let network = vars("BOTCODER_SANDBOX_NETWORK")
+            .map(|v| matches!(v.as_str(), "on" | "true" | "1"))
+            .unwrap_or(false);
+        let mounts = vars("BOTCODER_SANDBOX_MOUNTS")
+            .map(|v| {
+                v.split(',')
+                    .map(|m| m.trim().to_string())
+                    .filter(|m| !m.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Some(Self {
+            runtime,
+            image,
+            network,
+            mounts,
+        })
+    }
+
+    // Builds `<runtime> run --rm -v <root>:/workspace ... <image> sh -c <command>`,
+    // mounting the project read-only in a --read-only session. `dir` is the
+    // working directory within the project, `env` the extra variables.
+    pub fn command(
+        &self,
+        command: &str,
+        root: &str,
+        dir: Option<&str>,
+        env: &BTreeMap<String, String>,
+    ) -> Command {
+        let host_root = fs::canonicalize(root)
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|_| root.to_string());
+
+        let mut cmd = Command::new(&self.runtime);
+        cmd.args(["run", "--rm", "-i"])
+            .arg("-v")
+            .arg(format!(
+                "{}:{}{}",
+                host_root,
+                WORKDIR,
+                if write_scope::read_only() { ":ro" } else { "" }
+            ))
+            .arg("-w")
+            .arg(match dir {
+                Some(dir) => format!("{}/{}", WORKDIR, dir),
+                None => WORKDIR.to_string(),
+            });
+        for (name, value) in env {
+            cmd.arg("-e").arg(format!("{}={}", name, value));
+        }
+
+        if !self.network {
+            cmd.args(["--network", "none"]);
+        }
+
+        for mount in &self.mounts {
+            cmd.arg("-v").arg(mount);
+        }
+
+        cmd.arg(&self.image).args(["sh", "-c", command]);
+        cmd
+    }
+}
@@ -0,0 +1,70 @@
+// Terminal output made fit for the TUI panels and the model's context:
+// ANSI escape sequences (colors, cursor moves, titles) are dropped, a line
+// redrawn with \r or backspaces, like a progress bar, keeps only what was
+// drawn last, and other control characters but tabs are removed.
+pub fn clean(text: &str) -> String {
+    if !text.chars().any(|c| c.is_control() && c != '\n' && c != '\t') {
+        return text.to_string();
+    }
+    let text = strip_escapes(text);
+    let lines: Vec<String> = text.split('\n').map(last_drawn).collect();
+    lines.join("\n")
+}
+
+fn strip_escapes(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI, up to a final byte in @..~, e.g. "\x1b[1;31m"
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC, up to BEL or ESC \, e.g. window titles and hyperlinks
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' {
+                        chars.next_if_eq(&'\\');
+                        break;
+                    }
+                }
+            }
+            // Character set selection, e.g. "\x1b(B"
+            Some('(' | ')') => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+// What a terminal would leave of a line: the text after its last \r that
+// isn't empty, with backspaced characters removed
+fn last_drawn(line: &str) -> String {
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    let drawn = line.rsplit('\r').find(|part| !part.is_empty()).unwrap_or("");
+    let mut out = String::with_capacity(drawn.len());
+    for c in drawn.chars() {
+        match c {
+            '\x08' => {
+                out.pop();
+            }
+            '\t' => out.push(c),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
@@ -0,0 +1,613 @@
+use ignore::WalkBuilder;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use crate::app::ToolOutput;
+use crate::error::ToolError;
+use crate::http;
+use crate::ignore_rules::IgnoreRules;
+use crate::overlay;
+
+const INDEX_DIR: &str = ".botcoder/index";
+const INDEX_FILE: &str = "chunks.json";
+// Chunks are windows of this many lines, each starting CHUNK_STEP lines
+// after the last so a function cut at a boundary is whole in one of them
+const CHUNK_LINES: usize = 40;
+const CHUNK_STEP: usize = 30;
+// Larger files are generated or data, not worth indexing
+const MAX_FILE_BYTES: u64 = 256 * 1024;
+const MAX_FILES: usize = 5_000;
+const LOCAL_DIMS: usize = 512;
+const BATCH: usize = 64;
+const DEFAULT_RESULTS: usize = 8;
+const SNIPPET_LINES: usize = 12;
+const DEFAULT_WATCH_SECS: u64 = 5;
+const WATCH_TICK: Duration = Duration::from_millis(100);
+
+// One update of an index at a time, between searches and watchers
+static UPDATING: Mutex<()> = Mutex::new(());
+// Files the agent wrote, waiting for a watcher to re-embed them
+static EDITED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+static WATCHING: AtomicUsize = AtomicUsize::new(0);
+// Bumped when a watcher sees files added or removed, for the file tree
+// the TUI shows to be scanned again
+static TREE_CHANGES: AtomicUsize = AtomicUsize::new(0);
+
+// Turns text into vectors whose cosine similarity follows their meaning.
+// The index remembers which embedder built it and is rebuilt when that
+// changes.
+pub trait Embedder {
+    fn name(&self) -> String;
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, ToolError>;
+}
+
+// BOTCODER_EMBEDDINGS_URL selects an OpenAI-compatible /embeddings
+// endpoint (with BOTCODER_EMBEDDINGS_MODEL and BOTCODER_EMBEDDINGS_KEY);
+// without it the local embedder is used
+pub fn embedder_from_env() -> Box<dyn Embedder> {
+    match env::var("BOTCODER_EMBEDDINGS_URL") {
+        Ok(url) if !url.is_empty() => Box::new(HttpEmbedder {
+            url,
+            model: env::var("BOTCODER_EMBEDDINGS_MODEL")
+                .unwrap_or_else(|_| "text-embedding-3-small".to_string()),
+            key: env::var("BOTCODER_EMBEDDINGS_KEY").ok().filter(|k| !k.is_empty()),
+        }),
+        _ => Box::new(LocalEmbedder),
+    }
+}
+
+// Needs no model or network: the words and identifier parts of a text
+// (parse_json gives parse, json and parse_json) hashed into a fixed-size
+// vector. It finds code by the names and terms it uses rather than by
+// meaning, which goes a long way in a codebase.
+pub struct LocalEmbedder;
+
+impl Embedder for LocalEmbedder {
+    fn name(&self) -> String {
+        format!("local-hash-{}", LOCAL_DIMS)
+    }
+
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, ToolError> {
+        Ok(texts.iter().map(|text| hash_vector(text)).collect())
+    }
+}
+
+fn hash_vector(text: &str) -> Vec<f32> {
+    let mut vector = vec![0.0; LOCAL_DIMS];
+    for term in terms(text) {
+        // FNV-1a, which unlike the std hasher is the same across builds
+        let hash = term
+            .bytes()
+            .fold(0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+        let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+        vector[(hash % LOCAL_DIMS as u64) as usize] += sign;
+    }
+    normalize(vector)
+}
+
+// Lowercase words of two letters or more, and the parts of snake_case and
+// camelCase identifiers
+fn terms(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    for word in text.split(|c: char| !c.is_alphanumeric() && c != '_') {
+        let mut parts = Vec::new();
+        let mut part = String::new();
+        let mut last_lower = false;
+        for c in word.chars() {
+            if c == '_' || (c.is_uppercase() && last_lower) {
+                parts.push(std::mem::take(&mut part));
+            }
+            if c != '_' {
+                part.extend(c.to_lowercase());
+            }
+            last_lower = c.is_lowercase() || c.is_ascii_digit();
+        }
+        parts.push(part);
+        parts.retain(|part| part.chars().count() >= 2);
+        if parts.len() > 1 {
+            terms.push(word.to_lowercase());
+        }
+        terms.extend(parts);
+    }
+    terms
+}
+
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+    vector
+}
+
+// An OpenAI-compatible embeddings endpoint, e.g. a local Ollama or
+// llama.cpp server or a hosted model
+pub struct HttpEmbedder {
+    pub url: String,
+    pub model: String,
+    pub key: Option<String>,
+}
+
+impl HttpEmbedder {
+    fn url(&self) -> String {
+        let base = self.url.trim_end_matches('/');
+        if base.ends_with("/embeddings") {
+            base.to_string()
+        } else {
+            format!("{}/embeddings", base)
+        }
+    }
+}
+
+impl Embedder for HttpEmbedder {
+    fn name(&self) -> String {
+        format!("{} at {}", self.model, self.url)
+    }
+
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, ToolError> {
+        let (url, model, key, texts) = (self.url(), self.model.clone(), self.key.clone(), texts.to_vec());
+        // The blocking client runs its own runtime, which can't live on one
+        // of tokio's threads
+        thread::spawn(move || request_embeddings(&url, &model, key.as_deref(), &texts))
+            .join()
+            .map_err(|_| ToolError::Embeddings("the request panicked".to_string()))?
+    }
+}
+
+fn request_embeddings(url: &str, model: &str, key: Option<&str>, texts: &[String]) -> Result<Vec<Vec<f32>>, ToolError> {
+    let client = http::blocking_builder()
+        .map_err(|e| ToolError::Embeddings(e.to_string()))?
+        .build()?;
+    let mut vectors = Vec::with_capacity(texts.len());
+    for batch in texts.chunks(BATCH) {
+        let mut request = client.post(url).json(&json!({ "model": model, "input": batch }));
+        if let Some(key) = key {
+            request = request.bearer_auth(key);
+        }
+        let response = request.send()?;
+        let status = response.status();
+        let body: Value = response.json()?;
+        if !status.is_success() {
+            return Err(ToolError::Embeddings(format!("{}: {}", status, body)));
+        }
+        let mut data: Vec<(u64, Vec<f32>)> = body["data"]
+            .as_array()
+            .ok_or_else(|| ToolError::Embeddings(format!("no data in the reply: {}", body)))?
+            .iter()
+            .map(|item| {
+                let vector = item["embedding"]
+                    .as_array()
+                    .map(|values| values.iter().filter_map(|v| v.as_f64()).map(|v| v as f32).collect())
+                    .unwrap_or_default();
+                (item["index"].as_u64().unwrap_or(0), vector)
+            })
+            .collect();
+        if data.len() != batch.len() {
+            return Err(ToolError::Embeddings(format!(
+                "{} embeddings for {} inputs",
+                data.len(),
+                batch.len()
+            )));
+        }
+        data.sort_by_key(|(index, _)| *index);
+        vectors.extend(data.into_iter().map(|(_, vector)| normalize(vector)));
+    }
+    Ok(vectors)
+}
+
+// The on-disk index: each file's chunks with their vectors, and the size and
+// modification time they were built from
+#[derive(Default, Serialize, Deserialize)]
+struct Index {
+    embedder: String,
+    files: BTreeMap<String, IndexedFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexedFile {
+    modified: u64,
+    size: u64,
+    chunks: Vec<Chunk>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Chunk {
+    // 1-based, inclusive
+    start: usize,
+    end: usize,
+    vector: Vec<f32>,
+}
+
+pub struct Hit {
+    pub path: String,
+    pub start: usize,
+    pub end: usize,
+    pub score: f32,
+}
+
+// Semantic search over the project's text files, indexed under
+// .botcoder/index/ and brought up to date before every search: only files
+// added or changed since are embedded again
+pub struct SemanticIndex {
+    root: String,
+    embedder: Box<dyn Embedder>,
+}
+
+impl SemanticIndex {
+    pub fn new(root: &str) -> Self {
+        Self {
+            root: root.to_string(),
+            embedder: embedder_from_env(),
+        }
+    }
+
+    pub fn with_embedder(mut self, embedder: impl Embedder + 'static) -> Self {
+        self.embedder = Box::new(embedder);
+        self
+    }
+
+    // The chunks closest to the query, best first
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<Hit>, ToolError> {
+        let index = self.update()?;
+        let query = self
+            .embedder
+            .embed(&[query.to_string()])?
+            .pop()
+            .unwrap_or_default();
+        let mut hits: Vec<Hit> = index
+            .files
+            .iter()
+            .flat_map(|(path, file)| {
+                file.chunks.iter().map(|chunk| Hit {
+                    path: path.clone(),
+                    start: chunk.start,
+                    end: chunk.end,
+                    score: dot(&query, &chunk.vector),
+                })
+            })
+            .filter(|hit| hit.score > 0.0)
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score));
+        hits.truncate(limit);
+        Ok(hits)
+    }
+
+    // Re-embeds what changed since the index was saved and saves it again
+    fn update(&self) -> Result<Index, ToolError> {
+        let _guard = UPDATING.lock().unwrap_or_else(|e| e.into_inner());
+        let mut index = self.load();
+        let files = project_files(&self.root);
+        let before = index.files.len();
+        index.files.retain(|path, _| files.iter().any(|(p, _, _)| p == path));
+        let mut changed = index.files.len() != before;
+        for (file, modified, size) in files {
+            let fresh = index
+                .files
+                .get(&file)
+                .is_some_and(|f| f.modified == modified && f.size == size);
+            if !fresh {
+                changed |= self.embed_file(&mut index, file, modified, size)?;
+            }
+        }
+        if changed {
+            self.save(&index);
+        }
+        Ok(index)
+    }
+
+    // Re-embeds just these files, relative to the root, without walking the
+    // project; a file that is gone or no longer indexed is dropped. Does
+    // nothing until a search has built the index.
+    pub fn refresh(&self, paths: &[String]) -> Result<(), ToolError> {
+        let _guard = UPDATING.lock().unwrap_or_else(|e| e.into_inner());
+        if !index_exists(&self.root) {
+            return Ok(());
+        }
+        let mut index = self.load();
+        let rules = IgnoreRules::load(&self.root);
+        for path in paths {
+            let path = path.trim_start_matches("./").to_string();
+            index.files.remove(&path);
+            let Some((modified, size)) = file_stamp(&Path::new(&self.root).join(&path)) else {
+                continue;
+            };
+            let hidden = path.split('/').any(|part| part.starts_with('.'));
+            if !hidden && !rules.is_ignored(&path, false) && size > 0 && size <= MAX_FILE_BYTES {
+                self.embed_file(&mut index, path, modified, size)?;
+            }
+        }
+        self.save(&index);
+        Ok(())
+    }
+
+    // The saved index, or an empty one when there is none or it was built
+    // by another embedder
+    fn load(&self) -> Index {
+        let path = overlay::state_path(&self.root, INDEX_DIR).join(INDEX_FILE);
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str::<Index>(&text).ok())
+            .filter(|index| index.embedder == self.embedder.name())
+            .unwrap_or_else(|| Index {
+                embedder: self.embedder.name(),
+                files: BTreeMap::new(),
+            })
+    }
+
+    fn save(&self, index: &Index) {
+        let dir = overlay::state_path(&self.root, INDEX_DIR);
+        let written = fs::create_dir_all(&dir)
+            // Keeps the index out of git without touching .gitignore
+            .and_then(|_| fs::write(dir.join(".gitignore"), "*\n"))
+            .and_then(|_| fs::write(dir.join(INDEX_FILE), serde_json::to_string(index).unwrap_or_default()));
+        if let Err(e) = written {
+            log::warn!("Could not save the search index: {}", e);
+        }
+    }
+
+    // Whether the file could be read as text and was indexed
+    fn embed_file(&self, index: &mut Index, file: String, modified: u64, size: u64) -> Result<bool, ToolError> {
+        let Ok(content) = fs::read_to_string(Path::new(&self.root).join(&file)) else {
+            return Ok(false);
+        };
+        let windows = chunk_lines(&content);
+        let texts: Vec<String> = windows
+            .iter()
+            .map(|(start, end, text)| format!("{}:{}-{}\n{}", file, start, end, text))
+            .collect();
+        let vectors = self.embedder.embed(&texts)?;
+        let chunks = windows
+            .into_iter()
+            .zip(vectors)
+            .map(|((start, end, _), vector)| Chunk { start, end, vector })
+            .collect();
+        index.files.insert(file, IndexedFile { modified, size, chunks });
+        Ok(true)
+    }
+}
+
+pub fn index_exists(root: &str) -> bool {
+    overlay::state_path(root, INDEX_DIR).join(INDEX_FILE).is_file()
+}
+
+// Modification time in milliseconds and size
+fn file_stamp(path: &Path) -> Option<(u64, u64)> {
+    let metadata = fs::metadata(path).ok().filter(|m| m.is_file())?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    Some((modified, metadata.len()))
+}
+
+// Keeps a project's index current while botcoder runs, so a search doesn't
+// wait for files changed since to be embedded: files the agent edits are
+// re-embedded right away, and every BOTCODER_INDEX_WATCH_SECS seconds
+// (default 5, 0 off) the project is checked for files changed otherwise,
+// e.g. by the user in their editor; files added or removed also bring the
+// TUI's file tree up to date (see `tree_changes`). Only watches once the
+// index exists or retrieval is on.
+//
+// The check polls a walk of the whole tree, stat-ing each file, in place of
+// a file system watcher such as the notify crate, which isn't among
+// botcoder's dependencies. So outside changes are seen up to an interval
+// late, each check costs a walk of up to MAX_FILES files, and on a large
+// repository the interval is best raised.
+pub struct IndexWatcher {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl IndexWatcher {
+    pub fn from_env(root: &str) -> Option<Self> {
+        let seconds = env::var("BOTCODER_INDEX_WATCH_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_WATCH_SECS);
+        if seconds == 0 || !(index_exists(root) || retrieval_chunks() > 0) {
+            return None;
+        }
+        Some(Self::start(root, Duration::from_secs(seconds)))
+    }
+
+    pub fn start(root: &str, interval: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let root = root.to_string();
+        WATCHING.fetch_add(1, Ordering::SeqCst);
+        let handle = {
+            let stop = stop.clone();
+            thread::spawn(move || watch(&root, interval, &stop))
+        };
+        Self {
+            stop,
+            handle: Some(handle),
+        }
+    }
+}
+
+impl Drop for IndexWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        // Edits of projects no watcher is left for
+        if WATCHING.fetch_sub(1, Ordering::SeqCst) == 1 {
+            EDITED.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        }
+    }
+}
+
+fn watch(root: &str, interval: Duration, stop: &AtomicBool) {
+    let index = SemanticIndex::new(root);
+    let base = Path::new(root);
+    let mut last_scan: Option<(Instant, Stamps)> = None;
+    while !stop.load(Ordering::SeqCst) {
+        let edited: Vec<String> = {
+            let mut queue = EDITED.lock().unwrap_or_else(|e| e.into_inner());
+            let (ours, others) = queue.drain(..).partition(|path: &PathBuf| path.starts_with(base));
+            *queue = others;
+            ours.iter()
+                .filter_map(|path| path.strip_prefix(base).ok())
+                .map(|path| path.to_string_lossy().replace('\\', "/"))
+                .collect()
+        };
+        if !edited.is_empty() {
+            if let Err(e) = index.refresh(&edited) {
+                log::warn!("Could not update the search index: {}", e);
+            }
+        }
+
+        if last_scan.as_ref().is_none_or(|(at, _)| at.elapsed() >= interval) {
+            let files = project_files(root);
+            let changed = last_scan.as_ref().is_none_or(|(_, last)| *last != files);
+            let added_or_removed = last_scan.as_ref().is_some_and(|(_, last)| {
+                last.len() != files.len() || last.iter().zip(&files).any(|(a, b)| a.0 != b.0)
+            });
+            if added_or_removed {
+                TREE_CHANGES.fetch_add(1, Ordering::SeqCst);
+            }
+            if changed && (index_exists(root) || retrieval_chunks() > 0) {
+                if let Err(e) = index.update() {
+                    log::warn!("Could not update the search index: {}", e);
+                }
+            }
+            last_scan = Some((Instant::now(), files));
+        }
+        thread::sleep(WATCH_TICK);
+    }
+}
+
+// Counts the file additions and removals watchers have seen; the file tree
+// is due a new scan whenever it moves
+pub fn tree_changes() -> usize {
+    TREE_CHANGES.load(Ordering::SeqCst)
+}
+
+// Queues a file the agent just wrote for the watchers to re-embed; path
+// is relative to the root
+pub fn note_edit(root: &str, path: &str) {
+    if WATCHING.load(Ordering::SeqCst) > 0 {
+        EDITED
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(Path::new(root).join(path));
+    }
+}
+
+// Each file's path, modification time and size
+type Stamps = Vec<(String, u64, u64)>;
+
+// Text files the agent may read, relative to the root, with their
+// modification time and size
+fn project_files(root: &str) -> Stamps {
+    let rules = IgnoreRules::load(root);
+    let base = Path::new(root);
+    WalkBuilder::new(root)
+        .require_git(false)
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .filter_map(|entry| {
+            let path = entry.path().strip_prefix(base).ok()?.to_string_lossy().replace('\\', "/");
+            let (modified, size) = file_stamp(entry.path())?;
+            if rules.is_ignored(&path, false) || size > MAX_FILE_BYTES || size == 0 {
+                return None;
+            }
+            Some((path, modified, size))
+        })
+        .take(MAX_FILES)
+        .collect()
+}
+
+// (first line, last line, text) windows of a file
+fn chunk_lines(content: &str) -> Vec<(usize, usize, String)> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        let end = (start + CHUNK_LINES).min(lines.len());
+        chunks.push((start + 1, end, lines[start..end].join("\n")));
+        if end == lines.len() {
+            break;
+        }
+        start += CHUNK_STEP;
+    }
+    chunks
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+// The hits with the start of each chunk, numbered like read_file's
+// numbered mode
+fn render(root: &str, hits: &[Hit], snippet_lines: usize) -> String {
+    let sections: Vec<String> = hits
+        .iter()
+        .map(|hit| {
+            let content = fs::read_to_string(Path::new(root).join(&hit.path)).unwrap_or_default();
+            let lines: Vec<String> = content
+                .lines()
+                .enumerate()
+                .skip(hit.start - 1)
+                .take((hit.end + 1 - hit.start).min(snippet_lines))
+                .map(|(i, line)| format!("{:4} | {}", i + 1, line))
+                .collect();
+            format!("{}:{}-{} (score {:.2})\n{}", hit.path, hit.start, hit.end, hit.score, lines.join("\n"))
+        })
+        .collect();
+    sections.join("\n\n")
+}
+
+// semantic_search: the code most related to a description, e.g. "where
+// retries are scheduled"
+pub fn search_tool(query: &str, root: &str) -> Result<ToolOutput, ToolError> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Err(ToolError::InvalidArgs("semantic_search needs a query".to_string()));
+    }
+    let hits = SemanticIndex::new(root).search(query, DEFAULT_RESULTS)?;
+    if hits.is_empty() {
+        return Ok(ToolOutput::text(format!("No code found for \"{}\"", query)));
+    }
+    Ok(ToolOutput::text(render(root, &hits, SNIPPET_LINES)))
+}
+
+// BOTCODER_RETRIEVAL_CHUNKS: how many chunks related to each new task go
+// into the system prompt (default 0, off)
+pub fn retrieval_chunks() -> usize {
+    env::var("BOTCODER_RETRIEVAL_CHUNKS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+}
+
+// The system prompt section with the chunks most related to a task, or
+// empty when nothing relates to it or the index can't be built
+pub fn prompt_section(root: &str, task: &str, chunks: usize) -> String {
+    match SemanticIndex::new(root).search(task, chunks) {
+        Ok(hits) if !hits.is_empty() => format!(
+            "RELEVANT CODE\nFound by semantic search for the current task; read the files before editing them.\n\n{}",
+            render(root, &hits, CHUNK_LINES)
+        ),
+        Ok(_) => String::new(),
+        Err(e) => {
+            log::warn!("Semantic search for the task failed: {}", e);
+            String::new()
+        }
+    }
+}
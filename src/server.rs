@@ -0,0 +1,408 @@
+use axum::{
+    extract::{
+        ws::{Message as WsMessage, WebSocket, WebSocketUpgrade},
+        Path, Request, State,
+    },
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    env,
+    process::Command,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+
+use crate::engine::AgentEngine;
+use crate::error::AgentError;
+use crate::events::{AgentEvent, SessionRecorder};
+use crate::dry_run;
+use crate::file_tree;
+use crate::github::GitHubConfig;
+use crate::interrupt;
+use crate::webhooks::{self, RunOutcome, RunSummary, Webhooks};
+
+// Events kept for slow SSE and WebSocket clients before they start missing some
+const EVENT_BUFFER: usize = 256;
+
+pub type EngineFactory = dyn Fn() -> Result<AgentEngine, AgentError> + Send + Sync;
+
+// botcoder serve: the agent over HTTP, for the General Bots server and web
+// frontends. Each session is its own AgentEngine on the project, fed by
+// posted messages; its events are streamed as server-sent events or over a
+// WebSocket.
+#[derive(Clone)]
+struct Server {
+    sessions: Arc<Mutex<HashMap<String, Arc<Session>>>>,
+    new_engine: Arc<EngineFactory>,
+    project_root: String,
+    max_iterations: u32,
+    next_id: Arc<AtomicU64>,
+    webhooks: Arc<Webhooks>,
+    github: Option<Arc<GitHubConfig>>,
+}
+
+struct Session {
+    id: String,
+    inbox: mpsc::UnboundedSender<String>,
+    events: broadcast::Sender<AgentEvent>,
+    status: Mutex<SessionStatus>,
+    transcript: SessionRecorder,
+}
+
+#[derive(Clone, Serialize)]
+struct SessionStatus {
+    id: String,
+    // "idle", "running" or "failed"
+    state: &'static str,
+    iteration: u32,
+    succeeded: bool,
+    // Files changed by the session, relative to the project root
+    modified: Vec<String>,
+    error: Option<String>,
+    // Opened when a message succeeds, with BOTCODER_GITHUB_REPO set
+    pull_request: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct NewSession {
+    message: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    text: String,
+}
+
+// BOTCODER_HOST sets the address to bind (default 127.0.0.1). With
+// BOTCODER_SERVER_TOKEN set, every request needs "Authorization: Bearer
+// <token>"; the API runs shell commands, so set it before exposing the port.
+pub async fn serve(
+    port: u16,
+    project_root: String,
+    max_iterations: u32,
+    new_engine: Arc<EngineFactory>,
+) -> std::io::Result<()> {
+    let server = Server {
+        sessions: Arc::default(),
+        new_engine,
+        project_root,
+        max_iterations,
+        next_id: Arc::default(),
+        webhooks: Arc::new(Webhooks::from_env()),
+        github: GitHubConfig::from_env().map(Arc::new),
+    };
+
+    let app = Router::new()
+        .route("/sessions", post(create_session).get(list_sessions))
+        .route("/sessions/{id}", get(session_status))
+        .route("/sessions/{id}/messages", post(post_message))
+        .route("/sessions/{id}/events", get(session_events))
+        .route("/sessions/{id}/ws", get(session_socket))
+        .route("/sessions/{id}/diff", get(session_diff))
+        .layer(middleware::from_fn(authorize))
+        .with_state(server);
+
+    let host = env::var("BOTCODER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+    let listener = tokio::net::TcpListener::bind((host.as_str(), port)).await?;
+    log::info!("Listening on {}", listener.local_addr()?);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+}
+
+async fn authorize(request: Request, next: Next) -> Response {
+    let Ok(token) = env::var("BOTCODER_SERVER_TOKEN") else {
+        return next.run(request).await;
+    };
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| given == token);
+    if authorized {
+        next.run(request).await
+    } else {
+        StatusCode::UNAUTHORIZED.into_response()
+    }
+}
+
+async fn create_session(
+    State(server): State<Server>,
+    body: Option<Json<NewSession>>,
+) -> Result<(StatusCode, Json<SessionStatus>), (StatusCode, String)> {
+    let mut engine = (server.new_engine)()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let id = format!("{:x}-{}", secs, server.next_id.fetch_add(1, Ordering::Relaxed));
+
+    let (inbox, messages) = mpsc::unbounded_channel();
+    let (events, _) = broadcast::channel(EVENT_BUFFER);
+    let session = Arc::new(Session {
+        id: id.clone(),
+        inbox,
+        events,
+        status: Mutex::new(SessionStatus {
+            id: id.clone(),
+            state: "idle",
+            iteration: 0,
+            succeeded: false,
+            modified: Vec::new(),
+            error: None,
+            pull_request: None,
+        }),
+        transcript: SessionRecorder::default(),
+    });
+
+    let observed = session.clone();
+    engine.subscribe(move |event: &AgentEvent| observed.observe(event));
+    engine.subscribe(session.transcript.clone());
+    spawn_worker(engine, messages, session.clone(), &server);
+
+    if let Some(message) = body.and_then(|Json(body)| body.message) {
+        let _ = session.inbox.send(message);
+    }
+    let status = session.status();
+    if let Ok(mut sessions) = server.sessions.lock() {
+        sessions.insert(id, session);
+    }
+    Ok((StatusCode::CREATED, Json(status)))
+}
+
+async fn list_sessions(State(server): State<Server>) -> Json<Vec<SessionStatus>> {
+    let mut sessions: Vec<SessionStatus> = server
+        .sessions
+        .lock()
+        .map(|s| s.values().map(|session| session.status()).collect())
+        .unwrap_or_default();
+    sessions.sort_by(|a, b| a.id.cmp(&b.id));
+    Json(sessions)
+}
+
+async fn session_status(
+    State(server): State<Server>,
+    Path(id): Path<String>,
+) -> Result<Json<SessionStatus>, StatusCode> {
+    Ok(Json(server.session(&id)?.status()))
+}
+
+async fn post_message(
+    State(server): State<Server>,
+    Path(id): Path<String>,
+    Json(message): Json<Message>,
+) -> Result<StatusCode, StatusCode> {
+    server
+        .session(&id)?
+        .inbox
+        .send(message.text)
+        .map_err(|_| StatusCode::GONE)?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+// One "message" per AgentEvent, named after its kind, with the event's JSON
+// as data
+async fn session_events(
+    State(server): State<Server>,
+    Path(id): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    let events = BroadcastStream::new(server.session(&id)?.events.subscribe());
+    let stream = events.filter_map(|event| {
+        let event = event.ok()?;
+        let data = serde_json::to_value(&event).ok()?;
+        let kind = data["event"].as_str().unwrap_or("event").to_string();
+        Some(Ok(Event::default().event(kind).data(data.to_string())))
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+// The same events as /events, one JSON text frame each, for dashboards that
+// prefer a socket. Frames from the client are ignored.
+async fn session_socket(
+    State(server): State<Server>,
+    Path(id): Path<String>,
+    upgrade: WebSocketUpgrade,
+) -> Result<Response, StatusCode> {
+    let events = server.session(&id)?.events.subscribe();
+    Ok(upgrade.on_upgrade(move |socket| forward_events(socket, events)))
+}
+
+async fn forward_events(mut socket: WebSocket, mut events: broadcast::Receiver<AgentEvent>) {
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    // A slow client misses events rather than stalling the agent
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let Ok(json) = serde_json::to_string(&event) else {
+                    continue;
+                };
+                if socket.send(WsMessage::text(json)).await.is_err() {
+                    break;
+                }
+            }
+            frame = socket.recv() => match frame {
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+// git diff of the files the session changed
+async fn session_diff(
+    State(server): State<Server>,
+    Path(id): Path<String>,
+) -> Result<String, (StatusCode, String)> {
+    let modified = server
+        .session(&id)
+        .map_err(|status| (status, String::new()))?
+        .status()
+        .modified;
+    if modified.is_empty() {
+        return Ok(String::new());
+    }
+
+    let output = Command::new("git")
+        .arg("diff")
+        .arg("--")
+        .args(&modified)
+        .current_dir(&server.project_root)
+        .output()
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if !output.status.success() {
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+impl Server {
+    fn session(&self, id: &str) -> Result<Arc<Session>, StatusCode> {
+        self.sessions
+            .lock()
+            .ok()
+            .and_then(|sessions| sessions.get(id).cloned())
+            .ok_or(StatusCode::NOT_FOUND)
+    }
+}
+
+impl Session {
+    fn status(&self) -> SessionStatus {
+        self.status.lock().map(|s| s.clone()).unwrap_or_else(|e| e.into_inner().clone())
+    }
+
+    fn update(&self, f: impl FnOnce(&mut SessionStatus)) {
+        if let Ok(mut status) = self.status.lock() {
+            f(&mut status);
+        }
+    }
+
+    fn observe(&self, event: &AgentEvent) {
+        match event {
+            AgentEvent::IterationStarted { iteration } => self.update(|s| s.iteration = *iteration),
+            AgentEvent::FileModified { path } => self.update(|s| {
+                let path = file_tree::normalize(path);
+                if !s.modified.contains(&path) {
+                    s.modified.push(path);
+                }
+            }),
+            AgentEvent::Success => self.update(|s| s.succeeded = true),
+            _ => {}
+        }
+        // No subscribers is fine
+        let _ = self.events.send(event.clone());
+    }
+}
+
+// Runs the session's messages one at a time. Tools block, so the engine
+// gets a blocking thread rather than a runtime worker.
+fn spawn_worker(
+    mut engine: AgentEngine,
+    mut messages: mpsc::UnboundedReceiver<String>,
+    session: Arc<Session>,
+    server: &Server,
+) {
+    let (max_iterations, webhooks) = (server.max_iterations, server.webhooks.clone());
+    let github = server.github.clone();
+    let runtime = tokio::runtime::Handle::current();
+    tokio::task::spawn_blocking(move || {
+        runtime.block_on(async move {
+            while let Some(text) = messages.recv().await {
+                session.update(|s| {
+                    s.state = "running";
+                    s.error = None;
+                });
+                engine.push_user(text.clone());
+                let start = engine.iteration();
+                let limit = start + max_iterations;
+                let result = engine.run_to_completion(limit).await;
+                session.update(|s| match &result {
+                    Ok(_) => s.state = "idle",
+                    Err(e) => {
+                        s.state = "failed";
+                        s.error = Some(e.to_string());
+                    }
+                });
+
+                if let (Ok(true), Some(github)) = (&result, &github) {
+                    if !dry_run::enabled() {
+                        let summary = format!("Task: {}", text);
+                        match github.open_pull_request(&mut engine, &session.id, &summary).await {
+                            Ok(url) => session.update(|s| s.pull_request = Some(url)),
+                            Err(e) => session.update(|s| {
+                                s.error = Some(format!("Could not open a pull request: {}", e))
+                            }),
+                        }
+                    }
+                }
+
+                if webhooks.is_empty() || interrupt::is_interrupted() {
+                    continue;
+                }
+                let summary = match &result {
+                    Ok(true) => RunSummary::new(RunOutcome::Succeeded, &session.id, text),
+                    Ok(false) if engine.iteration() >= limit => {
+                        RunSummary::new(RunOutcome::BudgetExceeded, &session.id, text)
+                    }
+                    Ok(false) => RunSummary::new(RunOutcome::InputNeeded, &session.id, text),
+                    Err(e) => RunSummary::new(RunOutcome::Failed, &session.id, text)
+                        .with_error(e.to_string()),
+                };
+                let mut summary = summary
+                    .with_usage(engine.iteration() - start, engine.tpm_limiter().get_total_tokens());
+                summary.transcript = webhooks::save_transcript(
+                    engine.project_root(),
+                    &session.id,
+                    &session.transcript.transcript(),
+                );
+                webhooks.notify(&summary).await;
+            }
+        })
+    });
+}
@@ -0,0 +1,287 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::engine::AgentEngine;
+use crate::error::ToolError;
+use crate::llm::ChatMessage;
+use crate::overlay;
+use crate::timeline::IterationRecord;
+use crate::webhooks;
+
+const SESSIONS_DIR: &str = ".botcoder/sessions";
+// Length of the task line shown in listings
+const TASK_CHARS: usize = 80;
+
+// What `botcoder sessions list` shows of a saved session
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SessionMeta {
+    pub name: String,
+    pub project: String,
+    // The session's first message
+    pub task: String,
+    pub model: String,
+    pub iterations: u32,
+    pub tokens: u32,
+    // Only known with BOTCODER_COST_PER_1K_TOKENS set
+    pub cost: Option<f64>,
+    // Seconds since the epoch
+    pub saved_at: u64,
+    // The session a /fork branched off, and the iteration it kept
+    #[serde(default)]
+    pub parent: Option<String>,
+    #[serde(default)]
+    pub forked_at: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Message {
+    role: String,
+    content: String,
+}
+
+// A conversation saved with /save under .botcoder/sessions/<name>.json.
+// Attached images aren't saved.
+#[derive(Serialize, Deserialize)]
+pub struct SavedSession {
+    #[serde(flatten)]
+    pub meta: SessionMeta,
+    pinned: Vec<String>,
+    messages: Vec<Message>,
+    // The timeline of every iteration, which outlives the trimmed history
+    // so the session can be forked at any of them
+    #[serde(default)]
+    pub turns: Vec<IterationRecord>,
+}
+
+impl SavedSession {
+    pub fn from_engine(engine: &AgentEngine, name: &str) -> Self {
+        let history = engine.history();
+        let task = history
+            .iter()
+            .find(|m| m.role == "user" && !m.is_tool_results())
+            .and_then(|m| m.content.lines().next())
+            .unwrap_or_default()
+            .chars()
+            .take(TASK_CHARS)
+            .collect();
+        let tokens = engine.tpm_limiter().get_total_tokens();
+        Self {
+            meta: SessionMeta {
+                name: name.to_string(),
+                project: engine.project_root().to_string(),
+                task,
+                model: engine.next_model(),
+                iterations: engine.iteration(),
+                tokens,
+                cost: webhooks::cost(tokens),
+                saved_at: now(),
+                parent: None,
+                forked_at: None,
+            },
+            pinned: engine.pinned_files().to_vec(),
+            messages: history
+                .iter()
+                .map(|m| Message {
+                    role: m.role.clone(),
+                    content: m.content.clone(),
+                })
+                .collect(),
+            turns: Vec::new(),
+        }
+    }
+
+    pub fn with_turns(mut self, turns: Vec<IterationRecord>) -> Self {
+        self.turns = turns;
+        self
+    }
+
+    // A branch of this session holding its iterations up to `turn`, with
+    // the conversation rebuilt from them
+    pub fn fork(&self, name: &str, turn: u32) -> Result<Self, ToolError> {
+        let turns: Vec<IterationRecord> = self
+            .turns
+            .iter()
+            .filter(|r| r.iteration <= turn)
+            .cloned()
+            .collect();
+        let Some(last) = turns.last().filter(|r| r.iteration == turn) else {
+            return Err(ToolError::InvalidArgs(format!(
+                "{} has no iteration {}",
+                self.meta.name, turn
+            )));
+        };
+        let tokens = turns.iter().map(|r| r.tokens).sum();
+        let mut messages = Vec::new();
+        for record in &turns {
+            let mut push = |role: &str, content: String| {
+                messages.push(Message {
+                    role: role.to_string(),
+                    content,
+                })
+            };
+            for text in &record.user {
+                push("user", text.clone());
+            }
+            if !record.thoughts.is_empty() {
+                push("assistant", record.thoughts.clone());
+            }
+            if !record.tools.is_empty() {
+                let results: Vec<String> = record
+                    .tools
+                    .iter()
+                    .map(|t| format!("{}: {}", t.tool, t.result))
+                    .collect();
+                push("user", ChatMessage::tool_results(results.join("\n")).content);
+            }
+        }
+        Ok(Self {
+            meta: SessionMeta {
+                name: name.to_string(),
+                project: self.meta.project.clone(),
+                task: self.meta.task.clone(),
+                model: last.model.clone(),
+                iterations: turn,
+                tokens,
+                cost: webhooks::cost(tokens),
+                saved_at: now(),
+                parent: Some(self.meta.name.clone()),
+                forked_at: Some(turn),
+            },
+            pinned: self.pinned.clone(),
+            messages,
+            turns,
+        })
+    }
+
+    // Puts the saved conversation and pinned files in the engine's place
+    pub fn restore(&self, engine: &mut AgentEngine) {
+        let history = self
+            .messages
+            .iter()
+            .map(|m| match m.role.as_str() {
+                "assistant" => ChatMessage::assistant(m.content.clone()),
+                "system" => ChatMessage::system(m.content.clone()),
+                _ => ChatMessage::user(m.content.clone()),
+            })
+            .collect();
+        engine.restore(history, self.meta.iterations);
+        for path in &self.pinned {
+            if let Err(e) = engine.pin(path) {
+                log::warn!("Could not pin {} again: {}", path, e);
+            }
+        }
+    }
+
+    // The metadata followed by the conversation, for `sessions show`
+    pub fn describe(&self) -> String {
+        let mut text = describe_meta(&self.meta);
+        for message in &self.messages {
+            text.push_str(&format!("\n\n**{}**: {}", message.role, message.content));
+        }
+        text
+    }
+}
+
+// Saves the engine's conversation with the iterations it went through,
+// replacing a session saved under the same name
+pub fn save(
+    engine: &AgentEngine,
+    name: &str,
+    turns: Vec<IterationRecord>,
+) -> Result<PathBuf, ToolError> {
+    let mut session = SavedSession::from_engine(engine, name).with_turns(turns);
+    // Saving a fork again keeps its place in the lineage
+    if let Ok(previous) = load(engine.project_root(), name) {
+        session.meta.parent = previous.meta.parent;
+        session.meta.forked_at = previous.meta.forked_at;
+    }
+    write(&session)
+}
+
+pub fn write(session: &SavedSession) -> Result<PathBuf, ToolError> {
+    let path = path(&session.meta.project, &session.meta.name)?;
+    let json = serde_json::to_string_pretty(session)
+        .map_err(|e| ToolError::InvalidArgs(format!("could not encode the session: {}", e)))?;
+    let write_error = |source| ToolError::Write {
+        path: path.display().to_string(),
+        source,
+    };
+    fs::create_dir_all(path.parent().unwrap_or(Path::new("."))).map_err(write_error)?;
+    fs::write(&path, json).map_err(write_error)?;
+    Ok(path)
+}
+
+pub fn load(project_root: &str, name: &str) -> Result<SavedSession, ToolError> {
+    let path = path(project_root, name)?;
+    let text = fs::read_to_string(&path).map_err(|source| ToolError::Read {
+        path: path.display().to_string(),
+        source,
+    })?;
+    serde_json::from_str(&text)
+        .map_err(|e| ToolError::InvalidArgs(format!("{} is not a saved session: {}", path.display(), e)))
+}
+
+pub fn delete(project_root: &str, name: &str) -> Result<(), ToolError> {
+    let path = path(project_root, name)?;
+    fs::remove_file(&path).map_err(|source| ToolError::Write {
+        path: path.display().to_string(),
+        source,
+    })
+}
+
+// Saved sessions, most recently saved first
+pub fn list(project_root: &str) -> Vec<SessionMeta> {
+    let Ok(entries) = fs::read_dir(overlay::state_path(project_root, SESSIONS_DIR)) else {
+        return Vec::new();
+    };
+    let mut sessions: Vec<SessionMeta> = entries
+        .flatten()
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|e| fs::read_to_string(e.path()).ok())
+        .filter_map(|text| serde_json::from_str::<SavedSession>(&text).ok())
+        .map(|session| session.meta)
+        .collect();
+    sessions.sort_by_key(|s| std::cmp::Reverse(s.saved_at));
+    sessions
+}
+
+// "name: task" with the model, iterations, tokens, cost and, for a fork,
+// the session it came from
+pub fn describe_meta(meta: &SessionMeta) -> String {
+    let cost = meta.cost.map(|c| format!(", ${:.2}", c)).unwrap_or_default();
+    let parent = match (&meta.parent, meta.forked_at) {
+        (Some(parent), Some(turn)) => format!(" | forked from {} at #{}", parent, turn),
+        (Some(parent), None) => format!(" | forked from {}", parent),
+        _ => String::new(),
+    };
+    format!(
+        "{}: {}\n  {} | {} iteration(s), {} tokens{} | {}{}",
+        meta.name, meta.task, meta.model, meta.iterations, meta.tokens, cost, meta.project, parent
+    )
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Names become file names, so they are kept to letters, digits, '-', '_'
+// and '.'
+fn path(project_root: &str, name: &str) -> Result<PathBuf, ToolError> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    if !valid {
+        return Err(ToolError::InvalidArgs(format!(
+            "invalid session name \"{}\": use letters, digits, '-', '_' and '.'",
+            name
+        )));
+    }
+    Ok(overlay::state_path(project_root, SESSIONS_DIR).join(format!("{}.json", name)))
+}
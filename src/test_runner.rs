@@ -0,0 +1,283 @@
+use crate::app::{self, ToolOutput};
+use crate::error::ToolError;
+use crate::project::ProjectKind;
+
+pub struct TestReport {
+    pub passed: u32,
+    pub failed: u32,
+    pub ignored: u32,
+    pub failing: Vec<String>,
+    pub failures: Vec<String>,
+    pub exit_code: i32,
+}
+
+impl TestReport {
+    pub fn all_passed(&self) -> bool {
+        self.exit_code == 0 && self.failed == 0
+    }
+
+    pub fn to_tool_output(&self) -> ToolOutput {
+        let mut out = format!(
+            "tests: passed={} failed={} ignored={}\nstatus: {}",
+            self.passed,
+            self.failed,
+            self.ignored,
+            if self.all_passed() {
+                "PASSED"
+            } else {
+                "FAILED"
+            }
+        );
+
+        if !self.failing.is_empty() {
+            out.push_str("\nfailing:");
+            for name in &self.failing {
+                out.push_str(&format!("\n- {}", name));
+            }
+        }
+
+        if !self.failures.is_empty() {
+            out.push_str("\n\n");
+            out.push_str(&self.failures.join("\n\n"));
+        }
+
+        out.push_str(&format!("\nexit_code: {}", self.exit_code));
+        ToolOutput::command(out, self.exit_code)
+    }
+}
+
+// cargo test, pytest or npm test depending on the project, unless
+// BOTCODER_TEST_COMMAND is set
+pub fn test_command(root: &str) -> String {
+    ProjectKind::detect(root).test_command()
+}
+
+pub fn run_tests(filter: &str, root: &str) -> Result<TestReport, ToolError> {
+    let command = if filter.is_empty() {
+        test_command(root)
+    } else {
+        format!("{} {}", test_command(root), filter)
+    };
+
+    let output = app::execute_tool("execute_command", &command, root)?;
+    Ok(parse_test_output(&output))
+}
+
+pub fn parse_test_output(output: &ToolOutput) -> TestReport {
+    let exit_code = output.exit_code.unwrap_or(-1);
+    let output = output.streams();
+    let mut report = TestReport {
+        passed: 0,
+        failed: 0,
+        ignored: 0,
+        failing: Vec::new(),
+        failures: parse_failures(&output),
+        exit_code,
+    };
+
+    for line in output.lines() {
+        let line = line.trim();
+
+        // e.g. "test result: FAILED. 3 passed; 1 failed; 0 ignored; ..."
+        if let Some(summary) = line.strip_prefix("test result:") {
+            add_counts(&mut report, summary.split(';'));
+        }
+        // pytest: "==== 1 failed, 3 passed in 0.12s ====", or without the
+        // rules in -q mode
+        if let Some((counts, _)) = line.trim_matches(|c| c == '=' || c == ' ').split_once(" in ") {
+            let parts: Vec<&str> = counts.split(',').collect();
+            let is_summary = parts.iter().all(|part| {
+                let mut words = part.split_whitespace();
+                words.next().is_some_and(|n| n.parse::<u32>().is_ok())
+                    && words.next().is_some()
+                    && words.next().is_none()
+            });
+            if is_summary {
+                add_counts(&mut report, parts.into_iter());
+            }
+        }
+        // Jest: "Tests:       1 failed, 3 passed, 4 total"
+        if let Some(summary) = line.strip_prefix("Tests:") {
+            add_counts(&mut report, summary.split(','));
+        }
+
+        if let Some(name) = line
+            .strip_prefix("test ")
+            .and_then(|rest| rest.strip_suffix(" ... FAILED"))
+        {
+            report.failing.push(name.to_string());
+        }
+        // pytest: "FAILED tests/test_app.py::test_name - AssertionError"
+        if let Some(rest) = line.strip_prefix("FAILED ") {
+            let name = rest.split(" - ").next().unwrap_or(rest);
+            report.failing.push(name.trim().to_string());
+        }
+        // Jest: "● Suite › test name"
+        if let Some(name) = line.strip_prefix("● ") {
+            if !report.failing.iter().any(|f| f == name) {
+                report.failing.push(name.to_string());
+            }
+        }
+    }
+
+    report
+}
+
+// "3 passed", "1 failed", ... from a test summary
+fn add_counts<'a>(report: &mut TestReport, parts: impl Iterator<Item = &'a str>) {
+    for part in parts {
+        let mut words = part.split_whitespace().rev();
+        let label = words.next().unwrap_or("");
+        let count: u32 = words.next().and_then(|n| n.parse().ok()).unwrap_or(0);
+        match label {
+            "passed" => report.passed += count,
+            "failed" | "error" | "errors" => report.failed += count,
+            "ignored" | "skipped" => report.ignored += count,
+            _ => {}
+        }
+    }
+}
+
+// Collects the "---- name stdout ----" blocks cargo prints for each failure,
+// or pytest's "____ test_name ____" ones
+pub fn parse_failures(output: &str) -> Vec<String> {
+    let mut failures = Vec::new();
+    let mut current: Option<String> = None;
+
+    for line in output.lines() {
+        let pytest_header = line.starts_with("___") && line.ends_with("___");
+        if line.starts_with("---- ") || pytest_header {
+            if let Some(block) = current.take() {
+                failures.push(block);
+            }
+            current = Some(format!("{}\n", line));
+        } else if line == "failures:" || line.starts_with("test result:") || line.starts_with("====") {
+            if let Some(block) = current.take() {
+                failures.push(block);
+            }
+        } else if let Some(block) = current.as_mut() {
+            block.push_str(line);
+            block.push('\n');
+        }
+    }
+    if let Some(block) = current {
+        failures.push(block);
+    }
+
+    failures.into_iter().map(|f| f.trim().to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(stdout: &str, exit_code: i32) -> TestReport {
+        parse_test_output(&ToolOutput::process(stdout.to_string(), String::new(), exit_code))
+    }
+
+    #[test]
+    fn cargo_summaries_add_up_across_test_binaries() {
+        let report = report(
+            "running 3 tests\n\
+             test config::parses ... ok\n\
+             test config::rejects_empty ... FAILED\n\
+             test net::slow ... ignored\n\
+             \n\
+             failures:\n\
+             \n\
+             ---- config::rejects_empty stdout ----\n\
+             thread 'config::rejects_empty' panicked at src/config.rs:40:9:\n\
+             assertion failed: result.is_err()\n\
+             \n\
+             failures:\n    config::rejects_empty\n\
+             \n\
+             test result: FAILED. 1 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out; finished in 0.01s\n\
+             \n\
+             running 2 tests\n\
+             test it_works ... ok\n\
+             test it_still_works ... ok\n\
+             \n\
+             test result: ok. 2 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out; finished in 0.00s\n",
+            101,
+        );
+        assert_eq!((report.passed, report.failed, report.ignored), (3, 1, 1));
+        assert_eq!(report.failing, ["config::rejects_empty"]);
+        assert_eq!(report.failures.len(), 1);
+        assert!(report.failures[0].contains("assertion failed: result.is_err()"), "{:?}", report.failures);
+        assert!(!report.all_passed());
+    }
+
+    #[test]
+    fn cargo_with_no_tests_passes_with_zero_counts() {
+        let report = report(
+            "running 0 tests\n\
+             \n\
+             test result: ok. 0 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out; finished in 0.00s\n",
+            0,
+        );
+        assert_eq!((report.passed, report.failed, report.ignored), (0, 0, 0));
+        assert!(report.failing.is_empty() && report.failures.is_empty());
+        assert!(report.all_passed());
+    }
+
+    #[test]
+    fn pytest_summaries_count_failures_errors_and_skips() {
+        let report = report(
+            "============================= test session starts ==============================\n\
+             collected 5 items\n\
+             \n\
+             tests/test_app.py .F.sE                                                  [100%]\n\
+             \n\
+             =================================== FAILURES ===================================\n\
+             _________________________________ test_divide __________________________________\n\
+             \n\
+             >       assert divide(1, 0) is None\n\
+             E       ZeroDivisionError: division by zero\n\
+             \n\
+             tests/test_app.py:12: ZeroDivisionError\n\
+             =========================== short test summary info ============================\n\
+             FAILED tests/test_app.py::test_divide - ZeroDivisionError: division by zero\n\
+             ERROR tests/test_app.py::test_db\n\
+             =========== 1 failed, 2 passed, 1 skipped, 1 warning, 1 error in 0.12s ===========\n",
+            1,
+        );
+        assert_eq!((report.passed, report.failed, report.ignored), (2, 2, 1));
+        assert_eq!(report.failing, ["tests/test_app.py::test_divide"]);
+        assert!(report.failures[0].contains("ZeroDivisionError"), "{:?}", report.failures);
+
+        // Nothing passed
+        let report = self::report("========================= 2 failed in 0.05s =========================\n", 1);
+        assert_eq!((report.passed, report.failed), (0, 2));
+        assert!(!report.all_passed());
+
+        // A clean run, and one that ran nothing
+        let report = self::report("============================== 3 passed in 0.02s ===============================\n", 0);
+        assert_eq!((report.passed, report.failed), (3, 0));
+        assert!(report.all_passed());
+        let report = self::report("============================ no tests ran in 0.01s =============================\n", 5);
+        assert_eq!((report.passed, report.failed), (0, 0));
+        assert!(!report.all_passed());
+    }
+
+    #[test]
+    fn jest_summaries_skip_the_suite_line() {
+        let report = report(
+            " FAIL  src/sum.test.js\n\
+             \x20 ● math › adds numbers\n\
+             \n\
+             \x20   expect(received).toBe(expected) // Object.is equality\n\
+             \n\
+             Test Suites: 1 failed, 1 passed, 2 total\n\
+             Tests:       1 failed, 1 skipped, 4 passed, 6 total\n\
+             Snapshots:   0 total\n\
+             Time:        0.512 s\n",
+            1,
+        );
+        assert_eq!((report.passed, report.failed, report.ignored), (4, 1, 1));
+        assert_eq!(report.failing, ["math › adds numbers"]);
+
+        let report = self::report("Test Suites: 1 passed, 1 total\nTests:       0 passed, 0 total\n", 0);
+        assert_eq!((report.passed, report.failed), (0, 0));
+        assert!(report.all_passed());
+    }
+}
@@ -0,0 +1,188 @@
+use ratatui::style::{Color, Modifier, Style};
+use std::env;
+
+pub const THEME_NAMES: [&str; 4] = ["dark", "light", "high-contrast", "retro"];
+
+#[derive(Clone, Copy)]
+pub struct Theme {
+    pub name: &'static str,
+    pub bg: Color,
+    pub border: Color,
+    pub title_bar: Color,
+    pub title_text: Color,
+    pub text: Color,
+    pub highlight: Color,
+    pub success: Color,
+    pub warning: Color,
+    pub error: Color,
+    pub muted: Color,
+    pub selection: Color,
+    pub disabled: Color,
+    pub overlay: Color,
+    pub read: Color,
+    pub command: Color,
+    pub test: Color,
+    // syntect theme used for code blocks; None disables highlighting
+    pub syntax_theme: Option<&'static str>,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl Theme {
+    // BOTCODER_THEME picks a preset; NO_COLOR always wins
+    pub fn from_env() -> Self {
+        if no_color() {
+            return Self::plain();
+        }
+
+        env::var("BOTCODER_THEME")
+            .ok()
+            .and_then(|name| Self::by_name(&name))
+            .unwrap_or_default()
+    }
+
+    pub fn by_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "dark" => Some(Self::dark()),
+            "light" => Some(Self::light()),
+            "high-contrast" | "highcontrast" | "contrast" => Some(Self::high_contrast()),
+            "retro" => Some(Self::retro()),
+            "plain" | "none" => Some(Self::plain()),
+            _ => None,
+        }
+    }
+
+    pub fn selection_style(&self) -> Style {
+        if self.selection == Color::Reset {
+            Style::default().add_modifier(Modifier::REVERSED)
+        } else {
+            Style::default().bg(self.selection)
+        }
+    }
+
+    pub fn dark() -> Self {
+        Self {
+            name: "dark",
+            bg: Color::Rgb(25, 28, 35),
+            border: Color::Rgb(70, 80, 95),
+            title_bar: Color::Rgb(45, 55, 72),
+            title_text: Color::Rgb(230, 237, 243),
+            text: Color::Rgb(203, 213, 225),
+            highlight: Color::Rgb(56, 189, 248),
+            success: Color::Rgb(34, 197, 94),
+            warning: Color::Rgb(251, 191, 36),
+            error: Color::Rgb(239, 68, 68),
+            muted: Color::Rgb(100, 116, 139),
+            selection: Color::Rgb(45, 55, 72),
+            disabled: Color::Rgb(50, 50, 50),
+            overlay: Color::Rgb(20, 23, 30),
+            read: Color::Rgb(96, 165, 250),
+            command: Color::Rgb(167, 139, 250),
+            test: Color::Rgb(45, 212, 191),
+            syntax_theme: Some("base16-ocean.dark"),
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            name: "light",
+            bg: Color::Rgb(250, 250, 250),
+            border: Color::Rgb(160, 170, 180),
+            title_bar: Color::Rgb(226, 232, 240),
+            title_text: Color::Rgb(15, 23, 42),
+            text: Color::Rgb(30, 41, 59),
+            highlight: Color::Rgb(2, 132, 199),
+            success: Color::Rgb(22, 163, 74),
+            warning: Color::Rgb(202, 138, 4),
+            error: Color::Rgb(220, 38, 38),
+            muted: Color::Rgb(148, 163, 184),
+            selection: Color::Rgb(203, 213, 225),
+            disabled: Color::Rgb(200, 200, 200),
+            overlay: Color::Rgb(241, 245, 249),
+            read: Color::Rgb(37, 99, 235),
+            command: Color::Rgb(124, 58, 237),
+            test: Color::Rgb(13, 148, 136),
+            syntax_theme: Some("InspiredGitHub"),
+        }
+    }
+
+    pub fn high_contrast() -> Self {
+        Self {
+            name: "high-contrast",
+            bg: Color::Black,
+            border: Color::White,
+            title_bar: Color::Black,
+            title_text: Color::White,
+            text: Color::White,
+            highlight: Color::Yellow,
+            success: Color::LightGreen,
+            warning: Color::LightYellow,
+            error: Color::LightRed,
+            muted: Color::Gray,
+            selection: Color::Blue,
+            disabled: Color::DarkGray,
+            overlay: Color::Black,
+            read: Color::LightCyan,
+            command: Color::LightMagenta,
+            test: Color::LightGreen,
+            syntax_theme: Some("base16-eighties.dark"),
+        }
+    }
+
+    // Classic 16-color blue desktop look
+    pub fn retro() -> Self {
+        Self {
+            name: "retro",
+            bg: Color::Rgb(0, 0, 170),
+            border: Color::Rgb(170, 170, 170),
+            title_bar: Color::Rgb(0, 0, 128),
+            title_text: Color::Rgb(255, 255, 255),
+            text: Color::Rgb(255, 255, 255),
+            highlight: Color::Rgb(85, 255, 255),
+            success: Color::Rgb(85, 255, 85),
+            warning: Color::Rgb(255, 255, 85),
+            error: Color::Rgb(255, 85, 85),
+            muted: Color::Rgb(170, 170, 170),
+            selection: Color::Rgb(0, 170, 170),
+            disabled: Color::Rgb(85, 85, 85),
+            overlay: Color::Rgb(0, 0, 128),
+            read: Color::Rgb(85, 255, 255),
+            command: Color::Rgb(255, 85, 255),
+            test: Color::Rgb(85, 255, 85),
+            syntax_theme: Some("Solarized (dark)"),
+        }
+    }
+
+    // Terminal default colors only, used for NO_COLOR
+    pub fn plain() -> Self {
+        Self {
+            name: "plain",
+            bg: Color::Reset,
+            border: Color::Reset,
+            title_bar: Color::Reset,
+            title_text: Color::Reset,
+            text: Color::Reset,
+            highlight: Color::Reset,
+            success: Color::Reset,
+            warning: Color::Reset,
+            error: Color::Reset,
+            muted: Color::Reset,
+            selection: Color::Reset,
+            disabled: Color::Reset,
+            overlay: Color::Reset,
+            read: Color::Reset,
+            command: Color::Reset,
+            test: Color::Reset,
+            syntax_theme: None,
+        }
+    }
+}
+
+// https://no-color.org: any non-empty value disables color
+pub fn no_color() -> bool {
+    env::var("NO_COLOR").map(|v| !v.is_empty()).unwrap_or(false)
+}
@@ -0,0 +1,277 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use crate::events::{AgentEvent, AgentObserver};
+use crate::overlay;
+
+const TIMELINE_DIR: &str = ".botcoder/timeline";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    // Still waiting for the reply or running its tools
+    Running,
+    Ok,
+    ToolFailed,
+    Error,
+    Success,
+}
+
+impl Outcome {
+    pub fn icon(&self) -> &'static str {
+        match self {
+            Outcome::Running => "…",
+            Outcome::Ok => "•",
+            Outcome::ToolFailed => "⚠",
+            Outcome::Error => "✗",
+            Outcome::Success => "✓",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ToolRecord {
+    pub tool: String,
+    pub param: String,
+    pub result: String,
+    pub ok: bool,
+}
+
+// One iteration of the session: the model's reply and the tools it ran
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IterationRecord {
+    pub iteration: u32,
+    // The user's messages since the previous iteration
+    #[serde(default)]
+    pub user: Vec<String>,
+    pub model: String,
+    pub duration_ms: u64,
+    pub tokens: u32,
+    pub thoughts: String,
+    pub tools: Vec<ToolRecord>,
+    pub outcome: Outcome,
+}
+
+impl IterationRecord {
+    fn new(iteration: u32) -> Self {
+        Self {
+            iteration,
+            user: Vec::new(),
+            model: String::new(),
+            duration_ms: 0,
+            tokens: 0,
+            thoughts: String::new(),
+            tools: Vec::new(),
+            outcome: Outcome::Running,
+        }
+    }
+
+    // The reply followed by each tool call and its result, for the detail
+    // view
+    pub fn details(&self) -> String {
+        let mut text: String = self.user.iter().map(|m| format!("You: {}\n\n", m.trim_end())).collect();
+        text.push_str(&format!("{}\n", self.thoughts.trim_end()));
+        for tool in &self.tools {
+            let mark = if tool.ok { "✓" } else { "✗" };
+            text.push_str(&format!("\n{} {}: {}\n{}\n", mark, tool.tool, tool.param, tool.result));
+        }
+        text
+    }
+}
+
+// Records each iteration as the engine's events arrive and appends it to
+// .botcoder/timeline/<session>.jsonl whenever it changes, so the log holds
+// the session even if it ends abruptly. Reading the log keeps the last line
+// of each iteration.
+#[derive(Clone)]
+pub struct Timeline {
+    path: PathBuf,
+    current: Arc<Mutex<Current>>,
+}
+
+#[derive(Default)]
+struct Current {
+    record: Option<(IterationRecord, Instant)>,
+    // User messages waiting for the iteration they start
+    user: Vec<String>,
+}
+
+impl Timeline {
+    pub fn new(project_root: &str, session: &str) -> Self {
+        Self {
+            path: overlay::state_path(project_root, TIMELINE_DIR).join(format!("{}.jsonl", session)),
+            current: Arc::new(Mutex::new(Current::default())),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // The session's iterations in order, read back from the log
+    pub fn records(&self) -> Vec<IterationRecord> {
+        load(&self.path)
+    }
+
+    // Starts the log over with these iterations, when a saved or forked
+    // session takes the current one's place
+    pub fn replace(&self, records: &[IterationRecord]) {
+        if let Ok(mut current) = self.current.lock() {
+            *current = Current::default();
+        }
+        if let Err(e) = fs::remove_file(&self.path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log::warn!("Could not clear {}: {}", self.path.display(), e);
+            }
+        }
+        for record in records {
+            self.save(record);
+        }
+    }
+
+    fn save(&self, record: &IterationRecord) {
+        let Ok(line) = serde_json::to_string(record) else {
+            return;
+        };
+        if let Some(dir) = self.path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| writeln!(file, "{}", line));
+        if let Err(e) = written {
+            log::warn!("Could not write {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+impl AgentObserver for Timeline {
+    fn on_event(&mut self, event: &AgentEvent) {
+        let Ok(mut current) = self.current.lock() else {
+            return;
+        };
+        match event {
+            AgentEvent::IterationStarted { iteration } => {
+                let mut record = IterationRecord::new(*iteration);
+                record.user = std::mem::take(&mut current.user);
+                current.record = Some((record, Instant::now()));
+                return;
+            }
+            AgentEvent::UserMessage { text } => {
+                current.user.push(text.clone());
+                return;
+            }
+            _ => {}
+        }
+        let Some((record, started)) = current.record.as_mut() else {
+            return;
+        };
+        match event {
+            AgentEvent::LlmRequest {
+                role,
+                model,
+                input_tokens,
+            } => {
+                record.model = format!("{}: {}", role.name(), model);
+                record.tokens += input_tokens;
+                return;
+            }
+            AgentEvent::LlmResponse {
+                text, output_tokens, ..
+            } => {
+                record.thoughts = text.clone();
+                record.tokens += output_tokens;
+                record.outcome = Outcome::Ok;
+            }
+            AgentEvent::ToolExecuted {
+                tool,
+                param,
+                result,
+                ok,
+                ..
+            } => {
+                record.tools.push(ToolRecord {
+                    tool: tool.clone(),
+                    param: param.clone(),
+                    result: result.clone(),
+                    ok: *ok,
+                });
+                if !ok && record.outcome == Outcome::Ok {
+                    record.outcome = Outcome::ToolFailed;
+                }
+            }
+            AgentEvent::Success => record.outcome = Outcome::Success,
+            AgentEvent::Error { message } => {
+                record.outcome = Outcome::Error;
+                record.thoughts.push_str(&format!("\n\n⚠ {}", message));
+            }
+            _ => return,
+        }
+        record.duration_ms = started.elapsed().as_millis() as u64;
+        let record = record.clone();
+        drop(current);
+        self.save(&record);
+    }
+}
+
+// The iterations in a timeline log, the last line of each one winning
+pub fn load(path: &Path) -> Vec<IterationRecord> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let mut records: Vec<IterationRecord> = Vec::new();
+    for record in text
+        .lines()
+        .filter_map(|line| serde_json::from_str::<IterationRecord>(line).ok())
+    {
+        match records.iter_mut().find(|r| r.iteration == record.iteration) {
+            Some(existing) => *existing = record,
+            None => records.push(record),
+        }
+    }
+    records.sort_by_key(|r| r.iteration);
+    records
+}
+
+// A line of a logged iteration containing the searched text
+pub struct SearchMatch {
+    pub iteration: u32,
+    // "you", "reply" or the tool call, e.g. "write_file: src/config.rs"
+    pub source: String,
+    pub line: String,
+}
+
+// Case-insensitive search through the messages, replies, tool calls and
+// results of the iterations, in order
+pub fn search(records: &[IterationRecord], query: &str) -> Vec<SearchMatch> {
+    let query = query.to_lowercase();
+    let mut matches = Vec::new();
+    for record in records {
+        let mut texts: Vec<(String, &str)> =
+            record.user.iter().map(|m| ("you".to_string(), m.as_str())).collect();
+        texts.push(("reply".to_string(), record.thoughts.as_str()));
+        for tool in &record.tools {
+            let call = format!("{}: {}", tool.tool, tool.param);
+            texts.push((call.clone(), tool.param.as_str()));
+            texts.push((call, tool.result.as_str()));
+        }
+        for (source, text) in texts {
+            for line in text.lines().filter(|l| l.to_lowercase().contains(&query)) {
+                matches.push(SearchMatch {
+                    iteration: record.iteration,
+                    source: source.clone(),
+                    line: line.trim().to_string(),
+                });
+            }
+        }
+    }
+    matches
+}
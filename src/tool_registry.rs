@@ -0,0 +1,215 @@
+use crate::plugins;
+
+// Tools the model can call, with the arguments each one takes. Used to
+// describe the JSON protocol to the model and to validate its replies.
+pub struct ToolSpec {
+    pub name: &'static str,
+    pub description: &'static str,
+    // (name, required, description)
+    pub args: &'static [(&'static str, bool, &'static str)],
+}
+
+pub const TOOLS: &[ToolSpec] = &[
+    ToolSpec {
+        name: "read_file",
+        description: "Read a file from the project",
+        args: &[
+            ("path", true, "path relative to the project root"),
+            ("lines", false, "line range to read, as \"START-END\""),
+            ("numbered", false, "\"true\" to prefix each line with its number"),
+        ],
+    },
+    ToolSpec {
+        name: "outline",
+        description: "List a file's items and function signatures with line numbers",
+        args: &[("path", true, "path relative to the project root")],
+    },
+    ToolSpec {
+        name: "execute_command",
+        description: "Run a shell command in the project root or one of its directories",
+        args: &[
+            ("command", true, "the command line to run"),
+            ("cwd", false, "directory to run it in, relative to the project root"),
+            ("env", false, "extra environment variables, as \"NAME=value NAME2=value2\""),
+        ],
+    },
+    ToolSpec {
+        name: "run_tests",
+        description: "Run the test suite and get a pass/fail summary",
+        args: &[("filter", false, "only run tests whose name contains this")],
+    },
+    ToolSpec {
+        name: "write_file_delta",
+        description: "Replace an exact snippet of a file with new content",
+        args: &[
+            ("path", true, "path relative to the project root"),
+            ("old", true, "the exact current content to replace"),
+            ("new", true, "the replacement content"),
+        ],
+    },
+    ToolSpec {
+        name: "edit_lines",
+        description: "Replace a range of lines, as numbered by a numbered read_file",
+        args: &[
+            ("path", true, "path relative to the project root"),
+            ("start", true, "first line to replace, 1-based"),
+            ("end", true, "last line to replace, inclusive"),
+            ("content", false, "the replacement lines; empty deletes the range"),
+        ],
+    },
+    ToolSpec {
+        name: "append_to_file",
+        description: "Add content to the end of a file, creating it if needed",
+        args: &[
+            ("path", true, "path relative to the project root"),
+            ("content", true, "the lines to append"),
+        ],
+    },
+    ToolSpec {
+        name: "insert_after",
+        description: "Insert content after the one line containing the anchor text",
+        args: &[
+            ("path", true, "path relative to the project root"),
+            ("anchor", true, "text that appears on exactly one line"),
+            ("content", true, "the lines to insert"),
+        ],
+    },
+    ToolSpec {
+        name: "switch_project",
+        description: "Make another project of the workspace the active one (workspaces only)",
+        args: &[("name", true, "the project's name from the workspace list")],
+    },
+    ToolSpec {
+        name: "step_done",
+        description: "Mark the current step of the approved plan as done and move to the next one (plan mode only)",
+        args: &[("summary", false, "what the step changed")],
+    },
+    ToolSpec {
+        name: "git_status",
+        description: "Show the current branch and the staged, unstaged and untracked files",
+        args: &[],
+    },
+    ToolSpec {
+        name: "git_diff",
+        description: "Show uncommitted changes against HEAD, including new files",
+        args: &[("path", false, "only show changes to this path")],
+    },
+    ToolSpec {
+        name: "git_log",
+        description: "List recent commits, newest first",
+        args: &[("count", false, "number of commits, default 10")],
+    },
+    ToolSpec {
+        name: "cargo_add",
+        description: "Add a dependency to Cargo.toml with cargo add",
+        args: &[("args", true, "cargo add arguments, e.g. \"serde --features derive\"")],
+    },
+    ToolSpec {
+        name: "cargo_remove",
+        description: "Remove a dependency from Cargo.toml with cargo remove",
+        args: &[("args", true, "the crate name and any cargo remove flags")],
+    },
+    ToolSpec {
+        name: "cargo_tree",
+        description: "Show the dependency tree",
+        args: &[("args", false, "cargo tree arguments, default \"--depth 1\"; \"-i serde\" shows what pulls in serde")],
+    },
+    ToolSpec {
+        name: "cargo_deps",
+        description: "List the dependencies declared in Cargo.toml with versions and features",
+        args: &[("name", false, "only show this crate")],
+    },
+    ToolSpec {
+        name: "cargo_clippy",
+        description: "Run clippy and list each lint with its location and suggested fix",
+        args: &[("args", false, "cargo clippy arguments, e.g. \"--all-targets\"")],
+    },
+    ToolSpec {
+        name: "cargo_fmt",
+        description: "Format the project with rustfmt and list the files it changed",
+        args: &[("mode", false, "\"check\" only lists the files that need formatting")],
+    },
+    ToolSpec {
+        name: "lookup_crate",
+        description: "Look up a crate on crates.io and docs.rs: latest version, features and public items",
+        args: &[("name", true, "the crate name, or \"name@version\" for a specific release")],
+    },
+    ToolSpec {
+        name: "fetch_url",
+        description: "Read a web page as plain text, e.g. an error explanation or API docs (allowed domains only)",
+        args: &[("url", true, "an http(s) URL on an allowed domain")],
+    },
+    ToolSpec {
+        name: "semantic_search",
+        description: "Find the code most related to a description, when you don't know its names or files",
+        args: &[("query", true, "what the code does, e.g. \"where retries are scheduled\"")],
+    },
+    ToolSpec {
+        name: "rename_symbol",
+        description: "Rename an identifier across the project's source files, leaving strings and comments alone",
+        args: &[
+            ("old", true, "the current name"),
+            ("new", true, "the new name"),
+        ],
+    },
+    ToolSpec {
+        name: "ask_user",
+        description: "Ask the user a question and wait for the answer, when the task is ambiguous and guessing wrong would waste work",
+        args: &[("question", true, "one clear question")],
+    },
+    ToolSpec {
+        name: "git_commit",
+        description: "Commit the files modified this session; in the terminal UI the user approves the message first",
+        args: &[("message", true, "a Conventional Commits message, e.g. \"fix(parser): handle empty replies\"")],
+    },
+    ToolSpec {
+        name: "done",
+        description: "Finish the turn without calling a tool",
+        args: &[("summary", false, "what was done or what is needed next")],
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static ToolSpec> {
+    TOOLS.iter().find(|t| t.name == name)
+}
+
+// Built-in tools followed by the project's plugins
+pub fn names() -> Vec<String> {
+    TOOLS
+        .iter()
+        .map(|t| t.name.to_string())
+        .chain(plugins::registered().into_iter().map(|p| p.name))
+        .collect()
+}
+
+// Appended to the system prompt when BOTCODER_TOOL_FORMAT=json
+pub fn json_instructions() -> String {
+    let mut out = String::from(
+        "TOOL CALL FORMAT\n\
+         Ignore any other tool syntax described above. Reply with exactly one JSON \
+         object and nothing else, in the form:\n\
+         {\"reasoning\": \"...\", \"tool\": \"<name>\", \"args\": {...}}\n\n\
+         Available tools:\n",
+    );
+
+    for tool in TOOLS {
+        out.push_str(&format!("- {}: {}\n", tool.name, tool.description));
+        for (arg, required, description) in tool.args {
+            let kind = if *required { "required" } else { "optional" };
+            out.push_str(&format!(
+                "    {} (string, {}): {}\n",
+                arg, kind, description
+            ));
+        }
+    }
+
+    for plugin in plugins::registered() {
+        out.push_str(&plugin.describe());
+    }
+
+    out.push_str(
+        "\nExample: {\"tool\": \"read_file\", \"args\": {\"path\": \"src/main.rs\"}}\n\
+         \"reasoning\" is optional. Use the done tool when there is nothing left to call.",
+    );
+    out
+}
@@ -3,25 +3,87 @@ use std::{
     time::{Duration, SystemTime},
 };
 
+use crate::limits::LimitProfile;
+
 pub struct TPMLimiter {
     max_tpm: u32,
+    max_rpm: u32,
     min_interval: Duration,
+    // Limits given at construction, restored when no profile matches
+    defaults: LimitProfile,
+    profile: String,
+    // Sessions sharing the limits, each getting this fraction of them
+    share: u32,
     token_usage: VecDeque<(SystemTime, u32)>,
     last_request: Option<SystemTime>,
     total_tokens_used: u32,
 }
 
 impl TPMLimiter {
+    // Tokens per minute and the interval only, with no limit on requests
     pub fn new(max_tpm: u32, min_interval_secs: u64) -> Self {
+        Self::from_profile(LimitProfile {
+            name: "default".to_string(),
+            tpm: max_tpm,
+            rpm: u32::MAX,
+            burst: 1,
+            min_interval: min_interval_secs,
+        })
+    }
+
+    pub fn from_profile(profile: LimitProfile) -> Self {
         Self {
-            max_tpm,
-            min_interval: Duration::from_secs(min_interval_secs),
+            max_tpm: profile.tpm,
+            max_rpm: profile.rpm.max(1),
+            min_interval: Duration::from_secs(profile.min_interval),
+            profile: profile.name.clone(),
+            defaults: profile,
+            share: 1,
             token_usage: VecDeque::new(),
             last_request: None,
             total_tokens_used: 0,
         }
     }
 
+    // Limits for one of `sessions` sessions running at once on the same
+    // deployment: TPM and RPM divided between them
+    pub fn with_share(mut self, sessions: u32) -> Self {
+        self.share = sessions.max(1);
+        let profile = self.defaults.clone();
+        self.select(Some(profile));
+        self
+    }
+
+    // Switches to the limits of the model the next request goes to, or back
+    // to the defaults; usage so far still counts against them
+    pub fn select(&mut self, profile: Option<LimitProfile>) {
+        let profile = profile.unwrap_or_else(|| self.defaults.clone());
+        if profile.name != self.profile {
+            log::info!(
+                "Rate limits: {} ({} TPM, {} RPM)",
+                profile.name,
+                profile.tpm,
+                profile.rpm
+            );
+        }
+        self.max_tpm = (profile.tpm / self.share).max(1);
+        self.max_rpm = (profile.rpm / self.share).max(1);
+        self.min_interval = Duration::from_secs(profile.min_interval);
+        self.profile = profile.name;
+    }
+
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    pub fn max_tpm(&self) -> u32 {
+        self.max_tpm
+    }
+
+    pub fn max_rpm(&self) -> u32 {
+        self.max_rpm
+    }
+
     pub fn add_token_usage(&mut self, tokens: u32) {
         let now = SystemTime::now();
         self.token_usage.push_back((now, tokens));
@@ -38,19 +100,49 @@ impl TPMLimiter {
         }
     }
 
-    pub fn wait_if_needed(&mut self) {
-        if let Some(last_request) = self.last_request {
-            if let Ok(elapsed) = last_request.elapsed() {
-                if elapsed < self.min_interval {
-                    let sleep_time = self.min_interval - elapsed;
-                    std::thread::sleep(sleep_time);
-                }
+    // How long the next request has to wait, and for which limit: the
+    // minimum interval after the last request, or until enough requests or
+    // tokens have left the one-minute window to get under the RPM or TPM
+    // limit
+    pub fn wait_time(&self) -> Option<(Duration, &'static str)> {
+        let now = SystemTime::now();
+        if let Some(elapsed) = self.last_request.and_then(|last| now.duration_since(last).ok()) {
+            if elapsed < self.min_interval {
+                return Some((self.min_interval - elapsed, "request interval"));
+            }
+        }
+
+        let one_minute_ago = now - Duration::from_secs(60);
+        let window: Vec<SystemTime> = self
+            .token_usage
+            .iter()
+            .map(|(time, _)| *time)
+            .filter(|time| *time >= one_minute_ago)
+            .collect();
+        if window.len() as u32 >= self.max_rpm {
+            let oldest = window[window.len() - self.max_rpm as usize];
+            return Some((oldest.duration_since(one_minute_ago).unwrap_or_default(), "RPM window"));
+        }
+
+        let mut current_tpm = self.get_current_tpm();
+        for (time, tokens) in self.token_usage.iter().filter(|(time, _)| *time >= one_minute_ago) {
+            if current_tpm < self.max_tpm {
+                break;
+            }
+            current_tpm -= tokens;
+            if current_tpm < self.max_tpm {
+                let wait = time.duration_since(one_minute_ago).unwrap_or_default();
+                return Some((wait, "TPM window"));
             }
         }
+        None
+    }
 
-        let current_tpm = self.get_current_tpm();
-        if current_tpm >= self.max_tpm {
-            std::thread::sleep(Duration::from_secs(60));
+    // Sleeps without blocking the runtime until wait_time is over
+    pub async fn wait_if_needed(&self) {
+        while let Some((wait, limit)) = self.wait_time() {
+            log::info!("Waiting {}s for the {}", wait.as_secs(), limit);
+            tokio::time::sleep(wait.max(Duration::from_millis(10))).await;
         }
     }
 
@@ -65,6 +157,15 @@ impl TPMLimiter {
             .sum()
     }
 
+    // Requests in the last minute
+    pub fn get_current_rpm(&self) -> u32 {
+        let one_minute_ago = SystemTime::now() - Duration::from_secs(60);
+        self.token_usage
+            .iter()
+            .filter(|(time, _)| *time >= one_minute_ago)
+            .count() as u32
+    }
+
     pub fn get_total_tokens(&self) -> u32 {
         self.total_tokens_used
     }
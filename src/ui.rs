@@ -1,44 +1,100 @@
 use ratatui::{
     layout::{Constraint, Direction, Layout, Rect},
-    style::{Color, Modifier, Style},
+    style::{Modifier, Style},
     text::{Line, Span},
     widgets::{
-        BarChart, Block, Borders, Gauge, List, ListItem, Paragraph, Scrollbar, ScrollbarOrientation,
-        ScrollbarState, Wrap,
+        BarChart, Block, Borders, Clear, Gauge, List, ListItem, ListState, Paragraph, Scrollbar, ScrollbarOrientation,
+        ScrollbarState, Sparkline, Wrap,
     },
     Frame,
 };
 
-use crate::app::AppState;
-
-pub fn draw_ui(f: &mut Frame, app: &AppState, spinner: &str) {
-    // Modern dark theme color palette
-    let bg = Color::Rgb(25, 28, 35);
-    let border = Color::Rgb(70, 80, 95);
-    let title_bar = Color::Rgb(45, 55, 72);
-    let title_text = Color::Rgb(230, 237, 243);
-    let text = Color::Rgb(203, 213, 225);
-    let highlight = Color::Rgb(56, 189, 248);
-    let success = Color::Rgb(34, 197, 94);
-    let warning = Color::Rgb(251, 191, 36);
-    let error = Color::Rgb(239, 68, 68);
-
-    let chunks = Layout::default()
+use std::{rc::Rc, time::Instant};
+
+use crate::app::{self, AppState, DetailView, Focus, OutputKind, RunMode, TimelineView};
+use crate::dry_run;
+use crate::file_tree::FileMark;
+use crate::glyphs;
+use crate::highlight;
+use crate::orchestrator::{Phase, SessionView};
+use crate::plan::StepStatus;
+use crate::queue::TaskOutcome;
+use crate::theme::Theme;
+use crate::timeline::Outcome;
+use crate::webhooks;
+use crate::write_scope;
+
+// Header, main panels, chat input and footer rows
+// Header, panels, message box (`input_height` rows) and footer
+pub fn outer_layout(area: Rect, input_height: u16) -> Rc<[Rect]> {
+    Layout::default()
         .direction(Direction::Vertical)
         .constraints([
             Constraint::Length(3),
             Constraint::Min(10),
-            Constraint::Length(3),
+            Constraint::Length(input_height),
             Constraint::Length(3),
         ])
-        .split(f.area());
+        .split(area)
+}
+
+// Lines the message box shows before it scrolls
+const MAX_INPUT_LINES: u16 = 8;
+
+// The message box grows with a multi-line message, borders included
+pub fn input_height(app: &AppState) -> u16 {
+    let input = searched_entry(app).unwrap_or(&app.chat_input);
+    input_lines(input).min(MAX_INPUT_LINES) + 2
+}
+
+// The sent message Ctrl+R found, shown in place of the message box
+fn searched_entry(app: &AppState) -> Option<&str> {
+    app.history_search
+        .as_ref()
+        .and_then(|s| s.matched)
+        .and_then(|i| app.input_history.get(i))
+}
+
+fn input_lines(input: &str) -> u16 {
+    let lines = input.lines().count() + usize::from(input.ends_with('\n'));
+    lines.max(1) as u16
+}
+
+// Files, thoughts, tools and stats columns, sized by percentage
+pub fn panel_layout(area: Rect, widths: &[u16; 4]) -> Rc<[Rect]> {
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(widths.map(Constraint::Percentage))
+        .split(area)
+}
+
+pub fn draw_ui(f: &mut Frame, app: &AppState, spinner: &str) {
+    let theme = &app.theme;
+    let bg = theme.bg;
+    let border = theme.border;
+    let title_bar = theme.title_bar;
+    let title_text = theme.title_text;
+    let text = theme.text;
+    let highlight = theme.highlight;
+    let success = theme.success;
+    let warning = theme.warning;
+    let error = theme.error;
+
+    let chunks = outer_layout(f.area(), input_height(app));
 
     // Header
-    let status_color = if app.processing { warning } else { success };
-    let status_text = if app.processing {
-        "⚡ Processing"
-    } else {
-        "✓ Ready"
+    let (status_text, status_color) = match (app.rate_limited, app.run_mode, &app.replay) {
+        (_, _, Some(replay)) => (
+            format!("{} (space: pause, s: step, +/-: speed, q: quit)", replay.status()),
+            highlight,
+        ),
+        (Some((until, limit)), _, _) => {
+            let left = until.saturating_duration_since(Instant::now()).as_secs_f32().ceil();
+            (format!("⏳ Waiting {}s for {} (Esc: cancel)", left, limit), warning)
+        }
+        (None, RunMode::Paused, _) if app.processing => ("⏸ Paused (s: step, r: resume, e: edit)".to_string(), warning),
+        _ if app.processing => ("⚡ Processing (p: pause)".to_string(), warning),
+        _ => ("✓ Ready".to_string(), success),
     };
 
     let header = Paragraph::new(Line::from(vec![
@@ -49,12 +105,22 @@ pub fn draw_ui(f: &mut Frame, app: &AppState, spinner: &str) {
                 .fg(title_text)
                 .add_modifier(Modifier::BOLD),
         ),
+        Span::styled(
+            if dry_run::enabled() { "DRY RUN " } else { "" },
+            Style::default().fg(warning).add_modifier(Modifier::BOLD),
+        ),
+        Span::styled(
+            if write_scope::read_only() { "READ ONLY " } else { "" },
+            Style::default().fg(warning).add_modifier(Modifier::BOLD),
+        ),
         Span::styled("| ", Style::default().fg(border)),
         Span::styled(
             format!("Iteration #{} ", app.iteration),
             Style::default().fg(text),
         ),
         Span::styled("| ", Style::default().fg(border)),
+        Span::styled(format!("{} ", app.active_model), Style::default().fg(text)),
+        Span::styled("| ", Style::default().fg(border)),
         Span::styled(status_text, Style::default().fg(status_color)),
         Span::styled(
             format!(" {} ", spinner),
@@ -72,35 +138,155 @@ pub fn draw_ui(f: &mut Frame, app: &AppState, spinner: &str) {
     f.render_widget(header, chunks[0]);
 
     // Main content panels
-    let main_chunks = Layout::default()
-        .direction(Direction::Horizontal)
-        .constraints([
-            Constraint::Percentage(40),
-            Constraint::Percentage(40),
-            Constraint::Percentage(20),
-        ])
-        .split(chunks[1]);
+    let main_chunks = panel_layout(chunks[1], &app.panel_widths);
+
+    // With a plan, the files column shares its space with the plan panel
+    let (files_area, plan_area) = match &app.plan {
+        Some(_) => {
+            let split = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .split(main_chunks[0]);
+            (split[0], Some(split[1]))
+        }
+        None => (main_chunks[0], None),
+    };
 
-    // AI Thoughts panel
-    let thoughts_block = Block::default()
-        .title(" 💭 AI Thoughts ")
+    // File tree panel
+    let files_block = Block::default()
+        .title(" 📁 Files ")
         .title_style(Style::default().fg(title_text).add_modifier(Modifier::BOLD))
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(border))
+        .border_style(Style::default().fg(if app.focus == Focus::Files {
+            highlight
+        } else {
+            border
+        }))
         .style(Style::default().bg(bg));
 
-    let thoughts_lines: Vec<Line> = app
-        .current_thoughts
-        .lines()
-        .map(|line| Line::from(Span::styled(line, Style::default().fg(text))))
+    let file_items: Vec<ListItem> = app
+        .file_tree
+        .iter()
+        .map(|entry| {
+            let mark = app.file_marks.get(&entry.path);
+            let (mark_text, mark_color) = match mark {
+                Some(FileMark::Modified) => (FileMark::Modified.symbol(), warning),
+                Some(FileMark::Read) => (FileMark::Read.symbol(), theme.read),
+                None => (" ", text),
+            };
+            let name = if entry.is_dir {
+                format!("{}▾ {}", "  ".repeat(entry.depth), entry.name)
+            } else {
+                format!("{}  {}", "  ".repeat(entry.depth), entry.name)
+            };
+
+            ListItem::new(Line::from(vec![
+                Span::styled(
+                    format!("{} ", mark_text),
+                    Style::default().fg(mark_color).add_modifier(Modifier::BOLD),
+                ),
+                Span::styled(
+                    name,
+                    Style::default().fg(if mark.is_some() { title_text } else { text }),
+                ),
+            ]))
+        })
         .collect();
 
+    let files_list = List::new(file_items)
+        .block(files_block)
+        .highlight_style(theme.selection_style());
+
+    let mut files_state = ListState::default();
+    if app.focus == Focus::Files && !app.file_tree.is_empty() {
+        files_state.select(Some(app.selected_file));
+    }
+
+    f.render_stateful_widget(files_list, files_area, &mut files_state);
+
+    // Plan panel, with each step's status
+    if let (Some(plan), Some(area)) = (&app.plan, plan_area) {
+        let plan_block = Block::default()
+            .title(if app.plan_review {
+                " 📋 Plan (review) "
+            } else {
+                " 📋 Plan "
+            })
+            .title_style(Style::default().fg(title_text).add_modifier(Modifier::BOLD))
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(if app.plan_review { highlight } else { border }))
+            .style(Style::default().bg(bg));
+
+        let step_items: Vec<ListItem> = plan
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| {
+                let (color, modifier) = match step.status {
+                    StepStatus::Active => (highlight, Modifier::BOLD),
+                    StepStatus::Done => (success, Modifier::empty()),
+                    StepStatus::Pending => (text, Modifier::empty()),
+                };
+                let label = if app.editing_step == Some(i) {
+                    format!("{}. {}▏", i + 1, app.chat_input)
+                } else {
+                    format!("{}. {}", i + 1, step.text)
+                };
+                ListItem::new(Line::from(vec![
+                    Span::styled(format!("{} ", step.status.symbol()), Style::default().fg(color)),
+                    Span::styled(label, Style::default().fg(color).add_modifier(modifier)),
+                ]))
+            })
+            .collect();
+
+        let plan_list = List::new(step_items)
+            .block(plan_block)
+            .highlight_style(theme.selection_style());
+
+        let mut plan_state = ListState::default();
+        if app.plan_review {
+            plan_state.select(Some(app.selected_step));
+        }
+        f.render_stateful_widget(plan_list, area, &mut plan_state);
+    }
+
+    // The reasoning panel shares the thoughts column once a reasoning model
+    // has replied
+    let (thoughts_area, reasoning_area) = if app.reasoning.is_empty() {
+        (main_chunks[1], None)
+    } else {
+        let split = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+            .split(main_chunks[1]);
+        (split[0], Some(split[1]))
+    };
+
+    // AI Thoughts panel, which holds an ask_user question until answered
+    let thoughts_block = Block::default()
+        .title(if app.question.is_some() { " ❓ The agent asks " } else { " 💭 AI Thoughts " })
+        .title_style(Style::default().fg(title_text).add_modifier(Modifier::BOLD))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(if app.focus == Focus::Thoughts || app.question.is_some() {
+            highlight
+        } else {
+            border
+        }))
+        .style(Style::default().bg(bg));
+
+    let thoughts_lines = highlight::highlight(
+        &app.current_thoughts,
+        None,
+        Style::default().fg(text),
+        theme.syntax_theme,
+    );
+
     let thoughts_paragraph = Paragraph::new(thoughts_lines)
         .block(thoughts_block)
         .wrap(Wrap { trim: true })
         .scroll((app.thoughts_scroll as u16, 0));
 
-    f.render_widget(thoughts_paragraph, main_chunks[0]);
+    f.render_widget(thoughts_paragraph, thoughts_area);
 
     // Scrollbar for thoughts
     let thoughts_scrollbar = Scrollbar::default()
@@ -115,26 +301,48 @@ pub fn draw_ui(f: &mut Frame, app: &AppState, spinner: &str) {
 
     f.render_stateful_widget(
         thoughts_scrollbar,
-        main_chunks[0],
+        thoughts_area,
         &mut thoughts_scrollbar_state,
     );
 
+    // Reasoning panel, the latest reply's reasoning
+    if let Some(area) = reasoning_area {
+        let reasoning_block = Block::default()
+            .title(" 🧠 Reasoning ")
+            .title_style(Style::default().fg(title_text).add_modifier(Modifier::BOLD))
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(border))
+            .style(Style::default().bg(bg));
+        let reasoning_paragraph = Paragraph::new(app.reasoning.as_str())
+            .block(reasoning_block)
+            .style(Style::default().fg(text).add_modifier(Modifier::ITALIC))
+            .wrap(Wrap { trim: true });
+        f.render_widget(reasoning_paragraph, area);
+    }
+
     // Tools panel
     let tools_block = Block::default()
         .title(" 🛠️  Tool Execution ")
         .title_style(Style::default().fg(title_text).add_modifier(Modifier::BOLD))
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(border))
+        .border_style(Style::default().fg(if app.focus == Focus::Tools {
+            highlight
+        } else {
+            border
+        }))
         .style(Style::default().bg(bg));
 
-    let tool_items: Vec<ListItem> = app
+    let mut tool_items: Vec<ListItem> = app
         .current_tools
         .iter()
-        .map(|(tool, param, result)| {
+        .map(|(tool, param, result, status)| {
             let tool_color = match tool.as_str() {
-                "read_file" => Color::Rgb(96, 165, 250),
-                "write_file_delta" => Color::Rgb(251, 191, 36),
-                "execute_command" => Color::Rgb(167, 139, 250),
+                "read_file" | "outline" | "semantic_search" => theme.read,
+                "write_file_delta" | "edit_lines" | "append_to_file" | "insert_after"
+                | "rename_symbol" => warning,
+                "execute_command" => theme.command,
+                "ask_user" => highlight,
+                "run_tests" => theme.test,
                 _ => text,
             };
 
@@ -155,34 +363,81 @@ pub fn draw_ui(f: &mut Frame, app: &AppState, spinner: &str) {
                 ),
             ]);
 
-            let result_color = if result.contains("Error") || result.contains("✗") {
-                error
-            } else if result.contains("✓") {
-                success
-            } else {
-                text
+            // Commands by their exit status, other results by their text
+            let result_color = match status.as_ref().and_then(|status| status.exit_code) {
+                Some(0) => success,
+                Some(_) => error,
+                None if result.contains("Error") || result.contains("✗") => error,
+                None if result.contains("✓") => success,
+                None => text,
             };
 
+            // A command's output without its stdout:/stderr: headings, under
+            // a line with its exit status, time and output sizes
+            let command = status.as_ref().filter(|status| status.kind == OutputKind::Command);
+            let result = match command {
+                Some(_) => result
+                    .lines()
+                    .find(|line| !matches!(*line, "stdout:" | "stderr:"))
+                    .unwrap_or(""),
+                None => result.as_str(),
+            };
             let result_preview = if result.len() > 55 {
                 format!("  {}...", &result[..52])
             } else {
                 format!("  {}", result)
             };
 
-            let result_line = Line::from(Span::styled(
+            let mut lines = vec![tool_line];
+            if let Some(status) = command {
+                lines.push(Line::from(Span::styled(
+                    format!("  {}", status.summary()),
+                    Style::default().fg(result_color),
+                )));
+            }
+            lines.push(Line::from(Span::styled(
                 result_preview,
-                Style::default().fg(result_color),
-            ));
+                Style::default().fg(if command.is_some() { text } else { result_color }),
+            )));
 
-            ListItem::new(vec![tool_line, result_line])
+            ListItem::new(lines)
         })
         .collect();
 
+    // The tool still running, tailing its output
+    if let Some(running) = &app.running_tool {
+        let mut lines = vec![
+            Line::from(vec![
+                Span::styled(
+                    format!("▸ {}: ", running.tool),
+                    Style::default().fg(highlight).add_modifier(Modifier::BOLD),
+                ),
+                Span::styled(running.param.chars().take(35).collect::<String>(), Style::default().fg(text)),
+            ]),
+            Line::from(Span::styled(
+                format!("  ⏳ running for {}s", running.started.elapsed().as_secs()),
+                Style::default().fg(highlight),
+            )),
+        ];
+        lines.extend(running.output.iter().map(|line| {
+            Line::from(Span::styled(
+                format!("  │ {}", line.chars().take(60).collect::<String>()),
+                Style::default().fg(text).add_modifier(Modifier::DIM),
+            ))
+        }));
+        tool_items.push(ListItem::new(lines));
+    }
+
     let tools_list = List::new(tool_items)
         .block(tools_block)
-        .highlight_style(Style::default().bg(Color::Rgb(45, 55, 72)));
+        .highlight_style(theme.selection_style());
+
+    let mut tools_state = ListState::default();
+    if app.focus == Focus::Tools && !app.current_tools.is_empty() {
+        tools_state.select(Some(app.selected_tool));
+    }
 
-    f.render_widget(tools_list, main_chunks[1]);
+    f.render_stateful_widget(tools_list, main_chunks[2], &mut tools_state);
 
     // Statistics panel
     let stats_block = Block::default()
@@ -195,6 +450,9 @@ pub fn draw_ui(f: &mut Frame, app: &AppState, spinner: &str) {
     let stats_chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
+            Constraint::Length(2),
+            Constraint::Length(1),
+            Constraint::Length(1),
             Constraint::Length(2),
             Constraint::Length(2),
             Constraint::Length(2),
@@ -202,50 +460,87 @@ pub fn draw_ui(f: &mut Frame, app: &AppState, spinner: &str) {
             Constraint::Length(4),
             Constraint::Min(1),
         ])
-        .split(main_chunks[2]);
+        .split(main_chunks[3]);
 
-    f.render_widget(stats_block, main_chunks[2]);
+    f.render_widget(stats_block, main_chunks[3]);
 
     // Token stats with icons
-    let total_tokens = Paragraph::new(format!("🎯 Total: {}", app.stats.total_tokens))
-        .style(Style::default().fg(text));
+    let total = match webhooks::cost(app.stats.total_tokens) {
+        Some(cost) => format!("🎯 Total: {} (${:.2})", app.stats.total_tokens, cost),
+        None => format!("🎯 Total: {}", app.stats.total_tokens),
+    };
+    let total_tokens = Paragraph::new(total).style(Style::default().fg(text));
     f.render_widget(total_tokens, stats_chunks[0]);
 
-    let tpm_color = if app.stats.current_tpm as f32 / app.stats.max_tpm as f32 > 0.8 {
-        warning
-    } else {
-        success
-    };
+    let tpm_percentage = percent(app.stats.current_tpm, app.stats.max_tpm);
+    let rpm_percentage = percent(app.stats.current_rpm, app.stats.max_rpm);
+    let limit_color = |percentage: u16| if percentage > 80 { warning } else { success };
     let tpm_usage = Paragraph::new(format!(
         "⚡ TPM: {}/{}",
         app.stats.current_tpm, app.stats.max_tpm
     ))
-    .style(Style::default().fg(tpm_color));
+    .style(Style::default().fg(limit_color(tpm_percentage)));
     f.render_widget(tpm_usage, stats_chunks[1]);
 
+    let rpm_usage = Paragraph::new(format!(
+        "🔁 RPM: {}/{}",
+        app.stats.current_rpm, app.stats.max_rpm
+    ))
+    .style(Style::default().fg(limit_color(rpm_percentage)));
+    f.render_widget(rpm_usage, stats_chunks[2]);
+
     let input_tokens = Paragraph::new(format!("📥 In: {}", app.stats.input_tokens))
-        .style(Style::default().fg(Color::Rgb(96, 165, 250)));
-    f.render_widget(input_tokens, stats_chunks[2]);
+        .style(Style::default().fg(theme.read));
+    f.render_widget(input_tokens, stats_chunks[3]);
 
     let output_tokens = Paragraph::new(format!("📤 Out: {}", app.stats.output_tokens))
-        .style(Style::default().fg(Color::Rgb(167, 139, 250)));
-    f.render_widget(output_tokens, stats_chunks[3]);
+        .style(Style::default().fg(theme.command));
+    f.render_widget(output_tokens, stats_chunks[4]);
+
+    // TPM and RPM gauges
+    for (name, percentage, chunk) in [
+        ("TPM", tpm_percentage, stats_chunks[5]),
+        ("RPM", rpm_percentage, stats_chunks[6]),
+    ] {
+        let gauge = Gauge::default()
+            .block(Block::default().style(Style::default().bg(bg)))
+            .gauge_style(Style::default().fg(if percentage > 80 {
+                error
+            } else if percentage > 60 {
+                warning
+            } else {
+                success
+            }))
+            .percent(percentage)
+            .label(format!("{} {}%", name, percentage));
+        f.render_widget(gauge, chunk);
+    }
 
-    // TPM Gauge
-    let tpm_percentage =
-        (app.stats.current_tpm as f64 / app.stats.max_tpm as f64 * 100.0).min(100.0) as u16;
-    let gauge = Gauge::default()
-        .block(Block::default().style(Style::default().bg(bg)))
-        .gauge_style(Style::default().fg(if tpm_percentage > 80 {
-            error
-        } else if tpm_percentage > 60 {
-            warning
-        } else {
-            success
-        }))
-        .percent(tpm_percentage)
-        .label(format!("{}%", tpm_percentage));
-    f.render_widget(gauge, stats_chunks[4]);
+    // Tokens per minute over the last half hour, scaled to the TPM limit so
+    // a full bar means the limit was reached
+    let per_minute = app.stats.per_minute(app::current_minute());
+    let last = per_minute.last().copied().unwrap_or(0);
+    let title = match webhooks::cost(last as u32) {
+        Some(cost) => format!("Tokens/min: {} (${:.2})", last, cost),
+        None => format!("Tokens/min: {}", last),
+    };
+    let sparkline = Sparkline::default()
+        .block(
+            Block::default()
+                .title(title)
+                .title_style(Style::default().fg(text))
+                .style(Style::default().bg(bg)),
+        )
+        .data(&per_minute)
+        .max(u64::from(app.stats.max_tpm.max(1)))
+        .style(Style::default().fg(
+            if per_minute.iter().any(|&t| t >= u64::from(app.stats.max_tpm)) {
+                warning
+            } else {
+                highlight
+            },
+        ));
+    f.render_widget(sparkline, stats_chunks[7]);
 
     // Token distribution chart
     let data = vec![
@@ -265,34 +560,55 @@ pub fn draw_ui(f: &mut Frame, app: &AppState, spinner: &str) {
         .style(Style::default().fg(text))
         .value_style(Style::default().fg(highlight));
 
-    f.render_widget(chart, stats_chunks[5]);
+    f.render_widget(chart, stats_chunks[8]);
 
     // Chat input
+    let chat_title = match (app.editing_step, &app.history_search) {
+        (Some(index), _) => format!(" ✏ Step {} (Enter to save, Esc to cancel) ", index + 1),
+        (None, Some(search)) => format!(
+            " 🔍 reverse-i-search `{}'{} (Ctrl+R: older, Enter: use, Esc: cancel) ",
+            search.query,
+            if search.query.is_empty() || search.matched.is_some() { "" } else { " (no match)" }
+        ),
+        (None, None) if app.question.is_some() => " ❓ Your answer (Enter to send) ".to_string(),
+        (None, None) => " 💬 Message ".to_string(),
+    };
     let chat_block = Block::default()
-        .title(" 💬 Message ")
+        .title(chat_title)
         .title_style(Style::default().fg(title_text).add_modifier(Modifier::BOLD))
         .borders(Borders::ALL)
-        .border_style(Style::default().fg(if app.processing {
-            Color::Rgb(50, 50, 50)
+        .border_style(Style::default().fg(if app.question.is_some() {
+            highlight
+        } else if app.processing {
+            theme.disabled
         } else {
             border
         }))
         .style(Style::default().bg(bg));
 
-    let input_display = if app.chat_input.is_empty() {
-        "Type your message... (Enter to send)"
-    } else {
-        &app.chat_input
+    let searched = searched_entry(app);
+    let input_display = match searched {
+        Some(entry) => entry,
+        None if app.chat_input.is_empty() && app.question.is_some() => {
+            "Type your answer to the question above... (Esc: let the agent decide)"
+        }
+        None if app.chat_input.is_empty() => {
+            "Type your message... (Enter to send, Alt+Enter: new line, ↑/Ctrl+R: history)"
+        }
+        None => &app.chat_input,
     };
 
+    // Long messages show their last lines
+    let overflow = input_lines(input_display).saturating_sub(MAX_INPUT_LINES);
     let chat_input = Paragraph::new(input_display)
         .block(chat_block)
-        .style(if app.chat_input.is_empty() {
-            Style::default().fg(Color::Rgb(100, 116, 139))
+        .style(if app.chat_input.is_empty() && searched.is_none() {
+            Style::default().fg(theme.muted)
         } else {
             Style::default().fg(text)
         })
-        .wrap(Wrap { trim: true });
+        .wrap(Wrap { trim: false })
+        .scroll((overflow, 0));
 
     f.render_widget(chat_input, chunks[2]);
 
@@ -309,7 +625,10 @@ pub fn draw_ui(f: &mut Frame, app: &AppState, spinner: &str) {
         Span::styled(": Scroll ", Style::default().fg(text)),
         Span::styled("| ", Style::default().fg(border)),
         Span::styled("PgUp/PgDn", Style::default().fg(title_text).add_modifier(Modifier::BOLD)),
-        Span::styled(": Fast scroll", Style::default().fg(text)),
+        Span::styled(": Fast scroll ", Style::default().fg(text)),
+        Span::styled("| ", Style::default().fg(border)),
+        Span::styled("Tab", Style::default().fg(title_text).add_modifier(Modifier::BOLD)),
+        Span::styled(": Switch panel", Style::default().fg(text)),
     ]))
     .block(
         Block::default()
@@ -332,7 +651,7 @@ pub fn draw_ui(f: &mut Frame, app: &AppState, spinner: &str) {
             )
             .borders(Borders::ALL)
             .border_style(Style::default().fg(success))
-            .style(Style::default().bg(Color::Rgb(20, 23, 30)));
+            .style(Style::default().bg(theme.overlay));
 
         let success_text = vec![
             Line::from(""),
@@ -357,6 +676,177 @@ pub fn draw_ui(f: &mut Frame, app: &AppState, spinner: &str) {
 
         f.render_widget(success_paragraph, area);
     }
+
+    if let Some(view) = &app.timeline_view {
+        draw_timeline(f, view, theme);
+    }
+
+    if let Some(detail) = &app.detail {
+        draw_detail(f, detail, theme);
+    }
+
+    if glyphs::ascii() {
+        glyphs::asciify(f.buffer_mut());
+    }
+}
+
+// One line per iteration: outcome, duration, tokens, tools and model
+fn draw_timeline(f: &mut Frame, view: &TimelineView, theme: &Theme) {
+    let area = centered_rect(80, 70, f.area());
+    let block = Block::default()
+        .title(" 🕒 Timeline ")
+        .title_bottom(" ↑↓: Select | Enter: Inspect | Q/ESC: Close ")
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.highlight))
+        .style(Style::default().bg(theme.bg));
+
+    let items: Vec<ListItem> = view
+        .records
+        .iter()
+        .map(|record| {
+            let color = match record.outcome {
+                Outcome::Success => theme.success,
+                Outcome::Error => theme.error,
+                Outcome::ToolFailed => theme.warning,
+                Outcome::Ok | Outcome::Running => theme.text,
+            };
+            let tools: Vec<&str> = record.tools.iter().map(|t| t.tool.as_str()).collect();
+            ListItem::new(Line::from(vec![
+                Span::styled(
+                    format!("{} #{:<3} ", record.outcome.icon(), record.iteration),
+                    Style::default().fg(color),
+                ),
+                Span::styled(
+                    format!(
+                        "{:>6.1}s {:>7} tok  {}  ",
+                        record.duration_ms as f64 / 1000.0,
+                        record.tokens,
+                        record.model
+                    ),
+                    Style::default().fg(theme.text),
+                ),
+                Span::styled(tools.join(", "), Style::default().fg(theme.command)),
+            ]))
+        })
+        .collect();
+
+    let list = List::new(items)
+        .block(block)
+        .highlight_style(theme.selection_style());
+    let mut state = ListState::default();
+    state.select(Some(view.selected));
+
+    f.render_widget(Clear, area);
+    f.render_stateful_widget(list, area, &mut state);
+}
+
+// botcoder parallel: one line per session with its state, iteration,
+// tokens, time and latest tool, under a header with the totals
+pub fn draw_overview(f: &mut Frame, sessions: &[SessionView], theme: &Theme, spinner: &str) {
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Length(3), Constraint::Min(3)])
+        .split(f.area());
+
+    let count = |phase: fn(&Phase) -> bool| sessions.iter().filter(|s| phase(&s.phase)).count();
+    let running = count(|p| *p == Phase::Running);
+    let succeeded = count(|p| *p == Phase::Done(TaskOutcome::Succeeded));
+    let done = count(|p| matches!(p, Phase::Done(_)));
+    let header = Paragraph::new(Line::from(vec![
+        Span::styled("🤖 ", Style::default().fg(theme.highlight)),
+        Span::styled(
+            "BOTCODER PARALLEL ",
+            Style::default().fg(theme.title_text).add_modifier(Modifier::BOLD),
+        ),
+        Span::styled("| ", Style::default().fg(theme.border)),
+        Span::styled(
+            format!(
+                "{} running, {}/{} done, {} succeeded ",
+                running,
+                done,
+                sessions.len(),
+                succeeded
+            ),
+            Style::default().fg(theme.text),
+        ),
+        Span::styled(format!(" {} ", spinner), Style::default().fg(theme.highlight)),
+    ]))
+    .block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(theme.border))
+            .style(Style::default().bg(theme.title_bar)),
+    );
+    f.render_widget(header, chunks[0]);
+
+    let items: Vec<ListItem> = sessions
+        .iter()
+        .map(|session| {
+            let (icon, state, color) = match session.phase {
+                Phase::Queued => ("·", "queued".to_string(), theme.muted),
+                Phase::Running => ("⚡", "running".to_string(), theme.warning),
+                Phase::Done(TaskOutcome::Succeeded) => ("✓", "succeeded".to_string(), theme.success),
+                Phase::Done(TaskOutcome::Skipped) => ("-", "skipped".to_string(), theme.muted),
+                Phase::Done(outcome) => ("✗", format!("{:?}", outcome).to_lowercase(), theme.error),
+            };
+            ListItem::new(Line::from(vec![
+                Span::styled(format!("{} {:<20} {:<11}", icon, session.name, state), Style::default().fg(color)),
+                Span::styled(
+                    format!(
+                        "#{:<3} {:>8} tok {:>7.1}s  ",
+                        session.iteration,
+                        session.tokens,
+                        session.elapsed.as_secs_f64()
+                    ),
+                    Style::default().fg(theme.text),
+                ),
+                Span::styled(format!("{:<28} ", session.branch), Style::default().fg(theme.muted)),
+                Span::styled(session.activity.clone(), Style::default().fg(theme.command)),
+            ]))
+        })
+        .collect();
+    let list = List::new(items).block(
+        Block::default()
+            .title(" Sessions ")
+            .title_bottom(if done == sessions.len() {
+                " Q/ESC: Close "
+            } else {
+                " Q/ESC: Stop all sessions "
+            })
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(theme.highlight))
+            .style(Style::default().bg(theme.bg)),
+    );
+    f.render_widget(list, chunks[1]);
+
+    if glyphs::ascii() {
+        glyphs::asciify(f.buffer_mut());
+    }
+}
+
+fn draw_detail(f: &mut Frame, detail: &DetailView, theme: &Theme) {
+    let area = f.area();
+    let block = Block::default()
+        .title(format!(" {} ", detail.title))
+        .title_bottom(" ↑↓/PgUp/PgDn: Scroll | Q/ESC: Close ")
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(theme.highlight))
+        .style(Style::default().bg(theme.bg));
+
+    let lines = highlight::highlight(
+        &detail.content,
+        detail.lang.as_deref(),
+        Style::default().fg(theme.text),
+        theme.syntax_theme,
+    );
+
+    let paragraph = Paragraph::new(lines)
+        .block(block)
+        .wrap(Wrap { trim: false })
+        .scroll((detail.scroll, 0));
+
+    f.render_widget(Clear, area);
+    f.render_widget(paragraph, area);
 }
 
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
@@ -378,3 +868,8 @@ fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
         ])
         .split(popup_layout[1])[1]
 }
+
+// Share of a rate limit in use, capped at 100
+fn percent(current: u32, max: u32) -> u16 {
+    (current as f64 / max.max(1) as f64 * 100.0).min(100.0) as u16
+}
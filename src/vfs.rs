@@ -0,0 +1,260 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs, io,
+    path::{self, Component, Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use crate::dry_run;
+use crate::overlay;
+
+// Where the tools read and write files. The mode picks one for the session:
+// the disk, the disk read-only for --dry-run, or an overlay on the disk for
+// --patch-output; an --isolate worktree is the disk under another root.
+// Tests can put an in-memory tree in place for the current thread.
+pub trait Vfs: Send + Sync {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+    // Fails with ReadOnlyFilesystem where nothing may be written
+    fn write(&self, path: &Path, bytes: &[u8]) -> io::Result<()>;
+    fn is_file(&self, path: &Path) -> bool;
+    // Whether the file is read from disk as it is, so what file_cache keeps
+    // by its size and modification time holds
+    fn on_disk(&self, path: &Path) -> bool;
+
+    fn len(&self, path: &Path) -> io::Result<u64> {
+        self.read(path).map(|bytes| bytes.len() as u64)
+    }
+}
+
+pub struct RealFs;
+
+impl Vfs for RealFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    // Creates the directory if needed. A symlink is kept and the file it
+    // points at rewritten.
+    fn write(&self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        replace_file(&target, bytes)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn on_disk(&self, _path: &Path) -> bool {
+        true
+    }
+
+    fn len(&self, path: &Path) -> io::Result<u64> {
+        fs::metadata(path).map(|m| m.len())
+    }
+}
+
+// The disk with writes refused, for --dry-run
+pub struct ReadOnlyFs;
+
+impl Vfs for ReadOnlyFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(&self, _path: &Path, _bytes: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::ReadOnlyFilesystem, "nothing is written in a dry run"))
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn on_disk(&self, _path: &Path) -> bool {
+        true
+    }
+
+    fn len(&self, path: &Path) -> io::Result<u64> {
+        fs::metadata(path).map(|m| m.len())
+    }
+}
+
+// Files held in memory by absolute path, with nothing underneath
+#[derive(Default)]
+pub struct MemoryFs {
+    files: Mutex<BTreeMap<PathBuf, Vec<u8>>>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Every file, in path order
+    pub fn files(&self) -> Vec<(PathBuf, Vec<u8>)> {
+        self.lock().iter().map(|(path, bytes)| (path.clone(), bytes.clone())).collect()
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.lock().contains_key(&key(path))
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<PathBuf, Vec<u8>>> {
+        self.files.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Vfs for MemoryFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.lock()
+            .get(&key(path))
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file"))
+    }
+
+    fn write(&self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        self.lock().insert(key(path), bytes.to_vec());
+        Ok(())
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.contains(path)
+    }
+
+    fn on_disk(&self, _path: &Path) -> bool {
+        false
+    }
+}
+
+// Writes kept in memory over another file system: the disk for
+// --patch-output, or the session's files to try an edit out
+pub struct OverlayFs {
+    pub changes: MemoryFs,
+    base: Arc<dyn Vfs>,
+}
+
+impl OverlayFs {
+    pub fn over(base: Arc<dyn Vfs>) -> Self {
+        Self {
+            changes: MemoryFs::new(),
+            base,
+        }
+    }
+}
+
+impl Default for OverlayFs {
+    fn default() -> Self {
+        Self::over(REAL.clone())
+    }
+}
+
+impl Vfs for OverlayFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.changes.read(path).or_else(|_| self.base.read(path))
+    }
+
+    fn write(&self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        self.changes.write(path, bytes)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.changes.contains(path) || self.base.is_file(path)
+    }
+
+    fn on_disk(&self, path: &Path) -> bool {
+        !self.changes.contains(path) && self.base.on_disk(path)
+    }
+}
+
+static REAL: LazyLock<Arc<dyn Vfs>> = LazyLock::new(|| Arc::new(RealFs));
+static READ_ONLY: LazyLock<Arc<dyn Vfs>> = LazyLock::new(|| Arc::new(ReadOnlyFs));
+
+thread_local! {
+    static INSTALLED: RefCell<Option<Arc<dyn Vfs>>> = const { RefCell::new(None) };
+}
+
+// The file system for this thread's tool calls
+pub fn current() -> Arc<dyn Vfs> {
+    if let Some(fs) = INSTALLED.with(|installed| installed.borrow().clone()) {
+        return fs;
+    }
+    if dry_run::enabled() {
+        READ_ONLY.clone()
+    } else if overlay::enabled() {
+        overlay::fs()
+    } else {
+        REAL.clone()
+    }
+}
+
+// Puts fs in place for this thread's tool calls until the guard is dropped
+pub fn install(fs: Arc<dyn Vfs>) -> Installed {
+    let previous = INSTALLED.with(|installed| installed.replace(Some(fs)));
+    Installed { previous }
+}
+
+pub struct Installed {
+    previous: Option<Arc<dyn Vfs>>,
+}
+
+impl Drop for Installed {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        INSTALLED.with(|installed| *installed.borrow_mut() = previous);
+    }
+}
+
+// The absolute path without . and .. components, so one file always has one
+// key whether or not it exists yet
+pub fn key(path: &Path) -> PathBuf {
+    let absolute = path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut key = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                key.pop();
+            }
+            component => key.push(component),
+        }
+    }
+    key
+}
+
+// Writes through a temporary file beside the original, renamed over it once
+// complete, so an interrupted write never leaves half a file. The new file
+// gets the old one's permissions (the executable bit) and, where allowed,
+// its owner.
+fn replace_file(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let Ok(metadata) = fs::metadata(path) else {
+        return fs::write(path, bytes);
+    };
+    // Renaming would get round a read-only file
+    if metadata.permissions().readonly() {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the file is read-only"));
+    }
+    let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let temp = path.with_file_name(format!(".{}.botcoder-{}", name, std::process::id()));
+    let result = fs::write(&temp, bytes)
+        .and_then(|()| fs::set_permissions(&temp, metadata.permissions()))
+        .and_then(|()| {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                // Only root can give a file away; others keep their own
+                let _ = std::os::unix::fs::chown(&temp, Some(metadata.uid()), Some(metadata.gid()));
+            }
+            fs::rename(&temp, path)
+        });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
@@ -0,0 +1,231 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{env, fs, io, path::Path, time::Duration};
+
+use crate::events;
+use crate::exit;
+use crate::overlay;
+
+// Saved transcripts of runs that fired a webhook, one Markdown file per
+// session
+const TRANSCRIPT_DIR: &str = ".botcoder/transcripts";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// How a run ended
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunOutcome {
+    Succeeded,
+    Failed,
+    // Reached the iteration limit without succeeding
+    BudgetExceeded,
+    // The model stopped calling tools without succeeding and waits for a
+    // human to reply
+    InputNeeded,
+}
+
+impl RunOutcome {
+    pub fn name(self) -> &'static str {
+        match self {
+            RunOutcome::Succeeded => "succeeded",
+            RunOutcome::Failed => "failed",
+            RunOutcome::BudgetExceeded => "exceeded its iteration budget",
+            RunOutcome::InputNeeded => "needs input",
+        }
+    }
+
+    pub fn exit_code(self) -> i32 {
+        match self {
+            RunOutcome::Succeeded => exit::SUCCESS,
+            RunOutcome::Failed => exit::ERROR,
+            RunOutcome::BudgetExceeded => exit::BUDGET_EXCEEDED,
+            RunOutcome::InputNeeded => exit::GAVE_UP,
+        }
+    }
+}
+
+// Written after every headless run, unless --result points elsewhere
+pub const RESULT_FILE: &str = ".botcoder/result.json";
+
+// What counted as success and whether the run got there
+#[derive(Serialize)]
+pub struct SuccessCheck {
+    pub criteria: String,
+    pub met: bool,
+}
+
+#[derive(Serialize)]
+pub struct RunSummary {
+    pub outcome: RunOutcome,
+    pub session: String,
+    pub task: String,
+    pub iterations: u32,
+    pub tokens: u32,
+    // Only known with BOTCODER_COST_PER_1K_TOKENS set
+    pub cost: Option<f64>,
+    pub error: Option<String>,
+    // Path of the saved transcript
+    pub transcript: Option<String>,
+    // The process exit status of a headless run
+    pub exit_code: i32,
+    pub files_changed: Vec<String>,
+    pub success: Option<SuccessCheck>,
+}
+
+impl RunSummary {
+    pub fn new(outcome: RunOutcome, session: impl Into<String>, task: impl Into<String>) -> Self {
+        Self {
+            outcome,
+            session: session.into(),
+            task: task.into(),
+            iterations: 0,
+            tokens: 0,
+            cost: None,
+            error: None,
+            transcript: None,
+            exit_code: outcome.exit_code(),
+            files_changed: Vec::new(),
+            success: None,
+        }
+    }
+
+    pub fn with_usage(mut self, iterations: u32, tokens: u32) -> Self {
+        self.iterations = iterations;
+        self.tokens = tokens;
+        self.cost = cost(tokens);
+        self
+    }
+
+    pub fn with_error(mut self, error: impl Into<String>) -> Self {
+        self.error = Some(error.into());
+        self
+    }
+
+    pub fn with_exit_code(mut self, exit_code: i32) -> Self {
+        self.exit_code = exit_code;
+        self
+    }
+
+    pub fn with_files(mut self, files: &[String]) -> Self {
+        self.files_changed = files.to_vec();
+        self
+    }
+
+    pub fn with_success_check(mut self, criteria: impl Into<String>, met: bool) -> Self {
+        self.success = Some(SuccessCheck {
+            criteria: criteria.into(),
+            met,
+        });
+        self
+    }
+
+    // The summary as pretty JSON, as CI reads it
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(path, json)
+    }
+
+    // Chat-style text for Slack and Discord
+    pub fn message(&self) -> String {
+        let mut message = format!(
+            "botcoder session {} {} after {} iteration(s), {} tokens",
+            self.session,
+            self.outcome.name(),
+            self.iterations,
+            self.tokens
+        );
+        if let Some(cost) = self.cost {
+            message.push_str(&format!(" (${:.2})", cost));
+        }
+        message.push_str(&format!("\nTask: {}", self.task));
+        if let Some(error) = &self.error {
+            message.push_str(&format!("\nError: {}", error));
+        }
+        if let Some(transcript) = &self.transcript {
+            message.push_str(&format!("\nTranscript: {}", transcript));
+        }
+        message
+    }
+}
+
+// BOTCODER_WEBHOOKS: comma-separated URLs POSTed to when a headless run
+// ends. Slack and Discord webhook URLs get a chat message, any other URL the
+// RunSummary as JSON.
+#[derive(Default)]
+pub struct Webhooks {
+    urls: Vec<String>,
+    client: reqwest::Client,
+}
+
+impl Webhooks {
+    pub fn from_env() -> Self {
+        let urls = env::var("BOTCODER_WEBHOOKS")
+            .map(|v| {
+                v.split(',')
+                    .map(|url| url.trim().to_string())
+                    .filter(|url| !url.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            urls,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.urls.is_empty()
+    }
+
+    // Failures are logged; a webhook being down never fails the run
+    pub async fn notify(&self, summary: &RunSummary) {
+        for url in &self.urls {
+            let result = self
+                .client
+                .post(url)
+                .timeout(TIMEOUT)
+                .json(&payload(url, summary))
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                log::warn!("Webhook {} failed: {}", url, e);
+            }
+        }
+    }
+}
+
+fn payload(url: &str, summary: &RunSummary) -> Value {
+    if url.contains("hooks.slack.com") {
+        json!({ "text": summary.message() })
+    } else if url.contains("discord.com/api/webhooks") || url.contains("discordapp.com/api/webhooks") {
+        json!({ "content": summary.message() })
+    } else {
+        serde_json::to_value(summary).unwrap_or_default()
+    }
+}
+
+// Writes the transcript to .botcoder/transcripts/<session>.md and returns
+// its path
+pub fn save_transcript(
+    project_root: &str,
+    session: &str,
+    transcript: &[(String, String)],
+) -> Option<String> {
+    let dir = overlay::state_path(project_root, TRANSCRIPT_DIR);
+    fs::create_dir_all(&dir).ok()?;
+    let path = dir.join(format!("{}.md", session));
+    fs::write(&path, events::transcript_markdown(transcript)).ok()?;
+    Some(path.to_string_lossy().into_owned())
+}
+
+// Price of the tokens at BOTCODER_COST_PER_1K_TOKENS, when it is set
+pub fn cost(tokens: u32) -> Option<f64> {
+    env::var("BOTCODER_COST_PER_1K_TOKENS")
+        .ok()
+        .and_then(|v| v.parse::<f64>().ok())
+        .map(|price| price * f64::from(tokens) / 1000.0)
+}
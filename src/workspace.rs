@@ -0,0 +1,208 @@
+use std::{
+    env, fs,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::error::ToolError;
+use crate::file_tree;
+
+// Files listed per project in the workspace map
+const MAP_FILES: usize = 40;
+
+pub struct Project {
+    pub name: String,
+    pub root: String,
+}
+
+// Several project roots the agent can move between with switch_project:
+// the members of a Cargo workspace (--workspace) and any roots listed in
+// BOTCODER_PROJECTS, comma-separated
+pub struct Workspace {
+    pub projects: Vec<Project>,
+}
+
+impl Workspace {
+    // None unless --workspace is given or BOTCODER_PROJECTS is set
+    pub fn load(root: &str, cargo_members: bool) -> Option<Self> {
+        let mut projects = Vec::new();
+        if cargo_members {
+            projects.push(Project {
+                name: package_name(Path::new(root)).unwrap_or_else(|| "root".to_string()),
+                root: root.to_string(),
+            });
+            projects.extend(cargo_workspace_members(root));
+        }
+
+        for extra in env::var("BOTCODER_PROJECTS").unwrap_or_default().split(',') {
+            let extra = extra.trim();
+            if extra.is_empty() || projects.iter().any(|p| p.root == extra) {
+                continue;
+            }
+            let path = Path::new(extra);
+            let name = package_name(path).unwrap_or_else(|| dir_name(path));
+            projects.push(Project {
+                name,
+                root: extra.to_string(),
+            });
+        }
+
+        if projects.is_empty() {
+            return None;
+        }
+        if !projects.iter().any(|p| p.root == root) {
+            projects.insert(
+                0,
+                Project {
+                    name: dir_name(Path::new(root)),
+                    root: root.to_string(),
+                },
+            );
+        }
+        Some(Self { projects })
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Project> {
+        let name = name.trim();
+        self.projects
+            .iter()
+            .find(|p| p.name == name)
+            .or_else(|| self.projects.iter().find(|p| p.root == name))
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.projects.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    // Added to the system prompt: every project with its root and the
+    // start of its file list
+    pub fn map(&self, active: &str) -> String {
+        let mut out = String::from(
+            "WORKSPACE\nThis workspace has several projects. Paths are relative to the active \
+             project; use switch_project(\"name\") to work in another one.\n",
+        );
+        for project in &self.projects {
+            let marker = if project.root == active { " (active)" } else { "" };
+            out.push_str(&format!("\n{}{} at {}\n", project.name, marker, project.root));
+
+            let files: Vec<String> = file_tree::scan(&project.root)
+                .into_iter()
+                .filter(|entry| !entry.is_dir)
+                .map(|entry| entry.path)
+                .collect();
+            for file in files.iter().take(MAP_FILES) {
+                out.push_str(&format!("  {}\n", file));
+            }
+            if files.len() > MAP_FILES {
+                out.push_str(&format!("  ... {} more files\n", files.len() - MAP_FILES));
+            }
+        }
+        out
+    }
+}
+
+// Refuses paths that lead outside the project root, e.g. "../other", an
+// absolute path elsewhere, or a symlink pointing out of the project
+pub fn refuse_outside(root: &str, path: &str) -> Result<(), ToolError> {
+    let outside = || ToolError::OutsideProject(path.to_string());
+    let root = Path::new(root);
+    let full = Path::new(path.trim());
+    let relative = if full.is_absolute() {
+        full.strip_prefix(root).map_err(|_| outside())?
+    } else {
+        full
+    };
+
+    let mut depth = 0usize;
+    for component in relative.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::ParentDir => depth = depth.checked_sub(1).ok_or_else(outside)?,
+            Component::CurDir => {}
+            Component::RootDir | Component::Prefix(_) => return Err(outside()),
+        }
+    }
+
+    // Symlinks are resolved: the deepest part of the path that exists must
+    // end up inside the root. A dangling link could create a file anywhere.
+    let Ok(root) = root.canonicalize() else {
+        return Ok(());
+    };
+    let mut existing = root.join(relative);
+    loop {
+        match existing.canonicalize() {
+            Ok(resolved) if resolved.starts_with(&root) => return Ok(()),
+            Ok(_) => return Err(outside()),
+            Err(_) if existing.symlink_metadata().is_ok() => return Err(outside()),
+            Err(_) => {}
+        }
+        if !existing.pop() {
+            return Ok(());
+        }
+    }
+}
+
+// Members listed in [workspace] members of the root Cargo.toml; "dir/*"
+// entries match every subdirectory with a Cargo.toml
+fn cargo_workspace_members(root: &str) -> Vec<Project> {
+    let Some(manifest) = read_manifest(Path::new(root)) else {
+        return Vec::new();
+    };
+    let list = |key: &str| -> Vec<String> {
+        manifest
+            .get("workspace")
+            .and_then(|w| w.get(key))
+            .and_then(|v| v.as_array())
+            .map(|a| a.iter().filter_map(|v| v.as_str().map(str::to_string)).collect())
+            .unwrap_or_default()
+    };
+    let exclude = list("exclude");
+
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    for member in list("members") {
+        match member.strip_suffix("/*") {
+            Some(parent) => {
+                let Ok(entries) = fs::read_dir(Path::new(root).join(parent)) else {
+                    continue;
+                };
+                let mut found: Vec<PathBuf> = entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| path.join("Cargo.toml").is_file())
+                    .collect();
+                found.sort();
+                dirs.extend(found);
+            }
+            None => dirs.push(Path::new(root).join(&member)),
+        }
+    }
+
+    dirs.into_iter()
+        .filter(|dir| {
+            let relative = dir.strip_prefix(root).unwrap_or(dir);
+            !exclude.iter().any(|e| relative == Path::new(e))
+        })
+        .map(|dir| Project {
+            name: package_name(&dir).unwrap_or_else(|| dir_name(&dir)),
+            root: dir.to_string_lossy().into_owned(),
+        })
+        .collect()
+}
+
+fn read_manifest(dir: &Path) -> Option<toml::Table> {
+    fs::read_to_string(dir.join("Cargo.toml")).ok()?.parse().ok()
+}
+
+fn package_name(dir: &Path) -> Option<String> {
+    read_manifest(dir)?
+        .get("package")?
+        .get("name")?
+        .as_str()
+        .map(str::to_string)
+}
+
+fn dir_name(path: &Path) -> String {
+    path.canonicalize()
+        .ok()
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| path.display().to_string())
+}
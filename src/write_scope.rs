@@ -0,0 +1,173 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::Deserialize;
+use std::{
+    env,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::app;
+use crate::config;
+use crate::error::ToolError;
+use crate::file_tree;
+use crate::overlay;
+
+const DEFAULT_MAX_FILE_MB: u64 = 5;
+const DEFAULT_MAX_WRITTEN_MB: u64 = 50;
+const MB: u64 = 1024 * 1024;
+
+// --read-only: every tool that writes to the project is turned off, for
+// analysis-only sessions
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+pub fn set_read_only(enabled: bool) {
+    READ_ONLY.store(enabled, Ordering::Relaxed);
+}
+
+pub fn read_only() -> bool {
+    READ_ONLY.load(Ordering::Relaxed)
+}
+
+// Paths the agent may read but never modify, as gitignore-style globs:
+//
+//   [write]
+//   read_only = ["Cargo.lock", "migrations/**", ".github/**"]
+pub struct WriteScope {
+    protected: Gitignore,
+    read_only: bool,
+}
+
+#[derive(Default, Deserialize)]
+struct Rules {
+    #[serde(default)]
+    read_only: Vec<String>,
+}
+
+impl WriteScope {
+    // No protected paths without a .botcoder.toml; an invalid one is skipped
+    // with a warning
+    pub fn load(root: &str) -> Self {
+        let rules = config::section::<Rules>(root, "write").read_only;
+        let mut builder = GitignoreBuilder::new(root);
+        for rule in &rules {
+            if let Err(e) = builder.add_line(None, rule) {
+                log::warn!("Skipping read-only path {:?}: {}", rule, e);
+            }
+        }
+        Self {
+            protected: builder.build().unwrap_or_else(|_| Gitignore::empty()),
+            read_only: read_only(),
+        }
+    }
+
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    // path is relative to the project root
+    pub fn is_protected(&self, path: &str) -> bool {
+        let path = file_tree::clean(path);
+        let path = Path::new(&path);
+        let path = match path.strip_prefix(self.protected.path()) {
+            Ok(relative) => relative,
+            Err(_) if path.has_root() => return false,
+            Err(_) => path,
+        };
+        self.protected
+            .matched_path_or_any_parents(path, false)
+            .is_ignore()
+    }
+
+    // Fails a call that would write to a protected path, or any writing call
+    // in a read-only session
+    pub fn refuse(&self, tool: &str, param: &str) -> Result<(), ToolError> {
+        if overlay::enabled() && overlay::runs_commands(tool) {
+            return Err(ToolError::CommandsOff(tool.to_string()));
+        }
+        if !is_write_tool(tool, param) {
+            return Ok(());
+        }
+        if self.read_only {
+            return Err(ToolError::ReadOnlySession(tool.to_string()));
+        }
+        // Only edits go to the overlay; the rest would change the tree
+        if overlay::enabled() && !app::is_edit_tool(tool) && tool != "rename_symbol" {
+            return Err(ToolError::PatchOnly(tool.to_string()));
+        }
+        let paths: Vec<&str> = match tool {
+            // They rewrite the manifest and the lockfile
+            "cargo_add" | "cargo_remove" => vec!["Cargo.toml", "Cargo.lock"],
+            _ => app::tool_path(tool, param).into_iter().collect(),
+        };
+        match paths.into_iter().find(|path| self.is_protected(path)) {
+            Some(path) => Err(ToolError::ReadOnly(path.trim().to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+// Tools that change the project. Shell commands and project tools can too,
+// but what they touch isn't known up front.
+pub fn is_write_tool(tool: &str, param: &str) -> bool {
+    app::is_edit_tool(tool)
+        || matches!(tool, "git_commit" | "cargo_add" | "cargo_remove" | "rename_symbol")
+        || (tool == "cargo_fmt" && param.trim() != "check")
+}
+
+// How much an agent may write before a person has to confirm: the size of
+// any one file after an edit, BOTCODER_MAX_FILE_MB (default 5), and the
+// bytes written over the session, BOTCODER_MAX_WRITTEN_MB (default 50).
+// 0 turns a limit off.
+#[derive(Clone, Copy, Debug)]
+pub struct WriteLimits {
+    pub file_bytes: u64,
+    pub session_bytes: u64,
+}
+
+impl WriteLimits {
+    pub fn from_env() -> Self {
+        let var = |name: &str, default: u64| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+                * MB
+        };
+        Self {
+            file_bytes: var("BOTCODER_MAX_FILE_MB", DEFAULT_MAX_FILE_MB),
+            session_bytes: var("BOTCODER_MAX_WRITTEN_MB", DEFAULT_MAX_WRITTEN_MB),
+        }
+    }
+
+    // Why an edit leaving a file of `size` bytes, after `written` bytes so
+    // far this session, needs confirming
+    pub fn exceeded(&self, path: &str, size: u64, written: u64) -> Option<String> {
+        if self.file_bytes > 0 && size > self.file_bytes {
+            return Some(format!(
+                "{} would grow to {}, over the {} limit for one file",
+                path,
+                human_size(size),
+                human_size(self.file_bytes)
+            ));
+        }
+        if self.session_bytes > 0 && written + size > self.session_bytes {
+            return Some(format!(
+                "writing {} ({}) would take this session to {}, over its {} limit",
+                path,
+                human_size(size),
+                human_size(written + size),
+                human_size(self.session_bytes)
+            ));
+        }
+        None
+    }
+}
+
+pub fn human_size(bytes: u64) -> String {
+    match bytes {
+        b if b >= MB => format!("{:.1} MB", b as f64 / MB as f64),
+        b if b >= 1024 => format!("{:.1} KB", b as f64 / 1024.0),
+        b => format!("{} bytes", b),
+    }
+}
@@ -0,0 +1,53 @@
+// Shared by the integration tests: a scratch project, an engine on a mock
+// model and git with a fixed identity. Not every test binary uses them all.
+#![allow(dead_code)]
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+use botcoder::mock::MockProvider;
+use botcoder::parser::{ResponseParser, ToolFormat};
+use botcoder::router::ModelRouter;
+use botcoder::tpm_limiter::TPMLimiter;
+use botcoder::AgentEngine;
+
+// A fresh directory holding notes.txt, "hello"
+pub fn project(name: &str) -> PathBuf {
+    let root = env::temp_dir().join(format!("botcoder-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    fs::write(root.join("notes.txt"), "hello\n").unwrap();
+    root
+}
+
+pub fn engine(mock: &MockProvider, root: &Path) -> AgentEngine {
+    let parser = ResponseParser {
+        format: ToolFormat::Text,
+        max_retries: 2,
+    };
+    AgentEngine::new(
+        ModelRouter::single(mock.clone()),
+        "You are a test assistant.",
+        root.to_string_lossy(),
+        TPMLimiter::new(1_000_000, 0),
+        parser,
+    )
+}
+
+pub fn git(dir: &Path, args: &[&str]) -> Output {
+    Command::new("git").args(args).current_dir(dir).output().unwrap()
+}
+
+// A repository with notes.txt committed. The identity goes in its own
+// config, so commits made by the engine, in it or its worktrees, work
+// without one in the environment.
+pub fn init_repo(root: &Path) {
+    git(root, &["init", "-q"]);
+    git(root, &["config", "user.name", "test"]);
+    git(root, &["config", "user.email", "test@example.com"]);
+    git(root, &["add", "notes.txt"]);
+    git(root, &["commit", "-q", "-m", "init"]);
+}
@@ -0,0 +1,38 @@
+// The deterministic switch is process-wide, so its test runs in a test
+// binary of its own
+use botcoder::deterministic;
+use botcoder::events::{AgentEvent, AgentObserver, SessionRecorder};
+use botcoder::llm::{ChatCompletionRequest, ModelOptions};
+use botcoder::router::Role;
+
+#[test]
+fn deterministic_runs_fix_sampling_and_log_fingerprints() {
+    let options = ModelOptions::default();
+    deterministic::set_enabled(true);
+    let request = ChatCompletionRequest::new(Vec::new(), "gpt-4o", &options, 0.7, Some(100));
+    deterministic::set_enabled(false);
+    assert_eq!(request.temperature, Some(0.0));
+    assert_eq!(request.seed, Some(42));
+    let request = ChatCompletionRequest::new(Vec::new(), "gpt-4o", &options, 0.7, Some(100));
+    assert_eq!((request.temperature, request.seed), (Some(0.7), None));
+
+    let mut session = SessionRecorder::default();
+    for fingerprint in ["fp_1", "fp_1", "fp_2"] {
+        session.on_event(&AgentEvent::LlmResponse {
+            role: Role::Planner,
+            model: "gpt-4o".to_string(),
+            text: "Done.".to_string(),
+            reasoning: None,
+            fingerprint: Some(fingerprint.to_string()),
+            fallbacks: Vec::new(),
+            output_tokens: 1,
+        });
+    }
+    let models: Vec<String> = session
+        .transcript()
+        .into_iter()
+        .filter(|(role, _)| role == "model")
+        .map(|(_, content)| content)
+        .collect();
+    assert_eq!(models, ["gpt-4o (fingerprint fp_1)", "gpt-4o (fingerprint fp_2)"]);
+}
@@ -0,0 +1,468 @@
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+};
+
+use botcoder::app;
+use botcoder::context::ContextBudget;
+use botcoder::error_context;
+use botcoder::events::AgentEvent;
+use botcoder::failures;
+use botcoder::llm::{self, ChatCompletionRequest, ChatMessage};
+use botcoder::mock::MockProvider;
+use botcoder::plan::SubtaskBudget;
+
+mod common;
+use common::{engine, project};
+
+#[tokio::test]
+async fn plan_mode_anchors_requests_to_the_current_step() {
+    let root = project("plan");
+    let mock = MockProvider::new([
+        "Here is the plan:\n1. Read notes.txt\n2) **Greet the world in notes.txt**\n\nDone.",
+        "read_file(\"notes.txt\")\nstep_done(\"read it\")",
+        "step_done(\"greeted\")",
+    ]);
+    let mut engine = engine(&mock, &root);
+
+    let plan = engine.draft_plan("Greet the world").await.unwrap();
+    let steps: Vec<&str> = plan.steps.iter().map(|s| s.text.as_str()).collect();
+    assert_eq!(steps, ["Read notes.txt", "Greet the world in notes.txt"]);
+
+    engine.set_plan(Some(plan));
+    engine.push_user("Greet the world");
+    engine.step().await.unwrap();
+    assert!(mock.requests()[1][0]
+        .content
+        .contains("You are on step 1 of 2: Read notes.txt"));
+    let (_, _, result) = engine.executed_tools().last().unwrap();
+    assert!(result.contains("Now step 2 of 2"), "{}", result);
+
+    engine.step().await.unwrap();
+    assert!(mock.requests()[2][0].content.contains("You are on step 2 of 2"));
+    assert_eq!(engine.plan().unwrap().current(), None);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn plan_steps_run_as_nested_subtasks() {
+    let root = project("subtasks");
+    let mock = MockProvider::new([
+        "1. Read notes.txt\n2. Greet the world in notes.txt",
+        "read_file(\"notes.txt\")\nstep_done(\"notes.txt says hello\")",
+        "step_done(\"greeted\")",
+    ]);
+    let budget = SubtaskBudget {
+        iterations: 5,
+        tokens: 1_000_000,
+    };
+    let mut engine = engine(&mock, &root).with_subtasks(budget);
+
+    let plan = engine.draft_plan("Greet the world").await.unwrap();
+    engine.set_plan(Some(plan));
+    engine.push_user("Greet the world");
+    engine.step().await.unwrap();
+    engine.step().await.unwrap();
+
+    // The second step starts from a brief, not from the first step's reads
+    let second = mock.requests()[2].clone();
+    let brief = &second.last().unwrap().content;
+    assert!(brief.contains("1. Read notes.txt: notes.txt says hello"), "{}", brief);
+    assert!(!second.iter().any(|m| m.content.contains("Tool Results")));
+
+    // Both steps are rolled up into the main session
+    let history: Vec<&str> = engine.history().iter().map(|m| m.content.as_str()).collect();
+    assert_eq!(history[0], "Greet the world");
+    assert!(history[1].starts_with("Sub-task 1 (Read notes.txt) finished: notes.txt says hello"));
+    assert!(history[2].starts_with("Sub-task 2 (Greet the world in notes.txt) finished: greeted"));
+    assert_eq!(history.len(), 3);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn repeated_reads_are_not_run_again() {
+    let root = project("dedup");
+    let read = "read_file(\"notes.txt\")";
+    let mock = MockProvider::new([
+        format!("{}\n{}\n{}", read, read, read),
+        read.to_string(),
+        read.to_string(),
+    ]);
+    let mut engine = engine(&mock, &root);
+    engine.push_user("Read notes.txt");
+
+    // Identical calls in one reply run once
+    engine.step().await.unwrap();
+    assert_eq!(engine.executed_tools().len(), 1);
+    assert_eq!(engine.executed_tools()[0].2, "hello\n");
+
+    engine.step().await.unwrap();
+    let (_, _, result) = &engine.executed_tools()[0];
+    assert!(result.starts_with("cached: notes.txt"), "{}", result);
+
+    // A change on disk makes the next read a real one
+    fs::write(root.join("notes.txt"), "hello again\n").unwrap();
+    engine.step().await.unwrap();
+    assert_eq!(engine.executed_tools()[0].2, "hello again\n");
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn reads_summarized_out_of_the_prompt_are_run_again() {
+    let root = project("dedup-summarized");
+    let read = "read_file(\"notes.txt\")";
+    // Longer than the mock's whole context window
+    let long = format!("{}{}", "word ".repeat(150_000), read);
+    let mock = MockProvider::new([read.to_string(), long, read.to_string()]);
+    let mut engine = engine(&mock, &root);
+    engine.push_user("Read notes.txt");
+
+    engine.step().await.unwrap();
+    engine.step().await.unwrap();
+    let (_, _, result) = &engine.executed_tools()[0];
+    assert!(result.starts_with("cached: notes.txt"), "{}", result);
+
+    // The first read's result only reached the model as a summary line
+    engine.step().await.unwrap();
+    let sent = &mock.requests()[2];
+    assert!(sent.iter().any(|m| m.content.starts_with("Earlier in this session")));
+    assert_eq!(engine.executed_tools()[0].2, "hello\n");
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn context_is_fitted_to_the_model_window() {
+    assert_eq!(ContextBudget::for_model("gpt-4o-mini").max_tokens, 128_000);
+    assert_eq!(ContextBudget::for_model("gpt-4").max_tokens, 8_192);
+
+    let budget = ContextBudget {
+        max_tokens: 3_000,
+        reply_tokens: 500,
+    };
+    let mut messages = vec![ChatMessage::system("You are a test assistant.")];
+    for i in 0..20 {
+        messages.push(ChatMessage::user(format!("turn {}\n{}", i, "word ".repeat(150))));
+    }
+    let pinned = [("src/lib.rs".to_string(), "pub fn answer() -> u32 { 42 }".to_string())];
+    let fitted = budget.fit(messages, &pinned);
+
+    let tokens: u32 = fitted.iter().map(|m| app::count_tokens(&m.content)).sum();
+    assert!(tokens <= budget.available(), "{} tokens", tokens);
+    assert!(fitted[0].content.contains("== src/lib.rs ==\npub fn answer()"));
+    assert!(fitted[1].content.starts_with("Earlier in this session"));
+    assert!(fitted[1].content.contains("- user: turn 0"));
+    assert!(fitted.last().unwrap().content.starts_with("turn 19"));
+}
+
+#[tokio::test]
+async fn reasoning_is_kept_out_of_history() {
+    let (reasoning, answer) = app::split_reasoning("<thinking>a\n</thinking>Hi<think>b");
+    assert_eq!(reasoning.as_deref(), Some("a\n\nb"));
+    assert_eq!(answer, "Hi");
+
+    let request = ChatCompletionRequest {
+        messages: Vec::new(),
+        temperature: None,
+        max_tokens: None,
+        max_completion_tokens: Some(25_000),
+        reasoning_effort: Some("low".to_string()),
+        top_p: None,
+        frequency_penalty: None,
+        presence_penalty: None,
+        seed: None,
+        model: "o3-mini".to_string(),
+    };
+    let json = serde_json::to_string(&request).unwrap();
+    assert!(!json.contains("temperature") && !json.contains("\"max_tokens\""), "{}", json);
+    assert!(llm::is_reasoning_model("o3-mini") && !llm::is_reasoning_model("gpt-4o"));
+
+    let root = project("reasoning");
+    let mock = MockProvider::new([
+        "<think>The notes are in notes.txt</think>\nread_file(\"notes.txt\")",
+        "Done.",
+    ]);
+    let mut engine = engine(&mock, &root);
+    engine.push_user("Read notes.txt");
+    engine.step().await.unwrap();
+    engine.step().await.unwrap();
+
+    assert_eq!(engine.history()[1].content, "read_file(\"notes.txt\")");
+    assert!(!mock.requests()[1].iter().any(|m| m.content.contains("The notes are")));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn images_go_with_the_next_user_message() {
+    let root = project("image");
+    fs::write(root.join("mockup.png"), b"\x89PNG\r\n\x1a\nfake").unwrap();
+    let mock = MockProvider::new(["Done."]);
+    let mut engine = engine(&mock, &root);
+
+    assert!(engine.attach_image("notes.txt").is_err());
+    engine.attach_image("mockup.png").unwrap();
+    engine.push_user("Make it look like this");
+    engine.step().await.unwrap();
+
+    let message = mock.requests()[0].last().unwrap().clone();
+    assert_eq!(message.images.len(), 1);
+    let json = serde_json::to_value(&message).unwrap();
+    assert_eq!(json["content"][0]["text"], "Make it look like this");
+    let url = json["content"][1]["image_url"]["url"].as_str().unwrap();
+    assert_eq!(url, "data:image/png;base64,iVBORw0KGgpmYWtl");
+    assert!(engine.attached_images().is_empty());
+
+    // Without images the content stays a plain string
+    let json = serde_json::to_value(ChatMessage::user("hi")).unwrap();
+    assert_eq!(json["content"], "hi");
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn shell_passthrough_output_reaches_the_model() {
+    let root = project("passthrough");
+    fs::write(root.join(".botcoder.toml"), "[hooks.before]\nexecute_command = [\"exit 1\"]\n").unwrap();
+    let mock = MockProvider::new(["Done."]);
+    let mut engine = engine(&mock, &root);
+    assert!(engine.run_user_command("echo refused").is_err());
+
+    fs::remove_file(root.join(".botcoder.toml")).unwrap();
+    let output = engine.run_user_command("cat notes.txt").unwrap();
+    assert!(output.text.contains("hello"));
+    engine.push_user("Why does it say hello?");
+    engine.step().await.unwrap();
+
+    let sent = &mock.requests()[0];
+    assert!(sent.iter().any(|m| m.role == "system" && m.content.contains("The user ran `cat notes.txt`") && m.content.contains("hello")));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn independent_reads_run_side_by_side() {
+    let root = project("parallel-reads");
+    for name in ["a", "b", "c", "d"] {
+        fs::write(root.join(format!("{}.txt", name)), format!("{}\n", name)).unwrap();
+    }
+    // Each read takes 300ms through its before hook
+    fs::write(root.join(".botcoder.toml"), "[hooks.before]\nread_file = [\"sleep 0.3\"]\n").unwrap();
+    let mock = MockProvider::new(Vec::<&str>::new());
+    let mut engine = engine(&mock, &root);
+    let (tx, rx) = botcoder::events::channel();
+    engine.subscribe(tx);
+    for name in ["a", "b", "c", "d"] {
+        engine.queue_tool("read_file", format!("{}.txt", name));
+    }
+    engine.queue_tool("append_to_file", "a.txt:::more");
+    engine.queue_tool("read_file", "a.txt:::numbered");
+
+    let start = std::time::Instant::now();
+    assert_eq!(engine.execute_many().await, 6);
+    assert!(start.elapsed().as_millis() < 1200, "{:?}", start.elapsed());
+
+    // Results keep the order of the calls, and the read after the edit sees it
+    let results: Vec<(String, String)> = rx
+        .try_iter()
+        .filter_map(|event| match event {
+            AgentEvent::ToolExecuted { tool, result, .. } => Some((tool, result)),
+            _ => None,
+        })
+        .collect();
+    let tools: Vec<&str> = results.iter().map(|(tool, _)| tool.as_str()).collect();
+    assert_eq!(tools, ["read_file", "read_file", "read_file", "read_file", "append_to_file", "read_file"]);
+    for (i, name) in ["a", "b", "c", "d"].iter().enumerate() {
+        assert!(results[i].1.contains(&format!("{}\n", name)), "{}", results[i].1);
+    }
+    assert!(results[5].1.contains("more"), "{}", results[5].1);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn tools_can_run_on_a_worker_thread_while_their_output_streams() {
+    let root = project("worker-thread");
+    let mock = MockProvider::new(Vec::<&str>::new());
+    let mut engine = engine(&mock, &root);
+
+    engine.queue_tool("execute_command", "echo one; echo two >&2");
+    let (tool, param, project_root) = engine.next_call().unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    botcoder::interrupt::stream_output(Some(tx));
+    let worker = std::thread::spawn(move || app::execute_tool(&tool, &param, &project_root));
+    let output = worker.join().unwrap();
+    botcoder::interrupt::stream_output(None);
+    let lines: Vec<String> = rx.try_iter().collect();
+    assert!(lines.contains(&"one".to_string()) && lines.contains(&"two".to_string()), "{:?}", lines);
+
+    let (tool, _, result) = engine.complete_next(output).unwrap();
+    assert_eq!(tool, "execute_command");
+    assert!(result.contains("exit_code: 0"), "{}", result);
+    assert!(engine.pending_tools().is_empty());
+
+    // Calls the engine answers or handles itself stay with it
+    engine.queue_tool("git_commit", "msg");
+    assert!(engine.next_call().is_none());
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn conventions_files_join_the_system_prompt() {
+    let root = project("conventions");
+    fs::write(root.join("CONVENTIONS.md"), "# Style\nUse anyhow, not Box<dyn Error>.\n").unwrap();
+    fs::create_dir_all(root.join(".botcoder")).unwrap();
+    fs::write(root.join(".botcoder/rules.md"), "Never touch generated/.\n").unwrap();
+    let mock = MockProvider::new(["Noted."]);
+    let mut engine = engine(&mock, &root);
+    engine.push_user("Hi");
+    engine.step().await.unwrap();
+
+    let system = &mock.requests()[0][0].content;
+    assert!(system.contains("PROJECT CONVENTIONS"), "{}", system);
+    assert!(system.contains("== CONVENTIONS.md ==\n# Style\nUse anyhow, not Box<dyn Error>."));
+    assert!(system.contains("== .botcoder/rules.md ==\nNever touch generated/."));
+
+    // Cut to an eighth of a small context window
+    fs::write(root.join("CONVENTIONS.md"), "Keep functions short.\n".repeat(2000)).unwrap();
+    let budget = ContextBudget {
+        max_tokens: 8_000,
+        reply_tokens: 0,
+    };
+    let section = botcoder::conventions::prompt_section(root.to_str().unwrap(), &budget);
+    assert!(section.contains("[truncated to about 1000 tokens of"), "{}", section.len());
+    assert!(app::count_tokens(&section) < 1_100);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn build_errors_bring_their_source_into_the_prompt() {
+    let root = project("error-context");
+    fs::create_dir_all(root.join("src")).unwrap();
+    let source: String = (1..=60).map(|n| format!("let line{} = {};\n", n, n)).collect();
+    fs::write(root.join("src/main.rs"), &source).unwrap();
+    let stderr = "warning: unused variable\n --> src/main.rs:2:5\n\n\
+                  error[E0425]: cannot find value `x` in this scope\n  --> src/main.rs:30:9\n   |\n\
+                  note: defined here\n  --> /home/me/.cargo/registry/src/dep/lib.rs:4:1\n\n\
+                  error: could not compile `demo` (bin \"demo\") due to 1 previous error\n";
+    assert_eq!(
+        error_context::error_locations(stderr),
+        vec![
+            ("src/main.rs".to_string(), 30),
+            ("/home/me/.cargo/registry/src/dep/lib.rs".to_string(), 4)
+        ]
+    );
+    assert_eq!(
+        error_context::error_locations("src/main.rs:7:1: error[E0308]: mismatched types\nsrc/main.rs:9:1: warning: unused\n"),
+        vec![("src/main.rs".to_string(), 7)]
+    );
+
+    let mock = MockProvider::new(["Fixing it.", "Done."]);
+    let mut engine = engine(&mock, &root).with_error_context(3);
+    let failed = app::ToolOutput::process(String::new(), stderr.to_string(), 101);
+    engine.note_build_output("execute_command", "cargo build", &failed);
+    engine.push_user("Fix the build");
+    engine.step().await.unwrap();
+    let system = &mock.requests()[0][0].content;
+    assert!(system.contains("SOURCE AROUND THE COMPILER ERRORS"), "{}", system);
+    assert!(system.contains("== src/main.rs:27-33 ==\n27 | let line27 = 27;"), "{}", system);
+    assert!(!system.contains("line26 ") && !system.contains("line34 ") && !system.contains("registry"));
+
+    // A passing build clears it
+    let passed = app::ToolOutput::process("Finished".to_string(), String::new(), 0);
+    engine.note_build_output("execute_command", "cargo build", &passed);
+    engine.push_user("Anything else?");
+    engine.step().await.unwrap();
+    assert!(!mock.requests()[1][0].content.contains("COMPILER ERRORS"));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn repeated_failures_change_the_advice() {
+    let root = project("failures");
+    let mismatch = "CHANGE: notes.txt\n<<<<<<< CURRENT\ngoodbye\n=======\nhello, world\n>>>>>>> NEW";
+    let mock = MockProvider::new([
+        mismatch,
+        mismatch,
+        "execute_command(\"botcoder-missing-program --version\")",
+        "CHANGE: notes.txt\n<<<<<<< CURRENT\nhello\n=======\nhello, world\n>>>>>>> NEW",
+        "Done.",
+    ]);
+    let mut engine = engine(&mock, &root);
+    engine.push_user("Greet the world in notes.txt");
+    for _ in 0..5 {
+        engine.step().await.unwrap();
+    }
+    let systems: Vec<String> = mock.requests().iter().map(|r| r[0].content.clone()).collect();
+
+    // No advice before anything failed, a reminder after the first
+    // mismatch, a switch to line edits after the second
+    assert!(!systems[0].contains("AFTER THE LAST FAILURES"));
+    assert!(systems[1].contains("An edit to notes.txt didn't match"), "{}", systems[1]);
+    assert!(systems[2].contains("failed to match 2 times in a row"), "{}", systems[2]);
+    assert!(systems[2].contains("read_file(\"notes.txt\", numbered)") && systems[2].contains("EDIT_LINES"));
+    assert!(systems[3].contains("`botcoder-missing-program` isn't installed"), "{}", systems[3]);
+    assert!(!systems[3].contains("notes.txt"));
+    // A batch without failures brings no advice
+    assert!(!systems[4].contains("AFTER THE LAST FAILURES"), "{}", systems[4]);
+    assert_eq!(fs::read_to_string(root.join("notes.txt")).unwrap(), "hello, world\n");
+
+    assert_eq!(
+        failures::classify(
+            "run_tests",
+            "",
+            &Ok(app::ToolOutput::command("tests: passed=1 failed=1", 101))
+        ),
+        Some(failures::FailureKind::TestFailure)
+    );
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn ask_user_waits_for_an_answer() {
+    let root = project("ask-user");
+    let asked = "ask_user(\"Per user or global?\")\nread_file(\"notes.txt\")";
+
+    // A console run answers through the asker; what the model queued after
+    // its question doesn't run, and the answer follows as a user message
+    let questions = Arc::new(Mutex::new(Vec::new()));
+    let seen = questions.clone();
+    let mock = MockProvider::new([asked, "Global it is."]);
+    let mut console = engine(&mock, &root).with_asker(move |question| {
+        seen.lock().unwrap().push(question.to_string());
+        Some("Global".to_string())
+    });
+    console.push_user("Add a cache");
+    assert_eq!(console.step().await.unwrap(), Some(1));
+    assert_eq!(*questions.lock().unwrap(), vec!["Per user or global?"]);
+    let executed = console.executed_tools();
+    assert!(executed[0].0 == "ask_user" && executed[1].2.contains("Not run"), "{:?}", executed);
+    console.step().await.unwrap();
+    let second = &mock.requests()[1];
+    assert_eq!(second.last().unwrap().content, "Global");
+    assert!(second.iter().any(|m| m.content.contains("Asked the user")));
+
+    // The TUI answers it itself; without an answer the model is told to
+    // assume
+    let mock = MockProvider::new([asked, asked]);
+    let mut engine = engine(&mock, &root);
+    engine.push_user("Add a cache");
+    engine.request().await.unwrap();
+    assert_eq!(engine.pending_question(), Some("Per user or global?"));
+    assert!(engine.next_call().is_none());
+    engine.answer_question("Per user\n");
+    assert!(engine.pending_tools().is_empty());
+    engine.request().await.unwrap();
+    assert_eq!(mock.requests()[1].last().unwrap().content, "Per user");
+    let (_, _, result) = engine.execute_next().unwrap();
+    assert!(result.contains("make a reasonable assumption"), "{}", result);
+
+    fs::remove_dir_all(root).unwrap();
+}
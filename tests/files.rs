@@ -0,0 +1,411 @@
+use std::{env, fs, io, path::Path, sync::Arc};
+
+use botcoder::app;
+use botcoder::mock::MockProvider;
+use botcoder::vfs::{self, MemoryFs, Vfs};
+use botcoder::write_scope::{WriteLimits, WriteScope};
+use botcoder::ToolError;
+
+mod common;
+use common::{engine, project};
+
+#[tokio::test]
+async fn refuses_edit_of_externally_changed_file() {
+    let root = project("stale");
+    let mock = MockProvider::new([
+        "read_file(\"notes.txt\")",
+        "CHANGE: notes.txt\n<<<<<<< CURRENT\nhello\n=======\nhello, world\n>>>>>>> NEW",
+    ]);
+    let mut engine = engine(&mock, &root);
+
+    engine.push_user("Greet the world in notes.txt");
+    engine.step().await.unwrap();
+    fs::write(root.join("notes.txt"), "hello\nedited elsewhere\n").unwrap();
+    engine.step().await.unwrap();
+
+    let (_, _, result) = engine.executed_tools().last().unwrap();
+    assert!(result.contains("changed outside the agent"), "{}", result);
+    assert_eq!(
+        fs::read_to_string(root.join("notes.txt")).unwrap(),
+        "hello\nedited elsewhere\n"
+    );
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn utf16_files_stay_fresh_between_edits() {
+    let root = project("stale-utf16");
+    let utf16 = |text: &str| -> Vec<u8> {
+        [0xFF, 0xFE].into_iter().chain(text.encode_utf16().flat_map(u16::to_le_bytes)).collect()
+    };
+    fs::write(root.join("wide.txt"), utf16("one\r\n")).unwrap();
+    let mock = MockProvider::new(Vec::<&str>::new());
+    let mut engine = engine(&mock, &root);
+
+    // An outline between the edits caches the file without the engine
+    // noting it; the next edit must still match what it last wrote
+    for (old, new) in [("one", "two"), ("two", "three")] {
+        engine.queue_tool("outline", "wide.txt");
+        engine.execute_next().unwrap();
+        let delta = app::encode_delta("wide.txt", &[(old.into(), new.into())]);
+        engine.queue_tool("write_file_delta", delta);
+        let (_, _, result) = engine.execute_next().unwrap();
+        assert!(!result.contains("changed outside the agent"), "{}", result);
+    }
+    assert_eq!(fs::read(root.join("wide.txt")).unwrap(), utf16("three\r\n"));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn file_cache_is_dropped_on_writes() {
+    let root = project("file-cache");
+    let root_str = root.to_string_lossy();
+    let notes = root.join("notes.txt");
+    let read = || app::execute_tool("read_file", "notes.txt", &root_str).unwrap().text;
+    assert_eq!(read(), "hello\n");
+
+    // Same size and modification time: the cached content is used
+    let modified = fs::metadata(&notes).unwrap().modified().unwrap();
+    fs::write(&notes, "howdy\n").unwrap();
+    let file = fs::File::options().write(true).open(&notes).unwrap();
+    file.set_modified(modified).unwrap();
+    assert_eq!(read(), "hello\n");
+
+    // A write through the tools drops it
+    let delta = app::encode_delta("notes.txt", &[("howdy".into(), "hi".into())]);
+    app::execute_tool("write_file_delta", &delta, &root_str).unwrap();
+    assert_eq!(read(), "hi\n");
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn proposed_edits_can_be_rewritten_before_they_run() {
+    let root = project("edit-next");
+    let root_str = root.to_str().unwrap().to_string();
+    let mock = MockProvider::new(Vec::<&str>::new());
+    let mut engine = engine(&mock, &root);
+
+    // The proposal is worked out without touching the project
+    let proposed = botcoder::app::proposed_content("append_to_file", "notes.txt:::world", &root_str).unwrap();
+    assert_eq!(proposed, "hello\nworld\n");
+    assert_eq!(fs::read_to_string(root.join("notes.txt")).unwrap(), "hello\n");
+    assert!(botcoder::app::proposed_content("execute_command", "ls", &root_str).is_err());
+
+    // $EDITOR takes the file and hands back what was saved
+    let edited = botcoder::editor::edit_with("sed -i.bak s/world/there/", &proposed, "notes.txt").unwrap();
+    assert_eq!(edited, "hello\nthere\n");
+    let _ = fs::remove_file(env::temp_dir().join(format!("botcoder-{}-notes.txt.bak", std::process::id())));
+
+    engine.queue_tool("append_to_file", "notes.txt:::world");
+    engine.edit_next(&edited).unwrap();
+    let (tool, _, result) = engine.execute_next().unwrap();
+    assert_eq!(tool, "write_file_delta");
+    assert!(result.contains("the user rewrote this change"));
+    assert_eq!(fs::read_to_string(root.join("notes.txt")).unwrap(), "hello\nthere\n");
+
+    engine.queue_tool("execute_command", "ls");
+    assert!(engine.edit_next("x").is_err());
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn edits_keep_permissions_and_refuse_symlinks_out_of_the_project() {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    let root = project("symlinks");
+    let root_str = root.to_str().unwrap();
+    let outside = project("symlinks-outside");
+    fs::write(root.join("build.sh"), "echo one\n").unwrap();
+    fs::set_permissions(root.join("build.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+
+    let delta = botcoder::app::encode_delta("build.sh", &[("one".into(), "two".into())]);
+    botcoder::app::execute_tool("write_file_delta", &delta, root_str).unwrap();
+    assert_eq!(fs::read_to_string(root.join("build.sh")).unwrap(), "echo two\n");
+    assert_eq!(fs::metadata(root.join("build.sh")).unwrap().permissions().mode() & 0o777, 0o755);
+
+    // A link within the project is edited through and stays a link
+    symlink(root.join("notes.txt"), root.join("alias.txt")).unwrap();
+    botcoder::app::execute_tool("append_to_file", "alias.txt:::again", root_str).unwrap();
+    assert!(fs::symlink_metadata(root.join("alias.txt")).unwrap().file_type().is_symlink());
+    assert_eq!(fs::read_to_string(root.join("notes.txt")).unwrap(), "hello\nagain\n");
+
+    // Links that lead out of it are refused, dangling ones included
+    symlink(&outside, root.join("elsewhere")).unwrap();
+    symlink(outside.join("new.txt"), root.join("dangling.txt")).unwrap();
+    for path in ["elsewhere/notes.txt", "elsewhere/new.txt", "dangling.txt"] {
+        let result = botcoder::app::execute_tool("append_to_file", &format!("{}:::pwned", path), root_str);
+        assert!(matches!(result, Err(ToolError::OutsideProject(_))), "{}", path);
+    }
+    assert!(botcoder::app::execute_tool("read_file", "elsewhere/notes.txt", root_str).is_err());
+    assert_eq!(fs::read_to_string(outside.join("notes.txt")).unwrap(), "hello\n");
+    assert!(!outside.join("new.txt").exists());
+
+    fs::remove_dir_all(root).unwrap();
+    fs::remove_dir_all(outside).unwrap();
+}
+
+#[test]
+fn read_only_paths_are_readable_but_never_modified() {
+    let root = project("write-scope");
+    let root_str = root.to_str().unwrap();
+    fs::write(
+        root.join(".botcoder.toml"),
+        "[write]\nread_only = [\"Cargo.lock\", \"migrations/**\", \".github/**\"]\n",
+    )
+    .unwrap();
+    fs::create_dir_all(root.join("migrations/2024")).unwrap();
+    fs::write(root.join("migrations/2024/init.sql"), "create table t;\n").unwrap();
+
+    let read = app::execute_tool("read_file", "migrations/2024/init.sql", root_str).unwrap();
+    assert!(read.text.contains("create table"));
+    for (tool, param) in [
+        ("append_to_file", "migrations/2024/init.sql:::drop table t;"),
+        ("write_file_delta", "./Cargo.lock:::x"),
+        ("edit_lines", ".github/workflows/ci.yml:::1-1\nx"),
+        ("cargo_add", "serde"),
+        // A path that only reaches them through ".." is the same path
+        ("append_to_file", "src/../migrations/2024/init.sql:::drop table t;"),
+        ("append_to_file", "./migrations/./2024/../2024/init.sql:::drop table t;"),
+    ] {
+        let result = app::execute_tool(tool, param, root_str);
+        assert!(matches!(result, Err(ToolError::ReadOnly(_))), "{} {}", tool, param);
+    }
+    assert_eq!(fs::read_to_string(root.join("migrations/2024/init.sql")).unwrap(), "create table t;\n");
+    app::execute_tool("append_to_file", "notes.txt:::fine", root_str).unwrap();
+
+    // --read-only turns every writing tool off and leaves the rest alone
+    let scope = WriteScope::load(root_str).with_read_only(true);
+    let refused = scope.refuse("append_to_file", "notes.txt:::x").unwrap_err();
+    assert!(matches!(refused, ToolError::ReadOnlySession(_)));
+    assert!(refused.is_policy_violation());
+    assert!(scope.refuse("git_commit", "msg").is_err());
+    assert!(scope.refuse("cargo_fmt", "").is_err());
+    assert!(scope.refuse("cargo_fmt", "check").is_ok());
+    assert!(scope.refuse("read_file", "notes.txt").is_ok());
+    assert!(scope.refuse("run_tests", "").is_ok());
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn ignored_paths_stay_hidden_behind_dot_dot() {
+    let root = project("ignore-dots");
+    let root_str = root.to_str().unwrap();
+    fs::write(root.join(".botcoderignore"), "/secrets/\nconfig/*.key\n").unwrap();
+    for dir in ["secrets", "config", "src"] {
+        fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    fs::write(root.join("secrets/key"), "hunter2\n").unwrap();
+    fs::write(root.join("config/api.key"), "hunter2\n").unwrap();
+
+    for path in ["secrets/key", "src/../secrets/key", "./src/./../config/api.key"] {
+        let result = app::execute_tool("read_file", path, root_str);
+        assert!(matches!(result, Err(ToolError::Ignored(_))), "{}", path);
+    }
+    let read = app::execute_tool("read_file", "src/../notes.txt", root_str).unwrap();
+    assert!(read.text.contains("hello"));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn writes_over_the_limits_wait_for_confirmation() {
+    let root = project("write-limits");
+    let mock = MockProvider::new(Vec::<&str>::new());
+    let mut engine = engine(&mock, &root).with_write_limits(WriteLimits {
+        file_bytes: 64,
+        session_bytes: 100,
+    });
+
+    // "hello\n" plus 10 bytes stays under both limits
+    engine.queue_tool("append_to_file", "notes.txt:::0123456789");
+    assert_eq!(engine.write_limit_exceeded(), None);
+    engine.execute_next().unwrap();
+    assert_eq!(engine.bytes_written(), 17);
+
+    // A runaway append is refused without touching the file
+    let big = "x".repeat(80);
+    engine.queue_tool("append_to_file", format!("notes.txt:::{}", big));
+    let reason = engine.write_limit_exceeded().unwrap();
+    assert!(reason.contains("notes.txt") && reason.contains("limit for one file"), "{}", reason);
+    let (_, _, result) = engine.execute_next().unwrap();
+    assert!(result.contains("Write refused"), "{}", result);
+    assert_eq!(fs::read_to_string(root.join("notes.txt")).unwrap(), "hello\n0123456789\n");
+
+    // Once confirmed it goes through, and counts toward the session
+    engine.queue_tool("append_to_file", format!("notes.txt:::{}", big));
+    engine.confirm_write();
+    engine.execute_next().unwrap();
+    assert_eq!(engine.bytes_written(), 17 + 98);
+    engine.queue_tool("append_to_file", "other.txt:::small");
+    assert!(engine.write_limit_exceeded().unwrap().contains("this session"));
+
+    // A whole-file CHANGE is measured by its new content
+    let shrink = app::encode_delta("notes.txt", &[(String::new(), "short".into())]);
+    assert_eq!(app::projected_size("write_file_delta", &shrink, root.to_str().unwrap()), Some(5));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn edits_keep_line_endings_boms_and_encodings() {
+    let root = project("encoding");
+    let root_str = root.to_str().unwrap();
+    let windows = root.join("windows.txt");
+    fs::write(&windows, b"\xEF\xBB\xBFfn a() {\r\n    1\r\n}\r\n").unwrap();
+
+    // A delta with \n line breaks matches \r\n ones, and the file keeps
+    // its BOM, \r\n and final newline
+    let delta = botcoder::app::encode_delta("windows.txt", &[("fn a() {\n    1".into(), "fn a() {\n    2".into())]);
+    botcoder::app::execute_tool("write_file_delta", &delta, root_str).unwrap();
+    assert_eq!(fs::read(&windows).unwrap(), b"\xEF\xBB\xBFfn a() {\r\n    2\r\n}\r\n");
+    botcoder::app::execute_tool("append_to_file", "windows.txt:::// end", root_str).unwrap();
+    assert_eq!(fs::read(&windows).unwrap(), b"\xEF\xBB\xBFfn a() {\r\n    2\r\n}\r\n// end\r\n");
+
+    // No final newline stays that way
+    fs::write(root.join("bare.txt"), "one\ntwo").unwrap();
+    botcoder::app::execute_tool("edit_lines", "bare.txt:::2-2\nthree", root_str).unwrap();
+    assert_eq!(fs::read_to_string(root.join("bare.txt")).unwrap(), "one\nthree");
+
+    // UTF-16 is read as text and written back as UTF-16
+    let utf16: Vec<u8> = [0xFF, 0xFE].into_iter().chain("héllo\r\n".encode_utf16().flat_map(u16::to_le_bytes)).collect();
+    fs::write(root.join("wide.txt"), &utf16).unwrap();
+    let read = botcoder::app::execute_tool("read_file", "wide.txt", root_str).unwrap();
+    assert!(read.text.contains("héllo"), "{}", read.text);
+    let delta = botcoder::app::encode_delta("wide.txt", &[("héllo".into(), "hi".into())]);
+    botcoder::app::execute_tool("write_file_delta", &delta, root_str).unwrap();
+    let expected: Vec<u8> = [0xFF, 0xFE].into_iter().chain("hi\r\n".encode_utf16().flat_map(u16::to_le_bytes)).collect();
+    assert_eq!(fs::read(root.join("wide.txt")).unwrap(), expected);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn rename_symbol_changes_identifiers_but_not_strings_or_comments() {
+    let root = project("rename");
+    let root_str = root.to_str().unwrap();
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::create_dir_all(root.join("vendor")).unwrap();
+    fs::write(
+        root.join("src/lib.rs"),
+        "/// parse_config reads the file\npub fn parse_config() -> u8 { 1 }\n\
+         fn main() { let s = \"parse_config\"; parse_config(); parse_config_twice(); }\n",
+    )
+    .unwrap();
+    fs::write(
+        root.join("tool.py"),
+        "from lib import parse_config\n# parse_config here\nprint('parse_config', parse_config())\n",
+    )
+    .unwrap();
+    fs::write(root.join("vendor/lib.rs"), "fn parse_config() {}\n").unwrap();
+    fs::write(root.join("notes.txt"), "parse_config\n").unwrap();
+    fs::write(root.join(".botcoder.toml"), "[write]\nread_only = [\"vendor/**\"]\n").unwrap();
+
+    let (tools, _) = app::extract_tools_checked("rename_symbol(\"parse_config\", \"load_config\")");
+    assert_eq!(tools, vec![("rename_symbol".to_string(), "parse_config:::load_config".to_string())]);
+    let output = app::execute_tool(&tools[0].0, &tools[0].1, root_str).unwrap();
+    assert!(output.text.contains("src/lib.rs (2)") && output.text.contains("tool.py (2)"), "{}", output.text);
+    assert!(output.text.contains("protected: vendor/lib.rs"), "{}", output.text);
+    let mut modified = app::modified_paths(&tools[0].0, &tools[0].1, &output);
+    modified.sort();
+    assert_eq!(modified, vec!["src/lib.rs", "tool.py"]);
+
+    assert_eq!(
+        fs::read_to_string(root.join("src/lib.rs")).unwrap(),
+        "/// parse_config reads the file\npub fn load_config() -> u8 { 1 }\n\
+         fn main() { let s = \"parse_config\"; load_config(); parse_config_twice(); }\n"
+    );
+    assert_eq!(
+        fs::read_to_string(root.join("tool.py")).unwrap(),
+        "from lib import load_config\n# parse_config here\nprint('parse_config', load_config())\n"
+    );
+    assert_eq!(fs::read_to_string(root.join("vendor/lib.rs")).unwrap(), "fn parse_config() {}\n");
+    assert_eq!(fs::read_to_string(root.join("notes.txt")).unwrap(), "parse_config\n");
+
+    // A name that only appears in strings and comments
+    fs::write(root.join("src/lib.rs"), "// old_name\nconst S: &str = \"old_name\";\n").unwrap();
+    assert!(app::execute_tool("rename_symbol", "old_name:::new_name", root_str)
+        .unwrap_err()
+        .to_string()
+        .contains("no identifier"));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn tools_run_against_an_in_memory_tree() {
+    let root = env::temp_dir().join(format!("botcoder-memory-{}", std::process::id()));
+    let tree = Arc::new(MemoryFs::new());
+    tree.write(&root.join("notes.txt"), b"hello\n").unwrap();
+    let _fs = vfs::install(tree.clone());
+
+    let mock = MockProvider::new([
+        "read_file(\"notes.txt\")",
+        "CHANGE: notes.txt\n<<<<<<< CURRENT\nhello\n=======\nhello, memory\n>>>>>>> NEW",
+        "Done.",
+    ]);
+    let mut engine = engine(&mock, &root);
+    engine.push_user("Greet the tree");
+    engine.run_to_completion(5).await.unwrap();
+
+    assert!(mock.requests()[1].last().unwrap().content.contains("read_file: hello"));
+    assert_eq!(tree.read(&root.join("notes.txt")).unwrap(), b"hello, memory\n");
+    assert_eq!(engine.modified_files(), ["notes.txt"]);
+    assert_eq!(engine.bytes_written(), 14);
+
+    // Size limits and edit previews see the same tree
+    let root_str = root.to_str().unwrap();
+    assert_eq!(app::projected_size("append_to_file", "notes.txt:::again", root_str), Some(19));
+    let proposed = app::proposed_content("append_to_file", "notes.txt:::again", root_str).unwrap();
+    assert_eq!(proposed, "hello, memory\nagain\n");
+    assert_eq!(tree.read(&root.join("notes.txt")).unwrap(), b"hello, memory\n");
+    // Only the audit log reaches the disk
+    assert!(!root.join("notes.txt").exists());
+
+    let _ = fs::remove_dir_all(root);
+}
+
+// Files that are there but can't be read, as without permission
+struct Unreadable(MemoryFs);
+
+impl Vfs for Unreadable {
+    fn read(&self, _path: &Path) -> io::Result<Vec<u8>> {
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, "permission denied"))
+    }
+
+    fn write(&self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        self.0.write(path, bytes)
+    }
+
+    fn is_file(&self, _path: &Path) -> bool {
+        true
+    }
+
+    fn on_disk(&self, _path: &Path) -> bool {
+        false
+    }
+}
+
+#[test]
+fn edits_of_unreadable_files_fail_instead_of_writing_over_them() {
+    let root = env::temp_dir().join(format!("botcoder-unreadable-{}", std::process::id()));
+    let tree = Arc::new(Unreadable(MemoryFs::new()));
+    let _fs = vfs::install(tree.clone());
+    let root_str = root.to_str().unwrap();
+
+    let result = app::execute_tool("append_to_file", "notes.txt:::world", root_str);
+    assert!(matches!(result, Err(ToolError::Read { .. })), "{:?}", result.err());
+    assert!(!tree.0.contains(&root.join("notes.txt")));
+
+    let change = "notes.txt:::<<<<<<< CURRENT\nhello\n=======\nhello, world\n>>>>>>> NEW";
+    let result = app::execute_tool("write_file_delta", change, root_str);
+    assert!(matches!(result, Err(ToolError::Read { .. })), "{:?}", result.err());
+    assert!(!tree.0.contains(&root.join("notes.txt")));
+
+    let _ = fs::remove_dir_all(root);
+}
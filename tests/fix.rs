@@ -0,0 +1,85 @@
+use std::fs;
+
+use botcoder::fix::FixLoop;
+use botcoder::llm::ChatMessage;
+use botcoder::mock::MockProvider;
+use botcoder::{AgentError, LlmError};
+
+mod common;
+use common::{engine, project};
+
+const NOT_FIXED: &str = "grep -q fixed notes.txt || { echo 'error: notes.txt is not fixed'; exit 1; }";
+
+// The failure reports fix mode sent, in order
+fn fix_requests(mock: &MockProvider) -> Vec<String> {
+    let requests = mock.requests();
+    let Some(last) = requests.last() else {
+        return Vec::new();
+    };
+    last.iter()
+        .filter(|m: &&ChatMessage| m.role == "user" && m.content.starts_with("The build or tests are failing"))
+        .map(|m| m.content.clone())
+        .collect()
+}
+
+#[tokio::test]
+async fn fix_loop_stops_once_the_checks_pass() {
+    let root = project("fix-green");
+
+    // Green from the start: the model isn't asked anything
+    let mock = MockProvider::new(Vec::<&str>::new());
+    let mut green = engine(&mock, &root);
+    let mut fix = FixLoop::new(&mut green, 3).with_commands(Some("true"), "true");
+    assert!(fix.run().await.unwrap());
+    assert_eq!(fix.iterations(), 0);
+    assert!(mock.requests().is_empty());
+
+    // A failing build check is sent, patched and checked again
+    let mock = MockProvider::new(["CHANGE: notes.txt\n<<<<<<< CURRENT\nhello\n=======\nfixed\n>>>>>>> NEW"]);
+    let mut engine = engine(&mock, &root);
+    let mut fix = FixLoop::new(&mut engine, 3).with_commands(Some(NOT_FIXED), "true");
+    assert!(fix.run().await.unwrap());
+    assert_eq!(fix.iterations(), 1);
+    assert_eq!(fs::read_to_string(root.join("notes.txt")).unwrap(), "fixed\n");
+    let sent = fix_requests(&mock);
+    assert_eq!(sent.len(), 1);
+    assert!(sent[0].contains("New failures:\nerror: notes.txt is not fixed"), "{}", sent[0]);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn fix_loop_gives_up_after_max_iterations_without_repeating_failures() {
+    let root = project("fix-give-up");
+    let mock = MockProvider::new(["read_file(\"notes.txt\")", "read_file(\"notes.txt\")", "Done."]);
+    let mut engine = engine(&mock, &root);
+
+    let mut fix = FixLoop::new(&mut engine, 2).with_commands(None, NOT_FIXED);
+    assert!(!fix.run().await.unwrap());
+    assert_eq!(fix.iterations(), 2);
+    assert_eq!(mock.remaining(), 1);
+
+    // The second turn only names the failure it was already sent
+    let sent = fix_requests(&mock);
+    assert_eq!(sent.len(), 2);
+    assert!(sent[0].contains("New failures:\nerror: notes.txt is not fixed"), "{}", sent[0]);
+    assert!(!sent[1].contains("New failures"), "{}", sent[1]);
+    assert!(sent[1].contains("Still failing (already reported):\n- error: notes.txt is not fixed"), "{}", sent[1]);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn fix_loop_ends_on_errors_another_iteration_cannot_fix() {
+    let root = project("fix-error");
+    // No response to give, as with a rejected API key
+    let mock = MockProvider::new(Vec::<&str>::new());
+    let mut engine = engine(&mock, &root);
+
+    let mut fix = FixLoop::new(&mut engine, 5).with_commands(None, NOT_FIXED);
+    let result = fix.run().await;
+    assert!(matches!(result, Err(AgentError::Llm(LlmError::Replay(_)))), "{:?}", result.err());
+    assert_eq!(fix.iterations(), 1);
+
+    fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,118 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use botcoder::isolation::{IsolatedCheckout, Isolation};
+use botcoder::mock::MockProvider;
+use botcoder::oneshot;
+
+mod common;
+use common::{engine, git, init_repo, project};
+
+#[tokio::test]
+async fn commits_only_modified_files() {
+    let root = project("commit");
+    fs::write(root.join("other.txt"), "untouched\n").unwrap();
+    init_repo(&root);
+
+    let mock = MockProvider::new([
+        "CHANGE: notes.txt\n<<<<<<< CURRENT\nhello\n=======\nhello, world\n>>>>>>> NEW",
+        "docs(notes): greet the world",
+    ]);
+    let mut engine = engine(&mock, &root);
+    engine.push_user("Greet the world in notes.txt");
+    engine.step().await.unwrap();
+    assert_eq!(engine.modified_files(), ["notes.txt"]);
+
+    let message = engine.draft_commit_message().await.unwrap();
+    assert_eq!(message, "docs(notes): greet the world");
+    // The diff is what the model was asked to describe
+    assert!(mock.requests()[1][1].content.contains("+hello, world"));
+
+    engine.queue_tool("git_commit", message);
+    let (_, _, result) = engine.execute_next().unwrap();
+    assert!(result.contains("exit_code: 0"), "{}", result);
+    assert!(engine.modified_files().is_empty());
+
+    let log = git(&root, &["log", "--format=%s", "--name-only", "-1"]);
+    let log = String::from_utf8_lossy(&log.stdout);
+    assert!(log.starts_with("docs(notes): greet the world"));
+    assert!(!log.contains("other.txt"));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn git_read_tools_show_changes() {
+    let root = project("git-read");
+    init_repo(&root);
+    fs::write(root.join("notes.txt"), "hello, world\n").unwrap();
+    fs::write(root.join("new.txt"), "new\n").unwrap();
+
+    let root_str = root.to_string_lossy();
+    let run = |tool: &str, param: &str| {
+        botcoder::app::execute_tool(tool, param, &root_str).unwrap().text
+    };
+
+    let status = run("git_status", "");
+    assert!(status.contains("unstaged:\n  M notes.txt"), "{}", status);
+    assert!(status.contains("untracked:\n  new.txt"), "{}", status);
+
+    let diff = run("git_diff", "notes.txt");
+    assert!(diff.contains("+hello, world"), "{}", diff);
+    assert!(!diff.contains("new.txt"));
+
+    let log = run("git_log", "5");
+    assert!(log.trim_end().ends_with("test: init"), "{}", log);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn isolated_runs_only_touch_the_checkout_when_they_succeed() {
+    let root = project("isolated");
+    let root_str = root.to_str().unwrap();
+    init_repo(&root);
+
+    let checkout_dir: &Path = &root;
+    let run = |isolation| async move {
+        let checkout = IsolatedCheckout::create(root_str, isolation).unwrap();
+        let mock = MockProvider::new([
+            "CHANGE: notes.txt\n<<<<<<< CURRENT\nhello\n=======\nhello again\n>>>>>>> NEW",
+            "CHANGE: todo.txt\n<<<<<<< CURRENT\n=======\nwave\n>>>>>>> NEW",
+            "execute_command(\"echo built > out.txt && git commit -q -m greet notes.txt\")",
+            "Done.",
+        ]);
+        let mut engine = engine(&mock, Path::new(checkout.root()));
+        oneshot::run(&mut engine, "Greet again and add a todo", 5).await.unwrap();
+        // Meanwhile the checkout stays as it was
+        assert_eq!(fs::read_to_string(checkout_dir.join("notes.txt")).unwrap(), "hello\n");
+        assert!(!checkout_dir.join("todo.txt").exists());
+        checkout
+    };
+
+    let checkout = run(Isolation::Merge).await;
+    let note = checkout.finish(false, root_str, "run-1");
+    assert!(note.contains("untouched"), "{}", note);
+    assert_eq!(fs::read_to_string(root.join("notes.txt")).unwrap(), "hello\n");
+
+    let checkout = run(Isolation::Patch).await;
+    let note = checkout.finish(true, root_str, "run-2");
+    assert!(note.contains(".botcoder/patches/run-2.patch"), "{}", note);
+    assert!(!root.join("todo.txt").exists());
+    let patch = fs::read_to_string(root.join(".botcoder/patches/run-2.patch")).unwrap();
+    assert!(!patch.contains(".botcoder/"), "{}", patch);
+
+    // The commit made in the worktree and the command's output come back too
+    let checkout = run(Isolation::Merge).await;
+    let worktree = PathBuf::from(checkout.root());
+    let note = checkout.finish(true, root_str, "run-3");
+    assert!(note.starts_with("✓ Merged the changes to 3 file(s)"), "{}", note);
+    assert_eq!(fs::read_to_string(root.join("notes.txt")).unwrap(), "hello again\n");
+    assert_eq!(fs::read_to_string(root.join("todo.txt")).unwrap(), "wave\n");
+    assert_eq!(fs::read_to_string(root.join("out.txt")).unwrap(), "built\n");
+    assert!(!worktree.exists());
+
+    fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,112 @@
+use std::fs;
+
+use botcoder::clipboard;
+use botcoder::completion;
+use botcoder::file_tree;
+use botcoder::input_history::{HistorySearch, InputHistory};
+
+mod common;
+use common::project;
+
+#[test]
+fn input_history_is_browsed_searched_and_kept_across_runs() {
+    let root = project("input-history");
+    let root_str = root.to_str().unwrap();
+    let mut history = InputHistory::load(root_str);
+    history.push("Fix the parser");
+    history.push("Here is the trace:\nthread 'main' panicked\n  at src/parser.rs:12");
+    history.push("Here is the trace:\nthread 'main' panicked\n  at src/parser.rs:12");
+    history.push("  ");
+    history.push("/timeline");
+
+    // Reloaded from disk, multi-line entries intact, blanks and repeats left out
+    let mut history = InputHistory::load(root_str);
+    assert_eq!(history.entries().len(), 3);
+    assert_eq!(history.entries()[1].lines().count(), 3);
+
+    assert_eq!(history.older("half typed").as_deref(), Some("/timeline"));
+    assert_eq!(history.older("").unwrap(), history.entries()[1]);
+    assert_eq!(history.older("").as_deref(), Some("Fix the parser"));
+    assert_eq!(history.older("").as_deref(), Some("Fix the parser"));
+    history.newer();
+    history.newer();
+    assert_eq!(history.newer(), "half typed");
+    assert!(!history.is_browsing());
+
+    let mut search = HistorySearch {
+        query: "PARSER".to_string(),
+        matched: None,
+    };
+    history.search(&mut search, false);
+    assert_eq!(search.matched, Some(1));
+    history.search(&mut search, true);
+    assert_eq!(search.matched, Some(0));
+    history.search(&mut search, true);
+    assert_eq!(search.matched, Some(0));
+    search.query = "nothing like it".to_string();
+    history.search(&mut search, false);
+    assert_eq!(search.matched, None);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn tab_completes_commands_tools_and_project_paths() {
+    let root = project("completion");
+    fs::create_dir_all(root.join("src/bin")).unwrap();
+    fs::create_dir_all(root.join("target")).unwrap();
+    fs::write(root.join("src/main.rs"), "").unwrap();
+    fs::write(root.join("src/markdown.rs"), "").unwrap();
+    fs::write(root.join("target/out.txt"), "").unwrap();
+    fs::write(root.join(".gitignore"), "target/\n").unwrap();
+    let files = file_tree::scan(root.to_str().unwrap());
+
+    assert_eq!(completion::complete("/ti", &files).input, "/timeline ");
+    let commands = completion::complete("/s", &files);
+    assert_eq!(commands.input, "/s");
+    assert_eq!(commands.candidates, ["/save", "/search"]);
+
+    assert_eq!(completion::complete("/pin sr", &files).input, "/pin src/");
+    let sources = completion::complete("/pin src/m", &files);
+    assert_eq!(sources.input, "/pin src/ma");
+    assert_eq!(sources.candidates, ["src/main.rs", "src/markdown.rs"]);
+    assert_eq!(completion::complete("look at read_file(\"src/mai", &files).input, "look at read_file(\"src/main.rs");
+    assert_eq!(completion::complete("run read_f", &files).input, "run read_file");
+    assert_eq!(completion::complete("check no", &files).input, "check notes.txt");
+    // Ignored files aren't offered
+    assert!(completion::complete("/pin tar", &files).candidates.is_empty());
+    assert_eq!(completion::complete("/pin tar", &files).input, "/pin tar");
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn clipboard_goes_through_the_first_tool_that_works() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // A stand-in xclip keeping the clipboard in a file
+    let root = project("clipboard");
+    let store = root.join("clipboard.txt");
+    let script = root.join("xclip");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\nif [ \"$3\" = \"-o\" ]; then cat '{0}'; else cat > '{0}'; fi\n",
+            store.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    let xclip = script.to_str().unwrap();
+
+    let copy = [vec!["botcoder-no-such-tool"], vec![xclip, "-selection", "clipboard"]];
+    assert_eq!(clipboard::copy_with(&copy, "fn main() {}\n").unwrap(), xclip);
+    assert_eq!(fs::read_to_string(&store).unwrap(), "fn main() {}\n");
+    fs::write(&store, "line one\r\nline two").unwrap();
+    let paste = [vec!["botcoder-no-such-tool"], vec![xclip, "-selection", "clipboard", "-o"]];
+    assert_eq!(clipboard::paste_with(&paste).unwrap(), "line one\nline two");
+    assert!(clipboard::paste_with(&[vec!["botcoder-no-such-tool"]]).is_err());
+
+    fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,91 @@
+// Ctrl+C is process-wide and stays pressed, so everything it stops is
+// checked in one test, in a test binary of its own
+use async_trait::async_trait;
+use serde_json::Value;
+use std::{
+    fs, io,
+    process::Command,
+    thread,
+    time::{Duration, Instant},
+};
+
+use botcoder::fix::FixLoop;
+use botcoder::interrupt;
+use botcoder::llm::{ChatMessage, LLMProvider};
+use botcoder::mock::MockProvider;
+use botcoder::oneshot::{self, RunEnd};
+use botcoder::parser::{ResponseParser, ToolFormat};
+use botcoder::router::ModelRouter;
+use botcoder::tpm_limiter::TPMLimiter;
+use botcoder::{AgentEngine, LlmError};
+
+mod common;
+use common::{engine, project};
+
+// A model that takes a minute to answer
+struct SlowProvider;
+
+#[async_trait]
+impl LLMProvider for SlowProvider {
+    fn model(&self) -> &str {
+        "slow"
+    }
+
+    async fn generate(&self, _prompt: &str, _config: &Value) -> Result<String, LlmError> {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Ok("Done.".to_string())
+    }
+
+    async fn generate_chat(&self, _messages: &[ChatMessage], _config: &Value) -> Result<String, LlmError> {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Ok("Done.".to_string())
+    }
+}
+
+#[tokio::test]
+async fn ctrl_c_cancels_requests_and_commands_and_stops_every_loop() {
+    let root = project("interrupt");
+    let parser = ResponseParser {
+        format: ToolFormat::Text,
+        max_retries: 2,
+    };
+    let mut slow = AgentEngine::new(
+        ModelRouter::single(SlowProvider),
+        "You are a test assistant.",
+        root.to_string_lossy(),
+        TPMLimiter::new(1_000_000, 0),
+        parser,
+    );
+
+    // The request in flight is abandoned
+    let started = Instant::now();
+    thread::spawn(|| {
+        thread::sleep(Duration::from_millis(200));
+        interrupt::trigger();
+    });
+    slow.push_user("Take your time");
+    assert_eq!(slow.step().await.unwrap(), None);
+    assert!(started.elapsed() < Duration::from_secs(10), "{:?}", started.elapsed());
+    assert!(interrupt::is_interrupted());
+    tokio::time::timeout(Duration::from_secs(1), interrupt::wait()).await.unwrap();
+
+    // A running command is killed rather than waited for
+    let started = Instant::now();
+    let mut sleep = Command::new("sleep");
+    sleep.arg("30");
+    let err = interrupt::output(sleep).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    assert!(started.elapsed() < Duration::from_secs(10), "{:?}", started.elapsed());
+
+    // Every loop stops before asking the model again
+    let mock = MockProvider::new(["read_file(\"notes.txt\")"]);
+    let mut engine = engine(&mock, &root);
+    assert_eq!(oneshot::run(&mut engine, "Read the notes", 5).await.unwrap(), RunEnd::Interrupted);
+    assert!(!engine.run_to_completion(5).await.unwrap());
+    let mut fix = FixLoop::new(&mut engine, 5).with_commands(None, "false");
+    assert!(!fix.run().await.unwrap());
+    assert_eq!(fix.iterations(), 0);
+    assert!(mock.requests().is_empty());
+
+    fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,59 @@
+// The overlay is switched on for the whole process, so its test runs in a
+// test binary of its own
+use std::fs;
+
+use botcoder::mock::MockProvider;
+use botcoder::overlay;
+
+mod common;
+use common::{engine, git, init_repo, project};
+
+#[tokio::test]
+async fn patch_output_collects_edits_without_touching_the_tree() {
+    let root = project("overlay");
+    init_repo(&root);
+
+    overlay::set_enabled(true);
+    let mock = MockProvider::new([
+        "CHANGE: notes.txt\n<<<<<<< CURRENT\nhello\n=======\nhello again\n>>>>>>> NEW",
+        "CHANGE: todo.txt\n<<<<<<< CURRENT\n=======\nwave\n>>>>>>> NEW",
+        "Done.",
+    ]);
+    let mut engine = engine(&mock, &root);
+    engine.push_user("Greet again and add a todo");
+    engine.run_to_completion(5).await.unwrap();
+
+    // Reads see the edits, the disk doesn't
+    engine.queue_tool("read_file", "notes.txt");
+    let (_, _, read) = engine.execute_next().unwrap();
+    assert!(read.contains("hello again"), "{}", read);
+    assert_eq!(fs::read_to_string(root.join("notes.txt")).unwrap(), "hello\n");
+    assert!(!root.join("todo.txt").exists());
+    engine.queue_tool("git_commit", "notes.txt:::Greet again");
+    let (_, _, commit) = engine.execute_next().unwrap();
+    assert!(commit.contains("only writes a patch"), "{}", commit);
+    // Commands would see the tree without the edits, and could change it
+    engine.queue_tool("execute_command", "touch made-by-a-command");
+    let (_, _, command) = engine.execute_next().unwrap();
+    assert!(command.contains("no commands run"), "{}", command);
+    assert!(!root.join("made-by-a-command").exists());
+    // botcoder's own state stays out of the project too
+    assert!(!root.join(".botcoder").exists());
+    let state = overlay::state_path(&root.to_string_lossy(), ".botcoder");
+    assert!(state.join("audit.log").is_file());
+
+    let root_str = root.to_string_lossy();
+    let patch_file = root.with_extension("patch");
+    let summary = overlay::save_patch(&root_str, &patch_file).unwrap();
+    assert!(summary.contains("2 file(s), +2 -1"), "{}", summary);
+    let patch = fs::read_to_string(&patch_file).unwrap();
+    assert!(patch.contains("--- /dev/null\n+++ b/todo.txt"), "{}", patch);
+    let check = git(&root, &["apply", "--check", patch_file.to_str().unwrap()]);
+    assert!(check.status.success(), "{}", String::from_utf8_lossy(&check.stderr));
+
+    overlay::set_enabled(false);
+    overlay::clear();
+    fs::remove_dir_all(state).unwrap();
+    fs::remove_file(patch_file).unwrap();
+    fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,234 @@
+use botcoder::app;
+use botcoder::parser::{ResponseParser, ToolFormat};
+
+fn tools(text: &str) -> Vec<(String, String)> {
+    app::extract_tools(text)
+}
+
+fn call(tool: &str, param: &str) -> (String, String) {
+    (tool.to_string(), param.to_string())
+}
+
+// A small xorshift generator, so the property tests are repeatable
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+        items[self.below(items.len())]
+    }
+}
+
+#[test]
+fn nested_quotes_are_unescaped() {
+    assert_eq!(
+        tools(r#"execute_command("grep \"fn main\" src/main.rs")"#),
+        [call("execute_command", r#"grep "fn main" src/main.rs"#)]
+    );
+    assert_eq!(
+        tools(r#"execute_command("echo 'it''s' \"done\"")"#),
+        [call("execute_command", r#"echo 'it''s' "done""#)]
+    );
+    assert_eq!(
+        tools(r#"execute_command('echo "hi"')"#),
+        [call("execute_command", r#"echo "hi""#)]
+    );
+    // Other escapes are the command's own
+    assert_eq!(
+        tools(r#"execute_command("printf 'a\tb\n'")"#),
+        [call("execute_command", r#"printf 'a\tb\n'"#)]
+    );
+}
+
+#[test]
+fn parentheses_inside_arguments_do_not_end_the_call() {
+    assert_eq!(
+        tools(r#"execute_command("echo $(date) (done)")"#),
+        [call("execute_command", "echo $(date) (done)")]
+    );
+    assert_eq!(
+        tools(r#"git_commit("fix(parser): keep (nested) parens")"#),
+        [call("git_commit", "fix(parser): keep (nested) parens")]
+    );
+    assert_eq!(
+        tools(r#"read_file("src/(generated).rs", 1-20) and more (prose)"#),
+        [call("read_file", "src/(generated).rs:::1-20")]
+    );
+    assert_eq!(tools("run_tests(parser::tests)"), [call("run_tests", "parser::tests")]);
+    // Part of a longer name
+    assert!(tools(r#"my_read_file("notes.txt")"#).is_empty());
+}
+
+#[test]
+fn crlf_line_endings_are_normalized() {
+    let reply = "read_file(\"notes.txt\")\r\nCHANGE: notes.txt\r\n<<<<<<< CURRENT\r\nhello\r\nworld\r\n=======\r\nhi\r\nthere\r\n>>>>>>> NEW\r\n";
+    assert_eq!(
+        tools(reply),
+        [
+            call("read_file", "notes.txt"),
+            call("write_file_delta", &app::encode_delta("notes.txt", &[("hello\nworld".into(), "hi\nthere".into())])),
+        ]
+    );
+    let edit = tools("EDIT_LINES: notes.txt 1-2\r\nreplaced\r\nEND_EDIT\r\n");
+    assert_eq!(edit, [call("edit_lines", "notes.txt:::1-2\nreplaced")]);
+}
+
+#[test]
+fn multiple_change_blocks_keep_their_own_hunks() {
+    let reply = "First the parser:\n\
+        CHANGE: src/parser.rs\n\
+        <<<<<<< CURRENT\na\n=======\nb\n>>>>>>> NEW\n\
+        then, further down:\n\
+        <<<<<<< CURRENT\nc\n=======\nd\n>>>>>>> NEW\n\
+        CHANGE: src/lib.rs\n\
+        <<<<<<< CURRENT\npub mod x;\n=======\npub mod y;\n>>>>>>> NEW\n";
+    assert_eq!(
+        tools(reply),
+        [
+            call(
+                "write_file_delta",
+                &app::encode_delta("src/parser.rs", &[("a".into(), "b".into()), ("c".into(), "d".into())])
+            ),
+            call("write_file_delta", &app::encode_delta("src/lib.rs", &[("pub mod x;".into(), "pub mod y;".into())])),
+        ]
+    );
+}
+
+#[test]
+fn a_hunk_missing_its_terminator_is_dropped_with_a_diagnostic() {
+    // Without the >>>>>>> NEW line, the first block must not swallow the
+    // second one or be applied with half its hunk
+    let reply = "CHANGE: src/a.rs\n<<<<<<< CURRENT\nold\n=======\nnew\n\
+        CHANGE: src/b.rs\n<<<<<<< CURRENT\nx\n=======\ny\n>>>>>>> NEW\n";
+    let (found, diagnostics) = app::extract_tools_checked(reply);
+    assert_eq!(found, [call("write_file_delta", &app::encode_delta("src/b.rs", &[("x".into(), "y".into())]))]);
+    assert_eq!(diagnostics.len(), 1);
+    assert!(diagnostics[0].contains("src/a.rs") && diagnostics[0].contains(">>>>>>> NEW"), "{:?}", diagnostics);
+
+    let (found, diagnostics) = app::extract_tools_checked("CHANGE: src/a.rs\n<<<<<<< CURRENT\nold\n>>>>>>> NEW\n");
+    assert!(found.is_empty());
+    assert!(diagnostics[0].contains("======="), "{:?}", diagnostics);
+
+    let (found, diagnostics) = app::extract_tools_checked("APPEND_TO_FILE: notes.txt\nmore\n");
+    assert!(found.is_empty());
+    assert!(diagnostics[0].contains("END_APPEND"), "{:?}", diagnostics);
+}
+
+#[test]
+fn unterminated_calls_degrade_to_no_tool_and_a_diagnostic() {
+    let parser = ResponseParser {
+        format: ToolFormat::Text,
+        max_retries: 2,
+    };
+    let error = parser.parse(r#"execute_command("cargo test)"#).unwrap_err();
+    assert!(error.contains("unterminated quote"), "{}", error);
+    let error = parser.parse("execute_command(cargo test)").unwrap_err();
+    assert!(error.contains("not a single quoted command"), "{}", error);
+    let error = parser.parse(r#"execute_command("ls" "-la")"#).unwrap_err();
+    assert!(error.contains("not a single quoted command"), "{}", error);
+
+    // The well-formed calls of a partly malformed reply still go through
+    let tools = parser
+        .parse("read_file(\"notes.txt\")\nexecute_command(\"echo oops)")
+        .unwrap();
+    assert_eq!(tools, [call("read_file", "notes.txt")]);
+}
+
+#[test]
+fn json_mode_rejects_malformed_and_off_schema_calls() {
+    let parser = ResponseParser {
+        format: ToolFormat::Json,
+        max_retries: 2,
+    };
+    // A call that matches the registry, fenced or not
+    let tools = parser
+        .parse("```json\n{\"tool\": \"read_file\", \"args\": {\"path\": \"notes.txt\"}, \"reasoning\": \"look first\"}\n```")
+        .unwrap();
+    assert_eq!(tools, [call("read_file", "notes.txt")]);
+
+    let rejected = [
+        ("read_file(\"notes.txt\")", "no JSON object found"),
+        (r#"{"tool": "read_file", "args": {"path": "notes.txt"}"#, "invalid JSON"),
+        (r#"{"tool": "read_file", "args": {"path": "notes.txt",}}"#, "invalid JSON"),
+        (r#"{"tool": "read_file" "args": {}}"#, "invalid JSON"),
+        (r#"{"tool": "read_file", "args": {"path": "notes.txt"}, "why": "x"}"#, "unexpected top-level field \"why\""),
+        (r#"{"args": {"path": "notes.txt"}}"#, "missing string field \"tool\""),
+        (r#"{"tool": 7}"#, "missing string field \"tool\""),
+        (r#"{"tool": "read_file", "args": ["notes.txt"]}"#, "\"args\" must be a JSON object"),
+        (r#"{"tool": "delete_file", "args": {"path": "notes.txt"}}"#, "unknown tool \"delete_file\""),
+        (r#"{"tool": "read_file", "args": {"file": "notes.txt"}}"#, "read_file does not take an argument named \"file\""),
+        (r#"{"tool": "read_file", "args": {"path": 3}}"#, "read_file.path must be a string"),
+        (r#"{"tool": "read_file", "args": {}}"#, "read_file requires the argument \"path\""),
+        (r#"{"tool": "write_file_delta", "args": {"path": "notes.txt", "old": "hello"}}"#, "requires the argument \"new\""),
+    ];
+    for (reply, expected) in rejected {
+        let error = parser.parse(reply).unwrap_err();
+        assert!(error.contains(expected), "{}: {}", reply, error);
+    }
+
+    // The retry tells the model the one shape that is accepted
+    let feedback = parser.error_feedback("invalid JSON");
+    assert!(feedback.contains(r#"{"tool": "<name>", "args": {...}}"#), "{}", feedback);
+}
+
+#[test]
+fn quoted_commands_round_trip() {
+    let mut rng = Rng(0x5eed);
+    let pieces = ["cargo", "test", " ", "\"", "'", "(", ")", ",", "$(pwd)", "-p", "a b", "é", "|", ";"];
+    for _ in 0..500 {
+        let command: String = (0..1 + rng.below(12)).map(|_| rng.pick(&pieces)).collect();
+        if command.trim().is_empty() || command.ends_with('\\') {
+            continue;
+        }
+        let reply = format!("Running it.\nexecute_command(\"{}\")\n", command.replace('"', "\\\""));
+        assert_eq!(tools(&reply), [call("execute_command", &command)], "{}", reply);
+    }
+}
+
+#[test]
+fn truncated_replies_never_yield_half_parsed_calls() {
+    let reply = "Let me look first.\n\
+        read_file(\"src/(generated).rs\", 1-40, numbered)\n\
+        execute_command(\"grep -n \\\"fn (\\\" src/lib.rs | head\")\n\
+        git_commit(\"fix(parser): handle ')' in args\")\n\
+        CHANGE: src/lib.rs\n<<<<<<< CURRENT\nfn a() {}\n=======\nfn a() { b() }\n>>>>>>> NEW\n\
+        EDIT_LINES: src/main.rs 3-4\nlet x = (1, 2);\nEND_EDIT\n\
+        run_tests(\"parser\")\n";
+    let full = tools(reply);
+    assert_eq!(full.len(), 6);
+
+    // Cutting the reply anywhere leaves whole calls or none
+    for (cut, _) in reply.char_indices() {
+        for tool in tools(&reply[..cut]) {
+            assert!(full.contains(&tool), "cut at {}: {:?}", cut, tool);
+        }
+    }
+}
+
+#[test]
+fn random_noise_never_panics() {
+    let mut rng = Rng(42);
+    let fragments = [
+        "read_file(", "execute_command(", "git_commit(", "run_tests(", "CHANGE: ", "CHANGE:",
+        "<<<<<<< CURRENT", "=======", ">>>>>>> NEW", "EDIT_LINES: a.rs 1-2", "END_EDIT",
+        "INSERT_AFTER: a.rs \"fn\"", "END_INSERT", "TOOL: x", "END_TOOL", "\"", "'", "\\", "(",
+        ")", ",", " ", "\n", "\r\n", "src/lib.rs", "é", "```rust", "```",
+    ];
+    for _ in 0..2000 {
+        let text: String = (0..rng.below(40)).map(|_| rng.pick(&fragments)).collect();
+        let (found, _) = app::extract_tools_checked(&text);
+        for (tool, param) in found {
+            assert!(!tool.is_empty(), "{:?} from {:?}", param, text);
+        }
+    }
+}
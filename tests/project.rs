@@ -0,0 +1,288 @@
+use std::fs;
+
+use botcoder::app;
+use botcoder::events::AgentEvent;
+use botcoder::mock::MockProvider;
+use botcoder::workspace::Workspace;
+use botcoder::ToolError;
+
+mod common;
+use common::{engine, project};
+
+#[tokio::test]
+async fn switches_between_workspace_members() {
+    let root = project("workspace");
+    fs::write(
+        root.join("Cargo.toml"),
+        "[workspace]\nmembers = [\"crates/*\"]\n",
+    )
+    .unwrap();
+    for name in ["core", "cli"] {
+        let dir = root.join("crates").join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("Cargo.toml"),
+            format!("[package]\nname = \"demo-{}\"\n", name),
+        )
+        .unwrap();
+        fs::write(dir.join("notes.txt"), format!("{}\n", name)).unwrap();
+    }
+
+    let workspace = Workspace::load(&root.to_string_lossy(), true).unwrap();
+    let names = workspace.names().join(",");
+    assert!(names.contains("demo-cli") && names.contains("demo-core"), "{}", names);
+
+    let mock = MockProvider::new([
+        "switch_project(\"demo-core\")\nread_file(\"notes.txt\")\nread_file(\"../cli/notes.txt\")",
+    ]);
+    let mut engine = engine(&mock, &root).with_workspace(workspace);
+    engine.push_user("Read the core notes");
+    engine.step().await.unwrap();
+
+    let results: Vec<&str> = engine
+        .executed_tools()
+        .iter()
+        .map(|(_, _, result)| result.as_str())
+        .collect();
+    assert!(results[0].contains("Switched to demo-core"), "{}", results[0]);
+    assert_eq!(results[1], "core\n");
+    assert!(results[2].contains("outside the project root"), "{}", results[2]);
+    // The system prompt lists every project
+    assert!(mock.requests()[0][0].content.contains("demo-cli"));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn cargo_deps_reads_the_manifest() {
+    let root = project("cargo-deps");
+    fs::write(
+        root.join("Cargo.toml"),
+        "[package]\nname = \"demo\"\n\n[dependencies]\nlog = \"0.4\"\n\
+         serde = { version = \"1.0\", features = [\"derive\"] }\n\n\
+         [dev-dependencies]\ntempfile = \"3\"\n",
+    )
+    .unwrap();
+    let root_str = root.to_string_lossy();
+    let run = |param: &str| {
+        botcoder::app::execute_tool("cargo_deps", param, &root_str).unwrap().text
+    };
+
+    let all = run("");
+    assert!(all.contains("[dependencies]\nlog = 0.4\nserde = 1.0 features=[derive]"), "{}", all);
+    assert!(all.contains("[dev-dependencies]\ntempfile = 3"), "{}", all);
+    assert_eq!(run("serde"), "[dependencies] serde = 1.0 features=[derive]");
+    assert_eq!(run("tokio"), "tokio is not a dependency in Cargo.toml");
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn clippy_json_becomes_structured_lints() {
+    let output = r#"stdout:
+{"reason":"compiler-artifact","target":{"name":"demo"}}
+{"reason":"compiler-message","message":{"level":"warning","message":"unneeded `return` statement","code":{"code":"clippy::needless_return"},"spans":[{"file_name":"src/main.rs","line_start":6,"column_start":5,"is_primary":true}],"children":[{"level":"help","message":"remove `return`","spans":[{"suggested_replacement":"1"}]}]}}
+{"reason":"compiler-message","message":{"level":"warning","message":"unneeded `return` statement","code":{"code":"clippy::needless_return"},"spans":[{"file_name":"src/main.rs","line_start":6,"column_start":5,"is_primary":true}],"children":[{"level":"help","message":"remove `return`","spans":[{"suggested_replacement":"1"}]}]}}
+{"reason":"compiler-message","message":{"level":"warning","message":"1 warning emitted","code":null,"spans":[],"children":[]}}
+stderr:
+exit_code: 0"#;
+
+    let lints = botcoder::quality::parse_lints(output);
+    assert_eq!(lints.len(), 1);
+    assert_eq!(
+        lints[0].render(),
+        "src/main.rs:6:5 warning[clippy::needless_return] unneeded `return` statement\n  help: remove `return`: `1`"
+    );
+}
+
+#[test]
+fn lookup_crate_answers_from_the_cache() {
+    let root = project("crate-cache");
+    let cache = root.join(".botcoder/cache/crates");
+    fs::create_dir_all(&cache).unwrap();
+    fs::write(cache.join("serde@latest.md"), "serde 1.0.228\n").unwrap();
+
+    // No request is made while the entry is fresh
+    let output = botcoder::app::execute_tool("lookup_crate", "Serde", &root.to_string_lossy()).unwrap();
+    assert_eq!(output.text, "serde 1.0.228\n");
+
+    // Names and versions that could lead the cache path elsewhere are refused
+    // before anything is read or fetched
+    for param in ["../stolen", "serde@../../stolen", "serde@1.0", "/etc/passwd", "serde@1.0.0/../x"] {
+        let result = botcoder::app::execute_tool("lookup_crate", param, &root.to_string_lossy());
+        assert!(matches!(result, Err(ToolError::InvalidArgs(_))), "{}", param);
+    }
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn fetched_html_becomes_readable_text() {
+    let html = "<!DOCTYPE html><html><head><title>E0502 &amp; borrows</title>\
+        <script>var x = 1;</script></head><body><nav>Home | Docs</nav>\
+        <h1>Error code E0502</h1><p>A variable was borrowed as <code>mut</code>\n   while \
+        borrowed.</p><ul><li>First</li><li>Second</li></ul>\
+        <pre>let a = &amp;mut x;\n    let b = &amp;x;</pre><footer>(c) 2024</footer></body></html>";
+
+    assert_eq!(
+        botcoder::fetch::html_to_text(html),
+        "# E0502 & borrows\n\n# Error code E0502\n\nA variable was borrowed as `mut` while borrowed.\n\n\
+         - First\n- Second\n\n```\nlet a = &mut x;\n    let b = &x;\n```"
+    );
+}
+
+#[test]
+fn hooks_run_around_tools() {
+    let root = project("hooks");
+    fs::write(
+        root.join(".botcoder.toml"),
+        "[hooks.before]\nexecute_command = [\"exit 3\"]\n\n\
+         [hooks.after]\nappend_to_file = [\"echo {tool} {path} >> hooked.txt\"]\n",
+    )
+    .unwrap();
+    let root_str = root.to_string_lossy();
+
+    botcoder::app::execute_tool("append_to_file", "notes.txt:::world", &root_str).unwrap();
+    assert_eq!(fs::read_to_string(root.join("hooked.txt")).unwrap(), "append_to_file notes.txt\n");
+
+    let blocked = botcoder::app::execute_tool("execute_command", "touch ran.txt", &root_str);
+    let Err(e) = blocked else { panic!("the before hook should block the command") };
+    assert!(e.to_string().starts_with("Hook `exit 3` failed"), "{}", e);
+    assert!(!root.join("ran.txt").exists());
+
+    // The path is one shell word, not more commands
+    let path = "a; touch injected.txt; 'b'.txt";
+    botcoder::app::execute_tool("append_to_file", &format!("{}:::world", path), &root_str).unwrap();
+    assert!(!root.join("injected.txt").exists());
+    let hooked = fs::read_to_string(root.join("hooked.txt")).unwrap();
+    assert!(hooked.ends_with(&format!("append_to_file {}\n", path)), "{}", hooked);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn hooks_skip_calls_the_project_refuses() {
+    let root = project("hooks-refused");
+    fs::write(
+        root.join(".botcoder.toml"),
+        "[write]\nread_only = [\"notes.txt\"]\n\n\
+         [hooks.before]\n\"*\" = [\"echo {tool} >> hooked.txt\"]\n",
+    )
+    .unwrap();
+    let root_str = root.to_string_lossy();
+
+    assert!(botcoder::app::execute_tool("append_to_file", "notes.txt:::world", &root_str).is_err());
+    assert!(botcoder::app::execute_tool("read_file", "../outside.txt", &root_str).is_err());
+    assert!(!root.join("hooked.txt").exists());
+
+    botcoder::app::execute_tool("read_file", "notes.txt", &root_str).unwrap();
+    assert_eq!(fs::read_to_string(root.join("hooked.txt")).unwrap(), "read_file\n");
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn python_projects_use_pytest() {
+    use botcoder::project::ProjectKind;
+
+    let root = project("python");
+    fs::write(root.join("pyproject.toml"), "[project]\nname = \"demo\"\n").unwrap();
+    let kind = ProjectKind::detect(&root.to_string_lossy());
+    assert_eq!(kind, ProjectKind::Python);
+    assert!(kind.prompt_section().contains("python -m pytest"));
+
+    let stdout = "F..\n\
+        ___________________________ test_add ___________________________\n\
+        E   assert 3 == 4\n\
+        ==================== short test summary info ====================\n\
+        FAILED tests/test_math.py::test_add - assert 3 == 4\n\
+        1 failed, 2 passed in 0.05s\n";
+    let output = app::ToolOutput::process(stdout.to_string(), String::new(), 1);
+    let report = botcoder::test_runner::parse_test_output(&output);
+    assert_eq!((report.passed, report.failed), (2, 1));
+    assert_eq!(report.failing, ["tests/test_math.py::test_add"]);
+    assert!(report.failures[0].contains("assert 3 == 4"));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn commands_can_run_in_a_subdirectory_with_extra_environment() {
+    let root = project("command-options");
+    let root_str = root.to_str().unwrap();
+    fs::create_dir_all(root.join("crates/api")).unwrap();
+
+    let found = app::extract_tools(r#"execute_command("pwd; echo $FOO", cwd="crates/api", env="FOO=bar BAZ=1")"#);
+    assert_eq!(found, [("execute_command".to_string(), "pwd; echo $FOO:::cwd=crates/api BAZ=1 FOO=bar".to_string())]);
+    let output = app::execute_tool(&found[0].0, &found[0].1, root_str).unwrap();
+    assert!(output.text.contains("crates/api") && output.text.contains("bar"), "{}", output.text);
+
+    // The directory must be inside the project
+    let outside = app::execute_tool("execute_command", "pwd:::cwd=../elsewhere", root_str);
+    assert!(matches!(outside, Err(ToolError::OutsideProject(_))));
+    let (_, diagnostics) = app::extract_tools_checked(r#"execute_command("ls", env="not a pair")"#);
+    assert!(diagnostics[0].contains("was not run"), "{:?}", diagnostics);
+
+    // .botcoder.toml defaults, which the call's own options override
+    fs::write(root.join(".botcoder.toml"), "[commands]\ncwd = \"crates\"\nenv = { FOO = \"default\", BAZ = \"2\" }\n").unwrap();
+    let output = app::execute_tool("execute_command", "pwd; echo $FOO $BAZ:::FOO=mine", root_str).unwrap();
+    assert!(output.text.contains("/crates\n") && output.text.contains("mine 2"), "{}", output.text);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn command_results_are_structured() {
+    let root = project("structured-output");
+    let root_str = root.to_str().unwrap();
+
+    let output = app::execute_tool("execute_command", "echo out; sleep 0.1; echo err >&2; exit 3", root_str).unwrap();
+    assert_eq!(output.kind, app::OutputKind::Command);
+    assert_eq!((output.exit_code, output.stdout.as_str(), output.stderr.as_str()), (Some(3), "out\n", "err\n"));
+    assert!(output.duration >= std::time::Duration::from_millis(100));
+    assert_eq!(output.text, "stdout:\nout\nstderr:\nerr\nexit_code: 3");
+    let status = output.status();
+    assert!(status.summary().starts_with("exit 3 · "), "{}", status.summary());
+    assert!(status.summary().ends_with(" · 4 bytes out · 4 bytes err"), "{}", status.summary());
+
+    // Empty streams are left out, and long ones cut in the middle for the
+    // model while kept whole in the output
+    let output = app::execute_tool("execute_command", "true", root_str).unwrap();
+    assert_eq!(output.text, "exit_code: 0");
+    let output = app::execute_tool("execute_command", "seq 1 50000", root_str).unwrap();
+    assert!(output.truncated && output.text.contains("cut ...]"), "{}", output.text.len());
+    assert!(output.text.starts_with("stdout:\n1\n2\n") && output.text.ends_with("50000\nexit_code: 0"));
+    assert!(output.text.len() < output.stdout.len());
+
+    // Tool events carry the status
+    let mock = MockProvider::new(Vec::<&str>::new());
+    let mut engine = engine(&mock, &root);
+    let (observer, events) = botcoder::events::channel();
+    engine.subscribe(observer);
+    engine.queue_tool("execute_command", "exit 2");
+    engine.execute_next();
+    let status = events.try_iter().find_map(|event| match event {
+        AgentEvent::ToolExecuted { status, .. } => status,
+        _ => None,
+    });
+    assert_eq!(status.and_then(|status| status.exit_code), Some(2));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn command_output_is_sanitized() {
+    assert_eq!(botcoder::sanitize::clean("\x1b[1;31merror\x1b[0m: bad\n"), "error: bad\n");
+    assert_eq!(botcoder::sanitize::clean("\x1b]0;title\x07done\r\n"), "done\n");
+    assert_eq!(botcoder::sanitize::clean("  10%\r  50%\r 100%\rfinished\nnext"), "finished\nnext");
+    assert_eq!(botcoder::sanitize::clean("abc\x08\x08d\tx\x00"), "ad\tx");
+    assert_eq!(botcoder::sanitize::clean("plain text\n"), "plain text\n");
+
+    let root = project("sanitize");
+    let command = r"printf '\033[32mok\033[0m\r\n'; printf 'a\rb\rc\n' >&2";
+    let output = app::execute_tool("execute_command", command, root.to_str().unwrap()).unwrap();
+    assert_eq!((output.stdout.as_str(), output.stderr.as_str()), ("ok\n", "c\n"));
+    assert_eq!(output.text, "stdout:\nok\nstderr:\nc\nexit_code: 0");
+
+    fs::remove_dir_all(root).unwrap();
+}
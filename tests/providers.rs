@@ -0,0 +1,323 @@
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use botcoder::app;
+use botcoder::bedrock::{self, AwsCredentials};
+use botcoder::entra::{EntraCredential, TokenSource};
+use botcoder::http;
+use botcoder::limits::LimitProfiles;
+use botcoder::llm::{self, ChatMessage};
+use botcoder::mock::MockProvider;
+use botcoder::router::{ModelRouter, Role};
+use botcoder::tpm_limiter::TPMLimiter;
+use botcoder::LlmError;
+
+mod common;
+use common::{engine, project};
+
+#[tokio::test]
+async fn entra_tokens_are_cached_until_invalidated() {
+    let fetches = Arc::new(AtomicUsize::new(0));
+    let counter = fetches.clone();
+    let app = axum::Router::new().route(
+        "/tenant/oauth2/v2.0/token",
+        axum::routing::post(move |body: String| async move {
+            assert!(body.contains("grant_type=client_credentials"), "{}", body);
+            let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            axum::Json(serde_json::json!({"access_token": format!("token-{}", n), "expires_in": 3599}))
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let authority = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let source = TokenSource::ClientSecret {
+        authority,
+        tenant: "tenant".to_string(),
+        client_id: "app".to_string(),
+        secret: "secret".to_string(),
+    };
+    let credential = EntraCredential::new(source, reqwest::Client::new());
+    assert_eq!(credential.token().await.unwrap(), "token-1");
+    assert_eq!(credential.token().await.unwrap(), "token-1");
+    credential.invalidate().await;
+    assert_eq!(credential.token().await.unwrap(), "token-2");
+    assert_eq!(fetches.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn connection_failures_say_why() {
+    let err = http::ca_bundle("/nonexistent/ca.pem").unwrap_err();
+    assert!(err.to_string().contains("Could not read CA bundle /nonexistent/ca.pem"), "{}", err);
+
+    // Nothing listens on port 1
+    let url = "http://127.0.0.1:1/chat/completions?api-version=1";
+    let err = http::client().unwrap().get(url).send().await.unwrap_err();
+    let message = http::connection_error(url, err).to_string();
+    assert!(message.starts_with("Could not reach http://127.0.0.1:1/chat/completions: "), "{}", message);
+    assert!(message.contains("proxy"), "{}", message);
+}
+
+#[tokio::test]
+async fn openai_compatible_endpoints_are_called_with_their_own_header() {
+    let app = axum::Router::new().route(
+        "/v1/chat/completions",
+        axum::routing::post(|headers: axum::http::HeaderMap, body: String| async move {
+            assert_eq!(headers["x-api-key"], "secret");
+            assert!(body.contains("\"model\":\"meta-llama/llama-3.1-8b\""), "{}", body);
+            // No id, usage or finish_reason, as some local servers reply
+            axum::Json(serde_json::json!({
+                "choices": [{"message": {"role": "assistant", "content": "Done.", "reasoning": "Easy"}}]
+            }))
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/v1", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let vars = HashMap::from([
+        ("COMPAT_TEST_PROVIDER", "openai"),
+        ("COMPAT_TEST_URL", &url),
+        ("COMPAT_TEST_MODEL", "meta-llama/llama-3.1-8b"),
+        ("COMPAT_TEST_KEY", "secret"),
+        ("COMPAT_TEST_AUTH_HEADER", "x-api-key"),
+    ]);
+    let client = llm::client_from("COMPAT_TEST", &|name| vars.get(name).map(|v| v.to_string())).unwrap();
+    assert_eq!(client.model(), "meta-llama/llama-3.1-8b");
+
+    let text = client
+        .generate_chat(&[ChatMessage::user("Hi")], &serde_json::json!({}))
+        .await
+        .unwrap();
+    assert_eq!(text, "<think>Easy</think>\nDone.");
+}
+
+#[tokio::test]
+async fn bedrock_requests_are_signed_and_report_usage() {
+    // get-vanilla from the AWS SigV4 test suite
+    let credentials = AwsCredentials {
+        access_key_id: "AKIDEXAMPLE".to_string(),
+        secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+        session_token: None,
+    };
+    let headers = [
+        ("host", "example.amazonaws.com".to_string()),
+        ("x-amz-date", "20150830T123600Z".to_string()),
+    ];
+    let authorization = bedrock::sign(
+        &credentials, "us-east-1", "service", "GET", "/", &headers, b"", "20150830T123600Z",
+    );
+    assert_eq!(
+        authorization,
+        "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+         SignedHeaders=host;x-amz-date, \
+         Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+    );
+    let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_440_938_160);
+    assert_eq!(bedrock::amz_date(time), "20150830T123600Z");
+
+    let app = axum::Router::new().route(
+        "/model/{model}/converse",
+        axum::routing::post(|headers: axum::http::HeaderMap, body: String| async move {
+            let auth = headers["authorization"].to_str().unwrap();
+            assert!(auth.contains("/us-west-2/bedrock/aws4_request"), "{}", auth);
+            // Consecutive user turns are merged into one
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(body["system"][0]["text"], "Be brief");
+            assert_eq!(body["messages"].as_array().unwrap().len(), 1);
+            axum::Json(serde_json::json!({
+                "output": {"message": {"role": "assistant", "content": [{"text": "Done."}]}},
+                "usage": {"inputTokens": 12, "outputTokens": 3, "totalTokens": 15},
+                "stopReason": "end_turn"
+            }))
+        }),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let vars = HashMap::from([
+        ("BEDROCK_TEST_PROVIDER", "bedrock"),
+        ("BEDROCK_TEST_URL", &url),
+        ("BEDROCK_TEST_REGION", "us-west-2"),
+        ("BEDROCK_TEST_MODEL", "anthropic.claude-3-5-sonnet-20240620-v1:0"),
+        ("AWS_ACCESS_KEY_ID", "AKIDEXAMPLE"),
+        ("AWS_SECRET_ACCESS_KEY", "secret"),
+    ]);
+    let client = llm::client_from("BEDROCK_TEST", &|name| vars.get(name).map(|v| v.to_string())).unwrap();
+    let messages = [
+        ChatMessage::system("Be brief"),
+        ChatMessage::user("Earlier turns"),
+        ChatMessage::user("Hi"),
+    ];
+    let text = client.generate_chat(&messages, &serde_json::json!({})).await.unwrap();
+    assert_eq!(text, "Done.");
+    assert_eq!(client.last_usage().unwrap().total_tokens, 15);
+}
+
+#[tokio::test]
+async fn content_filters_rewrite_prompts_and_block_replies() {
+    let root = project("filters");
+    fs::write(
+        root.join(".botcoder.toml"),
+        r#"
+[[filters.prompt]]
+pattern = "tok-[0-9]+"
+replace = "[redacted]"
+
+[[filters.prompt]]
+script = "sed s/hello/howdy/"
+
+[[filters.response]]
+pattern = "rm\\s+-rf\\s+/"
+action = "block"
+message = "no recursive deletes"
+"#,
+    )
+    .unwrap();
+    let mock = MockProvider::new(["execute_command(\"rm -rf /\")", "Done."]);
+    let mut engine = engine(&mock, &root);
+    engine.push_user("Say hello with tok-1234");
+
+    engine.step().await.unwrap();
+    let sent = mock.requests()[0].last().unwrap().content.clone();
+    assert_eq!(sent.trim(), "Say howdy with [redacted]");
+    assert!(engine.executed_tools().is_empty());
+    let history: Vec<&str> = engine.history().iter().map(|m| m.content.as_str()).collect();
+    assert_eq!(history[1], "[reply blocked by a content filter: no recursive deletes]");
+    assert!(history[2].contains("blocked by the project's content filter (no recursive deletes)"));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn requests_wait_for_a_free_slot_and_the_rpm_window() {
+    assert_eq!(llm::default_rpm(20_000), 120);
+    let limiter = llm::RateLimiter::with_limits(1_000_000, 2, 1);
+    let wait = std::time::Duration::from_millis(100);
+
+    let first = limiter.wait_if_needed(10).await;
+    // The only slot is taken until the first request finishes
+    assert!(tokio::time::timeout(wait, limiter.wait_if_needed(10)).await.is_err());
+    drop(first);
+    drop(limiter.wait_if_needed(10).await);
+    assert_eq!(limiter.get_current_rpm().await, 2);
+
+    // Plenty of tokens left, but no requests
+    assert!(tokio::time::timeout(wait, limiter.wait_if_needed(10)).await.is_err());
+    assert_eq!(limiter.get_current_tpm().await, 20);
+}
+
+#[test]
+fn rate_limit_waits_are_reported_instead_of_slept() {
+    let mut limiter = TPMLimiter::new(100, 0);
+    assert!(limiter.wait_time().is_none());
+    limiter.add_token_usage(60);
+    limiter.add_token_usage(60);
+    let (wait, limit) = limiter.wait_time().unwrap();
+    assert_eq!(limit, "TPM window");
+    assert!(wait > std::time::Duration::from_secs(58) && wait <= std::time::Duration::from_secs(60));
+
+    let mut limiter = TPMLimiter::new(1_000_000, 5);
+    limiter.add_token_usage(10);
+    let (wait, limit) = limiter.wait_time().unwrap();
+    assert_eq!(limit, "request interval");
+    assert!(wait <= std::time::Duration::from_secs(5));
+}
+
+#[test]
+fn limit_profiles_follow_the_model() {
+    let root = project("limits");
+    fs::write(
+        root.join(".botcoder.toml"),
+        r#"
+[limits.big]
+models = ["gpt-4o*", "mo*k"]
+tpm = 450000
+min_interval = 0
+
+[limits.small]
+provider = "azure"
+tpm = 20000
+rpm = 100
+burst = 2
+"#,
+    )
+    .unwrap();
+    let profiles = LimitProfiles::load(root.to_str().unwrap());
+    let big = profiles.for_model("openai", "gpt-4o-mini").unwrap();
+    assert_eq!((big.name.as_str(), big.tpm, big.rpm, big.min_interval), ("big", 450_000, 2_700, 0));
+    let small = profiles.for_model("azure", "gpt-4").unwrap();
+    assert_eq!((small.tpm, small.rpm, small.burst), (20_000, 100, 2));
+    assert!(profiles.for_model("bedrock", "claude").is_none());
+
+    let mock = MockProvider::new(["Done."]);
+    let mut engine = engine(&mock, &root);
+    engine.select_limits();
+    assert_eq!(engine.tpm_limiter().profile(), "big");
+    assert_eq!(engine.tpm_limiter().max_tpm(), 450_000);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn token_history_keeps_the_last_half_hour_per_minute() {
+    let mut stats = app::Stats::default();
+    stats.record(100, 500);
+    stats.record(100, 250);
+    stats.record(102, 40);
+
+    let per_minute = stats.per_minute(103);
+    assert_eq!(per_minute.len(), app::HISTORY_MINUTES);
+    assert_eq!(&per_minute[per_minute.len() - 4..], &[750, 0, 40, 0]);
+
+    stats.record(140, 1);
+    assert_eq!(stats.history.len(), 1);
+    assert_eq!(stats.per_minute(140).iter().sum::<u64>(), 1);
+}
+
+#[tokio::test]
+async fn only_errors_another_model_may_not_hit_fall_back() {
+    let messages = [ChatMessage::user("hi")];
+    let config = serde_json::json!({});
+    let api = |status: u16, body: &str| LlmError::Api {
+        status,
+        body: body.to_string(),
+    };
+
+    // Auth, a missing deployment and the content filter go to the next model
+    for error in [
+        api(401, "Unauthorized"),
+        api(404, "The API deployment for this resource does not exist"),
+        api(400, r#"{"error": {"code": "content_filter"}}"#),
+    ] {
+        let primary = MockProvider::new(Vec::<&str>::new()).named("primary").failing([error]);
+        let backup = MockProvider::new(["from the backup"]).named("backup");
+        let router = ModelRouter::single(primary).with_fallback(backup.clone());
+        let generation = router.generate(Role::Planner, &messages, &config).await.unwrap();
+        assert_eq!((generation.text.as_str(), generation.model.as_str()), ("from the backup", "backup"));
+        assert_eq!(generation.fallbacks.len(), 1);
+        assert!(generation.fallbacks[0].starts_with("primary failed (API error"), "{:?}", generation.fallbacks);
+        assert!(generation.fallbacks[0].ends_with("falling back to backup"), "{:?}", generation.fallbacks);
+        assert_eq!(backup.requests().len(), 1);
+    }
+
+    // Rate limits and timeouts are the caller's to retry on the same model
+    for error in [
+        api(429, "Rate limit reached"),
+        LlmError::Connection("https://example.com: operation timed out".to_string()),
+    ] {
+        let primary = MockProvider::new(Vec::<&str>::new()).named("primary").failing([error]);
+        let backup = MockProvider::new(["from the backup"]).named("backup");
+        let router = ModelRouter::single(primary).with_fallback(backup.clone());
+        let result = router.generate(Role::Planner, &messages, &config).await;
+        assert!(matches!(result, Err(LlmError::Api { status: 429, .. } | LlmError::Connection(_))), "{:?}", result.err());
+        assert!(backup.requests().is_empty());
+    }
+}
@@ -0,0 +1,128 @@
+use std::fs;
+
+use botcoder::events::AgentEvent;
+use botcoder::mock::{MockProvider, Recorder};
+use botcoder::oneshot;
+use botcoder::replay::{self, Replay};
+use botcoder::timeline::Timeline;
+
+mod common;
+use common::{engine, project};
+
+#[tokio::test]
+async fn replays_read_then_edit() {
+    let root = project("edit");
+    let mock = MockProvider::from_file("tests/fixtures/edit_notes.json").unwrap();
+    let mut engine = engine(&mock, &root);
+
+    engine.push_user("Greet the world in notes.txt");
+    let succeeded = engine.run_to_completion(5).await.unwrap();
+
+    assert!(!succeeded);
+    assert_eq!(mock.remaining(), 0);
+    assert_eq!(fs::read_to_string(root.join("notes.txt")).unwrap(), "hello, world\n");
+
+    // The read's result is fed back before the edit is requested
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 3);
+    let last = requests[1].last().unwrap();
+    assert!(last.is_tool_results());
+    assert!(last.content.contains("read_file: hello"));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn malformed_reply_gets_corrective_retry() {
+    let root = project("retry");
+    let mock = MockProvider::new([
+        "I'll use read_file on notes.txt",
+        "read_file(\"notes.txt\")",
+        "Done.",
+    ]);
+    let mut engine = engine(&mock, &root);
+
+    engine.push_user("Read notes.txt");
+    assert_eq!(engine.step().await.unwrap(), Some(1));
+
+    let requests = mock.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(engine.iteration(), 1);
+    let feedback = requests[1].last().unwrap();
+    assert_eq!(feedback.role, "user");
+    assert!(!feedback.is_tool_results());
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn exhausted_fixture_is_an_error() {
+    let root = project("exhausted");
+    let mock = MockProvider::new(Vec::<String>::new());
+    let mut engine = engine(&mock, &root);
+
+    engine.push_user("Anything");
+    assert!(engine.step().await.is_err());
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn recorded_fixture_replays() {
+    let root = project("record");
+    let fixture = root.join("fixture.json");
+    let recorder = Recorder::new(&fixture);
+    recorder.record("first");
+    recorder.record("second\nwith two lines");
+
+    let mock = MockProvider::from_file(&fixture).unwrap();
+    assert_eq!(mock.remaining(), 2);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn recorded_runs_replay_step_by_step_without_running_anything() {
+    let root = project("replay");
+    let mock = MockProvider::new([
+        "CHANGE: notes.txt\n<<<<<<< CURRENT\nhello\n=======\nhello again\n>>>>>>> NEW",
+        "The notes are updated.",
+    ]);
+    let mut recorded = engine(&mock, &root);
+    recorded.subscribe(Timeline::new(root.to_str().unwrap(), "run-1"));
+    oneshot::run(&mut recorded, "Update the notes", 5).await.unwrap();
+    fs::write(root.join("notes.txt"), "hello\n").unwrap();
+
+    let records = replay::load(root.to_str().unwrap(), "run-1").unwrap();
+    let mut replay = Replay::new("run-1", &records);
+    assert_eq!(replay.status(), "▶ Replay run-1 0/5 at 1×");
+    let mut kinds = Vec::new();
+    while let Some(step) = replay.next_step() {
+        kinds.push(
+            step.iter()
+                .map(|event| match event {
+                    AgentEvent::IterationStarted { .. } => "iteration",
+                    AgentEvent::UserMessage { .. } => "user",
+                    AgentEvent::LlmRequest { .. } => "request",
+                    AgentEvent::LlmResponse { .. } => "reply",
+                    AgentEvent::ToolExecuted { .. } => "tool",
+                    AgentEvent::FileModified { .. } => "modified",
+                    _ => "other",
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+        );
+    }
+    assert_eq!(
+        kinds,
+        ["iteration user request", "reply", "tool modified", "iteration request", "reply"]
+    );
+    replay.faster();
+    assert_eq!(replay.status(), "■ Replay run-1 5/5 at 2×");
+    assert_eq!(replay.delay().as_millis(), 500);
+    // Played back, not run again
+    assert_eq!(fs::read_to_string(root.join("notes.txt")).unwrap(), "hello\n");
+    assert!(replay::load(root.to_str().unwrap(), "missing").is_err());
+
+    fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,217 @@
+use std::{
+    fs,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use botcoder::bench;
+use botcoder::exit;
+use botcoder::mock::MockProvider;
+use botcoder::oneshot::{self, RunEnd};
+use botcoder::orchestrator::{self, Overview, Phase, SessionFactory};
+use botcoder::queue::{self, TaskOutcome, TaskQueue};
+use botcoder::webhooks::{self, RunOutcome, RunSummary};
+use botcoder::{AgentError, LlmError, ToolError};
+
+mod common;
+use common::{engine, git, init_repo, project};
+
+#[tokio::test]
+async fn one_shot_runs_stop_when_answered_or_out_of_iterations() {
+    let root = project("oneshot");
+    let mock = MockProvider::new(["read_file(\"notes.txt\")", "The notes just say hello."]);
+    let mut answered = engine(&mock, &root);
+    let end = oneshot::run(&mut answered, "What do the notes say?", 5).await.unwrap();
+    assert_eq!((end.exit_code(), answered.last_reply()), (0, Some("The notes just say hello.")));
+    assert_eq!(end, RunEnd::Answered);
+
+    let mock = MockProvider::new(["read_file(\"notes.txt\")", "read_file(\"notes.txt\")", "Done."]);
+    let mut limited = engine(&mock, &root);
+    let end = oneshot::run(&mut limited, "Keep reading", 2).await.unwrap();
+    assert_eq!(end, RunEnd::OutOfIterations);
+    assert_eq!(end.exit_code(), 3);
+    assert_eq!(mock.requests().len(), 2);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn headless_runs_exit_with_stable_codes_and_write_result_json() {
+    let blocked = AgentError::Tool(ToolError::OutsideProject("/etc/passwd".into()));
+    let provider = AgentError::Llm(LlmError::MissingConfig("AZURE_OPENAI_KEY".into()));
+    assert_eq!((blocked.exit_code(), provider.exit_code()), (exit::POLICY_VIOLATION, exit::PROVIDER_ERROR));
+
+    let root = project("result");
+    let mock = MockProvider::new([
+        "CHANGE: notes.txt\n<<<<<<< CURRENT\nhello\n=======\nhello again\n>>>>>>> NEW",
+        "The notes are updated.",
+    ]);
+    let mut engine = engine(&mock, &root);
+    let end = oneshot::run(&mut engine, "Update the notes", 1).await.unwrap();
+    let summary = RunSummary::new(RunOutcome::BudgetExceeded, "run-1", "Update the notes")
+        .with_usage(engine.iteration(), 0)
+        .with_files(engine.modified_files())
+        .with_success_check("tests_pass", engine.succeeded())
+        .with_exit_code(end.exit_code());
+    let path = root.join(webhooks::RESULT_FILE);
+    summary.save(&path).unwrap();
+
+    let result: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(result["exit_code"], exit::BUDGET_EXCEEDED);
+    assert_eq!(result["outcome"], "budget_exceeded");
+    assert_eq!(result["iterations"], 1);
+    assert_eq!(result["files_changed"], serde_json::json!(["notes.txt"]));
+    assert_eq!(result["success"], serde_json::json!({ "criteria": "tests_pass", "met": false }));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn bench_runs_task_fixtures_and_compares_reports() {
+    let root = project("bench");
+    let tasks = root.join("tasks");
+    for (name, task) in [
+        ("greet", "instruction = \"Say hello again\"\ncheck = \"grep -q again notes.txt\"\n"),
+        ("explain", "instruction = \"What do the notes say?\"\n"),
+    ] {
+        fs::create_dir_all(tasks.join(name).join("repo")).unwrap();
+        fs::write(tasks.join(name).join("task.toml"), task).unwrap();
+        fs::write(tasks.join(name).join("repo/notes.txt"), "hello\n").unwrap();
+    }
+
+    // Tasks run in name order: explain, then greet
+    let replies = [
+        vec!["They say hello."],
+        vec!["CHANGE: notes.txt\n<<<<<<< CURRENT\nhello\n=======\nhello again\n>>>>>>> NEW", "Done."],
+    ];
+    let started = AtomicUsize::new(0);
+    let report = bench::run(&tasks, 5, |task_root| {
+        let mock = MockProvider::new(replies[started.fetch_add(1, Ordering::SeqCst)].clone());
+        Ok(engine(&mock, Path::new(task_root)))
+    })
+    .await
+    .unwrap();
+
+    let outcomes: Vec<(&str, bool, u32)> =
+        report.tasks.iter().map(|t| (t.name.as_str(), t.success, t.iterations)).collect();
+    assert_eq!(outcomes, [("explain", false, 1), ("greet", true, 2)]);
+    assert_eq!(report.success_rate(), 0.5);
+    // The snapshots are worked on as copies
+    assert_eq!(fs::read_to_string(tasks.join("greet/repo/notes.txt")).unwrap(), "hello\n");
+
+    let path = report.save(root.to_str().unwrap()).unwrap();
+    let mut baseline = bench::load_report(&path).unwrap();
+    baseline.tasks[0].success = true;
+    baseline.tasks[1].success = false;
+    let comparison = report.compare(&baseline);
+    assert!(comparison.contains("✓ greet now succeeds"), "{}", comparison);
+    assert!(comparison.contains("✗ explain now fails"), "{}", comparison);
+    assert!(report.render().contains("Success 50%"));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn queue_runs_tasks_in_order_and_checkpoints_between_them() {
+    let root = project("queue");
+    let root_str = root.to_str().unwrap();
+    init_repo(&root);
+
+    assert!(TaskQueue::parse("[[tasks]]\nname = \"a\"\n").is_err());
+    let yaml = root.join("tasks.yaml");
+    fs::write(&yaml, "tasks:\n  - instruction: Say hello again\n").unwrap();
+    let Err(e) = TaskQueue::load(&yaml) else { panic!("YAML should be refused") };
+    assert!(e.to_string().contains("use TOML or JSON"), "{}", e);
+    fs::remove_file(yaml).unwrap();
+    let queue = TaskQueue::parse(
+        "[[tasks]]\nname = \"greet\"\ninstruction = \"Say hello again\"\ncheck = \"grep -q again notes.txt\"\n\n[[tasks]]\ninstruction = \"Break the notes\"\ncheck = \"grep -q again notes.txt\"\nmax_iterations = 3\n",
+    )
+    .unwrap();
+    assert_eq!(queue.tasks[1].name, "task-2");
+    assert_eq!(queue.tasks[1].max_iterations, Some(3));
+
+    let replies = [
+        vec!["CHANGE: notes.txt\n<<<<<<< CURRENT\nhello\n=======\nhello again\n>>>>>>> NEW", "Done."],
+        vec!["CHANGE: notes.txt\n<<<<<<< CURRENT\nhello again\n=======\nbroken\n>>>>>>> NEW", "Done."],
+    ];
+    let started = AtomicUsize::new(0);
+    let report = queue::run(&queue, root_str, 5, || {
+        let mock = MockProvider::new(replies[started.fetch_add(1, Ordering::SeqCst)].clone());
+        Ok(engine(&mock, &root))
+    })
+    .await
+    .unwrap();
+
+    let outcomes: Vec<(&str, TaskOutcome, Option<&str>)> = report
+        .tasks
+        .iter()
+        .map(|t| (t.name.as_str(), t.outcome, t.checkpoint.as_deref()))
+        .collect();
+    assert_eq!(
+        outcomes,
+        [
+            ("greet", TaskOutcome::Succeeded, Some("committed")),
+            ("task-2", TaskOutcome::Failed, Some("reverted, changes in the git stash")),
+        ]
+    );
+    // The failed task's change is undone, the good one committed
+    assert_eq!(fs::read_to_string(root.join("notes.txt")).unwrap(), "hello again\n");
+    let log = git(&root, &["log", "--format=%s", "-1"]);
+    assert_eq!(String::from_utf8_lossy(&log.stdout).trim(), "botcoder queue: greet");
+    assert_eq!(report.exit_code(), exit::GAVE_UP);
+    assert!(report.render().contains("1/2 succeeded"), "{}", report.render());
+    let saved = report.save(root_str).unwrap();
+    assert!(fs::read_to_string(saved).unwrap().contains("\"outcome\": \"failed\""));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn parallel_sessions_work_in_their_own_worktrees() {
+    let root = project("parallel");
+    let root_str = root.to_str().unwrap().to_string();
+    init_repo(&root);
+
+    let queue = TaskQueue::parse_json(
+        r#"[{"name": "greet", "instruction": "Say hello again", "check": "grep -q again notes.txt"},
+            {"name": "shout", "instruction": "Shout it", "check": "grep -q HELLO notes.txt"}]"#,
+    )
+    .unwrap();
+    // Sessions are told apart by their worktree
+    let new_engine: Arc<SessionFactory> = Arc::new(|worktree: &str| {
+        let reply = if worktree.ends_with("greet") {
+            "CHANGE: notes.txt\n<<<<<<< CURRENT\nhello\n=======\nhello again\n>>>>>>> NEW"
+        } else {
+            "CHANGE: notes.txt\n<<<<<<< CURRENT\nhello\n=======\nhello!\n>>>>>>> NEW"
+        };
+        let mock = MockProvider::new([reply, "Done."]);
+        Ok(engine(&mock, Path::new(worktree)))
+    });
+    let worktrees = root.with_file_name(format!("{}-worktrees", root.file_name().unwrap().to_string_lossy()));
+    let overview = Overview::quiet();
+    let report = orchestrator::run(queue, root_str.clone(), 2, 5, worktrees.clone(), new_engine, overview.clone())
+        .await
+        .unwrap();
+
+    let outcomes: Vec<(&str, TaskOutcome)> = report.tasks.iter().map(|t| (t.name.as_str(), t.outcome)).collect();
+    assert_eq!(outcomes, [("greet", TaskOutcome::Succeeded), ("shout", TaskOutcome::Failed)], "{}", report.render());
+    let sessions = overview.sessions();
+    assert_eq!(sessions[0].phase, Phase::Done(TaskOutcome::Succeeded));
+    assert_eq!(sessions[1].iteration, 2);
+    // The project itself is untouched; the good change is committed on its
+    // branch and the failed one left in its worktree
+    assert_eq!(fs::read_to_string(root.join("notes.txt")).unwrap(), "hello\n");
+    let log = git(&root, &["log", "--format=%s", "-1", &sessions[0].branch]);
+    assert_eq!(String::from_utf8_lossy(&log.stdout).trim(), "botcoder: greet");
+    assert_eq!(fs::read_to_string(Path::new(&sessions[1].worktree).join("notes.txt")).unwrap(), "hello!\n");
+    assert_eq!(
+        report.tasks[1].checkpoint.as_deref(),
+        Some(format!("left in {}", sessions[1].worktree).as_str())
+    );
+
+    fs::remove_dir_all(root).unwrap();
+    fs::remove_dir_all(worktrees).unwrap();
+}
@@ -0,0 +1,77 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+};
+
+use botcoder::sandbox::SandboxConfig;
+
+mod common;
+use common::project;
+
+fn config(vars: &[(&str, &str)]) -> Option<SandboxConfig> {
+    let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+    SandboxConfig::from_vars(&|name| vars.get(name).map(|v| v.to_string()))
+}
+
+#[test]
+fn sandbox_is_off_unless_a_runtime_is_named() {
+    assert!(config(&[]).is_none());
+    assert!(config(&[("BOTCODER_SANDBOX", "off")]).is_none());
+    assert!(config(&[("BOTCODER_SANDBOX", "")]).is_none());
+
+    // No network and no extra mounts by default
+    let sandbox = config(&[("BOTCODER_SANDBOX", "podman")]).unwrap();
+    assert_eq!(sandbox.runtime, "podman");
+    assert_eq!(sandbox.image, "rust:latest");
+    assert!(!sandbox.network);
+    assert!(sandbox.mounts.is_empty());
+
+    let sandbox = config(&[
+        ("BOTCODER_SANDBOX", "docker"),
+        ("BOTCODER_SANDBOX_IMAGE", "python:3.12"),
+        ("BOTCODER_SANDBOX_NETWORK", "on"),
+        ("BOTCODER_SANDBOX_MOUNTS", "/cache:/cache, ,/data:/data:ro"),
+    ])
+    .unwrap();
+    assert_eq!(sandbox.image, "python:3.12");
+    assert!(sandbox.network);
+    assert_eq!(sandbox.mounts, ["/cache:/cache", "/data:/data:ro"]);
+}
+
+#[test]
+fn sandboxed_commands_run_in_the_container_with_the_project_mounted() {
+    let root = project("sandbox");
+    let host_root = fs::canonicalize(&root).unwrap().display().to_string();
+    let env = BTreeMap::from([("RUST_LOG".to_string(), "debug".to_string())]);
+    let args = |sandbox: &SandboxConfig, dir: Option<&str>| -> Vec<String> {
+        let cmd = sandbox.command("cargo test", root.to_str().unwrap(), dir, &env);
+        assert_eq!(cmd.get_program(), "docker");
+        cmd.get_args().map(|a| a.to_string_lossy().into_owned()).collect()
+    };
+
+    let sandbox = config(&[("BOTCODER_SANDBOX", "docker"), ("BOTCODER_SANDBOX_MOUNTS", "/cache:/cache")]).unwrap();
+    let args = args(&sandbox, Some("crates/api"));
+    let volume = format!("{}:/workspace", host_root);
+    assert_eq!(&args[..3], ["run", "--rm", "-i"]);
+    assert!(args.windows(2).any(|a| a == ["-v", volume.as_str()]), "{:?}", args);
+    assert!(args.windows(2).any(|a| a == ["-w", "/workspace/crates/api"]), "{:?}", args);
+    assert!(args.windows(2).any(|a| a == ["-e", "RUST_LOG=debug"]), "{:?}", args);
+    assert!(args.windows(2).any(|a| a == ["--network", "none"]), "{:?}", args);
+    assert!(args.windows(2).any(|a| a == ["-v", "/cache:/cache"]), "{:?}", args);
+    // The image, then the command for its shell
+    assert_eq!(&args[args.len() - 4..], ["rust:latest", "sh", "-c", "cargo test"]);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn sandbox_network_can_be_turned_on() {
+    let root = project("sandbox-network");
+    let sandbox = config(&[("BOTCODER_SANDBOX", "docker"), ("BOTCODER_SANDBOX_NETWORK", "true")]).unwrap();
+    let cmd = sandbox.command("cargo fetch", root.to_str().unwrap(), None, &BTreeMap::new());
+    let args: Vec<String> = cmd.get_args().map(|a| a.to_string_lossy().into_owned()).collect();
+    assert!(!args.iter().any(|a| a == "--network"), "{:?}", args);
+    assert!(args.windows(2).any(|a| a == ["-w", "/workspace"]), "{:?}", args);
+
+    fs::remove_dir_all(root).unwrap();
+}
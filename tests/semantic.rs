@@ -0,0 +1,120 @@
+use std::{
+    fs,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use botcoder::app;
+use botcoder::mock::MockProvider;
+use botcoder::semantic::{self, Embedder, IndexWatcher, LocalEmbedder, SemanticIndex};
+use botcoder::ToolError;
+
+mod common;
+use common::{engine, project};
+
+// The local embedder, counting the texts it embeds
+struct CountingEmbedder(Arc<AtomicUsize>);
+
+impl Embedder for CountingEmbedder {
+    fn name(&self) -> String {
+        "counting".to_string()
+    }
+
+    fn embed(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, ToolError> {
+        self.0.fetch_add(texts.len(), Ordering::SeqCst);
+        LocalEmbedder.embed(texts)
+    }
+}
+
+#[tokio::test]
+async fn semantic_search_finds_code_by_what_it_does() {
+    let root = project("semantic");
+    let root_str = root.to_str().unwrap();
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(
+        root.join("src/retry.rs"),
+        "// Waits longer after each failed attempt\npub fn schedule_retry(attempt: u32) -> Duration {\n    backoff_delay(attempt)\n}\n",
+    )
+    .unwrap();
+    fs::write(root.join("src/config.rs"), "pub fn parse_json_config(text: &str) -> Config {\n    serde_json::from_str(text)\n}\n").unwrap();
+    fs::write(root.join(".gitignore"), "secret.rs\n").unwrap();
+    fs::write(root.join("secret.rs"), "fn retry_backoff_schedule() {}\n").unwrap();
+
+    let found = app::extract_tools(r#"semantic_search("retry backoff schedule")"#);
+    assert_eq!(found, [("semantic_search".to_string(), "retry backoff schedule".to_string())]);
+    let output = app::execute_tool("semantic_search", "retry backoff schedule", root_str).unwrap();
+    assert!(output.text.starts_with("src/retry.rs:1-4 (score"), "{}", output.text);
+    assert!(output.text.contains("   2 | pub fn schedule_retry"), "{}", output.text);
+    assert!(!output.text.contains("secret.rs"), "{}", output.text);
+    assert_eq!(fs::read_to_string(root.join(".botcoder/index/.gitignore")).unwrap(), "*\n");
+
+    // Only files changed since the last search are embedded again, besides
+    // the query
+    let embedded = Arc::new(AtomicUsize::new(0));
+    let index = || SemanticIndex::new(root_str).with_embedder(CountingEmbedder(embedded.clone()));
+    index().search("config", 3).unwrap();
+    assert_eq!(embedded.swap(0, Ordering::SeqCst), 4);
+    index().search("config", 3).unwrap();
+    assert_eq!(embedded.swap(0, Ordering::SeqCst), 1);
+    fs::write(root.join("src/config.rs"), "pub fn load_toml_config() {}\n").unwrap();
+    let hits = index().search("toml config", 3).unwrap();
+    assert_eq!(embedded.swap(0, Ordering::SeqCst), 2);
+    assert_eq!(hits[0].path, "src/config.rs");
+
+    // Retrieval adds the related chunks to the system prompt of the task
+    let mock = MockProvider::new(["On it."]);
+    let mut engine = engine(&mock, &root).with_retrieval(1);
+    engine.push_user("Make the retry backoff longer");
+    engine.step().await.unwrap();
+    let system = &mock.requests()[0][0].content;
+    assert!(system.contains("RELEVANT CODE") && system.contains("src/retry.rs:1-4"), "{}", system);
+    assert!(!system.contains("src/config.rs"));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn the_index_follows_edits_while_watched() {
+    let root = project("index-watch");
+    let root_str = root.to_str().unwrap();
+    fs::create_dir_all(root.join("src")).unwrap();
+    fs::write(root.join("src/a.rs"), "fn alpha() {}\n").unwrap();
+    app::execute_tool("semantic_search", "alpha", root_str).unwrap();
+    let index_file = root.join(".botcoder/index/chunks.json");
+    let wait_for = |check: &dyn Fn(&str) -> bool| {
+        let started = std::time::Instant::now();
+        while started.elapsed() < std::time::Duration::from_secs(5) {
+            if check(&fs::read_to_string(&index_file).unwrap_or_default()) {
+                return true;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        false
+    };
+
+    // Files the agent writes are embedded again right away, without
+    // waiting for the next scan or a search
+    let watcher = IndexWatcher::start(root_str, std::time::Duration::from_secs(3600));
+    let before = fs::read_to_string(&index_file).unwrap();
+    let mock = MockProvider::new(Vec::<&str>::new());
+    let mut engine = engine(&mock, &root);
+    engine.queue_tool("append_to_file", "src/a.rs:::fn zebra() {}");
+    engine.execute_next();
+    assert!(wait_for(&|index| index != before));
+    drop(watcher);
+
+    // Files changed outside the agent at the next scan
+    let watcher = IndexWatcher::start(root_str, std::time::Duration::from_millis(100));
+    fs::write(root.join("src/b.rs"), "fn beta() {}\n").unwrap();
+    assert!(wait_for(&|index| index.contains("\"src/b.rs\"")));
+    // A removed file also has the file tree scanned again
+    let tree_changes = semantic::tree_changes();
+    fs::remove_file(root.join("src/a.rs")).unwrap();
+    assert!(wait_for(&|index| !index.contains("\"src/a.rs\"")));
+    assert!(semantic::tree_changes() > tree_changes);
+
+    drop(watcher);
+    fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,187 @@
+use std::fs;
+
+use botcoder::audit;
+use botcoder::lock::SessionLock;
+use botcoder::mock::MockProvider;
+use botcoder::sessions;
+use botcoder::timeline::{self, Outcome, Timeline};
+
+mod common;
+use common::{engine, project};
+
+#[test]
+fn audit_log_chains_every_entry() {
+    let root = project("audit");
+    let root_str = root.to_str().unwrap();
+
+    for i in 0..3 {
+        audit::record(root_str, "read_file", &format!("notes-{}.txt", i), None, "hello");
+    }
+    assert_eq!(audit::verify(root_str), Ok(3));
+
+    // A log changed by someone else is read again rather than chained to
+    // the last entry this process wrote
+    fs::remove_file(audit::path(root_str)).unwrap();
+    audit::record(root_str, "read_file", "notes.txt", None, "hello");
+    audit::record(root_str, "read_file", "notes.txt", None, "hello");
+    assert_eq!(audit::verify(root_str), Ok(2));
+
+    let log = fs::read_to_string(audit::path(root_str)).unwrap();
+    fs::write(audit::path(root_str), log.replacen("notes.txt", "other.txt", 1)).unwrap();
+    assert_eq!(audit::verify(root_str), Err("line 1: contents do not match hash".to_string()));
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn session_lock_refuses_second_instance() {
+    let root = project("lock");
+    let root_str = root.to_string_lossy();
+
+    let lock = SessionLock::acquire(&root_str, false).unwrap();
+    let holder = SessionLock::acquire(&root_str, false).err().unwrap();
+    assert_eq!(holder.pid, std::process::id());
+
+    drop(lock);
+    assert!(!root.join(".botcoder/lock").exists());
+    let _lock = SessionLock::acquire(&root_str, false).unwrap();
+    assert!(SessionLock::acquire(&root_str, true).is_ok());
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn timeline_logs_each_iteration_with_its_tools() {
+    let root = project("timeline");
+    let mock = MockProvider::new(["read_file(\"notes.txt\")", "read_file(\"missing.txt\")"]);
+    let mut engine = engine(&mock, &root);
+    let timeline = Timeline::new(root.to_str().unwrap(), "test");
+    engine.subscribe(timeline.clone());
+
+    engine.push_user("Look around");
+    engine.step().await.unwrap();
+    engine.step().await.unwrap();
+
+    // Read back from the log, one record per iteration despite the
+    // snapshots appended as each one progressed
+    let records = timeline.records();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].iteration, 1);
+    assert_eq!(records[0].outcome, Outcome::Ok);
+    assert_eq!(records[0].tools[0].tool, "read_file");
+    assert!(records[0].tools[0].result.contains("hello"));
+    assert!(records[0].tokens > 0);
+    assert_eq!(records[1].outcome, Outcome::ToolFailed);
+    assert!(records[1].details().contains("✗ read_file: missing.txt"));
+    assert!(fs::read_to_string(timeline.path()).unwrap().lines().count() > 2);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn search_finds_messages_and_tool_results_by_iteration() {
+    let root = project("search");
+    let mock = MockProvider::new(["read_file(\"notes.txt\")", "Done."]);
+    let mut engine = engine(&mock, &root);
+    let timeline = Timeline::new(root.to_str().unwrap(), "test");
+    engine.subscribe(timeline.clone());
+
+    engine.push_user("Check the NOTES");
+    engine.step().await.unwrap();
+    engine.push_user("Thanks");
+    engine.step().await.unwrap();
+
+    let records = timeline.records();
+    let matches = timeline::search(&records, "notes");
+    let found: Vec<(u32, &str)> = matches.iter().map(|m| (m.iteration, m.source.as_str())).collect();
+    assert_eq!(found[0], (1, "you"));
+    assert!(found.contains(&(1, "reply")));
+    assert!(found.contains(&(1, "read_file: notes.txt")));
+    let hello = timeline::search(&records, "HELLO");
+    assert_eq!(hello.len(), 1);
+    assert_eq!((hello[0].iteration, hello[0].line.as_str()), (1, "hello"));
+    assert_eq!(timeline::search(&records, "thanks")[0].iteration, 2);
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn saved_sessions_can_be_listed_and_loaded_into_another_engine() {
+    let root = project("sessions");
+    let mock = MockProvider::new(["read_file(\"notes.txt\")"]);
+    let mut first = engine(&mock, &root);
+    first.pin("notes.txt").unwrap();
+    first.push_user("Tidy up the notes\nthey are a mess");
+    first.step().await.unwrap();
+    sessions::save(&first, "notes-cleanup", Vec::new()).unwrap();
+    assert!(sessions::save(&first, "../escape", Vec::new()).is_err());
+
+    let saved = sessions::list(root.to_str().unwrap());
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].name, "notes-cleanup");
+    assert_eq!(saved[0].task, "Tidy up the notes");
+    assert_eq!((saved[0].model.as_str(), saved[0].iterations), ("editor: mock", 1));
+
+    let mock = MockProvider::new(["Done."]);
+    let mut second = engine(&mock, &root);
+    sessions::load(root.to_str().unwrap(), "notes-cleanup")
+        .unwrap()
+        .restore(&mut second);
+    assert_eq!(second.history().len(), first.history().len());
+    assert_eq!(second.pinned_files(), ["notes.txt"]);
+    assert_eq!(second.iteration(), 1);
+    second.push_user("Carry on");
+    second.step().await.unwrap();
+    assert!(mock.requests()[0].iter().any(|m| m.content.contains("Tidy up the notes")));
+
+    sessions::delete(root.to_str().unwrap(), "notes-cleanup").unwrap();
+    assert!(sessions::list(root.to_str().unwrap()).is_empty());
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn forks_continue_from_an_earlier_iteration_and_keep_their_parent() {
+    let root = project("fork");
+    let mock = MockProvider::new(["read_file(\"notes.txt\")", "Rewrote everything.", "Deleted it all."]);
+    let mut first = engine(&mock, &root);
+    let timeline = Timeline::new(root.to_str().unwrap(), "test");
+    first.subscribe(timeline.clone());
+    first.push_user("Tidy up the notes");
+    first.step().await.unwrap();
+    first.push_user("Rewrite them");
+    first.step().await.unwrap();
+    first.push_user("Delete them");
+    first.step().await.unwrap();
+    sessions::save(&first, "notes", timeline.records()).unwrap();
+
+    let original = sessions::load(root.to_str().unwrap(), "notes").unwrap();
+    assert_eq!(original.turns.len(), 3);
+    assert!(original.fork("notes-9", 9).is_err());
+    let branch = original.fork("notes-1", 1).unwrap();
+    sessions::write(&branch).unwrap();
+    assert_eq!(branch.turns.len(), 1);
+    assert_eq!(branch.meta.parent.as_deref(), Some("notes"));
+    assert!(sessions::describe_meta(&branch.meta).contains("forked from notes at #1"));
+
+    // The branch picks up after the read, without the later turns
+    let mock = MockProvider::new(["Done."]);
+    let mut second = engine(&mock, &root);
+    branch.restore(&mut second);
+    assert_eq!(second.iteration(), 1);
+    second.push_user("Only fix the typos");
+    second.step().await.unwrap();
+    let sent = &mock.requests()[0];
+    assert!(sent.iter().any(|m| m.content.contains("Tidy up the notes")));
+    assert!(sent.iter().any(|m| m.is_tool_results() && m.content.contains("hello")));
+    assert!(!sent.iter().any(|m| m.content.contains("Rewrite them")));
+
+    // Saving the branch again keeps its parent; the original is untouched
+    sessions::save(&second, "notes-1", Vec::new()).unwrap();
+    let saved = sessions::list(root.to_str().unwrap());
+    assert_eq!(saved.len(), 2);
+    let fork = saved.iter().find(|s| s.name == "notes-1").unwrap();
+    assert_eq!((fork.parent.as_deref(), fork.forked_at), (Some("notes"), Some(1)));
+    assert_eq!(sessions::load(root.to_str().unwrap(), "notes").unwrap().turns.len(), 3);
+
+    fs::remove_dir_all(root).unwrap();
+}